@@ -0,0 +1,205 @@
+use std::rc::Rc;
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use jujutsu_lib::backend::CommitId;
+use jujutsu_lib::repo::{ReadonlyRepo, Repo};
+use jujutsu_lib::repo_path::RepoPath;
+use jujutsu_lib::revset::{
+    FilePattern, RevsetEvaluationOptions, RevsetExpression, RevsetFilterPredicate,
+};
+use testutils::TestRepo;
+
+/// Creates mostly linear history of `count` trunk commits on top of the root
+/// commit. Every 10th trunk commit merges a side commit branched off 5 commits
+/// earlier. Returns the trunk commits, starting from the root commit.
+fn create_linear_and_merge_history(
+    test_repo: &TestRepo,
+    count: usize,
+) -> (Arc<ReadonlyRepo>, Vec<CommitId>) {
+    let settings = testutils::user_settings();
+    let repo = &test_repo.repo;
+    let empty_tree_id = repo.store().empty_tree_id().clone();
+    let mut tx = repo.start_transaction(&settings, "create history");
+    let mut_repo = tx.mut_repo();
+    let mut trunk = vec![repo.store().root_commit_id().clone()];
+    while trunk.len() <= count {
+        let mut parents = vec![trunk.last().unwrap().clone()];
+        if trunk.len() % 10 == 0 {
+            let fork_point = trunk[trunk.len() - 5].clone();
+            let side = mut_repo
+                .new_commit(&settings, vec![fork_point], empty_tree_id.clone())
+                .set_description(format!("side {}", trunk.len()))
+                .write()
+                .unwrap();
+            parents.push(side.id().clone());
+        }
+        let commit = mut_repo
+            .new_commit(&settings, parents, empty_tree_id.clone())
+            .set_description(format!("trunk {}", trunk.len()))
+            .write()
+            .unwrap();
+        trunk.push(commit.id().clone());
+    }
+    (tx.commit(), trunk)
+}
+
+/// Creates linear history of `count` commits on top of the root commit. Each
+/// commit modifies a file in one of `num_dirs` directories in turn.
+fn create_file_modifying_history(
+    test_repo: &TestRepo,
+    count: usize,
+    num_dirs: usize,
+) -> Arc<ReadonlyRepo> {
+    let settings = testutils::user_settings();
+    let repo = &test_repo.repo;
+    let store = repo.store();
+    let mut tx = repo.start_transaction(&settings, "create history");
+    let mut_repo = tx.mut_repo();
+    let mut parent_id = store.root_commit_id().clone();
+    let mut tree_id = store.empty_tree_id().clone();
+    for i in 0..count {
+        let path = RepoPath::from_internal_string(&format!("dir{}/file", i % num_dirs));
+        let mut tree_builder = store.tree_builder(tree_id);
+        testutils::write_normal_file(&mut tree_builder, &path, &format!("{i}\n"));
+        tree_id = tree_builder.write_tree();
+        let commit = mut_repo
+            .new_commit(&settings, vec![parent_id], tree_id.clone())
+            .write()
+            .unwrap();
+        parent_id = commit.id().clone();
+    }
+    tx.commit()
+}
+
+fn evaluate_count(repo: &dyn Repo, expression: &Rc<RevsetExpression>) -> usize {
+    let expression = expression.clone().resolve(repo).unwrap();
+    expression.evaluate(repo).unwrap().count().unwrap()
+}
+
+fn bench_revsets(c: &mut Criterion) {
+    let test_repo = TestRepo::init(false);
+    let (repo, trunk) = create_linear_and_merge_history(&test_repo, 100_000);
+    let root = RevsetExpression::commit(trunk[0].clone());
+    let head = RevsetExpression::commit(trunk.last().unwrap().clone());
+
+    let mut group = c.benchmark_group("bench_dag_range");
+    group.sample_size(10);
+    // root:head
+    let expression = root.dag_range_to(&head);
+    group.bench_function("full_range", |b| {
+        b.iter(|| evaluate_count(repo.as_ref(), &expression))
+    });
+    // roots(merges()), which fills the range between the merges and their
+    // parents
+    let expression =
+        RevsetExpression::filter(RevsetFilterPredicate::ParentCount(2..u32::MAX)).roots();
+    group.bench_function("roots_of_merges", |b| {
+        b.iter(|| evaluate_count(repo.as_ref(), &expression))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("bench_union");
+    group.sample_size(10);
+    // a | b | c | ... of 50 commits spread over the history
+    let expression = trunk
+        .iter()
+        .step_by(trunk.len() / 50)
+        .take(50)
+        .map(|id| RevsetExpression::commit(id.clone()))
+        .reduce(|expression1, expression2| expression1.union(&expression2))
+        .unwrap();
+    group.bench_function("single_commits_50", |b| {
+        b.iter(|| evaluate_count(repo.as_ref(), &expression))
+    });
+    group.finish();
+}
+
+fn bench_intersection(c: &mut Criterion) {
+    let test_repo = TestRepo::init(false);
+    let (repo, trunk) = create_linear_and_merge_history(&test_repo, 500_000);
+    let root = RevsetExpression::commit(trunk[0].clone());
+    let head = RevsetExpression::commit(trunk.last().unwrap().clone());
+    let commits = RevsetExpression::commits(
+        [1, 2, 3]
+            .map(|i| trunk[trunk.len() * i / 4].clone())
+            .to_vec(),
+    );
+
+    // The sets are evaluated up front so only the intersection is measured
+    let mut group = c.benchmark_group("bench_intersection");
+    group.sample_size(10);
+    // ::head & (a | b | c), where the ancestors are walked lazily
+    let revset = head
+        .ancestors()
+        .intersection(&commits)
+        .resolve(repo.as_ref())
+        .unwrap()
+        .evaluate(repo.as_ref())
+        .unwrap();
+    group.bench_function("ancestors_and_3_commits", |b| {
+        b.iter(|| revset.count().unwrap())
+    });
+    // root:head & (a | b | c), where the range is collected into a list
+    let revset = root
+        .dag_range_to(&head)
+        .intersection(&commits)
+        .resolve(repo.as_ref())
+        .unwrap()
+        .evaluate(repo.as_ref())
+        .unwrap();
+    group.bench_function("dag_range_and_3_commits", |b| {
+        b.iter(|| revset.count().unwrap())
+    });
+    group.finish();
+}
+
+fn bench_file_filter(c: &mut Criterion) {
+    let test_repo = TestRepo::init(false);
+    let repo = create_file_modifying_history(&test_repo, 50_000, 10);
+    let expression = RevsetExpression::filter(RevsetFilterPredicate::File {
+        includes: Some(vec![FilePattern::PrefixPath(
+            RepoPath::from_internal_string("dir0"),
+        )]),
+        excludes: vec![],
+    })
+    .resolve(repo.as_ref())
+    .unwrap();
+    let evaluate_count = |options: &RevsetEvaluationOptions| {
+        let revset = expression
+            .evaluate_with_options(repo.as_ref(), options)
+            .unwrap();
+        revset.count().unwrap()
+    };
+    let parallel_options = RevsetEvaluationOptions {
+        parallel_filters: true,
+    };
+
+    // file(dir0), which diffs the trees of all commits. Compare the default
+    // sequential evaluation with the parallel one, which is run with a single
+    // worker thread and with the default number of threads.
+    let mut group = c.benchmark_group("bench_file_filter");
+    group.sample_size(10);
+    let single_thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    group.bench_function("sequential", |b| {
+        b.iter(|| evaluate_count(&RevsetEvaluationOptions::default()))
+    });
+    group.bench_function("parallel_single_thread", |b| {
+        b.iter(|| single_thread_pool.install(|| evaluate_count(&parallel_options)))
+    });
+    group.bench_function("parallel_default_threads", |b| {
+        b.iter(|| evaluate_count(&parallel_options))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_revsets,
+    bench_intersection,
+    bench_file_filter
+);
+criterion_main!(benches);
@@ -14,6 +14,7 @@
 
 use std::collections::BTreeMap;
 use std::fmt::{Debug, Error, Formatter};
+use std::io;
 use std::io::Read;
 use std::result::Result;
 use std::vec::Vec;
@@ -100,7 +101,7 @@ pub enum Phase {
 }
 
 content_hash! {
-    #[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+    #[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord, Hash)]
     pub struct MillisSinceEpoch(pub i64);
 }
 
@@ -147,6 +148,17 @@ content_hash! {
         pub description: String,
         pub author: Signature,
         pub committer: Signature,
+        /// Cryptographic signature and the data it signs, if the commit is
+        /// signed. The signature isn't verified.
+        pub secure_sig: Option<SecureSig>,
+    }
+}
+
+content_hash! {
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub struct SecureSig {
+        pub data: Vec<u8>,
+        pub sig: Vec<u8>,
     }
 }
 
@@ -365,6 +377,7 @@ pub fn make_root_commit(root_change_id: ChangeId, empty_tree_id: TreeId) -> Comm
         description: String::new(),
         author: signature.clone(),
         committer: signature,
+        secure_sig: None,
     }
 }
 
@@ -383,6 +396,17 @@ pub trait Backend: Send + Sync + Debug {
 
     fn read_file(&self, path: &RepoPath, id: &FileId) -> BackendResult<Box<dyn Read>>;
 
+    /// Size of the file contents in bytes. The default implementation streams
+    /// the contents, so backends that store the size should override this.
+    fn file_size(&self, path: &RepoPath, id: &FileId) -> BackendResult<u64> {
+        let mut file = self.read_file(path, id)?;
+        io::copy(&mut file, &mut io::sink()).map_err(|err| BackendError::ReadObject {
+            object_type: id.object_type(),
+            hash: id.hex(),
+            source: Box::new(err),
+        })
+    }
+
     fn write_file(&self, path: &RepoPath, contents: &mut dyn Read) -> BackendResult<FileId>;
 
     fn read_symlink(&self, path: &RepoPath, id: &SymlinkId) -> BackendResult<String>;
@@ -0,0 +1,148 @@
+// Copyright 2023 The Jujutsu Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bloom filter of the paths changed by a commit, similar to the changed-path
+//! filters in Git's commit-graph.
+
+use blake2::Blake2b512;
+use digest::Digest;
+
+use crate::commit::Commit;
+use crate::matchers::EverythingMatcher;
+use crate::repo_path::RepoPath;
+
+const BITS_PER_ENTRY: usize = 10;
+const NUM_HASHES: u64 = 7;
+/// Commits changing more paths than this get no filter, like in Git, because a
+/// filter wouldn't rule out much for them.
+const MAX_CHANGED_PATHS: usize = 512;
+
+/// Set of paths changed by a commit, which may report false positives but
+/// never false negatives.
+///
+/// The parent directories of the changed paths are added too, so a directory
+/// can be tested without knowing the files under it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChangedPathFilter {
+    bits: Vec<u8>,
+}
+
+impl ChangedPathFilter {
+    /// Builds the filter of paths changed by the `commit` compared to any of
+    /// its parents. This is a superset of the paths changed compared to the
+    /// merged parents, so the parents don't have to be merged. Returns `None`
+    /// if too many paths are changed.
+    pub fn from_commit(commit: &Commit) -> Option<Self> {
+        let tree = commit.tree();
+        let mut paths = vec![];
+        for parent in commit.parents() {
+            if parent.tree_id() == commit.tree_id() {
+                continue;
+            }
+            for (path, _) in parent.tree().diff(&tree, &EverythingMatcher) {
+                if paths.len() == MAX_CHANGED_PATHS {
+                    return None;
+                }
+                paths.push(path);
+            }
+        }
+        Some(ChangedPathFilter::from_paths(&paths))
+    }
+
+    pub fn from_paths<'a>(paths: impl IntoIterator<Item = &'a RepoPath>) -> Self {
+        let mut entries = vec![];
+        for path in paths {
+            let mut path = Some(path.clone());
+            while let Some(current) = path {
+                if current.is_root() {
+                    break;
+                }
+                path = current.parent();
+                entries.push(current.to_internal_file_string());
+            }
+        }
+        entries.sort_unstable();
+        entries.dedup();
+        let num_bytes = (entries.len() * BITS_PER_ENTRY + 7) / 8;
+        let mut filter = ChangedPathFilter {
+            bits: vec![0; num_bytes.max(1)],
+        };
+        for entry in &entries {
+            for bit in filter.bit_positions(entry) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    /// Restores the filter serialized by `as_bytes()`, which must not be empty.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        assert!(!bytes.is_empty());
+        ChangedPathFilter {
+            bits: bytes.to_vec(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    /// Returns `false` if the `path` is definitely not changed. The root path
+    /// is always considered changed.
+    pub fn might_contain(&self, path: &RepoPath) -> bool {
+        if path.is_root() {
+            return true;
+        }
+        let entry = path.to_internal_file_string();
+        self.bit_positions(&entry)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn bit_positions(&self, entry: &str) -> impl Iterator<Item = usize> {
+        // Double hashing as described in "Less Hashing, Same Performance". The
+        // filters are persisted in the index, so the hash function has to be
+        // stable, which rules out `DefaultHasher`.
+        let hash = Blake2b512::digest(entry.as_bytes());
+        let h1 = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap());
+        let num_bits = self.bits.len() as u64 * 8;
+        (0..NUM_HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_path_filter() {
+        let path = RepoPath::from_internal_string;
+        let filter = ChangedPathFilter::from_paths(&[path("dir/sub/file"), path("other")]);
+        assert!(filter.might_contain(&RepoPath::root()));
+        assert!(filter.might_contain(&path("dir")));
+        assert!(filter.might_contain(&path("dir/sub")));
+        assert!(filter.might_contain(&path("dir/sub/file")));
+        assert!(filter.might_contain(&path("other")));
+        // False positives are possible, but unlikely for this small set
+        assert!(!filter.might_contain(&path("dir/file")));
+        assert!(!filter.might_contain(&path("dir/sub/file2")));
+        assert!(!filter.might_contain(&path("sub")));
+
+        assert_eq!(ChangedPathFilter::from_bytes(filter.as_bytes()), filter);
+
+        let empty_filter = ChangedPathFilter::from_paths(&[]);
+        assert!(empty_filter.might_contain(&RepoPath::root()));
+        assert!(!empty_filter.might_contain(&path("dir")));
+    }
+}
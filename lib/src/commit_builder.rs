@@ -46,6 +46,7 @@ impl CommitBuilder<'_> {
             description: String::new(),
             author: signature.clone(),
             committer: signature,
+            secure_sig: None,
         };
         CommitBuilder {
             mut_repo,
@@ -63,6 +64,8 @@ impl CommitBuilder<'_> {
         let mut commit = predecessor.store_commit().clone();
         commit.predecessors = vec![predecessor.id().clone()];
         commit.committer = settings.signature();
+        // The signature wouldn't be valid for the rewritten commit.
+        commit.secure_sig = None;
         // If the user had not configured a name and email before but now they have,
         // update the author fields with the new information.
         if commit.author.name == UserSettings::user_name_placeholder() {
@@ -32,7 +32,8 @@ use smallvec::SmallVec;
 use tempfile::NamedTempFile;
 use thiserror::Error;
 
-use crate::backend::{ChangeId, CommitId, ObjectId};
+use crate::backend::{ChangeId, CommitId, MillisSinceEpoch, ObjectId};
+use crate::changed_path_filter::ChangedPathFilter;
 use crate::commit::Commit;
 use crate::file_util::persist_content_addressed_temp_file;
 use crate::index::{
@@ -45,7 +46,9 @@ use crate::index::{
 use crate::nightly_shims::BTreeSetExt;
 use crate::op_store::OperationId;
 use crate::operation::Operation;
-use crate::revset::{ResolvedExpression, Revset, RevsetEvaluationError};
+use crate::revset::{
+    GenerationMode, ResolvedExpression, Revset, RevsetEvaluationError, RevsetEvaluationOptions,
+};
 use crate::store::Store;
 use crate::{backend, dag_walk, default_revset_engine};
 
@@ -306,6 +309,55 @@ impl IndexPosition {
     pub const MAX: Self = IndexPosition(u32::MAX);
 }
 
+/// Set of positions within a fixed range, stored as a dense bit vector.
+///
+/// This is much smaller and faster than `HashSet<IndexPosition>` if the set
+/// covers a good part of the range. Positions outside the range are never
+/// contained.
+#[derive(Clone, Debug)]
+pub struct IndexPositionSet {
+    min_position: IndexPosition,
+    bits: Vec<u64>,
+}
+
+impl IndexPositionSet {
+    /// Creates empty set which can hold positions in
+    /// `min_position..=max_position`.
+    pub fn with_range(min_position: IndexPosition, max_position: IndexPosition) -> Self {
+        let len = max_position.0.saturating_sub(min_position.0) as usize + 1;
+        IndexPositionSet {
+            min_position,
+            bits: vec![0; (len + 63) / 64],
+        }
+    }
+
+    /// Creates empty set which can't hold any positions.
+    pub fn empty() -> Self {
+        IndexPositionSet {
+            min_position: IndexPosition::MAX,
+            bits: vec![],
+        }
+    }
+
+    fn offset(&self, pos: IndexPosition) -> Option<usize> {
+        let offset = pos.0.checked_sub(self.min_position.0)? as usize;
+        (offset < self.bits.len() * 64).then_some(offset)
+    }
+
+    pub fn contains(&self, pos: IndexPosition) -> bool {
+        match self.offset(pos) {
+            Some(offset) => self.bits[offset / 64] & (1 << (offset % 64)) != 0,
+            None => false,
+        }
+    }
+
+    /// Adds the `pos` to the set. Panics if it's out of the range.
+    pub fn insert(&mut self, pos: IndexPosition) {
+        let offset = self.offset(pos).expect("position should be within range");
+        self.bits[offset / 64] |= 1 << (offset % 64);
+    }
+}
+
 // SmallVec reuses two pointer-size fields as inline area, which meas we can
 // inline up to 16 bytes (on 64-bit platform) for free.
 type SmallIndexPositionsVec = SmallVec<[IndexPosition; 4]>;
@@ -320,7 +372,7 @@ struct CommitGraphEntry<'a> {
 // lowest set bit to determine which generation number the pointers point to.
 impl CommitGraphEntry<'_> {
     fn size(commit_id_length: usize, change_id_length: usize) -> usize {
-        20 + commit_id_length + change_id_length
+        28 + commit_id_length + change_id_length
     }
 
     fn generation_number(&self) -> u32 {
@@ -339,6 +391,10 @@ impl CommitGraphEntry<'_> {
         (&self.data[16..]).read_u32::<LittleEndian>().unwrap()
     }
 
+    fn committer_timestamp(&self) -> MillisSinceEpoch {
+        MillisSinceEpoch((&self.data[20..]).read_i64::<LittleEndian>().unwrap())
+    }
+
     // TODO: Consider storing the change ids in a separate table. That table could
     // be sorted by change id and have the end index into a list as value. That list
     // would be the concatenation of all index positions associated with the change.
@@ -346,13 +402,13 @@ impl CommitGraphEntry<'_> {
     // to better cache locality when walking it; ability to quickly find all
     // commits associated with a change id.
     fn change_id(&self) -> ChangeId {
-        ChangeId::new(self.data[20..20 + self.change_id_length].to_vec())
+        ChangeId::new(self.data[28..28 + self.change_id_length].to_vec())
     }
 
     fn commit_id(&self) -> CommitId {
         CommitId::from_bytes(
             &self.data
-                [20 + self.change_id_length..20 + self.change_id_length + self.commit_id_length],
+                [28 + self.change_id_length..28 + self.change_id_length + self.commit_id_length],
         )
     }
 }
@@ -393,19 +449,35 @@ pub enum IndexLoadError {
     IoError(#[from] io::Error),
 }
 
+/// Version of the index segment file format. Segments of any other version are
+/// treated as corrupt, so the index is rebuilt.
+const INDEX_SEGMENT_FILE_FORMAT_VERSION: u32 = 2;
+
 // File format:
+// u32: file format version
+// u32: parent file name length (0 means root)
+// <length number of bytes>: parent file name
 // u32: number of entries
 // u32: number of parent overflow entries
+// u32: number of changed-path filter bytes
 // for each entry, in some topological order with parents first:
+//   u32: flags (unused)
 //   u32: generation number
 //   u32: number of parents
 //   u32: position in this table for parent 1
 //   u32: position in the overflow table of parent 2
+//   i64: committer timestamp in milliseconds since the epoch
+//   <change id length number of bytes>: change id
 //   <hash length number of bytes>: commit id
 // for each entry, sorted by commit id:
 //   <hash length number of bytes>: commit id
 //    u32: position in the entry table above
-// TODO: add a version number
+// for each overflow parent:
+//   u32: position in the entry table above
+// for each entry, in the order of the entry table above:
+//   u32: end offset of its changed-path filter in the bytes below (the filter
+//        is absent if it's empty)
+// <number of changed-path filter bytes>: concatenated changed-path filters
 // TODO: replace the table by a trie so we don't have to repeat the full commit
 //       ids
 // TODO: add a fanout table like git's commit graph has?
@@ -422,6 +494,8 @@ pub struct ReadonlyIndexImpl {
     graph: Vec<u8>,
     lookup: Vec<u8>,
     overflow_parent: Vec<u8>,
+    changed_path_filter_ends: Vec<u8>,
+    changed_path_filters: Vec<u8>,
 }
 
 pub struct ReadonlyIndexWrapper(Arc<ReadonlyIndexImpl>);
@@ -460,7 +534,9 @@ struct MutableGraphEntry {
     commit_id: CommitId,
     change_id: ChangeId,
     generation_number: u32,
+    committer_timestamp: MillisSinceEpoch,
     parent_positions: SmallIndexPositionsVec,
+    changed_path_filter: Option<ChangedPathFilter>,
 }
 
 pub struct MutableIndexImpl {
@@ -502,11 +578,24 @@ impl MutableIndexImpl {
         CompositeIndex(self)
     }
 
+    /// Adds commit of unknown committer timestamp, which is recorded as the
+    /// epoch. This is for tests which don't write commit objects.
+    #[cfg(test)]
     pub(crate) fn add_commit_data(
         &mut self,
         commit_id: CommitId,
         change_id: ChangeId,
         parent_ids: &[CommitId],
+    ) {
+        self.add_commit_data_with_timestamp(commit_id, change_id, MillisSinceEpoch(0), parent_ids);
+    }
+
+    pub(crate) fn add_commit_data_with_timestamp(
+        &mut self,
+        commit_id: CommitId,
+        change_id: ChangeId,
+        committer_timestamp: MillisSinceEpoch,
+        parent_ids: &[CommitId],
     ) {
         if self.has_id(&commit_id) {
             return;
@@ -515,7 +604,9 @@ impl MutableIndexImpl {
             commit_id,
             change_id,
             generation_number: 0,
+            committer_timestamp,
             parent_positions: SmallVec::new(),
+            changed_path_filter: None,
         };
         for parent_id in parent_ids {
             let parent_entry = CompositeIndex(self)
@@ -534,16 +625,37 @@ impl MutableIndexImpl {
         self.graph.push(entry);
     }
 
+    /// Attaches the filter of paths changed by the commit, which must have
+    /// been added to this index segment. `add_commit()` does this for commits
+    /// loaded from the store.
+    pub fn set_changed_path_filter(&mut self, commit_id: &CommitId, filter: ChangedPathFilter) {
+        let pos = self.lookup[commit_id];
+        let local_pos = pos.0 - self.num_parent_commits;
+        self.graph[local_pos as usize].changed_path_filter = Some(filter);
+    }
+
     fn add_commits_from(&mut self, other_segment: &dyn IndexSegment) {
         let other = CompositeIndex(other_segment);
         for pos in other_segment.segment_num_parent_commits()..other.num_commits() {
             let entry = other.entry_by_pos(IndexPosition(pos));
+            let commit_id = entry.commit_id();
+            if self.has_id(&commit_id) {
+                continue;
+            }
             let parent_ids = entry
                 .parents()
                 .iter()
                 .map(|entry| entry.commit_id())
                 .collect_vec();
-            self.add_commit_data(entry.commit_id(), entry.change_id(), &parent_ids);
+            self.add_commit_data_with_timestamp(
+                commit_id.clone(),
+                entry.change_id(),
+                entry.committer_timestamp(),
+                &parent_ids,
+            );
+            if let Some(filter) = entry.changed_path_filter() {
+                self.set_changed_path_filter(&commit_id, filter);
+            }
         }
     }
 
@@ -554,6 +666,9 @@ impl MutableIndexImpl {
 
         let mut buf = vec![];
 
+        buf.write_u32::<LittleEndian>(INDEX_SEGMENT_FILE_FORMAT_VERSION)
+            .unwrap();
+
         if let Some(parent_file) = &self.parent_file {
             buf.write_u32::<LittleEndian>(parent_file.name.len() as u32)
                 .unwrap();
@@ -563,12 +678,21 @@ impl MutableIndexImpl {
         }
 
         buf.write_u32::<LittleEndian>(num_commits).unwrap();
-        // We'll write the actual value later
+        // We'll write the actual values later
         let parent_overflow_offset = buf.len();
         buf.write_u32::<LittleEndian>(0_u32).unwrap();
+        let changed_path_filters_offset = buf.len();
+        buf.write_u32::<LittleEndian>(0_u32).unwrap();
 
         let mut parent_overflow = vec![];
+        let mut changed_path_filter_ends = vec![];
+        let mut changed_path_filters = vec![];
         for entry in self.graph {
+            if let Some(filter) = &entry.changed_path_filter {
+                changed_path_filters.extend_from_slice(filter.as_bytes());
+            }
+            changed_path_filter_ends.push(changed_path_filters.len() as u32);
+
             let flags = 0;
             buf.write_u32::<LittleEndian>(flags).unwrap();
 
@@ -589,6 +713,9 @@ impl MutableIndexImpl {
             buf.write_u32::<LittleEndian>(parent1_pos.0).unwrap();
             buf.write_u32::<LittleEndian>(parent_overflow_pos).unwrap();
 
+            buf.write_i64::<LittleEndian>(entry.committer_timestamp.0)
+                .unwrap();
+
             assert_eq!(entry.change_id.as_bytes().len(), self.change_id_length);
             buf.write_all(entry.change_id.as_bytes()).unwrap();
 
@@ -609,6 +736,15 @@ impl MutableIndexImpl {
             buf.write_u32::<LittleEndian>(parent_pos.0).unwrap();
         }
 
+        buf[changed_path_filters_offset..changed_path_filters_offset + 4]
+            .as_mut()
+            .write_u32::<LittleEndian>(changed_path_filters.len() as u32)
+            .unwrap();
+        for end in changed_path_filter_ends {
+            buf.write_u32::<LittleEndian>(end).unwrap();
+        }
+        buf.write_all(&changed_path_filters).unwrap();
+
         buf
     }
 
@@ -736,9 +872,15 @@ impl Index for MutableIndexImpl {
         &'index self,
         expression: &ResolvedExpression,
         store: &Arc<Store>,
+        options: &RevsetEvaluationOptions,
     ) -> Result<Box<dyn Revset<'index> + 'index>, RevsetEvaluationError> {
-        let revset_impl =
-            default_revset_engine::evaluate(expression, store, self, CompositeIndex(self))?;
+        let revset_impl = default_revset_engine::evaluate(
+            expression,
+            store,
+            self,
+            CompositeIndex(self),
+            options,
+        )?;
         Ok(Box::new(revset_impl))
     }
 }
@@ -753,11 +895,18 @@ impl MutableIndex for MutableIndexImpl {
     }
 
     fn add_commit(&mut self, commit: &Commit) {
-        self.add_commit_data(
+        if self.has_id(commit.id()) {
+            return;
+        }
+        self.add_commit_data_with_timestamp(
             commit.id().clone(),
             commit.change_id().clone(),
+            commit.committer().timestamp.timestamp.clone(),
             commit.parent_ids(),
         );
+        if let Some(filter) = ChangedPathFilter::from_commit(commit) {
+            self.set_changed_path_filter(commit.id(), filter);
+        }
     }
 
     fn merge_in(&mut self, other: &dyn ReadonlyIndex) {
@@ -823,10 +972,14 @@ trait IndexSegment: Send + Sync {
 
     fn segment_change_id(&self, local_pos: u32) -> ChangeId;
 
+    fn segment_committer_timestamp(&self, local_pos: u32) -> MillisSinceEpoch;
+
     fn segment_num_parents(&self, local_pos: u32) -> u32;
 
     fn segment_parent_positions(&self, local_pos: u32) -> SmallIndexPositionsVec;
 
+    fn segment_changed_path_filter(&self, local_pos: u32) -> Option<ChangedPathFilter>;
+
     fn segment_entry_by_pos(&self, pos: IndexPosition, local_pos: u32) -> IndexEntry;
 }
 
@@ -1080,7 +1233,7 @@ impl<'a> CompositeIndex<'a> {
             .collect()
     }
 
-    fn heads_pos(
+    pub(crate) fn heads_pos(
         &self,
         mut candidate_positions: BTreeSet<IndexPosition>,
     ) -> BTreeSet<IndexPosition> {
@@ -1116,6 +1269,48 @@ impl<'a> CompositeIndex<'a> {
         candidate_positions
     }
 
+    /// Calculates the same heads as `heads_pos()` in a single scan, but the
+    /// `candidate_positions` must be sorted in descending order. The heads are
+    /// returned in the same order.
+    ///
+    /// A candidate is a head unless it's reachable from a visited candidate.
+    /// Since children have higher positions than their parents, the positions
+    /// reachable from the candidates can be marked on the way down.
+    pub(crate) fn heads_pos_descending(
+        &self,
+        candidate_positions: impl IntoIterator<Item = IndexPosition>,
+    ) -> Vec<IndexPosition> {
+        let mut candidate_positions = candidate_positions.into_iter().peekable();
+        let max_position = match candidate_positions.peek() {
+            Some(&pos) => pos,
+            None => return vec![],
+        };
+        let mark_parents_reachable = |reachable: &mut IndexPositionSet, pos| {
+            for parent_pos in self.entry_by_pos(pos).parent_positions() {
+                reachable.insert(parent_pos);
+            }
+        };
+        let mut reachable = IndexPositionSet::with_range(IndexPosition(0), max_position);
+        let mut heads = vec![];
+        // Position right above the next one to scan
+        let mut scan_end = max_position.0.saturating_add(1);
+        for pos in candidate_positions {
+            assert!(pos.0 < scan_end, "candidates must be in descending order");
+            // Propagate reachability through the non-candidates in between
+            for non_candidate_pos in (pos.0 + 1..scan_end).rev().map(IndexPosition) {
+                if reachable.contains(non_candidate_pos) {
+                    mark_parents_reachable(&mut reachable, non_candidate_pos);
+                }
+            }
+            if !reachable.contains(pos) {
+                heads.push(pos);
+            }
+            mark_parents_reachable(&mut reachable, pos);
+            scan_end = pos.0;
+        }
+        heads
+    }
+
     /// Parents before children
     fn topo_order(&self, input: &mut dyn Iterator<Item = &CommitId>) -> Vec<CommitId> {
         let mut ids = input.cloned().collect_vec();
@@ -1396,12 +1591,23 @@ impl<'a> RevWalk<'a> {
 
     /// Filters entries by generation (or depth from the current wanted set.)
     ///
-    /// The generation of the current wanted entries starts from 0.
-    pub fn filter_by_generation(self, generation_range: Range<u32>) -> RevWalkGenerationRange<'a> {
-        RevWalkGenerationRange(RevWalkGenerationRangeImpl::new(
-            self.0.queue,
-            generation_range,
-        ))
+    /// The generation of the current wanted entries starts from 0. If an entry
+    /// is reachable through multiple paths, `mode` determines which of the
+    /// path lengths is tested.
+    pub fn filter_by_generation(
+        self,
+        generation_range: Range<u32>,
+        mode: GenerationMode,
+    ) -> RevWalkGenerationRange<'a> {
+        let inner = match mode {
+            GenerationMode::Any => RevWalkGenerationRangeInner::Any(
+                RevWalkGenerationRangeImpl::new(self.0.queue, generation_range),
+            ),
+            GenerationMode::Min | GenerationMode::Max => RevWalkGenerationRangeInner::MinMax(
+                RevWalkMinMaxGenerationRangeImpl::new(self.0.queue, generation_range, mode),
+            ),
+        };
+        RevWalkGenerationRange(inner)
     }
 
     /// Walks ancestors until all of the reachable roots in `root_positions` get
@@ -1482,13 +1688,22 @@ impl<'a, I: RevWalkIndex<'a>> RevWalkImpl<'a, I> {
 }
 
 #[derive(Clone)]
-pub struct RevWalkGenerationRange<'a>(RevWalkGenerationRangeImpl<'a, CompositeIndex<'a>>);
+pub struct RevWalkGenerationRange<'a>(RevWalkGenerationRangeInner<'a>);
+
+#[derive(Clone)]
+enum RevWalkGenerationRangeInner<'a> {
+    Any(RevWalkGenerationRangeImpl<'a, CompositeIndex<'a>>),
+    MinMax(RevWalkMinMaxGenerationRangeImpl<'a, CompositeIndex<'a>>),
+}
 
 impl<'a> Iterator for RevWalkGenerationRange<'a> {
     type Item = IndexEntry<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next()
+        match &mut self.0 {
+            RevWalkGenerationRangeInner::Any(inner) => inner.next(),
+            RevWalkGenerationRangeInner::MinMax(inner) => inner.next(),
+        }
     }
 }
 
@@ -1591,6 +1806,89 @@ impl<'a, I: RevWalkIndex<'a>> RevWalkGenerationRangeImpl<'a, I> {
     }
 }
 
+/// Walks entries by tracking the shortest and longest paths from the wanted
+/// set.
+///
+/// Since the queue is sorted by index position, all descendants of an entry
+/// have been visited by the time the entry is popped, so its path lengths are
+/// final.
+#[derive(Clone)]
+struct RevWalkMinMaxGenerationRangeImpl<'a, I: RevWalkIndex<'a>> {
+    queue: RevWalkQueue<'a, I, RevWalkItemMinMaxGeneration>,
+    generation_range: Range<u32>,
+    mode: GenerationMode,
+}
+
+impl<'a, I: RevWalkIndex<'a>> RevWalkMinMaxGenerationRangeImpl<'a, I> {
+    fn new(
+        queue: RevWalkQueue<'a, I, ()>,
+        generation_range: Range<u32>,
+        mode: GenerationMode,
+    ) -> Self {
+        let gen = RevWalkItemMinMaxGeneration { min: 0, max: 0 };
+        RevWalkMinMaxGenerationRangeImpl {
+            queue: queue.map_wanted(|()| gen),
+            generation_range,
+            mode,
+        }
+    }
+
+    fn next(&mut self) -> Option<IndexEntry<'a>> {
+        while let Some(item) = self.queue.pop() {
+            if let RevWalkWorkItemState::Wanted(mut gen) = item.state {
+                while let Some(x) = self.queue.pop_eq(&item.entry) {
+                    if let RevWalkWorkItemState::Wanted(other) = x.state {
+                        gen = RevWalkItemMinMaxGeneration {
+                            min: min(gen.min, other.min),
+                            max: max(gen.max, other.max),
+                        };
+                    } else {
+                        unreachable!("no more unwanted items of the same entry");
+                    }
+                }
+                let generation = match self.mode {
+                    GenerationMode::Min => gen.min,
+                    GenerationMode::Max => gen.max,
+                    GenerationMode::Any => unreachable!(),
+                };
+                // The longest path of ancestors can't be determined without
+                // walking all of them, but the shortest path can be pruned.
+                if self.mode == GenerationMode::Max
+                    || gen.min.saturating_add(1) < self.generation_range.end
+                {
+                    let succ_gen = RevWalkItemMinMaxGeneration {
+                        min: gen.min.saturating_add(1),
+                        max: gen.max.saturating_add(1),
+                    };
+                    self.queue.push_wanted_adjacents(&item.entry, succ_gen);
+                }
+                if self.generation_range.contains(&generation) {
+                    return Some(item.entry);
+                }
+            } else if self.queue.items.len() == self.queue.unwanted_count {
+                // No more wanted entries to walk
+                debug_assert!(!self.queue.items.iter().any(|x| x.is_wanted()));
+                return None;
+            } else {
+                self.queue.skip_while_eq(&item.entry);
+                self.queue.push_unwanted_adjacents(&item.entry);
+            }
+        }
+
+        debug_assert_eq!(
+            self.queue.items.iter().filter(|x| !x.is_wanted()).count(),
+            self.queue.unwanted_count
+        );
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct RevWalkItemMinMaxGeneration {
+    min: u32,
+    max: u32,
+}
+
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 struct RevWalkItemGenerationRange {
     start: u32,
@@ -1689,6 +1987,10 @@ impl IndexSegment for ReadonlyIndexImpl {
         self.graph_entry(local_pos).change_id()
     }
 
+    fn segment_committer_timestamp(&self, local_pos: u32) -> MillisSinceEpoch {
+        self.graph_entry(local_pos).committer_timestamp()
+    }
+
     fn segment_num_parents(&self, local_pos: u32) -> u32 {
         self.graph_entry(local_pos).num_parents()
     }
@@ -1709,6 +2011,15 @@ impl IndexSegment for ReadonlyIndexImpl {
         parent_entries
     }
 
+    fn segment_changed_path_filter(&self, local_pos: u32) -> Option<ChangedPathFilter> {
+        let start = match local_pos.checked_sub(1) {
+            Some(prev_pos) => self.changed_path_filter_end(prev_pos),
+            None => 0,
+        };
+        let end = self.changed_path_filter_end(local_pos);
+        (start < end).then(|| ChangedPathFilter::from_bytes(&self.changed_path_filters[start..end]))
+    }
+
     fn segment_entry_by_pos(&self, pos: IndexPosition, local_pos: u32) -> IndexEntry {
         IndexEntry {
             source: self,
@@ -1783,6 +2094,10 @@ impl IndexSegment for MutableIndexImpl {
         self.graph[local_pos as usize].change_id.clone()
     }
 
+    fn segment_committer_timestamp(&self, local_pos: u32) -> MillisSinceEpoch {
+        self.graph[local_pos as usize].committer_timestamp.clone()
+    }
+
     fn segment_num_parents(&self, local_pos: u32) -> u32 {
         self.graph[local_pos as usize].parent_positions.len() as u32
     }
@@ -1791,6 +2106,10 @@ impl IndexSegment for MutableIndexImpl {
         self.graph[local_pos as usize].parent_positions.clone()
     }
 
+    fn segment_changed_path_filter(&self, local_pos: u32) -> Option<ChangedPathFilter> {
+        self.graph[local_pos as usize].changed_path_filter.clone()
+    }
+
     fn segment_entry_by_pos(&self, pos: IndexPosition, local_pos: u32) -> IndexEntry {
         IndexEntry {
             source: self,
@@ -1849,6 +2168,12 @@ impl<'a> IndexEntry<'a> {
         self.source.segment_change_id(self.local_pos)
     }
 
+    /// Committer timestamp of the commit, which can be used without loading
+    /// the commit from the store.
+    pub fn committer_timestamp(&self) -> MillisSinceEpoch {
+        self.source.segment_committer_timestamp(self.local_pos)
+    }
+
     pub fn num_parents(&self) -> u32 {
         self.source.segment_num_parents(self.local_pos)
     }
@@ -1857,6 +2182,11 @@ impl<'a> IndexEntry<'a> {
         self.source.segment_parent_positions(self.local_pos)
     }
 
+    /// Filter of paths changed by the commit, if available.
+    pub fn changed_path_filter(&self) -> Option<ChangedPathFilter> {
+        self.source.segment_changed_path_filter(self.local_pos)
+    }
+
     pub fn parents(&self) -> Vec<IndexEntry<'a>> {
         let composite = CompositeIndex(self.source);
         self.parent_positions()
@@ -1874,6 +2204,13 @@ impl ReadonlyIndexImpl {
         commit_id_length: usize,
         change_id_length: usize,
     ) -> Result<Arc<ReadonlyIndexImpl>, IndexLoadError> {
+        // Segments written before the version was recorded start with the
+        // parent file name length instead, which is either 0 or the length of
+        // a hash, so they never match.
+        let version = file.read_u32::<LittleEndian>()?;
+        if version != INDEX_SEGMENT_FILE_FORMAT_VERSION {
+            return Err(IndexLoadError::IndexCorrupt(name));
+        }
         let parent_filename_len = file.read_u32::<LittleEndian>()?;
         let num_parent_commits;
         let maybe_parent_file;
@@ -1898,6 +2235,7 @@ impl ReadonlyIndexImpl {
         };
         let num_commits = file.read_u32::<LittleEndian>()?;
         let num_parent_overflow_entries = file.read_u32::<LittleEndian>()?;
+        let num_changed_path_filter_bytes = file.read_u32::<LittleEndian>()?;
         let mut data = vec![];
         file.read_to_end(&mut data)?;
         let commit_graph_entry_size = CommitGraphEntry::size(commit_id_length, change_id_length);
@@ -1905,10 +2243,19 @@ impl ReadonlyIndexImpl {
         let commit_lookup_entry_size = CommitLookupEntry::size(commit_id_length);
         let lookup_size = (num_commits as usize) * commit_lookup_entry_size;
         let parent_overflow_size = (num_parent_overflow_entries as usize) * 4;
-        let expected_size = graph_size + lookup_size + parent_overflow_size;
+        let changed_path_filter_ends_size = (num_commits as usize) * 4;
+        let changed_path_filters_size = num_changed_path_filter_bytes as usize;
+        let expected_size = graph_size
+            + lookup_size
+            + parent_overflow_size
+            + changed_path_filter_ends_size
+            + changed_path_filters_size;
         if data.len() != expected_size {
             return Err(IndexLoadError::IndexCorrupt(name));
         }
+        let changed_path_filters = data.split_off(expected_size - changed_path_filters_size);
+        let changed_path_filter_ends =
+            data.split_off(graph_size + lookup_size + parent_overflow_size);
         let overflow_parent = data.split_off(graph_size + lookup_size);
         let lookup = data.split_off(graph_size);
         let graph = data;
@@ -1924,6 +2271,8 @@ impl ReadonlyIndexImpl {
             graph,
             lookup,
             overflow_parent,
+            changed_path_filter_ends,
+            changed_path_filters,
         }))
     }
 
@@ -1961,6 +2310,13 @@ impl ReadonlyIndexImpl {
         )
     }
 
+    fn changed_path_filter_end(&self, local_pos: u32) -> usize {
+        let offset = (local_pos as usize) * 4;
+        (&self.changed_path_filter_ends[offset..offset + 4])
+            .read_u32::<LittleEndian>()
+            .unwrap() as usize
+    }
+
     fn commit_id_byte_prefix_to_lookup_pos(&self, prefix: &CommitId) -> Option<u32> {
         if self.num_local_commits == 0 {
             // Avoid overflow when subtracting 1 below
@@ -2034,9 +2390,15 @@ impl Index for ReadonlyIndexImpl {
         &'index self,
         expression: &ResolvedExpression,
         store: &Arc<Store>,
+        options: &RevsetEvaluationOptions,
     ) -> Result<Box<dyn Revset<'index> + 'index>, RevsetEvaluationError> {
-        let revset_impl =
-            default_revset_engine::evaluate(expression, store, self, CompositeIndex(self))?;
+        let revset_impl = default_revset_engine::evaluate(
+            expression,
+            store,
+            self,
+            CompositeIndex(self),
+            options,
+        )?;
         Ok(Box::new(revset_impl))
     }
 }
@@ -2047,7 +2409,7 @@ mod tests {
     use test_case::test_case;
 
     use super::*;
-    use crate::backend::{ChangeId, CommitId, ObjectId};
+    use crate::backend::{ChangeId, CommitId, MillisSinceEpoch, ObjectId};
     use crate::index::Index;
 
     /// Generator of unique 16-byte ChangeId excluding root id
@@ -2158,7 +2520,12 @@ mod tests {
         // is fixed or file a new bug.
         let change_id2 = change_id1.clone();
         index.add_commit_data(id_0.clone(), change_id0, &[]);
-        index.add_commit_data(id_1.clone(), change_id1.clone(), &[id_0.clone()]);
+        index.add_commit_data_with_timestamp(
+            id_1.clone(),
+            change_id1.clone(),
+            MillisSinceEpoch(1_000),
+            &[id_0.clone()],
+        );
         index.add_commit_data(id_2.clone(), change_id2.clone(), &[id_0.clone()]);
 
         // If testing incremental indexing, write the first three commits to one file
@@ -2175,7 +2542,12 @@ mod tests {
         let id_5 = CommitId::from_hex("555555");
         let change_id5 = change_id3.clone();
         index.add_commit_data(id_3.clone(), change_id3.clone(), &[id_2.clone()]);
-        index.add_commit_data(id_4.clone(), change_id4, &[id_1.clone()]);
+        index.add_commit_data_with_timestamp(
+            id_4.clone(),
+            change_id4,
+            MillisSinceEpoch(-2_000),
+            &[id_1.clone()],
+        );
         index.add_commit_data(id_5.clone(), change_id5, &[id_4.clone(), id_2.clone()]);
         let index_segment: Box<dyn IndexSegment> = if on_disk {
             let saved_index = index.save_in(temp_dir.path().to_owned()).unwrap();
@@ -2214,7 +2586,9 @@ mod tests {
         );
         assert_eq!(entry_1.parents().len(), 1);
         assert_eq!(entry_1.parents()[0].pos, IndexPosition(0));
+        assert_eq!(entry_1.committer_timestamp(), MillisSinceEpoch(1_000));
         assert_eq!(entry_2.pos, IndexPosition(2));
+        assert_eq!(entry_2.committer_timestamp(), MillisSinceEpoch(0));
         assert_eq!(entry_2.commit_id(), id_2);
         assert_eq!(entry_2.change_id(), change_id2);
         assert_eq!(entry_2.generation_number(), 1);
@@ -2236,6 +2610,7 @@ mod tests {
             entry_4.parent_positions(),
             smallvec_inline![IndexPosition(1)]
         );
+        assert_eq!(entry_4.committer_timestamp(), MillisSinceEpoch(-2_000));
         assert_eq!(entry_5.generation_number(), 3);
         assert_eq!(entry_5.num_parents(), 2);
         assert_eq!(
@@ -2733,6 +3108,29 @@ mod tests {
         assert_eq!(common_ancestors, vec![id_2]);
     }
 
+    #[test]
+    fn test_index_position_set() {
+        let mut set = IndexPositionSet::with_range(IndexPosition(10), IndexPosition(80));
+        for pos in [10, 11, 73, 80] {
+            set.insert(IndexPosition(pos));
+        }
+        let contained = (0..200)
+            .filter(|&pos| set.contains(IndexPosition(pos)))
+            .collect_vec();
+        assert_eq!(contained, vec![10, 11, 73, 80]);
+        assert!(!set.contains(IndexPosition::MAX));
+
+        let mut set = IndexPositionSet::with_range(IndexPosition(5), IndexPosition(5));
+        assert!(!set.contains(IndexPosition(5)));
+        set.insert(IndexPosition(5));
+        assert!(set.contains(IndexPosition(5)));
+        assert!(!set.contains(IndexPosition(4)));
+
+        let set = IndexPositionSet::empty();
+        assert!(!set.contains(IndexPosition(0)));
+        assert!(!set.contains(IndexPosition::MAX));
+    }
+
     #[test]
     fn test_walk_revs() {
         let mut new_change_id = change_id_generator();
@@ -2849,7 +3247,7 @@ mod tests {
         let walk_commit_ids = |wanted: &[CommitId], unwanted: &[CommitId], range: Range<u32>| {
             index
                 .walk_revs(wanted, unwanted)
-                .filter_by_generation(range)
+                .filter_by_generation(range, GenerationMode::Any)
                 .map(|entry| entry.commit_id())
                 .collect_vec()
         };
@@ -2900,6 +3298,81 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_walk_revs_filter_by_generation_mode() {
+        let mut new_change_id = change_id_generator();
+        let mut index = MutableIndexImpl::full(3, 16);
+        //   5
+        //  / \
+        // 4   |
+        // |   3
+        // 2   |
+        //  \ /
+        //   1
+        //   |
+        //   0
+        let id_0 = CommitId::from_hex("000000");
+        let id_1 = CommitId::from_hex("111111");
+        let id_2 = CommitId::from_hex("222222");
+        let id_3 = CommitId::from_hex("333333");
+        let id_4 = CommitId::from_hex("444444");
+        let id_5 = CommitId::from_hex("555555");
+        index.add_commit_data(id_0.clone(), new_change_id(), &[]);
+        index.add_commit_data(id_1.clone(), new_change_id(), &[id_0.clone()]);
+        index.add_commit_data(id_2.clone(), new_change_id(), &[id_1.clone()]);
+        index.add_commit_data(id_3.clone(), new_change_id(), &[id_1.clone()]);
+        index.add_commit_data(id_4.clone(), new_change_id(), &[id_2.clone()]);
+        index.add_commit_data(id_5.clone(), new_change_id(), &[id_4.clone(), id_3.clone()]);
+
+        let walk_commit_ids = |unwanted: &[CommitId], range: Range<u32>, mode| {
+            index
+                .walk_revs(&[&id_5].map(Clone::clone), unwanted)
+                .filter_by_generation(range, mode)
+                .map(|entry| entry.commit_id())
+                .collect_vec()
+        };
+
+        // 1 is reachable from 5 through paths of length 2 and 3
+        assert_eq!(
+            walk_commit_ids(&[], 2..3, GenerationMode::Any),
+            [&id_2, &id_1].map(Clone::clone)
+        );
+        assert_eq!(
+            walk_commit_ids(&[], 2..3, GenerationMode::Min),
+            [&id_2, &id_1].map(Clone::clone)
+        );
+        assert_eq!(
+            walk_commit_ids(&[], 2..3, GenerationMode::Max),
+            [&id_2].map(Clone::clone)
+        );
+        assert_eq!(
+            walk_commit_ids(&[], 3..4, GenerationMode::Any),
+            [&id_1, &id_0].map(Clone::clone)
+        );
+        assert_eq!(
+            walk_commit_ids(&[], 3..4, GenerationMode::Min),
+            [&id_0].map(Clone::clone)
+        );
+        assert_eq!(
+            walk_commit_ids(&[], 3..4, GenerationMode::Max),
+            [&id_1].map(Clone::clone)
+        );
+
+        // Empty generation bounds
+        assert_eq!(walk_commit_ids(&[], 0..0, GenerationMode::Min), []);
+        assert_eq!(walk_commit_ids(&[], 0..0, GenerationMode::Max), []);
+
+        // Ancestors of unwanted commits are not walked
+        assert_eq!(
+            walk_commit_ids(&[&id_1].map(Clone::clone), 1..5, GenerationMode::Min),
+            [&id_4, &id_3, &id_2].map(Clone::clone)
+        );
+        assert_eq!(
+            walk_commit_ids(&[&id_1].map(Clone::clone), 1..5, GenerationMode::Max),
+            [&id_4, &id_3, &id_2].map(Clone::clone)
+        );
+    }
+
     #[test]
     #[allow(clippy::redundant_clone)] // allow id_n.clone()
     fn test_walk_revs_filter_by_generation_range_merging() {
@@ -2925,7 +3398,7 @@ mod tests {
         let walk_commit_ids = |wanted: &[CommitId], range: Range<u32>| {
             index
                 .walk_revs(wanted, &[])
-                .filter_by_generation(range)
+                .filter_by_generation(range, GenerationMode::Any)
                 .map(|entry| entry.commit_id())
                 .collect_vec()
         };
@@ -3120,4 +3593,49 @@ mod tests {
             vec![id_3, id_5]
         );
     }
+
+    #[test]
+    fn test_heads_pos_descending() {
+        let mut new_change_id = change_id_generator();
+        let mut index = MutableIndexImpl::full(3, 16);
+        // 5   7
+        // |\  |
+        // 3 4 6
+        // | |
+        // 1 2
+        // |/
+        // 0
+        let ids = (0..8)
+            .map(|i| CommitId::from_hex(&format!("{i}{i}{i}{i}{i}{i}")))
+            .collect_vec();
+        let parents: [&[usize]; 8] = [&[], &[0], &[0], &[1], &[2], &[3, 4], &[], &[6]];
+        for (id, parent_indices) in ids.iter().zip(parents) {
+            let parent_ids = parent_indices.iter().map(|&i| ids[i].clone()).collect_vec();
+            index.add_commit_data(id.clone(), new_change_id(), &parent_ids);
+        }
+        let index = index.as_composite();
+
+        // Every subset of the commits, including disconnected ones
+        for mask in 0..(1u32 << ids.len()) {
+            let candidate_positions = (0..ids.len() as u32)
+                .filter(|i| mask & (1 << i) != 0)
+                .map(IndexPosition)
+                .collect_vec();
+            let expected = index
+                .heads_pos(candidate_positions.iter().copied().collect())
+                .into_iter()
+                .rev()
+                .collect_vec();
+            assert_eq!(
+                index.heads_pos_descending(candidate_positions.into_iter().rev()),
+                expected,
+                "mask: {mask:08b}"
+            );
+        }
+        // Commit 2 is reachable from 5 through 4
+        assert_eq!(
+            index.heads_pos_descending([5, 2].map(IndexPosition)),
+            [IndexPosition(5)]
+        );
+    }
 }
@@ -12,55 +12,154 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cell::RefCell;
 use std::cmp::{Ordering, Reverse};
-use std::collections::{BinaryHeap, HashSet};
-use std::fmt;
-use std::iter::Peekable;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::io::Read;
+use std::iter::{self, Peekable};
 use std::ops::Range;
-use std::sync::Arc;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::{fmt, mem};
 
 use itertools::Itertools;
+use once_cell::unsync::OnceCell;
+use rayon::prelude::*;
+use regex::{Regex, RegexBuilder};
 
-use crate::backend::{ChangeId, CommitId, MillisSinceEpoch, ObjectId};
+use crate::backend::{
+    BackendError, ChangeId, CommitId, FileId, MillisSinceEpoch, ObjectId, TreeValue,
+};
+use crate::commit::Commit;
 use crate::default_index_store::{
-    CompositeIndex, IndexEntry, IndexEntryByPosition, IndexPosition, RevWalk,
+    CompositeIndex, IndexEntry, IndexEntryByPosition, IndexPosition, IndexPositionSet, RevWalk,
 };
 use crate::default_revset_graph_iterator::RevsetGraphIterator;
+use crate::diff::{Diff, DiffHunk};
 use crate::index::{HexPrefix, Index, PrefixResolution};
-use crate::matchers::{EverythingMatcher, Matcher, PrefixMatcher, Visit};
+use crate::matchers::{
+    DifferenceMatcher, EverythingMatcher, ExtensionMatcher, GlobMatcher, Matcher, NothingMatcher,
+    PrefixMatcher, UnionMatcher, Visit,
+};
 use crate::repo_path::RepoPath;
 use crate::revset::{
-    ChangeIdIndex, ResolvedExpression, ResolvedPredicateExpression, Revset, RevsetEvaluationError,
-    RevsetFilterPredicate, RevsetGraphEdge, GENERATION_RANGE_FULL,
+    CaseSensitivity, ChangeIdIndex, DescriptionScope, FilePattern, GenerationMode, LatestGroupKey,
+    ResolvedExpression, ResolvedPredicateExpression, Revset, RevsetEntry, RevsetEvaluationError,
+    RevsetEvaluationOptions, RevsetFilterPredicate, RevsetGraphEntryItem, RevsetGraphItem,
+    SignatureCheck, StringPattern, GENERATION_RANGE_FULL,
 };
+use crate::settings::UserSettings;
 use crate::store::Store;
-use crate::{backend, rewrite};
+use crate::tree::Tree;
+use crate::{backend, diff, rewrite, trailer, tree};
+
+/// Predicate function that doesn't depend on the evaluation order, and can be
+/// shared across threads.
+type SyncPredicateFn<'a> =
+    dyn Fn(&IndexEntry<'_>) -> Result<bool, RevsetEvaluationError> + Sync + 'a;
+
+/// Predicate function which fails if the commit to be tested can't be loaded.
+type BoxedPredicateFn<'a> =
+    Box<dyn FnMut(&IndexEntry<'_>) -> Result<bool, RevsetEvaluationError> + 'a>;
+
+/// Iterator over the entries of `InternalRevset`. An error is yielded in place
+/// of the entry which failed to evaluate.
+type BoxedRevsetIter<'a, 'index> = Box<dyn RevsetIterator<'index> + 'a>;
+
+trait RevsetIterator<'index>:
+    Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>
+{
+    /// Skips the entries above the `position`, and returns the next entry at
+    /// or below it. An error is returned as soon as reached.
+    ///
+    /// The default implementation visits the skipped entries one by one.
+    fn seek(&mut self, position: IndexPosition) -> Option<Self::Item> {
+        loop {
+            match self.next()? {
+                Ok(entry) if entry.position() > position => {}
+                item => return Some(item),
+            }
+        }
+    }
+}
+
+impl<'index, I: RevsetIterator<'index> + ?Sized> RevsetIterator<'index> for Box<I> {
+    fn seek(&mut self, position: IndexPosition) -> Option<Self::Item> {
+        (**self).seek(position)
+    }
+}
+
+/// Adapts iterator which can only skip entries by visiting them.
+struct SequentialRevsetIterator<I>(I);
+
+impl<'index, I> Iterator for SequentialRevsetIterator<I>
+where
+    I: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<'index, I> RevsetIterator<'index> for SequentialRevsetIterator<I> where
+    I: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>
+{
+}
 
 trait ToPredicateFn: fmt::Debug {
     /// Creates function that tests if the given entry is included in the set.
     ///
     /// The predicate function is evaluated in order of `RevsetIterator`.
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_>;
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_>;
+
+    /// Returns function that can test entries in parallel, if the predicate
+    /// opts in to parallel evaluation.
+    fn as_sync_predicate_fn(&self) -> Option<&SyncPredicateFn<'_>> {
+        None
+    }
 }
 
 impl<T: ToPredicateFn + ?Sized> ToPredicateFn for Box<T> {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
         <T as ToPredicateFn>::to_predicate_fn(self)
     }
+
+    fn as_sync_predicate_fn(&self) -> Option<&SyncPredicateFn<'_>> {
+        <T as ToPredicateFn>::as_sync_predicate_fn(self)
+    }
 }
 
 trait InternalRevset<'index>: fmt::Debug + ToPredicateFn {
     // All revsets currently iterate in order of descending index position
-    fn iter(&self) -> Box<dyn Iterator<Item = IndexEntry<'index>> + '_>;
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index>;
+
+    /// Returns true if the set has no entries. An entry which failed to
+    /// evaluate counts as non-empty.
+    fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    fn count(&self) -> Result<usize, RevsetEvaluationError> {
+        itertools::process_results(self.iter(), |entries| entries.count())
+    }
 
     fn into_predicate<'a>(self: Box<Self>) -> Box<dyn ToPredicateFn + 'a>
     where
         Self: 'a;
+
+    /// Moves out the materialized entries if the set was evaluated eagerly.
+    fn take_eager(&mut self) -> Option<EagerRevset<'index>> {
+        None
+    }
 }
 
 pub struct RevsetImpl<'index> {
     inner: Box<dyn InternalRevset<'index> + 'index>,
     index: CompositeIndex<'index>,
+    /// Lookup from change id to index positions, built on first use.
+    pos_by_change: OnceCell<Arc<IdIndex<ChangeId, IndexPosition>>>,
 }
 
 impl<'index> RevsetImpl<'index> {
@@ -71,11 +170,42 @@ impl<'index> RevsetImpl<'index> {
         Self {
             inner: revset,
             index,
+            pos_by_change: OnceCell::new(),
         }
     }
 
+    fn pos_by_change(&self) -> &Arc<IdIndex<ChangeId, IndexPosition>> {
+        self.pos_by_change.get_or_init(|| {
+            // Entries which failed to evaluate are left out. The error will be
+            // reported when the revset itself is iterated.
+            let pos_by_change = self
+                .inner
+                .iter()
+                .filter_map(Result::ok)
+                .map(|entry| (entry.change_id(), entry.position()))
+                .collect();
+            Arc::new(IdIndex::from_vec(pos_by_change))
+        })
+    }
+
     pub fn iter_graph_impl(&self) -> RevsetGraphIterator<'_, 'index> {
-        RevsetGraphIterator::new(self.inner.iter())
+        RevsetGraphIterator::new(Box::new(self.inner.iter()))
+    }
+
+    /// Restricts this revset to the given `commit_ids`. Ids not in the index
+    /// are ignored.
+    pub fn intersect_commit_ids(self, commit_ids: &[CommitId]) -> RevsetImpl<'index> {
+        let index_entries = commit_ids
+            .iter()
+            .filter_map(|id| self.index.entry_by_id(id))
+            .collect();
+        let revset = IntersectionRevset {
+            sets: vec![
+                self.inner,
+                Box::new(EagerRevset::from_unsorted_entries(index_entries)),
+            ],
+        };
+        RevsetImpl::new(Box::new(revset), self.index)
     }
 }
 
@@ -88,41 +218,111 @@ impl fmt::Debug for RevsetImpl<'_> {
 }
 
 impl<'index> Revset<'index> for RevsetImpl<'index> {
-    fn iter(&self) -> Box<dyn Iterator<Item = CommitId> + '_> {
-        Box::new(self.inner.iter().map(|index_entry| index_entry.commit_id()))
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<CommitId, RevsetEvaluationError>> + '_> {
+        Box::new(
+            self.inner
+                .iter()
+                .map_ok(|index_entry| index_entry.commit_id()),
+        )
+    }
+
+    fn iter_reverse(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<CommitId, RevsetEvaluationError>> + '_> {
+        match ReverseRevset::new(self.inner.as_ref()) {
+            Ok(revset) => Box::new(
+                revset
+                    .into_iter()
+                    .map(|index_entry| Ok(index_entry.commit_id())),
+            ),
+            Err(err) => Box::new(iter::once(Err(err))),
+        }
+    }
+
+    fn iter_entries(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<RevsetEntry, RevsetEvaluationError>> + '_> {
+        Box::new(self.inner.iter().map_ok(|index_entry| RevsetEntry {
+            commit_id: index_entry.commit_id(),
+            change_id: index_entry.change_id(),
+            generation_number: index_entry.generation_number(),
+        }))
     }
 
-    fn iter_graph(&self) -> Box<dyn Iterator<Item = (CommitId, Vec<RevsetGraphEdge>)> + '_> {
-        Box::new(RevsetGraphIterator::new(self.inner.iter()))
+    fn iter_graph(&self) -> Box<dyn Iterator<Item = RevsetGraphItem> + '_> {
+        Box::new(RevsetGraphIterator::new(Box::new(self.inner.iter())))
+    }
+
+    fn iter_graph_entries(&self) -> Box<dyn Iterator<Item = RevsetGraphEntryItem> + '_> {
+        let mut graph_iter = RevsetGraphIterator::new(Box::new(self.inner.iter()));
+        Box::new(
+            iter::from_fn(move || graph_iter.next_with_index_entry()).map_ok(
+                |(index_entry, edges)| {
+                    let entry = RevsetEntry {
+                        commit_id: index_entry.commit_id(),
+                        change_id: index_entry.change_id(),
+                        generation_number: index_entry.generation_number(),
+                    };
+                    (entry, edges)
+                },
+            ),
+        )
     }
 
     fn change_id_index(&self) -> Box<dyn ChangeIdIndex + 'index> {
-        // TODO: Create a persistent lookup from change id to commit ids.
-        let mut pos_by_change = vec![];
-        for entry in self.inner.iter() {
-            pos_by_change.push((entry.change_id(), entry.position()));
-        }
-        let pos_by_change = IdIndex::from_vec(pos_by_change);
+        // The lookup is built on first use and shared by all indexes created
+        // from this revset.
         Box::new(ChangeIdIndexImpl {
             index: self.index.clone(),
-            pos_by_change,
+            pos_by_change: self.pos_by_change().clone(),
         })
     }
 
     fn is_empty(&self) -> bool {
-        self.iter().next().is_none()
+        self.inner.is_empty()
+    }
+
+    fn count(&self) -> Result<usize, RevsetEvaluationError> {
+        self.inner.count()
+    }
+
+    fn contains(&self, id: &CommitId) -> Result<bool, RevsetEvaluationError> {
+        match self.index.entry_by_id(id) {
+            Some(entry) => self.inner.to_predicate_fn()(&entry),
+            None => Ok(false),
+        }
+    }
+
+    fn take(self: Box<Self>, n: usize) -> Box<dyn Revset<'index> + 'index> {
+        let revset = TakeRevset {
+            inner: self.inner,
+            n,
+        };
+        Box::new(RevsetImpl::new(Box::new(revset), self.index))
     }
 }
 
 struct ChangeIdIndexImpl<'index> {
     index: CompositeIndex<'index>,
-    pos_by_change: IdIndex<ChangeId, IndexPosition>,
+    pos_by_change: Arc<IdIndex<ChangeId, IndexPosition>>,
 }
 
 impl ChangeIdIndex for ChangeIdIndexImpl<'_> {
     fn resolve_prefix(&self, prefix: &HexPrefix) -> PrefixResolution<Vec<CommitId>> {
-        self.pos_by_change
-            .resolve_prefix_with(prefix, |pos| self.index.entry_by_pos(*pos).commit_id())
+        match self.pos_by_change.resolve_prefix_with(prefix, |pos| *pos) {
+            PrefixResolution::NoMatch => PrefixResolution::NoMatch,
+            PrefixResolution::SingleMatch(mut positions) => {
+                // IdIndex doesn't preserve the order of values. Sort them so the
+                // newest commit of divergent change comes first.
+                positions.sort_unstable_by(|a, b| b.cmp(a));
+                let commit_ids = positions
+                    .iter()
+                    .map(|pos| self.index.entry_by_pos(*pos).commit_id())
+                    .collect();
+                PrefixResolution::SingleMatch(commit_ids)
+            }
+            PrefixResolution::AmbiguousMatch => PrefixResolution::AmbiguousMatch,
+        }
     }
 
     fn shortest_unique_prefix_len(&self, change_id: &ChangeId) -> usize {
@@ -194,9 +394,12 @@ where
     ///   additional fact that it's the entire key). This case is extremely
     ///   unlikely for hashes with 12+ hexadecimal characters.
     pub fn shortest_unique_prefix_len(&self, key: &K) -> usize {
-        let pos = self.0.partition_point(|(k, _)| k < key);
-        let left = pos.checked_sub(1).map(|p| &self.0[p]);
-        let right = self.0[pos..].iter().find(|(k, _)| k != key);
+        // Duplicated keys (e.g. divergent changes) don't need to be
+        // disambiguated from each other, so look up the nearest different keys.
+        let left_pos = self.0.partition_point(|(k, _)| k < key);
+        let right_pos = self.0.partition_point(|(k, _)| k <= key);
+        let left = left_pos.checked_sub(1).map(|p| &self.0[p]);
+        let right = self.0.get(right_pos);
         itertools::chain(left, right)
             .map(|(neighbor, _value)| {
                 backend::common_hex_len(key.as_bytes(), neighbor.as_bytes()) + 1
@@ -219,9 +422,29 @@ impl EagerRevset<'static> {
     }
 }
 
+impl<'index> EagerRevset<'index> {
+    /// Sorts the entries in descending index position order and removes
+    /// duplicates.
+    fn from_unsorted_entries(mut index_entries: Vec<IndexEntry<'index>>) -> Self {
+        index_entries.sort_unstable_by_key(|b| Reverse(b.position()));
+        index_entries.dedup();
+        EagerRevset { index_entries }
+    }
+}
+
 impl<'index> InternalRevset<'index> for EagerRevset<'index> {
-    fn iter(&self) -> Box<dyn Iterator<Item = IndexEntry<'index>> + '_> {
-        Box::new(self.index_entries.iter().cloned())
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index> {
+        Box::new(EagerRevsetIterator {
+            index_entries: &self.index_entries,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.index_entries.is_empty()
+    }
+
+    fn count(&self) -> Result<usize, RevsetEvaluationError> {
+        Ok(self.index_entries.len())
     }
 
     fn into_predicate<'a>(self: Box<Self>) -> Box<dyn ToPredicateFn + 'a>
@@ -230,15 +453,89 @@ impl<'index> InternalRevset<'index> for EagerRevset<'index> {
     {
         self
     }
+
+    fn take_eager(&mut self) -> Option<EagerRevset<'index>> {
+        let index_entries = mem::take(&mut self.index_entries);
+        Some(EagerRevset { index_entries })
+    }
 }
 
 impl ToPredicateFn for EagerRevset<'_> {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
         predicate_fn_from_iter(self.iter())
     }
 }
 
+/// `EagerRevset` shared by the occurrences of a repeated subexpression.
+#[derive(Debug)]
+struct SharedEagerRevset<'index>(Rc<EagerRevset<'index>>);
+
+impl<'index> InternalRevset<'index> for SharedEagerRevset<'index> {
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index> {
+        self.0.iter()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn count(&self) -> Result<usize, RevsetEvaluationError> {
+        self.0.count()
+    }
+
+    fn into_predicate<'a>(self: Box<Self>) -> Box<dyn ToPredicateFn + 'a>
+    where
+        Self: 'a,
+    {
+        self
+    }
+}
+
+impl ToPredicateFn for SharedEagerRevset<'_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
+        self.0.to_predicate_fn()
+    }
+}
+
+/// Iterator over the remaining entries of `EagerRevset`.
+struct EagerRevsetIterator<'a, 'index> {
+    index_entries: &'a [IndexEntry<'index>],
+}
+
+impl<'index> Iterator for EagerRevsetIterator<'_, 'index> {
+    type Item = Result<IndexEntry<'index>, RevsetEvaluationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (entry, rest) = self.index_entries.split_first()?;
+        self.index_entries = rest;
+        Some(Ok(entry.clone()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.index_entries.len(), Some(self.index_entries.len()))
+    }
+}
+
+impl<'index> RevsetIterator<'index> for EagerRevsetIterator<'_, 'index> {
+    fn seek(&mut self, position: IndexPosition) -> Option<Self::Item> {
+        // Gallop over the entries above the position so a short skip is about
+        // as cheap as calling next(), then binary search the last stride.
+        let entries = self.index_entries;
+        let mut bound = 1;
+        while bound <= entries.len() && entries[bound - 1].position() > position {
+            bound *= 2;
+        }
+        let start = bound / 2;
+        let end = bound.min(entries.len());
+        let skip = start + entries[start..end].partition_point(|e| e.position() > position);
+        self.index_entries = &entries[skip..];
+        self.next()
+    }
+}
+
 struct RevWalkRevset<T> {
+    /// Walk over the index, which never fails. The entries are wrapped in `Ok`
+    /// so they can be passed through as `InternalRevset` entries.
     walk: T,
 }
 
@@ -250,10 +547,10 @@ impl<T> fmt::Debug for RevWalkRevset<T> {
 
 impl<'index, T> InternalRevset<'index> for RevWalkRevset<T>
 where
-    T: Iterator<Item = IndexEntry<'index>> + Clone,
+    T: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>> + Clone,
 {
-    fn iter(&self) -> Box<dyn Iterator<Item = IndexEntry<'index>> + '_> {
-        Box::new(self.walk.clone())
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index> {
+        Box::new(SequentialRevsetIterator(self.walk.clone()))
     }
 
     fn into_predicate<'a>(self: Box<Self>) -> Box<dyn ToPredicateFn + 'a>
@@ -266,35 +563,125 @@ where
 
 impl<'index, T> ToPredicateFn for RevWalkRevset<T>
 where
-    T: Iterator<Item = IndexEntry<'index>> + Clone,
+    T: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>> + Clone,
 {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
-        predicate_fn_from_iter(self.walk.clone())
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
+        predicate_fn_from_iter(SequentialRevsetIterator(self.walk.clone()))
     }
 }
 
 fn predicate_fn_from_iter<'index, 'iter>(
-    iter: impl Iterator<Item = IndexEntry<'index>> + 'iter,
-) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + 'iter> {
-    let mut iter = iter.fuse().peekable();
+    mut iter: impl RevsetIterator<'index> + 'iter,
+) -> BoxedPredicateFn<'iter> {
+    // Position of the last entry taken from the iterator, or `None` if the
+    // iterator is exhausted. No entry is positioned at `MAX`.
+    let mut head = Some(IndexPosition::MAX);
     Box::new(move |entry| {
-        while iter.next_if(|e| e.position() > entry.position()).is_some() {
-            continue;
+        if matches!(head, Some(position) if position > entry.position()) {
+            // Errors are positioned nowhere, so they are returned as soon as
+            // reached.
+            head = iter
+                .seek(entry.position())
+                .transpose()?
+                .map(|e| e.position());
         }
-        iter.next_if(|e| e.position() == entry.position()).is_some()
+        Ok(head == Some(entry.position()))
     })
 }
 
+/// First `n` entries of the `inner` revset.
+#[derive(Debug)]
+struct TakeRevset<'index> {
+    inner: Box<dyn InternalRevset<'index> + 'index>,
+    n: usize,
+}
+
+impl<'index> InternalRevset<'index> for TakeRevset<'index> {
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index> {
+        Box::new(SequentialRevsetIterator(self.inner.iter().take(self.n)))
+    }
+
+    fn into_predicate<'a>(self: Box<Self>) -> Box<dyn ToPredicateFn + 'a>
+    where
+        Self: 'a,
+    {
+        self
+    }
+}
+
+impl ToPredicateFn for TakeRevset<'_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
+        // Whether an entry is within the first n can't be told from the entry
+        // itself, so count them by walking the inner set in step.
+        predicate_fn_from_iter(self.iter())
+    }
+}
+
+/// Tests the `entry` by the predicate `p`. Returns `None` if the entry is
+/// filtered out.
+fn filter_entry<'index>(
+    entry: Result<IndexEntry<'index>, RevsetEvaluationError>,
+    mut p: impl FnMut(&IndexEntry<'_>) -> Result<bool, RevsetEvaluationError>,
+) -> Option<Result<IndexEntry<'index>, RevsetEvaluationError>> {
+    match entry {
+        Ok(entry) => p(&entry)
+            .map(|matched| matched.then_some(entry))
+            .transpose(),
+        Err(err) => Some(Err(err)),
+    }
+}
+
 #[derive(Debug)]
 struct FilterRevset<'index, P> {
     candidates: Box<dyn InternalRevset<'index> + 'index>,
     predicate: P,
 }
 
+/// Maximum number of candidates to be tested at once by parallel predicate.
+const PARALLEL_FILTER_MAX_CHUNK_SIZE: usize = 256;
+
+/// Size of the first chunk, which doubles up to the maximum. Chunks smaller
+/// than this are tested on the current thread since distributing a few
+/// candidates to the thread pool isn't worth it.
+const PARALLEL_FILTER_MIN_CHUNK_SIZE: usize = 16;
+
 impl<'index, P: ToPredicateFn> InternalRevset<'index> for FilterRevset<'index, P> {
-    fn iter(&self) -> Box<dyn Iterator<Item = IndexEntry<'index>> + '_> {
-        let p = self.predicate.to_predicate_fn();
-        Box::new(self.candidates.iter().filter(p))
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index> {
+        if let Some(p) = self.predicate.as_sync_predicate_fn() {
+            // Test chunk of candidates in parallel so the iterator stays lazy.
+            // The filtered entries are collected in the original order. The
+            // chunks start small so the first entries are yielded early.
+            let mut candidates = self.candidates.iter();
+            let mut chunk_size = PARALLEL_FILTER_MIN_CHUNK_SIZE;
+            let chunks = iter::from_fn(move || {
+                let chunk = candidates.by_ref().take(chunk_size).collect_vec();
+                chunk_size = (chunk_size * 2).min(PARALLEL_FILTER_MAX_CHUNK_SIZE);
+                if chunk.is_empty() {
+                    None
+                } else if chunk.len() < PARALLEL_FILTER_MIN_CHUNK_SIZE {
+                    Some(
+                        chunk
+                            .into_iter()
+                            .filter_map(|entry| filter_entry(entry, p))
+                            .collect_vec(),
+                    )
+                } else {
+                    Some(
+                        chunk
+                            .into_par_iter()
+                            .filter_map(|entry| filter_entry(entry, p))
+                            .collect(),
+                    )
+                }
+            });
+            return Box::new(SequentialRevsetIterator(chunks.flatten()));
+        }
+        let mut p = self.predicate.to_predicate_fn();
+        Box::new(SequentialRevsetIterator(
+            self.candidates
+                .iter()
+                .filter_map(move |entry| filter_entry(entry, &mut p)),
+        ))
     }
 
     fn into_predicate<'a>(self: Box<Self>) -> Box<dyn ToPredicateFn + 'a>
@@ -306,10 +693,10 @@ impl<'index, P: ToPredicateFn> InternalRevset<'index> for FilterRevset<'index, P
 }
 
 impl<P: ToPredicateFn> ToPredicateFn for FilterRevset<'_, P> {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
         let mut p1 = self.candidates.to_predicate_fn();
         let mut p2 = self.predicate.to_predicate_fn();
-        Box::new(move |entry| p1(entry) && p2(entry))
+        Box::new(move |entry| Ok(p1(entry)? && p2(entry)?))
     }
 }
 
@@ -317,24 +704,27 @@ impl<P: ToPredicateFn> ToPredicateFn for FilterRevset<'_, P> {
 struct NotInPredicate<S>(S);
 
 impl<S: ToPredicateFn> ToPredicateFn for NotInPredicate<S> {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
         let mut p = self.0.to_predicate_fn();
-        Box::new(move |entry| !p(entry))
+        Box::new(move |entry| Ok(!p(entry)?))
     }
 }
 
+/// Union of any number of sets, merged in a single pass.
 #[derive(Debug)]
 struct UnionRevset<'index> {
-    set1: Box<dyn InternalRevset<'index> + 'index>,
-    set2: Box<dyn InternalRevset<'index> + 'index>,
+    sets: Vec<Box<dyn InternalRevset<'index> + 'index>>,
 }
 
 impl<'index> InternalRevset<'index> for UnionRevset<'index> {
-    fn iter(&self) -> Box<dyn Iterator<Item = IndexEntry<'index>> + '_> {
-        Box::new(UnionRevsetIterator {
-            iter1: self.set1.iter().peekable(),
-            iter2: self.set2.iter().peekable(),
-        })
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index> {
+        Box::new(UnionRevsetIterator::new(
+            self.sets.iter().map(|set| set.iter()).collect(),
+        ))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.sets.iter().all(|set| set.is_empty())
     }
 
     fn into_predicate<'a>(self: Box<Self>) -> Box<dyn ToPredicateFn + 'a>
@@ -346,10 +736,20 @@ impl<'index> InternalRevset<'index> for UnionRevset<'index> {
 }
 
 impl ToPredicateFn for UnionRevset<'_> {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
-        let mut p1 = self.set1.to_predicate_fn();
-        let mut p2 = self.set2.to_predicate_fn();
-        Box::new(move |entry| p1(entry) || p2(entry))
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
+        let mut predicates = self
+            .sets
+            .iter()
+            .map(|set| set.to_predicate_fn())
+            .collect_vec();
+        Box::new(move |entry| {
+            for p in &mut predicates {
+                if p(entry)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        })
     }
 }
 
@@ -364,52 +764,110 @@ where
     S1: ToPredicateFn,
     S2: ToPredicateFn,
 {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
         let mut p1 = self.set1.to_predicate_fn();
         let mut p2 = self.set2.to_predicate_fn();
-        Box::new(move |entry| p1(entry) || p2(entry))
+        Box::new(move |entry| Ok(p1(entry)? || p2(entry)?))
     }
 }
 
-struct UnionRevsetIterator<
-    'index,
-    I1: Iterator<Item = IndexEntry<'index>>,
-    I2: Iterator<Item = IndexEntry<'index>>,
-> {
-    iter1: Peekable<I1>,
-    iter2: Peekable<I2>,
+#[derive(Debug)]
+struct IntersectionPredicate<S1, S2> {
+    set1: S1,
+    set2: S2,
 }
 
-impl<'index, I1: Iterator<Item = IndexEntry<'index>>, I2: Iterator<Item = IndexEntry<'index>>>
-    Iterator for UnionRevsetIterator<'index, I1, I2>
+impl<S1, S2> ToPredicateFn for IntersectionPredicate<S1, S2>
+where
+    S1: ToPredicateFn,
+    S2: ToPredicateFn,
 {
-    type Item = IndexEntry<'index>;
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
+        let mut p1 = self.set1.to_predicate_fn();
+        let mut p2 = self.set2.to_predicate_fn();
+        Box::new(move |entry| Ok(p1(entry)? && p2(entry)?))
+    }
+}
+
+/// Merges the iterators by picking the entry of the highest position among
+/// their heads. Entries yielded by more than one iterator are deduplicated.
+///
+/// Errors are yielded before the next entry. An iterator which yielded an
+/// error is no longer advanced.
+struct UnionRevsetIterator<'a, 'index> {
+    iters: Vec<BoxedRevsetIter<'a, 'index>>,
+    heads: BinaryHeap<(IndexEntryByPosition<'index>, usize)>,
+    errors: Vec<RevsetEvaluationError>,
+}
+
+impl<'a, 'index> UnionRevsetIterator<'a, 'index> {
+    fn new(iters: Vec<BoxedRevsetIter<'a, 'index>>) -> Self {
+        let mut iter = UnionRevsetIterator {
+            heads: BinaryHeap::with_capacity(iters.len()),
+            iters,
+            errors: vec![],
+        };
+        for i in 0..iter.iters.len() {
+            iter.advance(i);
+        }
+        iter
+    }
+
+    fn advance(&mut self, i: usize) {
+        match self.iters[i].next() {
+            Some(Ok(entry)) => self.heads.push((IndexEntryByPosition(entry), i)),
+            Some(Err(err)) => self.errors.push(err),
+            None => {}
+        }
+    }
+}
+
+impl<'index> Iterator for UnionRevsetIterator<'_, 'index> {
+    type Item = Result<IndexEntry<'index>, RevsetEvaluationError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match (self.iter1.peek(), self.iter2.peek()) {
-            (None, _) => self.iter2.next(),
-            (_, None) => self.iter1.next(),
-            (Some(entry1), Some(entry2)) => match entry1.position().cmp(&entry2.position()) {
-                Ordering::Less => self.iter2.next(),
-                Ordering::Equal => {
-                    self.iter1.next();
-                    self.iter2.next()
-                }
-                Ordering::Greater => self.iter1.next(),
-            },
+        if let Some(err) = self.errors.pop() {
+            return Some(Err(err));
         }
+        let (IndexEntryByPosition(entry), i) = self.heads.pop()?;
+        self.advance(i);
+        while let Some((head, _)) = self.heads.peek() {
+            if head.0.position() != entry.position() {
+                break;
+            }
+            let (_, i) = self.heads.pop().unwrap();
+            self.advance(i);
+        }
+        Some(Ok(entry))
+    }
+}
+
+impl<'index> RevsetIterator<'index> for UnionRevsetIterator<'_, 'index> {
+    fn seek(&mut self, position: IndexPosition) -> Option<Self::Item> {
+        while let Some((head, _)) = self.heads.peek() {
+            if head.0.position() <= position {
+                break;
+            }
+            let (_, i) = self.heads.pop().unwrap();
+            match self.iters[i].seek(position) {
+                Some(Ok(entry)) => self.heads.push((IndexEntryByPosition(entry), i)),
+                Some(Err(err)) => self.errors.push(err),
+                None => {}
+            }
+        }
+        self.next()
     }
 }
 
 #[derive(Debug)]
-struct IntersectionRevset<'index> {
+struct SymmetricDifferenceRevset<'index> {
     set1: Box<dyn InternalRevset<'index> + 'index>,
     set2: Box<dyn InternalRevset<'index> + 'index>,
 }
 
-impl<'index> InternalRevset<'index> for IntersectionRevset<'index> {
-    fn iter(&self) -> Box<dyn Iterator<Item = IndexEntry<'index>> + '_> {
-        Box::new(IntersectionRevsetIterator {
+impl<'index> InternalRevset<'index> for SymmetricDifferenceRevset<'index> {
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index> {
+        Box::new(SymmetricDifferenceRevsetIterator {
             iter1: self.set1.iter().peekable(),
             iter2: self.set2.iter().peekable(),
         })
@@ -423,67 +881,81 @@ impl<'index> InternalRevset<'index> for IntersectionRevset<'index> {
     }
 }
 
-impl ToPredicateFn for IntersectionRevset<'_> {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
+impl ToPredicateFn for SymmetricDifferenceRevset<'_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
         let mut p1 = self.set1.to_predicate_fn();
         let mut p2 = self.set2.to_predicate_fn();
-        Box::new(move |entry| p1(entry) && p2(entry))
+        Box::new(move |entry| Ok(p1(entry)? ^ p2(entry)?))
     }
 }
 
-struct IntersectionRevsetIterator<
+struct SymmetricDifferenceRevsetIterator<
     'index,
-    I1: Iterator<Item = IndexEntry<'index>>,
-    I2: Iterator<Item = IndexEntry<'index>>,
+    I1: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>,
+    I2: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>,
 > {
     iter1: Peekable<I1>,
     iter2: Peekable<I2>,
 }
 
-impl<'index, I1: Iterator<Item = IndexEntry<'index>>, I2: Iterator<Item = IndexEntry<'index>>>
-    Iterator for IntersectionRevsetIterator<'index, I1, I2>
+impl<'index, I1, I2> RevsetIterator<'index> for SymmetricDifferenceRevsetIterator<'index, I1, I2>
+where
+    I1: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>,
+    I2: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>,
 {
-    type Item = IndexEntry<'index>;
+}
+
+impl<'index, I1, I2> Iterator for SymmetricDifferenceRevsetIterator<'index, I1, I2>
+where
+    I1: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>,
+    I2: Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>,
+{
+    type Item = Result<IndexEntry<'index>, RevsetEvaluationError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             match (self.iter1.peek(), self.iter2.peek()) {
                 (None, _) => {
-                    return None;
+                    return self.iter2.next();
                 }
                 (_, None) => {
-                    return None;
+                    return self.iter1.next();
                 }
-                (Some(entry1), Some(entry2)) => match entry1.position().cmp(&entry2.position()) {
-                    Ordering::Less => {
-                        self.iter2.next();
-                    }
-                    Ordering::Equal => {
-                        self.iter1.next();
-                        return self.iter2.next();
-                    }
-                    Ordering::Greater => {
-                        self.iter1.next();
+                (Some(Err(_)), _) => {
+                    return self.iter1.next();
+                }
+                (_, Some(Err(_))) => {
+                    return self.iter2.next();
+                }
+                (Some(Ok(entry1)), Some(Ok(entry2))) => {
+                    match entry1.position().cmp(&entry2.position()) {
+                        Ordering::Less => {
+                            return self.iter2.next();
+                        }
+                        Ordering::Equal => {
+                            self.iter1.next();
+                            self.iter2.next();
+                        }
+                        Ordering::Greater => {
+                            return self.iter1.next();
+                        }
                     }
-                },
+                }
             }
         }
     }
 }
 
+/// Intersection of any number of sets, merged in a single pass.
 #[derive(Debug)]
-struct DifferenceRevset<'index> {
-    // The minuend (what to subtract from)
-    set1: Box<dyn InternalRevset<'index> + 'index>,
-    // The subtrahend (what to subtract)
-    set2: Box<dyn InternalRevset<'index> + 'index>,
+struct IntersectionRevset<'index> {
+    sets: Vec<Box<dyn InternalRevset<'index> + 'index>>,
 }
 
-impl<'index> InternalRevset<'index> for DifferenceRevset<'index> {
-    fn iter(&self) -> Box<dyn Iterator<Item = IndexEntry<'index>> + '_> {
-        Box::new(DifferenceRevsetIterator {
-            iter1: self.set1.iter().peekable(),
-            iter2: self.set2.iter().peekable(),
+impl<'index> InternalRevset<'index> for IntersectionRevset<'index> {
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index> {
+        Box::new(IntersectionRevsetIterator {
+            iters: self.sets.iter().map(|set| set.iter()).collect(),
         })
     }
 
@@ -495,54 +967,202 @@ impl<'index> InternalRevset<'index> for DifferenceRevset<'index> {
     }
 }
 
-impl ToPredicateFn for DifferenceRevset<'_> {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
-        let mut p1 = self.set1.to_predicate_fn();
-        let mut p2 = self.set2.to_predicate_fn();
-        Box::new(move |entry| p1(entry) && !p2(entry))
+impl ToPredicateFn for IntersectionRevset<'_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
+        let mut predicates = self
+            .sets
+            .iter()
+            .map(|set| set.to_predicate_fn())
+            .collect_vec();
+        Box::new(move |entry| {
+            for p in &mut predicates {
+                if !p(entry)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        })
     }
 }
 
-struct DifferenceRevsetIterator<
-    'index,
-    I1: Iterator<Item = IndexEntry<'index>>,
-    I2: Iterator<Item = IndexEntry<'index>>,
-> {
-    iter1: Peekable<I1>,
-    iter2: Peekable<I2>,
+/// Yields the entries found in all of the iterators.
+///
+/// The iterators are visited in turn, each seeking to the lowest position seen
+/// so far, until all of them agree on the same position.
+struct IntersectionRevsetIterator<'a, 'index> {
+    iters: Vec<BoxedRevsetIter<'a, 'index>>,
 }
 
-impl<'index, I1: Iterator<Item = IndexEntry<'index>>, I2: Iterator<Item = IndexEntry<'index>>>
-    Iterator for DifferenceRevsetIterator<'index, I1, I2>
-{
-    type Item = IndexEntry<'index>;
+impl<'index> IntersectionRevsetIterator<'_, 'index> {
+    /// Returns the next entry common to all iterators, starting from the
+    /// given `entry` of the first iterator.
+    fn next_common(
+        &mut self,
+        first: Option<Result<IndexEntry<'index>, RevsetEvaluationError>>,
+    ) -> Option<Result<IndexEntry<'index>, RevsetEvaluationError>> {
+        let mut target = match first? {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err)),
+        };
+        let mut num_matched = 1;
+        for i in (0..self.iters.len()).cycle().skip(1) {
+            if num_matched == self.iters.len() {
+                break;
+            }
+            match self.iters[i].seek(target.position())? {
+                Ok(entry) if entry.position() == target.position() => num_matched += 1,
+                Ok(entry) => {
+                    target = entry;
+                    num_matched = 1;
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        Some(Ok(target))
+    }
+}
+
+impl<'index> Iterator for IntersectionRevsetIterator<'_, 'index> {
+    type Item = Result<IndexEntry<'index>, RevsetEvaluationError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match (self.iter1.peek(), self.iter2.peek()) {
-                (None, _) => {
-                    return None;
-                }
-                (_, None) => {
-                    return self.iter1.next();
-                }
-                (Some(entry1), Some(entry2)) => match entry1.position().cmp(&entry2.position()) {
-                    Ordering::Less => {
-                        self.iter2.next();
-                    }
-                    Ordering::Equal => {
-                        self.iter2.next();
-                        self.iter1.next();
-                    }
-                    Ordering::Greater => {
-                        return self.iter1.next();
+        let first = self.iters.first_mut()?.next();
+        self.next_common(first)
+    }
+}
+
+impl<'index> RevsetIterator<'index> for IntersectionRevsetIterator<'_, 'index> {
+    fn seek(&mut self, position: IndexPosition) -> Option<Self::Item> {
+        let first = self.iters.first_mut()?.seek(position);
+        self.next_common(first)
+    }
+}
+
+#[derive(Debug)]
+struct DifferenceRevset<'index> {
+    // The minuend (what to subtract from)
+    set1: Box<dyn InternalRevset<'index> + 'index>,
+    // The subtrahend (what to subtract)
+    set2: Box<dyn InternalRevset<'index> + 'index>,
+}
+
+impl<'index> InternalRevset<'index> for DifferenceRevset<'index> {
+    fn iter(&self) -> BoxedRevsetIter<'_, 'index> {
+        Box::new(DifferenceRevsetIterator {
+            iter1: self.set1.iter(),
+            iter2: self.set2.iter(),
+            pending1: None,
+            head2: Some(IndexPosition::MAX),
+        })
+    }
+
+    fn into_predicate<'a>(self: Box<Self>) -> Box<dyn ToPredicateFn + 'a>
+    where
+        Self: 'a,
+    {
+        self
+    }
+}
+
+impl ToPredicateFn for DifferenceRevset<'_> {
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
+        let mut p1 = self.set1.to_predicate_fn();
+        let mut p2 = self.set2.to_predicate_fn();
+        Box::new(move |entry| Ok(p1(entry)? && !p2(entry)?))
+    }
+}
+
+struct DifferenceRevsetIterator<'a, 'index> {
+    iter1: BoxedRevsetIter<'a, 'index>,
+    iter2: BoxedRevsetIter<'a, 'index>,
+    /// Entry of `iter1` to be tested again after an error from `iter2`
+    pending1: Option<IndexEntry<'index>>,
+    /// Position of the last entry taken from `iter2`, or `None` if `iter2` is
+    /// exhausted. No entry is positioned at `MAX`.
+    head2: Option<IndexPosition>,
+}
+
+impl<'index> DifferenceRevsetIterator<'_, 'index> {
+    /// Returns the next entry not in `iter2`, starting from the given `first`
+    /// entry of `iter1`.
+    fn next_difference(
+        &mut self,
+        first: Option<Result<IndexEntry<'index>, RevsetEvaluationError>>,
+    ) -> Option<Result<IndexEntry<'index>, RevsetEvaluationError>> {
+        let mut item1 = first;
+        loop {
+            let entry1 = match item1? {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err)),
+            };
+            if matches!(self.head2, Some(position) if position > entry1.position()) {
+                match self.iter2.seek(entry1.position()) {
+                    Some(Ok(entry2)) => self.head2 = Some(entry2.position()),
+                    Some(Err(err)) => {
+                        self.pending1 = Some(entry1);
+                        return Some(Err(err));
                     }
-                },
+                    None => self.head2 = None,
+                }
+            }
+            if self.head2 != Some(entry1.position()) {
+                return Some(Ok(entry1));
             }
+            item1 = self.iter1.next();
         }
     }
 }
 
+impl<'index> Iterator for DifferenceRevsetIterator<'_, 'index> {
+    type Item = Result<IndexEntry<'index>, RevsetEvaluationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = match self.pending1.take() {
+            Some(entry) => Some(Ok(entry)),
+            None => self.iter1.next(),
+        };
+        self.next_difference(first)
+    }
+}
+
+impl<'index> RevsetIterator<'index> for DifferenceRevsetIterator<'_, 'index> {
+    fn seek(&mut self, position: IndexPosition) -> Option<Self::Item> {
+        let first = match self.pending1.take() {
+            Some(entry) if entry.position() <= position => Some(Ok(entry)),
+            _ => self.iter1.seek(position),
+        };
+        self.next_difference(first)
+    }
+}
+
+/// Yields entries of the inner revset in ascending order of index position.
+///
+/// `InternalRevset`s can only be iterated in descending order, so this
+/// necessarily materializes the whole set. The inner iterator is consumed once
+/// when this wrapper is created. Because the order differs, this isn't an
+/// `InternalRevset` and can't be combined with the other revsets.
+#[derive(Debug)]
+struct ReverseRevset<'index> {
+    index_entries: Vec<IndexEntry<'index>>,
+}
+
+impl<'index> ReverseRevset<'index> {
+    fn new(inner: &dyn InternalRevset<'index>) -> Result<Self, RevsetEvaluationError> {
+        Ok(ReverseRevset {
+            index_entries: inner.iter().try_collect()?,
+        })
+    }
+}
+
+impl<'index> IntoIterator for ReverseRevset<'index> {
+    type Item = IndexEntry<'index>;
+    type IntoIter = std::iter::Rev<std::vec::IntoIter<IndexEntry<'index>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.index_entries.into_iter().rev()
+    }
+}
+
 // TODO: Having to pass both `&dyn Index` and `CompositeIndex` is a bit ugly.
 // Maybe we should make `CompositeIndex` implement `Index`?
 pub fn evaluate<'index>(
@@ -550,20 +1170,177 @@ pub fn evaluate<'index>(
     store: &Arc<Store>,
     index: &'index dyn Index,
     composite_index: CompositeIndex<'index>,
+    options: &RevsetEvaluationOptions,
 ) -> Result<RevsetImpl<'index>, RevsetEvaluationError> {
     let context = EvaluationContext {
-        store: store.clone(),
-        index,
-        composite_index: composite_index.clone(),
+        repeated_expressions: repeated_subexpressions(expression),
+        parallel_filters: options.parallel_filters,
+        ..EvaluationContext::new(store.clone(), index, composite_index.clone())
     };
     let internal_revset = context.evaluate(expression)?;
     Ok(RevsetImpl::new(internal_revset, composite_index))
 }
 
+/// Builds function which tests if a commit matches the `predicate`.
+///
+/// Unlike `evaluate()`, the commit to be tested doesn't have to be in the
+/// index, so this can be used for e.g. a working-copy commit which hasn't been
+/// indexed yet. Such commit isn't contained in any set embedded in the
+/// predicate.
+pub fn build_commit_predicate<'index>(
+    predicate: &ResolvedPredicateExpression,
+    store: &Arc<Store>,
+    index: &'index dyn Index,
+    composite_index: CompositeIndex<'index>,
+) -> Result<Box<CommitPredicateFn<'index>>, RevsetEvaluationError> {
+    let context = EvaluationContext::new(store.clone(), index, composite_index);
+    context.evaluate_commit_predicate(predicate)
+}
+
 struct EvaluationContext<'index> {
     store: Arc<Store>,
     index: &'index dyn Index,
     composite_index: CompositeIndex<'index>,
+    /// Addresses of the subexpressions occurring more than once in the
+    /// evaluated expression, mapped to the key shared by the structurally
+    /// identical ones.
+    repeated_expressions: HashMap<*const ResolvedExpression, usize>,
+    /// Eagerly evaluated sets of `repeated_expressions`, keyed by the shared
+    /// key.
+    /// Lazy sets aren't cached since that would force the walk.
+    shared_sets: RefCell<HashMap<usize, Rc<EagerRevset<'index>>>>,
+    commit_cache: Arc<CommitCache>,
+    /// Whether expensive filters may be tested in parallel.
+    parallel_filters: bool,
+}
+
+/// Returns the direct subexpressions of `expression`, including the sets
+/// embedded in its predicate.
+fn child_expressions(expression: &ResolvedExpression) -> Vec<&ResolvedExpression> {
+    fn predicate_sets<'a>(
+        predicate: &'a ResolvedPredicateExpression,
+        sets: &mut Vec<&'a ResolvedExpression>,
+    ) {
+        match predicate {
+            ResolvedPredicateExpression::Filter(_) => {}
+            ResolvedPredicateExpression::Set(expression) => sets.push(expression),
+            ResolvedPredicateExpression::NotIn(complement) => predicate_sets(complement, sets),
+            ResolvedPredicateExpression::Union(predicate1, predicate2)
+            | ResolvedPredicateExpression::Intersection(predicate1, predicate2) => {
+                predicate_sets(predicate1, sets);
+                predicate_sets(predicate2, sets);
+            }
+        }
+    }
+
+    match expression {
+        ResolvedExpression::None | ResolvedExpression::Commits(_) => vec![],
+        ResolvedExpression::All { heads } | ResolvedExpression::Ancestors { heads, .. } => {
+            vec![heads]
+        }
+        ResolvedExpression::Parents(candidates)
+        | ResolvedExpression::Connected(candidates)
+        | ResolvedExpression::Heads(candidates)
+        | ResolvedExpression::Roots(candidates)
+        | ResolvedExpression::Latest { candidates, .. }
+        | ResolvedExpression::Earliest(candidates) => vec![candidates],
+        ResolvedExpression::Range { roots, heads, .. }
+        | ResolvedExpression::Descendants { roots, heads, .. }
+        | ResolvedExpression::DagRange { roots, heads, .. } => vec![roots, heads],
+        ResolvedExpression::Union(expression1, expression2)
+        | ResolvedExpression::SymmetricDifference(expression1, expression2)
+        | ResolvedExpression::Intersection(expression1, expression2)
+        | ResolvedExpression::Difference(expression1, expression2) => {
+            vec![expression1, expression2]
+        }
+        ResolvedExpression::FilterWithin {
+            candidates,
+            predicate,
+        } => {
+            let mut children = vec![candidates.as_ref()];
+            predicate_sets(predicate, &mut children);
+            children
+        }
+    }
+}
+
+/// Collects the structurally identical subexpressions which occur more than
+/// once in `expression`. The subexpressions of a repeated one aren't collected
+/// unless they also occur elsewhere, since the repeated one is evaluated once.
+///
+/// Returns the addresses of all occurrences mapped to the key shared by the
+/// identical ones, so the evaluation can look them up without comparing the
+/// trees.
+fn repeated_subexpressions(
+    expression: &ResolvedExpression,
+) -> HashMap<*const ResolvedExpression, usize> {
+    let mut all = vec![];
+    let mut counts: HashMap<&ResolvedExpression, usize> = HashMap::new();
+    let mut stack = vec![expression];
+    while let Some(expression) = stack.pop() {
+        all.push(expression);
+        *counts.entry(expression).or_default() += 1;
+        stack.extend(child_expressions(expression));
+    }
+
+    let mut keys: HashMap<&ResolvedExpression, usize> = HashMap::new();
+    let mut stack = vec![expression];
+    while let Some(expression) = stack.pop() {
+        if matches!(expression, ResolvedExpression::None) {
+            continue;
+        }
+        if counts[expression] > 1 {
+            let next_key = keys.len();
+            keys.entry(expression).or_insert(next_key);
+        } else {
+            stack.extend(child_expressions(expression).into_iter().rev());
+        }
+    }
+    all.into_iter()
+        .filter_map(|expression| {
+            let key = *keys.get(expression)?;
+            Some((expression as *const ResolvedExpression, key))
+        })
+        .collect()
+}
+
+/// Collects the operands of nested `Union`s so they can be merged at once.
+fn flatten_union(expression: &ResolvedExpression) -> Vec<&ResolvedExpression> {
+    let mut operands = vec![];
+    let mut stack = vec![expression];
+    while let Some(expression) = stack.pop() {
+        match expression {
+            ResolvedExpression::Union(expression1, expression2) => {
+                stack.push(expression2);
+                stack.push(expression1);
+            }
+            _ => operands.push(expression),
+        }
+    }
+    operands
+}
+
+/// Collects the operands of nested `Intersection`s so they can be merged at
+/// once.
+fn flatten_intersection(expression: &ResolvedExpression) -> Vec<&ResolvedExpression> {
+    let mut operands = vec![];
+    let mut stack = vec![expression];
+    while let Some(expression) = stack.pop() {
+        match expression {
+            ResolvedExpression::Intersection(expression1, expression2) => {
+                stack.push(expression2);
+                stack.push(expression1);
+            }
+            _ => operands.push(expression),
+        }
+    }
+    operands
+}
+
+/// Bit set of the generations in `range`, which must end within 64.
+fn generation_bits(range: &Range<u32>) -> u64 {
+    let below = |n: u32| u64::MAX.checked_shr(u64::BITS - n).unwrap_or(0);
+    below(range.end) & !below(range.start.min(range.end))
 }
 
 fn to_u32_generation_range(range: &Range<u64>) -> Result<Range<u32>, RevsetEvaluationError> {
@@ -578,23 +1355,90 @@ fn to_u32_generation_range(range: &Range<u64>) -> Result<Range<u32>, RevsetEvalu
 }
 
 impl<'index> EvaluationContext<'index> {
+    fn new(
+        store: Arc<Store>,
+        index: &'index dyn Index,
+        composite_index: CompositeIndex<'index>,
+    ) -> Self {
+        EvaluationContext {
+            commit_cache: Arc::new(CommitCache::new(store.clone())),
+            store,
+            index,
+            composite_index,
+            repeated_expressions: HashMap::new(),
+            shared_sets: RefCell::new(HashMap::new()),
+            parallel_filters: false,
+        }
+    }
+
     fn evaluate(
         &self,
         expression: &ResolvedExpression,
+    ) -> Result<Box<dyn InternalRevset<'index> + 'index>, RevsetEvaluationError> {
+        let key = match self.repeated_expressions.get(&(expression as *const _)) {
+            Some(&key) => key,
+            None => return self.evaluate_uncached(expression),
+        };
+        if let Some(set) = self.shared_sets.borrow().get(&key) {
+            return Ok(Box::new(SharedEagerRevset(set.clone())));
+        }
+        let mut set = self.evaluate_uncached(expression)?;
+        match set.take_eager() {
+            Some(eager_set) => {
+                let shared_set = Rc::new(eager_set);
+                self.shared_sets
+                    .borrow_mut()
+                    .insert(key, shared_set.clone());
+                Ok(Box::new(SharedEagerRevset(shared_set)))
+            }
+            None => Ok(set),
+        }
+    }
+
+    fn evaluate_uncached(
+        &self,
+        expression: &ResolvedExpression,
     ) -> Result<Box<dyn InternalRevset<'index> + 'index>, RevsetEvaluationError> {
         match expression {
+            ResolvedExpression::None => Ok(Box::new(EagerRevset::empty())),
+            ResolvedExpression::All { heads } => {
+                let head_set = self.evaluate(heads)?;
+                let walk = self.walk_ancestors(&*head_set)?;
+                Ok(Box::new(RevWalkRevset { walk: walk.map(Ok) }))
+            }
             ResolvedExpression::Commits(commit_ids) => {
-                Ok(Box::new(self.revset_for_commit_ids(commit_ids)))
+                Ok(Box::new(self.revset_for_commit_ids(commit_ids)?))
             }
-            ResolvedExpression::Ancestors { heads, generation } => {
+            ResolvedExpression::Ancestors {
+                heads,
+                generation,
+                generation_mode,
+            } => {
                 let head_set = self.evaluate(heads)?;
-                let walk = self.walk_ancestors(&*head_set);
+                let walk = self.walk_ancestors(&*head_set)?;
                 if generation == &GENERATION_RANGE_FULL {
-                    Ok(Box::new(RevWalkRevset { walk }))
+                    Ok(Box::new(RevWalkRevset { walk: walk.map(Ok) }))
                 } else {
-                    let walk = walk.filter_by_generation(to_u32_generation_range(generation)?);
-                    Ok(Box::new(RevWalkRevset { walk }))
+                    let walk = walk.filter_by_generation(
+                        to_u32_generation_range(generation)?,
+                        *generation_mode,
+                    );
+                    Ok(Box::new(RevWalkRevset { walk: walk.map(Ok) }))
+                }
+            }
+            ResolvedExpression::Parents(candidates) => {
+                let candidate_set = self.evaluate(candidates)?;
+                let mut parent_positions = vec![];
+                for entry in candidate_set.iter() {
+                    parent_positions.extend(entry?.parent_positions());
                 }
+                parent_positions.sort_unstable_by_key(|&pos| Reverse(pos));
+                parent_positions.dedup();
+                let index_entries = parent_positions
+                    .into_iter()
+                    .map(|pos| self.composite_index.entry_by_pos(pos))
+                    .collect();
+                Ok(Box::new(EagerRevset { index_entries }))
             }
             ResolvedExpression::Range {
                 roots,
@@ -602,15 +1446,42 @@ impl<'index> EvaluationContext<'index> {
                 generation,
             } => {
                 let root_set = self.evaluate(roots)?;
-                let root_ids = root_set.iter().map(|entry| entry.commit_id()).collect_vec();
+                let root_ids: Vec<_> = root_set
+                    .iter()
+                    .map_ok(|entry| entry.commit_id())
+                    .try_collect()?;
                 let head_set = self.evaluate(heads)?;
-                let head_ids = head_set.iter().map(|entry| entry.commit_id()).collect_vec();
+                let head_ids: Vec<_> = head_set
+                    .iter()
+                    .map_ok(|entry| entry.commit_id())
+                    .try_collect()?;
                 let walk = self.composite_index.walk_revs(&head_ids, &root_ids);
                 if generation == &GENERATION_RANGE_FULL {
-                    Ok(Box::new(RevWalkRevset { walk }))
+                    Ok(Box::new(RevWalkRevset { walk: walk.map(Ok) }))
+                } else {
+                    let walk = walk.filter_by_generation(
+                        to_u32_generation_range(generation)?,
+                        GenerationMode::Any,
+                    );
+                    Ok(Box::new(RevWalkRevset { walk: walk.map(Ok) }))
+                }
+            }
+            ResolvedExpression::Descendants {
+                roots,
+                heads,
+                generation,
+            } => {
+                let root_set = self.evaluate(roots)?;
+                let head_set = self.evaluate(heads)?;
+                if generation == &GENERATION_RANGE_FULL {
+                    let (dag_range_set, _) = self.collect_dag_range(&*root_set, &*head_set)?;
+                    Ok(Box::new(dag_range_set))
                 } else {
-                    let walk = walk.filter_by_generation(to_u32_generation_range(generation)?);
-                    Ok(Box::new(RevWalkRevset { walk }))
+                    Ok(Box::new(self.walk_descendants_by_generation(
+                        &*root_set,
+                        &*head_set,
+                        to_u32_generation_range(generation)?,
+                    )?))
                 }
             }
             ResolvedExpression::DagRange {
@@ -621,64 +1492,113 @@ impl<'index> EvaluationContext<'index> {
                 let root_set = self.evaluate(roots)?;
                 let head_set = self.evaluate(heads)?;
                 if generation_from_roots == &(1..2) {
-                    Ok(Box::new(self.walk_children(&*root_set, &*head_set)))
+                    Ok(Box::new(self.walk_children(&*root_set, &*head_set)?))
                 } else if generation_from_roots == &GENERATION_RANGE_FULL {
-                    let (dag_range_set, _) = self.collect_dag_range(&*root_set, &*head_set);
+                    let (dag_range_set, _) = self.collect_dag_range(&*root_set, &*head_set)?;
                     Ok(Box::new(dag_range_set))
                 } else {
-                    // For small generation range, it might be better to build a reachable map
-                    // with generation bit set, which can be calculated incrementally from roots:
-                    //   reachable[pos] = (reachable[parent_pos] | ...) << 1
-                    let root_positions =
-                        root_set.iter().map(|entry| entry.position()).collect_vec();
-                    let walk = self
-                        .walk_ancestors(&*head_set)
-                        .descendants_filtered_by_generation(
-                            &root_positions,
-                            to_u32_generation_range(generation_from_roots)?,
-                        );
-                    let mut index_entries = walk.collect_vec();
-                    index_entries.reverse();
-                    Ok(Box::new(EagerRevset { index_entries }))
+                    Ok(Box::new(self.walk_descendants_by_generation(
+                        &*root_set,
+                        &*head_set,
+                        to_u32_generation_range(generation_from_roots)?,
+                    )?))
                 }
             }
+            ResolvedExpression::Connected(candidates) => {
+                let candidate_set = EagerRevset {
+                    index_entries: self.evaluate(candidates)?.iter().try_collect()?,
+                };
+                let (dag_range_set, _) = self.collect_dag_range(&candidate_set, &candidate_set)?;
+                Ok(Box::new(dag_range_set))
+            }
             ResolvedExpression::Heads(candidates) => {
                 let candidate_set = self.evaluate(candidates)?;
-                let candidate_ids = candidate_set
-                    .iter()
-                    .map(|entry| entry.commit_id())
-                    .collect_vec();
-                Ok(Box::new(self.revset_for_commit_ids(
-                    &self.composite_index.heads(&mut candidate_ids.iter()),
-                )))
+                let head_positions = itertools::process_results(
+                    candidate_set.iter().map_ok(|entry| entry.position()),
+                    |positions| self.composite_index.heads_pos_descending(positions),
+                )?;
+                let index_entries = head_positions
+                    .into_iter()
+                    .map(|pos| self.composite_index.entry_by_pos(pos))
+                    .collect();
+                Ok(Box::new(EagerRevset { index_entries }))
             }
             ResolvedExpression::Roots(candidates) => {
                 let candidate_set = EagerRevset {
-                    index_entries: self.evaluate(candidates)?.iter().collect(),
+                    index_entries: self.evaluate(candidates)?.iter().try_collect()?,
                 };
-                let (_, filled) = self.collect_dag_range(&candidate_set, &candidate_set);
-                let mut index_entries = vec![];
-                for candidate in candidate_set.iter() {
-                    if !candidate
-                        .parent_positions()
-                        .iter()
-                        .any(|parent| filled.contains(parent))
-                    {
-                        index_entries.push(candidate);
-                    }
+                let candidate_positions: HashSet<_> = candidate_set
+                    .index_entries
+                    .iter()
+                    .map(|entry| entry.position())
+                    .collect();
+                // A candidate having a parent in the set can't be a root.
+                let maybe_roots = candidate_set
+                    .index_entries
+                    .iter()
+                    .filter(|entry| {
+                        !entry
+                            .parent_positions()
+                            .iter()
+                            .any(|parent| candidate_positions.contains(parent))
+                    })
+                    .cloned()
+                    .collect_vec();
+                if maybe_roots.len() <= 1 {
+                    // Every candidate descends from the only remaining one.
+                    return Ok(Box::new(EagerRevset {
+                        index_entries: maybe_roots,
+                    }));
                 }
+                // The remaining candidates may still be descendants of other
+                // candidates through commits outside of the set.
+                let parent_set = EagerRevset::from_unsorted_entries(
+                    maybe_roots
+                        .iter()
+                        .flat_map(|entry| entry.parents())
+                        .collect(),
+                );
+                let (_, filled) = self.collect_dag_range(&candidate_set, &parent_set)?;
+                let index_entries = maybe_roots
+                    .into_iter()
+                    .filter(|entry| {
+                        !entry
+                            .parent_positions()
+                            .iter()
+                            .any(|&parent| filled.contains(parent))
+                    })
+                    .collect();
                 Ok(Box::new(EagerRevset { index_entries }))
             }
-            ResolvedExpression::Latest { candidates, count } => {
+            ResolvedExpression::Latest {
+                candidates,
+                count,
+                offset,
+                group_by,
+            } => {
+                let candidate_set = self.evaluate(candidates)?;
+                Ok(Box::new(self.take_latest_revset(
+                    candidate_set.as_ref(),
+                    *count,
+                    *offset,
+                    *group_by,
+                )?))
+            }
+            ResolvedExpression::Earliest(candidates) => {
                 let candidate_set = self.evaluate(candidates)?;
-                Ok(Box::new(
-                    self.take_latest_revset(candidate_set.as_ref(), *count),
-                ))
+                Ok(Box::new(self.take_earliest_revset(candidate_set.as_ref())?))
             }
-            ResolvedExpression::Union(expression1, expression2) => {
+            ResolvedExpression::Union(..) => {
+                let sets = flatten_union(expression)
+                    .into_iter()
+                    .map(|expression| self.evaluate(expression))
+                    .try_collect()?;
+                Ok(Box::new(UnionRevset { sets }))
+            }
+            ResolvedExpression::SymmetricDifference(expression1, expression2) => {
                 let set1 = self.evaluate(expression1)?;
                 let set2 = self.evaluate(expression2)?;
-                Ok(Box::new(UnionRevset { set1, set2 }))
+                Ok(Box::new(SymmetricDifferenceRevset { set1, set2 }))
             }
             ResolvedExpression::FilterWithin {
                 candidates,
@@ -687,10 +1607,12 @@ impl<'index> EvaluationContext<'index> {
                 candidates: self.evaluate(candidates)?,
                 predicate: self.evaluate_predicate(predicate)?,
             })),
-            ResolvedExpression::Intersection(expression1, expression2) => {
-                let set1 = self.evaluate(expression1)?;
-                let set2 = self.evaluate(expression2)?;
-                Ok(Box::new(IntersectionRevset { set1, set2 }))
+            ResolvedExpression::Intersection(..) => {
+                let sets = flatten_intersection(expression)
+                    .into_iter()
+                    .map(|expression| self.evaluate(expression))
+                    .try_collect()?;
+                Ok(Box::new(IntersectionRevset { sets }))
             }
             ResolvedExpression::Difference(expression1, expression2) => {
                 let set1 = self.evaluate(expression1)?;
@@ -705,11 +1627,14 @@ impl<'index> EvaluationContext<'index> {
         expression: &ResolvedPredicateExpression,
     ) -> Result<Box<dyn ToPredicateFn + 'index>, RevsetEvaluationError> {
         match expression {
-            ResolvedPredicateExpression::Filter(predicate) => Ok(build_predicate_fn(
+            ResolvedPredicateExpression::Filter(predicate) => build_predicate_fn(
                 self.store.clone(),
                 self.index,
+                self.composite_index.clone(),
+                self.commit_cache.clone(),
+                self.parallel_filters,
                 predicate,
-            )),
+            ),
             ResolvedPredicateExpression::Set(expression) => {
                 Ok(self.evaluate(expression)?.into_predicate())
             }
@@ -722,44 +1647,169 @@ impl<'index> EvaluationContext<'index> {
                 let set2 = self.evaluate_predicate(expression2)?;
                 Ok(Box::new(UnionPredicate { set1, set2 }))
             }
+            ResolvedPredicateExpression::Intersection(expression1, expression2) => {
+                let set1 = self.evaluate_predicate(expression1)?;
+                let set2 = self.evaluate_predicate(expression2)?;
+                Ok(Box::new(IntersectionPredicate { set1, set2 }))
+            }
+        }
+    }
+
+    fn evaluate_commit_predicate(
+        &self,
+        expression: &ResolvedPredicateExpression,
+    ) -> Result<Box<CommitPredicateFn<'index>>, RevsetEvaluationError> {
+        match expression {
+            ResolvedPredicateExpression::Filter(predicate) => build_commit_predicate_fn(
+                self.store.clone(),
+                self.index,
+                self.composite_index.clone(),
+                predicate,
+            ),
+            ResolvedPredicateExpression::Set(expression) => {
+                // Sets consist of indexed commits only. The set is evaluated
+                // once on first use since a commit outside the index doesn't
+                // need it. The evaluated set can't be shared across threads,
+                // so its positions are collected.
+                let expression = expression.as_ref().clone();
+                let store = self.store.clone();
+                let index = self.index;
+                let composite_index = self.composite_index.clone();
+                let commit_cache = self.commit_cache.clone();
+                let positions = once_cell::sync::OnceCell::new();
+                Ok(commit_predicate_fn(move |commit| {
+                    let entry = match composite_index.entry_by_id(commit.id()) {
+                        Some(entry) => entry,
+                        None => return Ok(false),
+                    };
+                    let positions: &HashSet<IndexPosition> = positions.get_or_try_init(|| {
+                        let context = EvaluationContext {
+                            commit_cache: commit_cache.clone(),
+                            ..EvaluationContext::new(store.clone(), index, composite_index.clone())
+                        };
+                        let set = context.evaluate(&expression)?;
+                        let positions = set.iter().map(|entry| Ok(entry?.position())).try_collect();
+                        positions
+                    })?;
+                    Ok(positions.contains(&entry.position()))
+                }))
+            }
+            ResolvedPredicateExpression::NotIn(complement) => {
+                let p = self.evaluate_commit_predicate(complement)?;
+                Ok(commit_predicate_fn(move |commit| Ok(!p(commit)?)))
+            }
+            ResolvedPredicateExpression::Union(expression1, expression2) => {
+                let p1 = self.evaluate_commit_predicate(expression1)?;
+                let p2 = self.evaluate_commit_predicate(expression2)?;
+                Ok(commit_predicate_fn(move |commit| {
+                    Ok(p1(commit)? || p2(commit)?)
+                }))
+            }
+            ResolvedPredicateExpression::Intersection(expression1, expression2) => {
+                let p1 = self.evaluate_commit_predicate(expression1)?;
+                let p2 = self.evaluate_commit_predicate(expression2)?;
+                Ok(commit_predicate_fn(move |commit| {
+                    Ok(p1(commit)? && p2(commit)?)
+                }))
+            }
         }
     }
 
-    fn walk_ancestors<'a, S>(&self, head_set: &S) -> RevWalk<'index>
+    fn walk_ancestors<'a, S>(&self, head_set: &S) -> Result<RevWalk<'index>, RevsetEvaluationError>
     where
         S: InternalRevset<'a> + ?Sized,
     {
-        let head_ids = head_set.iter().map(|entry| entry.commit_id()).collect_vec();
-        self.composite_index.walk_revs(&head_ids, &[])
+        let head_ids: Vec<_> = head_set
+            .iter()
+            .map_ok(|entry| entry.commit_id())
+            .try_collect()?;
+        Ok(self.composite_index.walk_revs(&head_ids, &[]))
     }
 
     fn walk_children<'a, 'b, S, T>(
         &self,
         root_set: &S,
         head_set: &T,
-    ) -> impl InternalRevset<'index> + 'index
+    ) -> Result<impl InternalRevset<'index> + 'index, RevsetEvaluationError>
     where
         S: InternalRevset<'a> + ?Sized,
         T: InternalRevset<'b> + ?Sized,
     {
-        let root_positions = root_set.iter().map(|entry| entry.position()).collect_vec();
+        let root_positions: Vec<_> = root_set
+            .iter()
+            .map_ok(|entry| entry.position())
+            .try_collect()?;
         let walk = self
-            .walk_ancestors(head_set)
+            .walk_ancestors(head_set)?
             .take_until_roots(&root_positions);
         let root_positions: HashSet<_> = root_positions.into_iter().collect();
-        let candidates = Box::new(RevWalkRevset { walk });
+        let candidates = Box::new(RevWalkRevset { walk: walk.map(Ok) });
         let predicate = PurePredicateFn(move |entry: &IndexEntry| {
-            entry
+            Ok(entry
                 .parent_positions()
                 .iter()
-                .any(|parent_pos| root_positions.contains(parent_pos))
+                .any(|parent_pos| root_positions.contains(parent_pos)))
         });
         // TODO: Suppose heads include all visible heads, ToPredicateFn version can be
         // optimized to only test the predicate()
-        FilterRevset {
+        Ok(FilterRevset {
             candidates,
             predicate,
+        })
+    }
+
+    /// Collects descendants of `root_set` within the `generation` range, which
+    /// are ancestors of `head_set`.
+    fn walk_descendants_by_generation<'a, 'b, S, T>(
+        &self,
+        root_set: &S,
+        head_set: &T,
+        generation: Range<u32>,
+    ) -> Result<EagerRevset<'index>, RevsetEvaluationError>
+    where
+        S: InternalRevset<'a> + ?Sized,
+        T: InternalRevset<'b> + ?Sized,
+    {
+        let root_positions: Vec<_> = root_set
+            .iter()
+            .map_ok(|entry| entry.position())
+            .try_collect()?;
+        let walk = self.walk_ancestors(head_set)?;
+        if generation.end <= u64::BITS {
+            // For small generation range, build a map of reachable generations
+            // as bit set, which can be calculated incrementally from roots:
+            //   reachable[pos] = (reachable[parent_pos] | ...) << 1
+            let mut index_entries = walk.take_until_roots(&root_positions).collect_vec();
+            let root_positions: HashSet<_> = root_positions.into_iter().collect();
+            // Only non-zero bit sets are stored, which are the descendants
+            // within the generation range.
+            let mut reachable: HashMap<IndexPosition, u64> = HashMap::new();
+            for entry in index_entries.iter().rev() {
+                let mut generations = entry
+                    .parent_positions()
+                    .iter()
+                    .filter_map(|parent_pos| reachable.get(parent_pos))
+                    .fold(0, |acc, parent_generations| acc | parent_generations)
+                    << 1;
+                if root_positions.contains(&entry.position()) {
+                    generations |= 1;
+                }
+                if generations != 0 {
+                    reachable.insert(entry.position(), generations);
+                }
+            }
+            let wanted = generation_bits(&generation);
+            index_entries.retain(|entry| {
+                reachable
+                    .get(&entry.position())
+                    .map_or(false, |generations| generations & wanted != 0)
+            });
+            return Ok(EagerRevset { index_entries });
         }
+        let walk = walk.descendants_filtered_by_generation(&root_positions, generation);
+        let mut index_entries = walk.collect_vec();
+        index_entries.reverse();
+        Ok(EagerRevset { index_entries })
     }
 
     /// Calculates `root_set:head_set`.
@@ -767,85 +1817,158 @@ impl<'index> EvaluationContext<'index> {
         &self,
         root_set: &S,
         head_set: &T,
-    ) -> (EagerRevset<'index>, HashSet<IndexPosition>)
+    ) -> Result<(EagerRevset<'index>, IndexPositionSet), RevsetEvaluationError>
     where
         S: InternalRevset<'a> + ?Sized,
         T: InternalRevset<'b> + ?Sized,
     {
-        let root_positions = root_set.iter().map(|entry| entry.position()).collect_vec();
-        let walk = self
-            .walk_ancestors(head_set)
-            .take_until_roots(&root_positions);
+        let root_positions: Vec<_> = root_set
+            .iter()
+            .map_ok(|entry| entry.position())
+            .try_collect()?;
+        // The walk can't yield the lowest entries first without visiting all
+        // ancestors, so it has to be buffered. Only the positions are kept,
+        // which are much smaller than the entries, and the same buffer is
+        // scanned backwards and then filtered in place to build the result.
+        let mut candidate_positions = self
+            .walk_ancestors(head_set)?
+            .take_until_roots(&root_positions)
+            .map(|entry| entry.position())
+            .collect_vec();
+        // The walk stops at the lowest root, so all candidates lie between it
+        // and the first (highest) candidate.
+        let mut reachable_positions =
+            match (root_positions.iter().min(), candidate_positions.first()) {
+                (Some(&min_position), Some(&max_position)) => {
+                    IndexPositionSet::with_range(min_position, max_position)
+                }
+                _ => IndexPositionSet::empty(),
+            };
         let root_positions: HashSet<_> = root_positions.into_iter().collect();
-        let mut reachable_positions = HashSet::new();
-        let mut index_entries = vec![];
-        for candidate in walk.collect_vec().into_iter().rev() {
-            if root_positions.contains(&candidate.position())
-                || candidate
+        // Scan from the bottom so parents are visited before their children.
+        for &position in candidate_positions.iter().rev() {
+            if root_positions.contains(&position)
+                || self
+                    .composite_index
+                    .entry_by_pos(position)
                     .parent_positions()
                     .iter()
-                    .any(|parent_pos| reachable_positions.contains(parent_pos))
+                    .any(|&parent_pos| reachable_positions.contains(parent_pos))
             {
-                reachable_positions.insert(candidate.position());
-                index_entries.push(candidate);
+                reachable_positions.insert(position);
             }
         }
-        index_entries.reverse();
-        (EagerRevset { index_entries }, reachable_positions)
+        candidate_positions.retain(|&position| reachable_positions.contains(position));
+        let index_entries = candidate_positions
+            .into_iter()
+            .map(|position| self.composite_index.entry_by_pos(position))
+            .collect();
+        Ok((EagerRevset { index_entries }, reachable_positions))
     }
 
-    fn revset_for_commit_ids(&self, commit_ids: &[CommitId]) -> EagerRevset<'index> {
+    fn revset_for_commit_ids(
+        &self,
+        commit_ids: &[CommitId],
+    ) -> Result<EagerRevset<'index>, RevsetEvaluationError> {
         let mut index_entries = vec![];
         for id in commit_ids {
-            index_entries.push(self.composite_index.entry_by_id(id).unwrap());
+            let entry = self.composite_index.entry_by_id(id).ok_or_else(|| {
+                RevsetEvaluationError::Other(format!("Commit {} not found in index", id.hex()))
+            })?;
+            index_entries.push(entry);
         }
-        index_entries.sort_unstable_by_key(|b| Reverse(b.position()));
-        index_entries.dedup();
-        EagerRevset { index_entries }
+        Ok(EagerRevset::from_unsorted_entries(index_entries))
     }
 
     fn take_latest_revset(
         &self,
         candidate_set: &dyn InternalRevset<'index>,
         count: usize,
-    ) -> EagerRevset<'index> {
+        offset: usize,
+        group_by: Option<LatestGroupKey>,
+    ) -> Result<EagerRevset<'index>, RevsetEvaluationError> {
         if count == 0 {
-            return EagerRevset::empty();
+            return Ok(EagerRevset::empty());
         }
 
         #[derive(Clone, Eq, Ord, PartialEq, PartialOrd)]
         struct Item<'a> {
             timestamp: MillisSinceEpoch,
-            entry: IndexEntryByPosition<'a>, // tie-breaker
+            // Tie-breaker which doesn't depend on the local index
+            change_id: ChangeId,
+            // Tie-breaker among divergent commits
+            entry: IndexEntryByPosition<'a>,
         }
 
         let make_rev_item = |entry: IndexEntry<'index>| {
-            let commit = self.store.get_commit(&entry.commit_id()).unwrap();
-            Reverse(Item {
-                timestamp: commit.committer().timestamp.timestamp.clone(),
+            // The timestamp is recorded in the index, so the commit has to be
+            // loaded only to look up the group.
+            let group_key = match group_by {
+                Some(LatestGroupKey::Author) => {
+                    Some(self.commit_cache.get(&entry)?.author().email.clone())
+                }
+                None => None,
+            };
+            let item = Item {
+                timestamp: entry.committer_timestamp(),
+                change_id: entry.change_id(),
                 entry: IndexEntryByPosition(entry),
-            })
+            };
+            Ok::<_, RevsetEvaluationError>((group_key, Reverse(item)))
         };
 
-        // Maintain min-heap containing the latest (greatest) count items. For small
-        // count and large candidate set, this is probably cheaper than building vec
-        // and applying selection algorithm.
-        let mut candidate_iter = candidate_set.iter().map(make_rev_item).fuse();
-        let mut latest_items = BinaryHeap::from_iter(candidate_iter.by_ref().take(count));
-        for item in candidate_iter {
-            let mut earliest = latest_items.peek_mut().unwrap();
-            if earliest.0 < item.0 {
-                *earliest = item;
+        // Maintain min-heap per group containing the latest (greatest) count +
+        // offset items. For small count and large candidate set, this is
+        // probably cheaper than building vec and applying selection algorithm.
+        let heap_size = count.saturating_add(offset);
+        let mut latest_items_by_group: HashMap<Option<String>, BinaryHeap<_>> = HashMap::new();
+        for entry in candidate_set.iter() {
+            let (group_key, item) = make_rev_item(entry?)?;
+            let latest_items = latest_items_by_group.entry(group_key).or_default();
+            if latest_items.len() < heap_size {
+                latest_items.push(item);
+            } else {
+                let mut earliest = latest_items.peek_mut().unwrap();
+                if earliest.0 < item.0 {
+                    *earliest = item;
+                }
             }
         }
 
-        assert!(latest_items.len() <= count);
-        let mut index_entries = latest_items
-            .into_iter()
-            .map(|item| item.0.entry.0)
+        let mut index_entries = latest_items_by_group
+            .into_values()
+            .flat_map(|latest_items| {
+                assert!(latest_items.len() <= heap_size);
+                // Sorted from the latest item since the items are reversed
+                latest_items
+                    .into_sorted_vec()
+                    .into_iter()
+                    .skip(offset)
+                    .map(|item| item.0.entry.0)
+            })
             .collect_vec();
         index_entries.sort_unstable_by_key(|b| Reverse(b.position()));
-        EagerRevset { index_entries }
+        Ok(EagerRevset { index_entries })
+    }
+
+    /// Selects the entry with the earliest author timestamp. Unlike
+    /// `take_latest_revset()`, this only keeps the running minimum.
+    fn take_earliest_revset(
+        &self,
+        candidate_set: &dyn InternalRevset<'index>,
+    ) -> Result<EagerRevset<'index>, RevsetEvaluationError> {
+        let items = candidate_set.iter().map(|entry| {
+            let entry = entry?;
+            let commit = self.commit_cache.get(&entry)?;
+            let timestamp = commit.author().timestamp.timestamp.clone();
+            // Tie-breakers in the same way as take_latest_revset()
+            Ok((timestamp, entry.change_id(), IndexEntryByPosition(entry)))
+        });
+        let earliest_entry =
+            itertools::process_results(items, |items| items.min())?.map(|(_, _, entry)| entry.0);
+        Ok(EagerRevset {
+            index_entries: earliest_entry.into_iter().collect(),
+        })
     }
 }
 
@@ -857,312 +1980,2932 @@ impl<F> fmt::Debug for PurePredicateFn<F> {
     }
 }
 
-impl<F: Fn(&IndexEntry<'_>) -> bool> ToPredicateFn for PurePredicateFn<F> {
-    fn to_predicate_fn(&self) -> Box<dyn FnMut(&IndexEntry<'_>) -> bool + '_> {
+impl<F> ToPredicateFn for PurePredicateFn<F>
+where
+    F: Fn(&IndexEntry<'_>) -> Result<bool, RevsetEvaluationError>,
+{
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
         Box::new(&self.0)
     }
 }
 
 fn pure_predicate_fn<'index>(
-    f: impl Fn(&IndexEntry<'_>) -> bool + 'index,
+    f: impl Fn(&IndexEntry<'_>) -> Result<bool, RevsetEvaluationError> + 'index,
 ) -> Box<dyn ToPredicateFn + 'index> {
     Box::new(PurePredicateFn(f))
 }
 
-fn build_predicate_fn<'index>(
-    store: Arc<Store>,
-    index: &'index dyn Index,
-    predicate: &RevsetFilterPredicate,
-) -> Box<dyn ToPredicateFn + 'index> {
-    match predicate {
-        RevsetFilterPredicate::ParentCount(parent_count_range) => {
-            let parent_count_range = parent_count_range.clone();
-            pure_predicate_fn(move |entry| parent_count_range.contains(&entry.num_parents()))
-        }
-        RevsetFilterPredicate::Description(needle) => {
-            let needle = needle.clone();
-            pure_predicate_fn(move |entry| {
-                store
-                    .get_commit(&entry.commit_id())
-                    .unwrap()
-                    .description()
-                    .contains(needle.as_str())
-            })
-        }
-        RevsetFilterPredicate::Author(needle) => {
-            let needle = needle.clone();
-            // TODO: Make these functions that take a needle to search for accept some
-            // syntax for specifying whether it's a regex and whether it's
-            // case-sensitive.
-            pure_predicate_fn(move |entry| {
-                let commit = store.get_commit(&entry.commit_id()).unwrap();
-                commit.author().name.contains(needle.as_str())
-                    || commit.author().email.contains(needle.as_str())
-            })
-        }
-        RevsetFilterPredicate::Committer(needle) => {
-            let needle = needle.clone();
-            pure_predicate_fn(move |entry| {
-                let commit = store.get_commit(&entry.commit_id()).unwrap();
-                commit.committer().name.contains(needle.as_str())
-                    || commit.committer().email.contains(needle.as_str())
+/// Pure predicate function which can be evaluated in parallel.
+struct ParallelPredicateFn<F>(F);
+
+impl<F> fmt::Debug for ParallelPredicateFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParallelPredicateFn")
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F> ToPredicateFn for ParallelPredicateFn<F>
+where
+    F: Fn(&IndexEntry<'_>) -> Result<bool, RevsetEvaluationError> + Sync,
+{
+    fn to_predicate_fn(&self) -> BoxedPredicateFn<'_> {
+        Box::new(&self.0)
+    }
+
+    fn as_sync_predicate_fn(&self) -> Option<&SyncPredicateFn<'_>> {
+        Some(&self.0)
+    }
+}
+
+fn parallel_predicate_fn<'index>(
+    f: impl Fn(&IndexEntry<'_>) -> Result<bool, RevsetEvaluationError> + Sync + 'index,
+) -> Box<dyn ToPredicateFn + 'index> {
+    Box::new(ParallelPredicateFn(f))
+}
+
+fn maybe_parallel_predicate_fn<'index>(
+    parallel: bool,
+    f: impl Fn(&IndexEntry<'_>) -> Result<bool, RevsetEvaluationError> + Sync + 'index,
+) -> Box<dyn ToPredicateFn + 'index> {
+    if parallel {
+        parallel_predicate_fn(f)
+    } else {
+        pure_predicate_fn(f)
+    }
+}
+
+/// Predicate function which tests a loaded commit. The commit doesn't have to
+/// be in the index.
+pub type CommitPredicateFn<'a> =
+    dyn Fn(&Commit) -> Result<bool, RevsetEvaluationError> + Send + Sync + 'a;
+
+fn commit_predicate_fn<'a>(
+    f: impl Fn(&Commit) -> Result<bool, RevsetEvaluationError> + Send + Sync + 'a,
+) -> Box<CommitPredicateFn<'a>> {
+    Box::new(f)
+}
+
+/// `StringPattern` compiled for repeated matching.
+#[derive(Debug)]
+enum StringMatcher {
+    /// Matches the whole string. Trailing newlines are ignored so that a
+    /// description can be matched without spelling out its line terminator.
+    Exact(String),
+    /// Case-insensitive version of `Exact`. The needle is lowercased in advance.
+    ExactInsensitive(String),
+    Substring(String),
+    /// Case-insensitive substring match. The needle is lowercased in advance,
+    /// and the haystack is case-folded on the fly.
+    SubstringInsensitive(String),
+    Word(String),
+    /// Case-insensitive version of `Word`. The needle is lowercased in advance.
+    WordInsensitive(String),
+    Regex(Regex),
+}
+
+impl StringMatcher {
+    fn new(pattern: &StringPattern, case: CaseSensitivity) -> Result<Self, RevsetEvaluationError> {
+        match (pattern, case) {
+            (StringPattern::Exact(needle), CaseSensitivity::Sensitive) => Ok(StringMatcher::Exact(
+                needle.trim_end_matches('\n').to_owned(),
+            )),
+            (StringPattern::Exact(needle), CaseSensitivity::Insensitive) => Ok(
+                StringMatcher::ExactInsensitive(needle.trim_end_matches('\n').to_lowercase()),
+            ),
+            (StringPattern::Substring(needle), CaseSensitivity::Sensitive) => {
+                Ok(StringMatcher::Substring(needle.clone()))
+            }
+            (StringPattern::Substring(needle), CaseSensitivity::Insensitive) => {
+                Ok(StringMatcher::SubstringInsensitive(needle.to_lowercase()))
+            }
+            (StringPattern::Word(needle), CaseSensitivity::Sensitive) => {
+                Ok(StringMatcher::Word(needle.clone()))
+            }
+            (StringPattern::Word(needle), CaseSensitivity::Insensitive) => {
+                Ok(StringMatcher::WordInsensitive(needle.to_lowercase()))
+            }
+            (StringPattern::Regex(needle), case) => {
+                // Multi-line mode so that `^` and `$` can match at line boundaries of
+                // multi-line descriptions.
+                let regex = RegexBuilder::new(needle)
+                    .multi_line(true)
+                    .case_insensitive(case == CaseSensitivity::Insensitive)
+                    .build()
+                    .map_err(|err| {
+                        RevsetEvaluationError::Other(format!(
+                            r#"Invalid regular expression "{needle}": {err}"#
+                        ))
+                    })?;
+                Ok(StringMatcher::Regex(regex))
+            }
+        }
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        match self {
+            StringMatcher::Exact(needle) => haystack.trim_end_matches('\n') == needle,
+            StringMatcher::ExactInsensitive(needle) => haystack
+                .trim_end_matches('\n')
+                .chars()
+                .flat_map(char::to_lowercase)
+                .eq(needle.chars()),
+            StringMatcher::Substring(needle) => haystack.contains(needle.as_str()),
+            StringMatcher::SubstringInsensitive(needle) => contains_ignoring_case(haystack, needle),
+            StringMatcher::Word(needle) => contains_word(haystack, |rest| {
+                rest.starts_with(needle.as_str()).then_some(needle.len())
+            }),
+            StringMatcher::WordInsensitive(needle) => {
+                contains_word(haystack, |rest| prefix_len_ignoring_case(rest, needle))
+            }
+            StringMatcher::Regex(regex) => regex.is_match(haystack),
+        }
+    }
+}
+
+/// Tests if `haystack` contains the `lower_needle` ignoring case. Unlike
+/// `haystack.to_lowercase().contains(lower_needle)`, this doesn't allocate.
+fn contains_ignoring_case(haystack: &str, lower_needle: &str) -> bool {
+    let starts_with = |start: usize| {
+        let mut lowered = haystack[start..].chars().flat_map(char::to_lowercase);
+        lower_needle.chars().all(|c| lowered.next() == Some(c))
+    };
+    lower_needle.is_empty() || haystack.char_indices().any(|(i, _)| starts_with(i))
+}
+
+/// Tests if `haystack` contains a match which isn't adjacent to word
+/// characters. `match_len` returns the byte length of the match at the start
+/// of the given string, if any.
+fn contains_word(haystack: &str, match_len: impl Fn(&str) -> Option<usize>) -> bool {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    haystack.char_indices().any(|(start, _)| {
+        let preceded_by_word = haystack[..start]
+            .chars()
+            .next_back()
+            .map_or(false, is_word_char);
+        if preceded_by_word {
+            return false;
+        }
+        match match_len(&haystack[start..]) {
+            Some(len) => !haystack[start + len..]
+                .chars()
+                .next()
+                .map_or(false, is_word_char),
+            None => false,
+        }
+    })
+}
+
+/// Returns the byte length of the prefix of `haystack` which equals the
+/// `lower_needle` ignoring case. The prefix must end at a char boundary.
+fn prefix_len_ignoring_case(haystack: &str, lower_needle: &str) -> Option<usize> {
+    let mut needle_chars = lower_needle.chars();
+    for (i, c) in haystack.char_indices() {
+        if needle_chars.as_str().is_empty() {
+            return Some(i);
+        }
+        if !c.to_lowercase().all(|c| needle_chars.next() == Some(c)) {
+            return None;
+        }
+    }
+    needle_chars.as_str().is_empty().then_some(haystack.len())
+}
+
+/// Number of commits kept by `CommitCache`. The predicates test the candidates
+/// in order, so only the recently loaded commits are likely to be tested again.
+const COMMIT_CACHE_CAPACITY: usize = 1024;
+
+/// Commits loaded during one evaluation, shared by the predicates so that
+/// combined filters read each commit once.
+///
+/// Commits read from the backend aren't added to the store's cache, so this is
+/// the only copy kept while the evaluation is in flight. The least recently
+/// used commit is evicted once the `capacity` is reached.
+#[derive(Debug)]
+struct CommitCache {
+    store: Arc<Store>,
+    capacity: usize,
+    commits: Mutex<CommitLru>,
+}
+
+#[derive(Debug, Default)]
+struct CommitLru {
+    /// Commits and the tick when they were last used.
+    commits: HashMap<CommitId, (Commit, u64)>,
+    /// Commit ids ordered by the tick when they were last used.
+    recency: BTreeMap<u64, CommitId>,
+    tick: u64,
+}
+
+impl CommitLru {
+    fn get(&mut self, commit_id: &CommitId) -> Option<Commit> {
+        let (commit, last_used) = self.commits.get_mut(commit_id)?;
+        self.recency.remove(last_used);
+        self.tick += 1;
+        *last_used = self.tick;
+        self.recency.insert(self.tick, commit_id.clone());
+        Some(commit.clone())
+    }
+
+    fn insert(&mut self, commit: Commit, capacity: usize) {
+        self.tick += 1;
+        let commit_id = commit.id().clone();
+        if let Some((_, last_used)) = self.commits.insert(commit_id.clone(), (commit, self.tick)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(self.tick, commit_id);
+        while self.commits.len() > capacity {
+            let oldest = *self.recency.keys().next().unwrap();
+            let commit_id = self.recency.remove(&oldest).unwrap();
+            self.commits.remove(&commit_id);
+        }
+    }
+}
+
+impl CommitCache {
+    fn new(store: Arc<Store>) -> Self {
+        Self::with_capacity(store, COMMIT_CACHE_CAPACITY)
+    }
+
+    fn with_capacity(store: Arc<Store>, capacity: usize) -> Self {
+        CommitCache {
+            store,
+            capacity,
+            commits: Mutex::new(CommitLru::default()),
+        }
+    }
+
+    fn get(&self, entry: &IndexEntry<'_>) -> Result<Commit, RevsetEvaluationError> {
+        let commit_id = entry.commit_id();
+        if let Some(commit) = self.commits.lock().unwrap().get(&commit_id) {
+            return Ok(commit);
+        }
+        // Not locked while loading so parallel predicates don't wait for each
+        // other.
+        let commit = self
+            .store
+            .get_commit_uncached(&commit_id)
+            .map_err(RevsetEvaluationError::StoreError)?;
+        self.commits
+            .lock()
+            .unwrap()
+            .insert(commit.clone(), self.capacity);
+        Ok(commit)
+    }
+}
+
+/// Returns the generation number of the `commit`, which is computed from the
+/// parents if the commit isn't in the index.
+fn generation_number(
+    composite_index: &CompositeIndex<'_>,
+    commit: &Commit,
+) -> Result<u32, RevsetEvaluationError> {
+    if let Some(entry) = composite_index.entry_by_id(commit.id()) {
+        return Ok(entry.generation_number());
+    }
+    let mut generation = None;
+    for parent_id in commit.parent_ids() {
+        let parent = composite_index.entry_by_id(parent_id).ok_or_else(|| {
+            RevsetEvaluationError::Other(format!(
+                "Parent commit {} is not in the index",
+                parent_id.hex()
+            ))
+        })?;
+        generation = generation.max(Some(parent.generation_number() + 1));
+    }
+    Ok(generation.unwrap_or(0))
+}
+
+/// Loads the parents of the `commit`. Unlike `Commit::parents()`, this doesn't
+/// panic if a parent can't be loaded.
+fn load_parents(store: &Arc<Store>, commit: &Commit) -> Result<Vec<Commit>, RevsetEvaluationError> {
+    commit
+        .parent_ids()
+        .iter()
+        .map(|id| store.get_commit(id))
+        .try_collect()
+        .map_err(RevsetEvaluationError::StoreError)
+}
+
+/// Builds predicate function for the filter. Commits are loaded through the
+/// `commit_cache` shared by the other predicates of the evaluation. Expensive
+/// filters are tested in parallel only if `parallel` is enabled.
+fn build_predicate_fn<'index>(
+    store: Arc<Store>,
+    index: &'index dyn Index,
+    composite_index: CompositeIndex<'index>,
+    commit_cache: Arc<CommitCache>,
+    parallel: bool,
+    predicate: &RevsetFilterPredicate,
+) -> Result<Box<dyn ToPredicateFn + 'index>, RevsetEvaluationError> {
+    let predicate_fn = match predicate {
+        // These can be answered by the index without loading the commit, which
+        // is too cheap to be worth distributing to the thread pool.
+        RevsetFilterPredicate::ParentCount(parent_count_range) => {
+            let parent_count_range = parent_count_range.clone();
+            pure_predicate_fn(move |entry| Ok(parent_count_range.contains(&entry.num_parents())))
+        }
+        RevsetFilterPredicate::Generation(generation_range) => {
+            let generation_range = generation_range.clone();
+            pure_predicate_fn(
+                move |entry| Ok(generation_range.contains(&entry.generation_number())),
+            )
+        }
+        RevsetFilterPredicate::CommitIdPrefixes(prefixes) => {
+            let prefixes = prefixes.clone();
+            pure_predicate_fn(move |entry| {
+                let commit_id = entry.commit_id();
+                Ok(prefixes.iter().any(|prefix| prefix.matches(&commit_id)))
             })
         }
-        RevsetFilterPredicate::File(paths) => {
-            // TODO: Add support for globs and other formats
-            let matcher: Box<dyn Matcher> = if let Some(paths) = paths {
-                Box::new(PrefixMatcher::new(paths))
-            } else {
-                Box::new(EverythingMatcher)
-            };
+        RevsetFilterPredicate::ChangeIdPrefixes(prefixes) => {
+            let prefixes = prefixes.clone();
             pure_predicate_fn(move |entry| {
-                has_diff_from_parent(&store, index, entry, matcher.as_ref())
+                let change_id = entry.change_id();
+                Ok(prefixes.iter().any(|prefix| prefix.matches(&change_id)))
+            })
+        }
+        RevsetFilterPredicate::Extension(extension) => {
+            let extension = extension.clone();
+            let parallel = parallel && extension.is_expensive();
+            maybe_parallel_predicate_fn(parallel, move |entry| {
+                extension.matches(&store, index, &entry.commit_id())
+            })
+        }
+        RevsetFilterPredicate::File { includes, .. } => {
+            let filter_paths = includes.as_deref().and_then(changed_path_filter_paths);
+            let f = build_commit_predicate_fn(store.clone(), index, composite_index, predicate)?;
+            maybe_parallel_predicate_fn(parallel, move |entry| {
+                if let (Some(paths), Some(filter)) = (&filter_paths, entry.changed_path_filter()) {
+                    // Fast path: no need to diff the trees
+                    if !paths.iter().any(|path| filter.might_contain(path)) {
+                        return Ok(false);
+                    }
+                }
+                f(&commit_cache.get(entry)?)
+            })
+        }
+        // Diffing trees is expensive, so these are tested in parallel.
+        RevsetFilterPredicate::AddsFiles { .. }
+        | RevsetFilterPredicate::DeletesFiles { .. }
+        | RevsetFilterPredicate::FileModeChanged { .. }
+        | RevsetFilterPredicate::ModeOnlyChanged { .. }
+        | RevsetFilterPredicate::TouchesSymlinks { .. }
+        | RevsetFilterPredicate::TouchesSubmodules { .. }
+        | RevsetFilterPredicate::TouchesBinary { .. }
+        | RevsetFilterPredicate::AddsLargeFiles { .. }
+        | RevsetFilterPredicate::Renames { .. }
+        | RevsetFilterPredicate::WhitespaceOnly => {
+            let f = build_commit_predicate_fn(store.clone(), index, composite_index, predicate)?;
+            maybe_parallel_predicate_fn(parallel, move |entry| f(&commit_cache.get(entry)?))
+        }
+        _ => {
+            let f = build_commit_predicate_fn(store.clone(), index, composite_index, predicate)?;
+            pure_predicate_fn(move |entry| f(&commit_cache.get(entry)?))
+        }
+    };
+    Ok(predicate_fn)
+}
+
+/// Builds function which tests the loaded commit against the filter. The commit
+/// doesn't have to be in the index, but its parents do for the predicates that
+/// look them up.
+fn build_commit_predicate_fn<'index>(
+    store: Arc<Store>,
+    index: &'index dyn Index,
+    composite_index: CompositeIndex<'index>,
+    predicate: &RevsetFilterPredicate,
+) -> Result<Box<CommitPredicateFn<'index>>, RevsetEvaluationError> {
+    let predicate_fn = match predicate {
+        RevsetFilterPredicate::ParentCount(parent_count_range) => {
+            let parent_count_range = parent_count_range.clone();
+            commit_predicate_fn(move |commit| {
+                let num_parents = u32::try_from(commit.parent_ids().len()).unwrap_or(u32::MAX);
+                Ok(parent_count_range.contains(&num_parents))
+            })
+        }
+        RevsetFilterPredicate::Generation(generation_range) => {
+            let generation_range = generation_range.clone();
+            commit_predicate_fn(move |commit| {
+                let generation = generation_number(&composite_index, commit)?;
+                Ok(generation_range.contains(&generation))
+            })
+        }
+        RevsetFilterPredicate::CommitIdPrefixes(prefixes) => {
+            let prefixes = prefixes.clone();
+            commit_predicate_fn(move |commit| {
+                Ok(prefixes.iter().any(|prefix| prefix.matches(commit.id())))
+            })
+        }
+        RevsetFilterPredicate::ChangeIdPrefixes(prefixes) => {
+            let prefixes = prefixes.clone();
+            commit_predicate_fn(move |commit| {
+                Ok(prefixes
+                    .iter()
+                    .any(|prefix| prefix.matches(commit.change_id())))
+            })
+        }
+        RevsetFilterPredicate::Description(pattern, case, scope) => {
+            let matcher = StringMatcher::new(pattern, *case)?;
+            let scope = *scope;
+            commit_predicate_fn(move |commit| {
+                Ok(matcher.is_match(description_part(commit.description(), scope)))
+            })
+        }
+        RevsetFilterPredicate::NoDescription => {
+            commit_predicate_fn(move |commit| Ok(commit.description().trim().is_empty()))
+        }
+        RevsetFilterPredicate::Subject(pattern, case) => {
+            let matcher = StringMatcher::new(pattern, *case)?;
+            commit_predicate_fn(move |commit| {
+                let subject = description_part(commit.description(), DescriptionScope::Subject);
+                Ok(matcher.is_match(subject))
+            })
+        }
+        RevsetFilterPredicate::Trailer(key, pattern, case) => {
+            let key = key.clone();
+            let matcher = StringMatcher::new(pattern, *case)?;
+            commit_predicate_fn(move |commit| {
+                Ok(trailer::parse_description_trailers(commit.description())
+                    .iter()
+                    .any(|trailer| trailer.key == key && matcher.is_match(&trailer.value)))
+            })
+        }
+        RevsetFilterPredicate::Coauthor(pattern, case) => {
+            let name_matcher = StringMatcher::new(pattern, *case)?;
+            let email_matcher = StringMatcher::new(pattern, CaseSensitivity::Insensitive)?;
+            commit_predicate_fn(move |commit| {
+                Ok(trailer::parse_description_trailers(commit.description())
+                    .iter()
+                    .filter(|trailer| trailer.key.eq_ignore_ascii_case("Co-authored-by"))
+                    .any(|trailer| {
+                        let (name, email) = trailer::split_name_and_email(&trailer.value);
+                        name_matcher.is_match(name) || email_matcher.is_match(email)
+                    }))
+            })
+        }
+        RevsetFilterPredicate::Author(pattern, case) => {
+            let matcher = StringMatcher::new(pattern, *case)?;
+            commit_predicate_fn(move |commit| {
+                Ok(matcher.is_match(&commit.author().name)
+                    || matcher.is_match(&commit.author().email))
+            })
+        }
+        RevsetFilterPredicate::AuthorName(pattern, case) => {
+            let matcher = StringMatcher::new(pattern, *case)?;
+            commit_predicate_fn(move |commit| Ok(matcher.is_match(&commit.author().name)))
+        }
+        RevsetFilterPredicate::AuthorEmail(pattern, case) => {
+            let matcher = StringMatcher::new(pattern, *case)?;
+            commit_predicate_fn(move |commit| Ok(matcher.is_match(&commit.author().email)))
+        }
+        RevsetFilterPredicate::AuthorEmailDomain(domain) => {
+            let matcher = StringMatcher::new(
+                &StringPattern::Exact(domain.clone()),
+                CaseSensitivity::Insensitive,
+            )?;
+            commit_predicate_fn(move |commit| {
+                Ok(match commit.author().email.rsplit_once('@') {
+                    Some((_, email_domain)) => matcher.is_match(email_domain),
+                    None => false,
+                })
+            })
+        }
+        RevsetFilterPredicate::Committer(pattern, case) => {
+            let matcher = StringMatcher::new(pattern, *case)?;
+            commit_predicate_fn(move |commit| {
+                Ok(matcher.is_match(&commit.committer().name)
+                    || matcher.is_match(&commit.committer().email))
+            })
+        }
+        RevsetFilterPredicate::CommitterName(pattern, case) => {
+            let matcher = StringMatcher::new(pattern, *case)?;
+            commit_predicate_fn(move |commit| Ok(matcher.is_match(&commit.committer().name)))
+        }
+        RevsetFilterPredicate::CommitterEmail(pattern, case) => {
+            let matcher = StringMatcher::new(pattern, *case)?;
+            commit_predicate_fn(move |commit| Ok(matcher.is_match(&commit.committer().email)))
+        }
+        RevsetFilterPredicate::AuthorDate(range) => {
+            let range = range.clone();
+            commit_predicate_fn(move |commit| {
+                Ok(range.contains(&commit.author().timestamp.timestamp))
+            })
+        }
+        RevsetFilterPredicate::CommitterDiffers(min_delay) => {
+            let min_delay_millis =
+                min_delay.map(|secs| i64::try_from(secs.saturating_mul(1000)).unwrap_or(i64::MAX));
+            commit_predicate_fn(move |commit| {
+                let (author, committer) = (commit.author(), commit.committer());
+                let identity_differs =
+                    author.name != committer.name || author.email != committer.email;
+                let delayed = min_delay_millis.map_or(false, |min_delay_millis| {
+                    let (authored, committed) =
+                        (&author.timestamp.timestamp, &committer.timestamp.timestamp);
+                    committed.0.saturating_sub(authored.0) >= min_delay_millis
+                });
+                Ok(identity_differs || delayed)
+            })
+        }
+        RevsetFilterPredicate::MalformedSignature(checks) => {
+            let checks = checks.clone();
+            commit_predicate_fn(move |commit| {
+                Ok(is_malformed_signature(commit.author(), &checks)
+                    || is_malformed_signature(commit.committer(), &checks))
+            })
+        }
+        RevsetFilterPredicate::DescriptionControlChars => {
+            commit_predicate_fn(move |commit| Ok(has_control_chars(commit.description())))
+        }
+        RevsetFilterPredicate::Signed => {
+            commit_predicate_fn(move |commit| Ok(commit.store_commit().secure_sig.is_some()))
+        }
+        RevsetFilterPredicate::CommitterDate(range) => {
+            let range = range.clone();
+            commit_predicate_fn(move |commit| {
+                Ok(range.contains(&commit.committer().timestamp.timestamp))
+            })
+        }
+        RevsetFilterPredicate::File { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                has_diff_from_parent(&store, index, commit, matcher.as_ref())
+            })
+        }
+        RevsetFilterPredicate::AddsFiles { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| adds_files(&store, index, commit, matcher.as_ref()))
+        }
+        RevsetFilterPredicate::DeletesFiles { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                deletes_files(&store, index, commit, matcher.as_ref())
+            })
+        }
+        RevsetFilterPredicate::FileModeChanged { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                changes_file_mode(&store, index, commit, matcher.as_ref())
             })
         }
-        RevsetFilterPredicate::HasConflict => pure_predicate_fn(move |entry| {
-            let commit = store.get_commit(&entry.commit_id()).unwrap();
-            commit.tree().has_conflict()
-        }),
+        RevsetFilterPredicate::ModeOnlyChanged { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                changes_mode_only(&store, index, commit, matcher.as_ref())
+            })
+        }
+        RevsetFilterPredicate::TouchesSymlinks { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                touches_symlinks(&store, index, commit, matcher.as_ref())
+            })
+        }
+        RevsetFilterPredicate::TouchesSubmodules { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                touches_submodules(&store, index, commit, matcher.as_ref())
+            })
+        }
+        RevsetFilterPredicate::TouchesBinary { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                touches_binary(&store, index, commit, matcher.as_ref())
+            })
+        }
+        RevsetFilterPredicate::AddsLargeFiles {
+            min_size,
+            includes,
+            excludes,
+        } => {
+            let min_size = *min_size;
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                adds_large_files(&store, index, commit, matcher.as_ref(), min_size)
+            })
+        }
+        RevsetFilterPredicate::Renames { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| renames(&store, index, commit, matcher.as_ref()))
+        }
+        RevsetFilterPredicate::DiffContains {
+            text,
+            case,
+            includes,
+            excludes,
+        } => {
+            let text_matcher = StringMatcher::new(text, *case)?;
+            let files_matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                diff_contains(&store, index, commit, files_matcher.as_ref(), &text_matcher)
+            })
+        }
+        RevsetFilterPredicate::DiffLines(range) => {
+            let range = range.clone();
+            commit_predicate_fn(move |commit| {
+                has_diff_lines_in_range(&store, index, commit, &range)
+            })
+        }
+        RevsetFilterPredicate::Empty => {
+            commit_predicate_fn(move |commit| is_empty_commit(&store, index, commit))
+        }
+        RevsetFilterPredicate::Extension(extension) => {
+            let extension = extension.clone();
+            commit_predicate_fn(move |commit| {
+                if index.has_id(commit.id()) {
+                    extension.matches(&store, index, commit.id())
+                } else {
+                    extension.matches_commit(&store, index, commit)
+                }
+            })
+        }
+        RevsetFilterPredicate::WhitespaceOnly => {
+            commit_predicate_fn(move |commit| is_whitespace_only_change(&store, index, commit))
+        }
+        RevsetFilterPredicate::HasConflict { includes, excludes } => {
+            let matcher = build_file_matcher(includes.as_deref(), excludes)?;
+            commit_predicate_fn(move |commit| {
+                Ok(commit.tree().has_conflict_matching(matcher.as_ref()))
+            })
+        }
+        RevsetFilterPredicate::ConflictCount(range) => {
+            let range = range.clone();
+            commit_predicate_fn(move |commit| {
+                let tree = commit.tree();
+                if !tree.has_conflict() {
+                    return Ok(range.contains(&0));
+                }
+                // No need to count conflicts beyond the upper bound
+                let count = tree
+                    .entries()
+                    .filter(|(_, value)| matches!(value, TreeValue::Conflict(_)))
+                    .take(range.end)
+                    .count();
+                Ok(range.contains(&count))
+            })
+        }
+    };
+    Ok(predicate_fn)
+}
+
+/// Builds matcher for the `file()` patterns. Invalid glob patterns are
+/// reported here so the error is surfaced before iterating the revset.
+fn build_file_matcher(
+    includes: Option<&[FilePattern]>,
+    excludes: &[FilePattern],
+) -> Result<Box<dyn Matcher + Send + Sync>, RevsetEvaluationError> {
+    let wanted = if let Some(includes) = includes {
+        build_file_patterns_matcher(includes)?
+    } else {
+        Box::new(EverythingMatcher)
+    };
+    if excludes.is_empty() {
+        Ok(wanted)
+    } else {
+        Ok(Box::new(ExcludingMatcher {
+            wanted,
+            unwanted: build_file_patterns_matcher(excludes)?,
+        }))
+    }
+}
+
+fn build_file_patterns_matcher(
+    patterns: &[FilePattern],
+) -> Result<Box<dyn Matcher + Send + Sync>, RevsetEvaluationError> {
+    let mut prefix_paths = vec![];
+    let mut glob_paths = vec![];
+    let mut extensions = vec![];
+    for pattern in patterns {
+        match pattern {
+            FilePattern::PrefixPath(path) => prefix_paths.push(path.clone()),
+            FilePattern::Glob(path) => glob_paths.push(path.clone()),
+            FilePattern::Extension(ext) => extensions.push(ext.clone()),
+        }
+    }
+    let mut matchers: Vec<Box<dyn Matcher + Send + Sync>> = vec![];
+    if !prefix_paths.is_empty() {
+        matchers.push(Box::new(PrefixMatcher::new(&prefix_paths)));
+    }
+    if !glob_paths.is_empty() {
+        let glob_matcher = GlobMatcher::new(&glob_paths)
+            .map_err(|err| RevsetEvaluationError::Other(format!("Invalid file pattern: {err}")))?;
+        matchers.push(Box::new(glob_matcher));
+    }
+    if !extensions.is_empty() {
+        matchers.push(Box::new(ExtensionMatcher::new(&extensions)));
+    }
+    let matcher = matchers
+        .into_iter()
+        .reduce(|input1, input2| Box::new(OwnedUnionMatcher { input1, input2 }))
+        .unwrap_or_else(|| Box::new(NothingMatcher));
+    Ok(matcher)
+}
+
+/// Owns the input matchers of `UnionMatcher` so it can be moved into the
+/// predicate function.
+struct OwnedUnionMatcher {
+    input1: Box<dyn Matcher + Send + Sync>,
+    input2: Box<dyn Matcher + Send + Sync>,
+}
+
+impl Matcher for OwnedUnionMatcher {
+    fn matches(&self, file: &RepoPath) -> bool {
+        UnionMatcher::new(self.input1.as_ref(), self.input2.as_ref()).matches(file)
+    }
+
+    fn visit(&self, dir: &RepoPath) -> Visit {
+        UnionMatcher::new(self.input1.as_ref(), self.input2.as_ref()).visit(dir)
+    }
+}
+
+/// Owns the input matchers of `DifferenceMatcher`. Excluded directories are
+/// visited as `Visit::Nothing`, so the diff doesn't descend into them.
+struct ExcludingMatcher {
+    wanted: Box<dyn Matcher + Send + Sync>,
+    unwanted: Box<dyn Matcher + Send + Sync>,
+}
+
+impl Matcher for ExcludingMatcher {
+    fn matches(&self, file: &RepoPath) -> bool {
+        DifferenceMatcher::new(self.wanted.as_ref(), self.unwanted.as_ref()).matches(file)
+    }
+
+    fn visit(&self, dir: &RepoPath) -> Visit {
+        DifferenceMatcher::new(self.wanted.as_ref(), self.unwanted.as_ref()).visit(dir)
     }
 }
 
+/// Returns the paths to be tested against the changed-path filters, or `None`
+/// if any of the `patterns` can't be tested that way.
+fn changed_path_filter_paths(patterns: &[FilePattern]) -> Option<Vec<RepoPath>> {
+    patterns
+        .iter()
+        .map(|pattern| match pattern {
+            FilePattern::PrefixPath(path) => Some(path.clone()),
+            FilePattern::Glob(_) | FilePattern::Extension(_) => None,
+        })
+        .collect()
+}
+
 fn has_diff_from_parent(
     store: &Arc<Store>,
     index: &dyn Index,
-    entry: &IndexEntry<'_>,
+    commit: &Commit,
     matcher: &dyn Matcher,
-) -> bool {
-    let commit = store.get_commit(&entry.commit_id()).unwrap();
-    let parents = commit.parents();
-    if let [parent] = parents.as_slice() {
+) -> Result<bool, RevsetEvaluationError> {
+    if matcher.visit(&RepoPath::root()) == Visit::AllRecursively {
         // Fast path: no need to load the root tree
-        let unchanged = commit.tree_id() == parent.tree_id();
-        if matcher.visit(&RepoPath::root()) == Visit::AllRecursively {
-            return !unchanged;
-        } else if unchanged {
-            return false;
+        if let [parent] = load_parents(store, commit)?.as_slice() {
+            return Ok(commit.tree_id() != parent.tree_id());
         }
     }
-    let from_tree = rewrite::merge_commit_trees_without_repo(store, index, &parents);
-    let to_tree = commit.tree();
-    from_tree.diff(&to_tree, matcher).next().is_some()
+    any_diff_from_parent(store, index, commit, matcher, |_, _| Ok(true))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::backend::{ChangeId, CommitId, ObjectId};
-    use crate::default_index_store::MutableIndexImpl;
+/// Tests if the commit adds any files matching the `matcher`.
+fn adds_files(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+) -> Result<bool, RevsetEvaluationError> {
+    any_diff_from_parent(store, index, commit, matcher, |_, diff| {
+        Ok(matches!(diff, tree::Diff::Added(_)))
+    })
+}
 
-    #[test]
-    fn test_id_index_resolve_prefix() {
-        fn sorted(resolution: PrefixResolution<Vec<i32>>) -> PrefixResolution<Vec<i32>> {
-            match resolution {
-                PrefixResolution::SingleMatch(mut xs) => {
-                    xs.sort(); // order of values might not be preserved by IdIndex
-                    PrefixResolution::SingleMatch(xs)
-                }
-                _ => resolution,
-            }
-        }
-        let id_index = IdIndex::from_vec(vec![
-            (ChangeId::from_hex("0000"), 0),
-            (ChangeId::from_hex("0099"), 1),
-            (ChangeId::from_hex("0099"), 2),
-            (ChangeId::from_hex("0aaa"), 3),
-            (ChangeId::from_hex("0aab"), 4),
-        ]);
+/// Tests if the commit deletes any files matching the `matcher`.
+fn deletes_files(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+) -> Result<bool, RevsetEvaluationError> {
+    // A file replaced by a directory (or vice versa) is reported as removed.
+    any_diff_from_parent(store, index, commit, matcher, |_, diff| {
+        Ok(matches!(diff, tree::Diff::Removed(_)))
+    })
+}
+
+/// Tests if the commit flips the executable bit of any files matching the
+/// `matcher`. Content changes alone don't count.
+fn changes_file_mode(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+) -> Result<bool, RevsetEvaluationError> {
+    any_diff_from_parent(store, index, commit, matcher, |_, diff| match diff {
+        tree::Diff::Modified(
+            TreeValue::File {
+                executable: executable_before,
+                ..
+            },
+            TreeValue::File {
+                executable: executable_after,
+                ..
+            },
+        ) => Ok(executable_before != executable_after),
+        _ => Ok(false),
+    })
+}
+
+/// Tests if the commit changes the executable bit or the kind of any files
+/// matching the `matcher` while keeping the content id. A file turned into a
+/// symlink to its former content counts as a kind change.
+fn changes_mode_only(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+) -> Result<bool, RevsetEvaluationError> {
+    any_diff_from_parent(store, index, commit, matcher, |_, diff| match diff {
+        tree::Diff::Modified(
+            TreeValue::File {
+                id: id_before,
+                executable: executable_before,
+            },
+            TreeValue::File {
+                id: id_after,
+                executable: executable_after,
+            },
+        ) => Ok(id_before == id_after && executable_before != executable_after),
+        tree::Diff::Modified(
+            TreeValue::File { id: file_id, .. },
+            TreeValue::Symlink(symlink_id),
+        )
+        | tree::Diff::Modified(
+            TreeValue::Symlink(symlink_id),
+            TreeValue::File { id: file_id, .. },
+        ) => Ok(file_id.as_bytes() == symlink_id.as_bytes()),
+        _ => Ok(false),
+    })
+}
+
+/// Tests if the commit adds, removes, or retargets any symlinks matching the
+/// `matcher`.
+fn touches_symlinks(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+) -> Result<bool, RevsetEvaluationError> {
+    any_diff_from_parent(store, index, commit, matcher, |_, diff| {
+        let (before, after) = diff.as_options();
+        Ok(matches!(before, Some(TreeValue::Symlink(_)))
+            || matches!(after, Some(TreeValue::Symlink(_))))
+    })
+}
+
+/// Tests if the commit adds, removes, or updates any Git submodules matching the
+/// `matcher`.
+fn touches_submodules(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+) -> Result<bool, RevsetEvaluationError> {
+    any_diff_from_parent(store, index, commit, matcher, |_, diff| {
+        let (before, after) = diff.as_options();
+        Ok(matches!(before, Some(TreeValue::GitSubmodule(_)))
+            || matches!(after, Some(TreeValue::GitSubmodule(_))))
+    })
+}
+
+/// Tests if the commit adds, modifies, or removes any binary files matching the
+/// `matcher`. A modified file counts if either side is binary.
+fn touches_binary(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+) -> Result<bool, RevsetEvaluationError> {
+    any_diff_from_parent(store, index, commit, matcher, |path, diff| {
+        let (before, after) = diff.as_options();
+        for value in [before, after] {
+            if let Some(TreeValue::File { id, .. }) = value {
+                if is_binary_file(store, path, id)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    })
+}
+
+/// Tests if the text contains control characters other than newline and tab.
+/// U+FFFD is included since backends substitute it for invalid UTF-8.
+fn has_control_chars(text: &str) -> bool {
+    text.chars()
+        .any(|c| (c.is_control() && c != '\n' && c != '\t') || c == char::REPLACEMENT_CHARACTER)
+}
+
+fn is_malformed_signature(signature: &backend::Signature, checks: &[SignatureCheck]) -> bool {
+    let name = signature.name.trim();
+    let email = signature.email.trim();
+    checks.iter().any(|check| match check {
+        SignatureCheck::EmptyName => name.is_empty(),
+        SignatureCheck::EmptyEmail => email.is_empty(),
+        SignatureCheck::InvalidEmail => !email.is_empty() && !email.contains('@'),
+        SignatureCheck::Placeholder => {
+            name == UserSettings::user_name_placeholder()
+                || email == UserSettings::user_email_placeholder()
+        }
+    })
+}
+
+fn adds_large_files(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+    min_size: u64,
+) -> Result<bool, RevsetEvaluationError> {
+    any_diff_from_parent(store, index, commit, matcher, |path, diff| {
+        match diff.as_options() {
+            // The contents are unchanged if only the executable bit changed
+            (Some(TreeValue::File { id: before_id, .. }), Some(TreeValue::File { id, .. }))
+                if before_id == id =>
+            {
+                Ok(false)
+            }
+            (_, Some(TreeValue::File { id, .. })) => {
+                let size = store
+                    .file_size(path, id)
+                    .map_err(RevsetEvaluationError::StoreError)?;
+                Ok(size >= min_size)
+            }
+            _ => Ok(false),
+        }
+    })
+}
+
+/// Number of leading bytes inspected to detect binary files.
+const BINARY_DETECTION_LEN: u64 = 8 * 1024;
+
+/// Tests if the file contains a NUL byte within the first
+/// `BINARY_DETECTION_LEN` bytes, as Git does.
+fn is_binary_file(
+    store: &Store,
+    path: &RepoPath,
+    id: &FileId,
+) -> Result<bool, RevsetEvaluationError> {
+    let content = read_file_content(store, path, id, BINARY_DETECTION_LEN)?;
+    Ok(content.contains(&0))
+}
+
+/// Reads up to `limit` bytes of the file.
+fn read_file_content(
+    store: &Store,
+    path: &RepoPath,
+    id: &FileId,
+    limit: u64,
+) -> Result<Vec<u8>, RevsetEvaluationError> {
+    let mut content = vec![];
+    let file = store
+        .read_file(path, id)
+        .map_err(RevsetEvaluationError::StoreError)?;
+    file.take(limit).read_to_end(&mut content).map_err(|err| {
+        RevsetEvaluationError::StoreError(BackendError::ReadObject {
+            object_type: "file".to_owned(),
+            hash: id.hex(),
+            source: err.into(),
+        })
+    })?;
+    Ok(content)
+}
+
+/// Walks the diff between the merged tree of the commit's parents and the
+/// commit's tree, and tests if any of the changed entries satisfies `f`. The
+/// tree diff is lazy, so the walk stops at the first match.
+fn any_diff_from_parent(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+    mut f: impl FnMut(&RepoPath, &tree::Diff<TreeValue>) -> Result<bool, RevsetEvaluationError>,
+) -> Result<bool, RevsetEvaluationError> {
+    if let Some((from_tree, to_tree)) = trees_to_diff_from_parent(store, index, commit)? {
+        for (path, diff) in from_tree.diff(&to_tree, matcher) {
+            if f(&path, &diff)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the merged tree of the commit's parents and the commit's tree, or
+/// `None` if the commit can be trivially determined to have no changes.
+fn trees_to_diff_from_parent(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+) -> Result<Option<(Tree, Tree)>, RevsetEvaluationError> {
+    let parents = load_parents(store, commit)?;
+    if let [parent] = parents.as_slice() {
+        // Fast path: no need to load the trees
+        if commit.tree_id() == parent.tree_id() {
+            return Ok(None);
+        }
+    }
+    let from_tree = rewrite::merge_commit_trees_without_repo(store, index, &parents);
+    Ok(Some((from_tree, commit.tree())))
+}
+
+/// Tests if the commit moves any files whose source or destination path
+/// matches the `matcher`. A move is detected by pairing a removed file with an
+/// added file of the same content id.
+fn renames(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    matcher: &dyn Matcher,
+) -> Result<bool, RevsetEvaluationError> {
+    let (from_tree, to_tree) = match trees_to_diff_from_parent(store, index, commit)? {
+        Some(trees) => trees,
+        None => return Ok(false),
+    };
+    let mut removed = vec![];
+    let mut added = vec![];
+    // The other end of a move can be anywhere, so the whole tree has to be
+    // walked.
+    for (path, diff) in from_tree.diff(&to_tree, &EverythingMatcher) {
+        match diff {
+            tree::Diff::Removed(TreeValue::File { id, .. }) => removed.push((path, id)),
+            tree::Diff::Added(TreeValue::File { id, .. }) => added.push((path, id)),
+            _ => {}
+        }
+    }
+    if removed.is_empty()
+        || added.is_empty()
+        || !removed
+            .iter()
+            .chain(&added)
+            .any(|(path, _)| matcher.matches(path))
+    {
+        return Ok(false);
+    }
+    let mut sources_by_id: HashMap<&FileId, Vec<&RepoPath>> = HashMap::new();
+    for (path, id) in &removed {
+        sources_by_id.entry(id).or_default().push(path);
+    }
+    Ok(added.iter().any(|(target, id)| {
+        sources_by_id.get(id).map_or(false, |sources| {
+            matcher.matches(target) || sources.iter().any(|source| matcher.matches(source))
+        })
+    }))
+}
+
+/// Tests if the commit's diff from its parents adds or removes lines matching
+/// the `text_matcher`. Binary files are skipped.
+fn diff_contains(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    files_matcher: &dyn Matcher,
+    text_matcher: &StringMatcher,
+) -> Result<bool, RevsetEvaluationError> {
+    any_diff_from_parent(store, index, commit, files_matcher, |path, diff| {
+        let (left_value, right_value) = diff.as_options();
+        let (left, right) = match (
+            read_text_content(store, path, left_value)?,
+            read_text_content(store, path, right_value)?,
+        ) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return Ok(false),
+        };
+        let diff = Diff::for_tokenizer(&[&left, &right], &diff::find_line_ranges);
+        let any_line_matches = |content: &[u8]| {
+            content
+                .split_inclusive(|b| *b == b'\n')
+                .any(|line| text_matcher.is_match(&String::from_utf8_lossy(line)))
+        };
+        let matched = diff.hunks().any(|hunk| match hunk {
+            DiffHunk::Matching(_) => false,
+            DiffHunk::Different(contents) => contents.into_iter().any(any_line_matches),
+        });
+        Ok(matched)
+    })
+}
+
+/// Tests if the number of lines added and removed by the commit is in the
+/// `range`. Binary files don't count.
+fn has_diff_lines_in_range(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+    range: &Range<u64>,
+) -> Result<bool, RevsetEvaluationError> {
+    let parents = load_parents(store, commit)?;
+    if let [parent] = parents.as_slice() {
+        // Fast path: no need to load the trees
+        if commit.tree_id() == parent.tree_id() {
+            return Ok(range.contains(&0));
+        }
+    }
+    let from_tree = rewrite::merge_commit_trees_without_repo(store, index, &parents);
+    let to_tree = commit.tree();
+    let mut count: u64 = 0;
+    for (path, diff) in from_tree.diff(&to_tree, &EverythingMatcher) {
+        let (left_value, right_value) = diff.as_options();
+        let (left, right) = match (
+            read_text_content(store, &path, left_value)?,
+            read_text_content(store, &path, right_value)?,
+        ) {
+            (Some(left), Some(right)) => (left, right),
+            _ => continue,
+        };
+        let diff = Diff::for_tokenizer(&[&left, &right], &diff::find_line_ranges);
+        for hunk in diff.hunks() {
+            if let DiffHunk::Different(contents) = hunk {
+                for content in contents {
+                    count += content.split_inclusive(|b| *b == b'\n').count() as u64;
+                }
+                // Stop early once the upper bound is exceeded.
+                if count >= range.end {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+    Ok(range.contains(&count))
+}
+
+/// Reads file content to be searched or counted by the diff predicates. Returns
+/// `None` if the file is binary. Non-file values are treated as empty.
+fn read_text_content(
+    store: &Store,
+    path: &RepoPath,
+    value: Option<&TreeValue>,
+) -> Result<Option<Vec<u8>>, RevsetEvaluationError> {
+    let content = match value {
+        Some(TreeValue::File { id, .. }) => read_file_content(store, path, id, u64::MAX)?,
+        _ => vec![],
+    };
+    // Files containing NUL bytes are considered binary, as Git does.
+    Ok((!content.contains(&0)).then_some(content))
+}
+
+/// Extracts the `scope` part of the `description`. The subject is the text up to
+/// the first blank line, and the body is the rest. A description without blank
+/// line is all subject.
+fn description_part(description: &str, scope: DescriptionScope) -> &str {
+    let (subject, body) = description.split_once("\n\n").unwrap_or((description, ""));
+    match scope {
+        DescriptionScope::Subject => subject.trim_end_matches('\n'),
+        DescriptionScope::Body => body.trim_start_matches('\n'),
+        DescriptionScope::Full => description,
+    }
+}
+
+/// Tests if the commit changes only whitespace in text files. Empty commits
+/// don't match.
+fn is_whitespace_only_change(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+) -> Result<bool, RevsetEvaluationError> {
+    let (from_tree, to_tree) = match trees_to_diff_from_parent(store, index, commit)? {
+        Some(trees) => trees,
+        None => return Ok(false),
+    };
+    let strip_whitespace = |content: Vec<u8>| -> Vec<u8> {
+        content
+            .into_iter()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect()
+    };
+    let mut changed = false;
+    for (path, diff) in from_tree.diff(&to_tree, &EverythingMatcher) {
+        let (left_value, right_value) = match diff.as_options() {
+            (
+                Some(TreeValue::File {
+                    executable: left_executable,
+                    ..
+                }),
+                Some(TreeValue::File {
+                    executable: right_executable,
+                    ..
+                }),
+            ) if left_executable != right_executable => return Ok(false),
+            (
+                left @ (Some(TreeValue::File { .. }) | None),
+                right @ (Some(TreeValue::File { .. }) | None),
+            ) => (left, right),
+            _ => return Ok(false),
+        };
+        let (left, right) = match (
+            read_text_content(store, &path, left_value)?,
+            read_text_content(store, &path, right_value)?,
+        ) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return Ok(false),
+        };
+        // Adding or removing a non-empty file isn't a whitespace change.
+        if (left_value.is_none() && !right.is_empty())
+            || (right_value.is_none() && !left.is_empty())
+        {
+            return Ok(false);
+        }
+        if strip_whitespace(left) != strip_whitespace(right) {
+            return Ok(false);
+        }
+        changed = true;
+    }
+    Ok(changed)
+}
+
+/// Tests if the commit's tree is identical to the merged tree of its parents.
+/// The root commit is empty if its tree is the empty tree.
+fn is_empty_commit(
+    store: &Arc<Store>,
+    index: &dyn Index,
+    commit: &Commit,
+) -> Result<bool, RevsetEvaluationError> {
+    let parents = load_parents(store, commit)?;
+    let empty = match parents.as_slice() {
+        [] => commit.tree_id() == store.empty_tree_id(),
+        // Fast path: no need to load the trees
+        [parent] => commit.tree_id() == parent.tree_id(),
+        _ => {
+            let from_tree = rewrite::merge_commit_trees_without_repo(store, index, &parents);
+            commit.tree_id() == from_tree.id()
+        }
+    };
+    Ok(empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{self, AtomicUsize};
+
+    use assert_matches::assert_matches;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::backend::{
+        Backend, BackendResult, ChangeId, CommitId, Conflict, ConflictId, FileId, ObjectId,
+        SymlinkId, TreeId,
+    };
+    use crate::changed_path_filter::ChangedPathFilter;
+    use crate::default_index_store::MutableIndexImpl;
+    use crate::local_backend::LocalBackend;
+    use crate::matchers::{VisitDirs, VisitFiles};
+    use crate::revset::RevsetFilterExtension;
+
+    #[test]
+    fn test_id_index_resolve_prefix() {
+        fn sorted(resolution: PrefixResolution<Vec<i32>>) -> PrefixResolution<Vec<i32>> {
+            match resolution {
+                PrefixResolution::SingleMatch(mut xs) => {
+                    xs.sort(); // order of values might not be preserved by IdIndex
+                    PrefixResolution::SingleMatch(xs)
+                }
+                _ => resolution,
+            }
+        }
+        let id_index = IdIndex::from_vec(vec![
+            (ChangeId::from_hex("0000"), 0),
+            (ChangeId::from_hex("0099"), 1),
+            (ChangeId::from_hex("0099"), 2),
+            (ChangeId::from_hex("0aaa"), 3),
+            (ChangeId::from_hex("0aab"), 4),
+        ]);
+        assert_eq!(
+            id_index.resolve_prefix_with(&HexPrefix::new("0").unwrap(), |&v| v),
+            PrefixResolution::AmbiguousMatch,
+        );
+        assert_eq!(
+            id_index.resolve_prefix_with(&HexPrefix::new("00").unwrap(), |&v| v),
+            PrefixResolution::AmbiguousMatch,
+        );
+        assert_eq!(
+            id_index.resolve_prefix_with(&HexPrefix::new("000").unwrap(), |&v| v),
+            PrefixResolution::SingleMatch(vec![0]),
+        );
+        assert_eq!(
+            id_index.resolve_prefix_with(&HexPrefix::new("0001").unwrap(), |&v| v),
+            PrefixResolution::NoMatch,
+        );
+        assert_eq!(
+            sorted(id_index.resolve_prefix_with(&HexPrefix::new("009").unwrap(), |&v| v)),
+            PrefixResolution::SingleMatch(vec![1, 2]),
+        );
+        assert_eq!(
+            id_index.resolve_prefix_with(&HexPrefix::new("0aa").unwrap(), |&v| v),
+            PrefixResolution::AmbiguousMatch,
+        );
+        assert_eq!(
+            id_index.resolve_prefix_with(&HexPrefix::new("0aab").unwrap(), |&v| v),
+            PrefixResolution::SingleMatch(vec![4]),
+        );
+        assert_eq!(
+            id_index.resolve_prefix_with(&HexPrefix::new("f").unwrap(), |&v| v),
+            PrefixResolution::NoMatch,
+        );
+    }
+
+    #[test]
+    fn test_id_index_shortest_unique_prefix_len() {
+        // No crash if empty
+        let id_index = IdIndex::from_vec(vec![] as Vec<(ChangeId, ())>);
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("00")),
+            0
+        );
+
+        let id_index = IdIndex::from_vec(vec![
+            (ChangeId::from_hex("ab"), ()),
+            (ChangeId::from_hex("acd0"), ()),
+            (ChangeId::from_hex("acd0"), ()), // duplicated key is allowed
+        ]);
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("acd0")),
+            2
+        );
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("ac")),
+            3
+        );
+
+        let id_index = IdIndex::from_vec(vec![
+            (ChangeId::from_hex("ab"), ()),
+            (ChangeId::from_hex("acd0"), ()),
+            (ChangeId::from_hex("acf0"), ()),
+            (ChangeId::from_hex("a0"), ()),
+            (ChangeId::from_hex("ba"), ()),
+        ]);
+
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("a0")),
+            2
+        );
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("ba")),
+            1
+        );
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("ab")),
+            2
+        );
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("acd0")),
+            3
+        );
+        // If it were there, the length would be 1.
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("c0")),
+            1
+        );
+
+        // 3-way divergent key surrounded by keys sharing long prefixes
+        let id_index = IdIndex::from_vec(vec![
+            (ChangeId::from_hex("acc0ff"), ()),
+            (ChangeId::from_hex("acd0ff"), ()),
+            (ChangeId::from_hex("acd0ff"), ()),
+            (ChangeId::from_hex("acd0ff"), ()),
+            (ChangeId::from_hex("acd1ff"), ()),
+        ]);
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("acd0ff")),
+            4
+        );
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("acc0ff")),
+            3
+        );
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("acd1ff")),
+            4
+        );
+
+        // All keys following the divergent key are duplicates
+        let id_index = IdIndex::from_vec(vec![
+            (ChangeId::from_hex("ab"), ()),
+            (ChangeId::from_hex("acd0"), ()),
+            (ChangeId::from_hex("acd0"), ()),
+            (ChangeId::from_hex("acd0"), ()),
+        ]);
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("acd0")),
+            2
+        );
+
+        // Only the divergent key exists, so it's unique in any length
+        let id_index = IdIndex::from_vec(vec![
+            (ChangeId::from_hex("acd0"), ()),
+            (ChangeId::from_hex("acd0"), ()),
+            (ChangeId::from_hex("acd0"), ()),
+        ]);
+        assert_eq!(
+            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("acd0")),
+            0
+        );
+    }
+
+    /// Generator of unique 16-byte ChangeId excluding root id
+    fn change_id_generator() -> impl FnMut() -> ChangeId {
+        let mut iter = (1_u128..).map(|n| ChangeId::new(n.to_le_bytes().into()));
+        move || iter.next().unwrap()
+    }
+
+    /// In-memory index with a store backed by a temporary directory.
+    struct TestIndex {
+        temp_dir: TempDir,
+        store: Arc<Store>,
+        index: MutableIndexImpl,
+        new_change_id: Box<dyn FnMut() -> ChangeId>,
+    }
+
+    impl TestIndex {
+        /// Creates an empty index of 3-byte commit ids. The commits added by
+        /// `add_commit()` don't exist in the store, so evaluation fails if it
+        /// tries to load any of them.
+        fn new() -> Self {
+            let temp_dir = testutils::new_temp_dir();
+            let store = Store::new(Box::new(LocalBackend::init(temp_dir.path())));
+            TestIndex {
+                temp_dir,
+                store,
+                index: MutableIndexImpl::full(3, 16),
+                new_change_id: Box::new(change_id_generator()),
+            }
+        }
+
+        /// Creates an index containing the root commit of the store. Commits
+        /// can be written to both by `write_commit()`.
+        fn with_root_commit() -> Self {
+            let mut test_index = TestIndex::new();
+            test_index.index = MutableIndexImpl::full(test_index.store.commit_id_length(), 16);
+            let root_commit_id = test_index.store.root_commit_id().clone();
+            test_index.add_commit(&root_commit_id, &[]);
+            test_index
+        }
+
+        /// Adds a commit to the index only.
+        fn add_commit(&mut self, id: &CommitId, parent_ids: &[&CommitId]) {
+            let parent_ids = parent_ids.iter().copied().cloned().collect_vec();
+            let change_id = (self.new_change_id)();
+            self.index
+                .add_commit_data(id.clone(), change_id, &parent_ids);
+        }
+
+        /// Adds `count` commits forming a linear history to the index only.
+        /// Returns their ids, starting from the root.
+        fn add_linear_history(&mut self, count: u8) -> Vec<CommitId> {
+            let ids = (0..count)
+                .map(|i| CommitId::new(vec![i, i, i]))
+                .collect_vec();
+            for (i, id) in ids.iter().enumerate() {
+                let parent_ids = ids[..i].last().into_iter().collect_vec();
+                self.add_commit(id, &parent_ids);
+            }
+            ids
+        }
+
+        /// Writes a child of the root commit modified by `edit` to the store,
+        /// and adds it to the index.
+        fn write_commit(&mut self, edit: impl FnOnce(&mut backend::Commit)) -> CommitId {
+            let mut commit = backend::make_root_commit(
+                (self.new_change_id)(),
+                self.store.empty_tree_id().clone(),
+            );
+            commit.parents = vec![self.store.root_commit_id().clone()];
+            edit(&mut commit);
+            let commit_id = self
+                .store
+                .write_commit(commit.clone())
+                .unwrap()
+                .id()
+                .clone();
+            self.index.add_commit_data_with_timestamp(
+                commit_id.clone(),
+                commit.change_id,
+                commit.committer.timestamp.timestamp,
+                &commit.parents,
+            );
+            commit_id
+        }
+
+        /// Creates a store which counts the commits read from the backend.
+        /// Unlike `self.store`, nothing is cached yet.
+        fn counting_store(&self) -> (Arc<Store>, Arc<AtomicUsize>) {
+            let read_count = Arc::new(AtomicUsize::new(0));
+            let store = Store::new(Box::new(CommitReadCountingBackend {
+                inner: Box::new(LocalBackend::load(self.temp_dir.path())),
+                read_count: read_count.clone(),
+            }));
+            (store, read_count)
+        }
+
+        fn entry(&self, id: &CommitId) -> IndexEntry<'_> {
+            self.index.as_composite().entry_by_id(id).unwrap()
+        }
+
+        fn evaluate(
+            &self,
+            expression: &ResolvedExpression,
+        ) -> Result<RevsetImpl<'_>, RevsetEvaluationError> {
+            evaluate(
+                expression,
+                &self.store,
+                &self.index,
+                self.index.as_composite(),
+                &RevsetEvaluationOptions::default(),
+            )
+        }
+
+        fn evaluate_ids(&self, expression: &ResolvedExpression) -> Vec<CommitId> {
+            let revset = self.evaluate(expression).unwrap();
+            revset.iter().try_collect().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_change_id_index_is_built_once() {
+        let mut test_index = TestIndex::new();
+        let id_0 = CommitId::from_hex("000000");
+        let id_1 = CommitId::from_hex("111111");
+        test_index.add_commit(&id_0, &[]);
+        test_index.add_commit(&id_1, &[&id_0]);
+        let change_id_1 = test_index.entry(&id_1).change_id();
+
+        let revset = RevsetImpl::new(
+            Box::new(EagerRevset {
+                index_entries: vec![test_index.entry(&id_1), test_index.entry(&id_0)],
+            }),
+            test_index.index.as_composite(),
+        );
+        assert!(revset.pos_by_change.get().is_none());
+
+        let change_id_index = revset.change_id_index();
+        let pos_by_change = revset.pos_by_change.get().unwrap().clone();
+        assert_eq!(
+            change_id_index.resolve_prefix(&HexPrefix::new(&change_id_1.hex()).unwrap()),
+            PrefixResolution::SingleMatch(vec![id_1.clone()])
+        );
+
+        // The lookup table is reused by the subsequent calls
+        let change_id_index = revset.change_id_index();
+        assert!(Arc::ptr_eq(
+            revset.pos_by_change.get().unwrap(),
+            &pos_by_change
+        ));
+        assert_eq!(
+            change_id_index.resolve_prefix(&HexPrefix::new(&change_id_1.hex()).unwrap()),
+            PrefixResolution::SingleMatch(vec![id_1])
+        );
+    }
+
+    #[test]
+    fn test_revset_combinator() {
+        let mut new_change_id = change_id_generator();
+        let mut index = MutableIndexImpl::full(3, 16);
+        let id_0 = CommitId::from_hex("000000");
+        let id_1 = CommitId::from_hex("111111");
+        let id_2 = CommitId::from_hex("222222");
+        let id_3 = CommitId::from_hex("333333");
+        let id_4 = CommitId::from_hex("444444");
+        index.add_commit_data(id_0.clone(), new_change_id(), &[]);
+        index.add_commit_data(id_1.clone(), new_change_id(), &[id_0.clone()]);
+        index.add_commit_data(id_2.clone(), new_change_id(), &[id_1.clone()]);
+        index.add_commit_data(id_3.clone(), new_change_id(), &[id_2.clone()]);
+        index.add_commit_data(id_4.clone(), new_change_id(), &[id_3.clone()]);
+
+        let get_entry = |id: &CommitId| index.as_composite().entry_by_id(id).unwrap();
+        let make_entries = |ids: &[&CommitId]| ids.iter().map(|id| get_entry(id)).collect_vec();
+        let make_set = |ids: &[&CommitId]| -> Box<dyn InternalRevset> {
+            let index_entries = make_entries(ids);
+            Box::new(EagerRevset { index_entries })
+        };
+
+        let set = make_set(&[&id_4, &id_3, &id_2, &id_0]);
+        assert_eq!(set.count().unwrap(), 4);
+        let mut p = set.to_predicate_fn();
+        assert!(p(&get_entry(&id_4)).unwrap());
+        assert!(p(&get_entry(&id_3)).unwrap());
+        assert!(p(&get_entry(&id_2)).unwrap());
+        assert!(!p(&get_entry(&id_1)).unwrap());
+        assert!(p(&get_entry(&id_0)).unwrap());
+        // Uninteresting entries can be skipped
+        let mut p = set.to_predicate_fn();
+        assert!(p(&get_entry(&id_3)).unwrap());
+        assert!(!p(&get_entry(&id_1)).unwrap());
+        assert!(p(&get_entry(&id_0)).unwrap());
+
+        let set = make_set(&[&id_4, &id_3, &id_2, &id_1, &id_0]);
+        assert_eq!(
+            ReverseRevset::new(set.as_ref())
+                .unwrap()
+                .into_iter()
+                .collect_vec(),
+            make_entries(&[&id_0, &id_1, &id_2, &id_3, &id_4])
+        );
+        let set = UnionRevset {
+            sets: vec![make_set(&[&id_4, &id_2]), make_set(&[&id_3, &id_2, &id_0])],
+        };
+        assert_eq!(
+            ReverseRevset::new(&set).unwrap().into_iter().collect_vec(),
+            make_entries(&[&id_0, &id_2, &id_3, &id_4])
+        );
+
+        let set = FilterRevset {
+            candidates: make_set(&[&id_4, &id_2, &id_0]),
+            predicate: pure_predicate_fn(|entry| Ok(entry.commit_id() != id_4)),
+        };
+        assert_eq!(
+            set.iter().map(Result::unwrap).collect_vec(),
+            make_entries(&[&id_2, &id_0])
+        );
+        let mut p = set.to_predicate_fn();
+        assert!(!p(&get_entry(&id_4)).unwrap());
+        assert!(!p(&get_entry(&id_3)).unwrap());
+        assert!(p(&get_entry(&id_2)).unwrap());
+        assert!(!p(&get_entry(&id_1)).unwrap());
+        assert!(p(&get_entry(&id_0)).unwrap());
+
+        // Intersection by FilterRevset
+        let set = FilterRevset {
+            candidates: make_set(&[&id_4, &id_2, &id_0]),
+            predicate: make_set(&[&id_3, &id_2, &id_1]),
+        };
+        assert_eq!(
+            set.iter().map(Result::unwrap).collect_vec(),
+            make_entries(&[&id_2])
+        );
+        let mut p = set.to_predicate_fn();
+        assert!(!p(&get_entry(&id_4)).unwrap());
+        assert!(!p(&get_entry(&id_3)).unwrap());
+        assert!(p(&get_entry(&id_2)).unwrap());
+        assert!(!p(&get_entry(&id_1)).unwrap());
+        assert!(!p(&get_entry(&id_0)).unwrap());
+
+        let set = UnionPredicate {
+            set1: make_set(&[&id_4, &id_2]),
+            set2: pure_predicate_fn(|entry| Ok(entry.commit_id() == id_1)),
+        };
+        let mut p = set.to_predicate_fn();
+        assert!(p(&get_entry(&id_4)).unwrap());
+        assert!(!p(&get_entry(&id_3)).unwrap());
+        assert!(p(&get_entry(&id_2)).unwrap());
+        assert!(p(&get_entry(&id_1)).unwrap());
+        assert!(!p(&get_entry(&id_0)).unwrap());
+
+        let set = IntersectionPredicate {
+            set1: pure_predicate_fn(|entry| Ok(entry.commit_id() != id_4)),
+            set2: make_set(&[&id_4, &id_3, &id_1]),
+        };
+        let mut p = set.to_predicate_fn();
+        assert!(!p(&get_entry(&id_4)).unwrap());
+        assert!(p(&get_entry(&id_3)).unwrap());
+        assert!(!p(&get_entry(&id_2)).unwrap());
+        assert!(p(&get_entry(&id_1)).unwrap());
+        assert!(!p(&get_entry(&id_0)).unwrap());
+        // The second predicate isn't evaluated if the first one doesn't match
+        let set = IntersectionPredicate {
+            set1: make_set(&[&id_3]),
+            set2: pure_predicate_fn(|entry| {
+                assert_ne!(entry.commit_id(), id_4, "must not be evaluated");
+                Ok(true)
+            }),
+        };
+        let mut p = set.to_predicate_fn();
+        assert!(!p(&get_entry(&id_4)).unwrap());
+        assert!(p(&get_entry(&id_3)).unwrap());
+
+        let set = UnionRevset {
+            sets: vec![make_set(&[&id_4, &id_2]), make_set(&[&id_3, &id_2, &id_1])],
+        };
+        assert_eq!(
+            set.iter().map(Result::unwrap).collect_vec(),
+            make_entries(&[&id_4, &id_3, &id_2, &id_1])
+        );
+        let mut p = set.to_predicate_fn();
+        assert!(p(&get_entry(&id_4)).unwrap());
+        assert!(p(&get_entry(&id_3)).unwrap());
+        assert!(p(&get_entry(&id_2)).unwrap());
+        assert!(p(&get_entry(&id_1)).unwrap());
+        assert!(!p(&get_entry(&id_0)).unwrap());
+        assert!(!set.is_empty());
+        let set = UnionRevset {
+            sets: vec![make_set(&[]), make_set(&[&id_1])],
+        };
+        assert!(!set.is_empty());
+        let set = UnionRevset {
+            sets: vec![make_set(&[]), make_set(&[])],
+        };
+        assert!(set.is_empty());
+        assert_eq!(set.count().unwrap(), 0);
+        let set = UnionRevset {
+            sets: vec![make_set(&[&id_4, &id_2]), make_set(&[&id_3, &id_2, &id_1])],
+        };
+        assert_eq!(set.count().unwrap(), 4);
+        let set = UnionRevset {
+            sets: vec![
+                make_set(&[&id_4]),
+                Box::new(FilterRevset {
+                    candidates: make_set(&[&id_3]),
+                    predicate: pure_predicate_fn(|_| panic!("must not be evaluated")),
+                }),
+            ],
+        };
+        assert!(!set.is_empty());
+
+        let set = SymmetricDifferenceRevset {
+            set1: make_set(&[&id_4, &id_2, &id_0]),
+            set2: make_set(&[&id_3, &id_2, &id_1]),
+        };
+        assert_eq!(
+            set.iter().map(Result::unwrap).collect_vec(),
+            make_entries(&[&id_4, &id_3, &id_1, &id_0])
+        );
+        let mut p = set.to_predicate_fn();
+        assert!(p(&get_entry(&id_4)).unwrap());
+        assert!(p(&get_entry(&id_3)).unwrap());
+        assert!(!p(&get_entry(&id_2)).unwrap());
+        assert!(p(&get_entry(&id_1)).unwrap());
+        assert!(p(&get_entry(&id_0)).unwrap());
+
+        let set = IntersectionRevset {
+            sets: vec![
+                make_set(&[&id_4, &id_2, &id_0]),
+                make_set(&[&id_3, &id_2, &id_1]),
+            ],
+        };
+        assert_eq!(
+            set.iter().map(Result::unwrap).collect_vec(),
+            make_entries(&[&id_2])
+        );
+        let mut p = set.to_predicate_fn();
+        assert!(!p(&get_entry(&id_4)).unwrap());
+        assert!(!p(&get_entry(&id_3)).unwrap());
+        assert!(p(&get_entry(&id_2)).unwrap());
+        assert!(!p(&get_entry(&id_1)).unwrap());
+        assert!(!p(&get_entry(&id_0)).unwrap());
+
+        let set = DifferenceRevset {
+            set1: make_set(&[&id_4, &id_2, &id_0]),
+            set2: make_set(&[&id_3, &id_2, &id_1]),
+        };
+        assert_eq!(
+            set.iter().map(Result::unwrap).collect_vec(),
+            make_entries(&[&id_4, &id_0])
+        );
+        let mut p = set.to_predicate_fn();
+        assert!(p(&get_entry(&id_4)).unwrap());
+        assert!(!p(&get_entry(&id_3)).unwrap());
+        assert!(!p(&get_entry(&id_2)).unwrap());
+        assert!(!p(&get_entry(&id_1)).unwrap());
+        assert!(p(&get_entry(&id_0)).unwrap());
+    }
+
+    #[test]
+    fn test_filter_revset_parallel() {
+        let mut test_index = TestIndex::new();
+        // Long history spanning multiple chunks, in which every third commit
+        // is a merge
+        let ids = (0..PARALLEL_FILTER_MAX_CHUNK_SIZE * 3)
+            .map(|n| CommitId::from_hex(&format!("{n:06x}")))
+            .collect_vec();
+        test_index.add_commit(&ids[0], &[]);
+        for i in 1..ids.len() {
+            let parent_ids = if i % 3 == 0 {
+                vec![&ids[i - 1], &ids[i - 2]]
+            } else {
+                vec![&ids[i - 1]]
+            };
+            test_index.add_commit(&ids[i], &parent_ids);
+        }
+
+        let get_entry = |id: &CommitId| test_index.entry(id);
+        let candidates = || -> Box<dyn InternalRevset> {
+            let index_entries = ids.iter().rev().map(get_entry).collect();
+            Box::new(EagerRevset { index_entries })
+        };
+        let is_merge = |entry: &IndexEntry| Ok(entry.num_parents() > 1);
+
+        let sequential_set = FilterRevset {
+            candidates: candidates(),
+            predicate: pure_predicate_fn(is_merge),
+        };
+        let parallel_set = FilterRevset {
+            candidates: candidates(),
+            predicate: parallel_predicate_fn(is_merge),
+        };
+        assert!(sequential_set.predicate.as_sync_predicate_fn().is_none());
+        assert!(parallel_set.predicate.as_sync_predicate_fn().is_some());
+        let expected_entries = ids
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(i, _)| i % 3 == 0 && *i > 0)
+            .map(|(_, id)| get_entry(id))
+            .collect_vec();
+        assert_eq!(
+            sequential_set.iter().map(Result::unwrap).collect_vec(),
+            expected_entries
+        );
+        // Entries are yielded in order of descending index position
+        assert_eq!(
+            parallel_set.iter().map(Result::unwrap).collect_vec(),
+            expected_entries
+        );
+        // Parallel predicate can also be evaluated sequentially
+        let mut p = parallel_set.to_predicate_fn();
+        assert!(p(&get_entry(&ids[3])).unwrap());
+        assert!(!p(&get_entry(&ids[2])).unwrap());
+
+        // The first chunk is small so the first entry is yielded early
+        let tested_count = AtomicUsize::new(0);
+        let counting_set = FilterRevset {
+            candidates: candidates(),
+            predicate: parallel_predicate_fn(|entry: &IndexEntry| {
+                tested_count.fetch_add(1, atomic::Ordering::Relaxed);
+                is_merge(entry)
+            }),
+        };
+        assert_eq!(
+            counting_set.iter().next().unwrap().unwrap(),
+            expected_entries[0]
+        );
+        assert_eq!(
+            tested_count.load(atomic::Ordering::Relaxed),
+            PARALLEL_FILTER_MIN_CHUNK_SIZE
+        );
+
+        // Errors from the worker threads are yielded in place
+        let failing_position = get_entry(&ids[PARALLEL_FILTER_MAX_CHUNK_SIZE]).position();
+        let failing_set = FilterRevset {
+            candidates: candidates(),
+            predicate: parallel_predicate_fn(|entry: &IndexEntry| {
+                if entry.position() == failing_position {
+                    Err(RevsetEvaluationError::Other("failed".to_owned()))
+                } else {
+                    is_merge(entry)
+                }
+            }),
+        };
+        let results = failing_set.iter().collect_vec();
+        assert_eq!(results.len(), expected_entries.len() + 1);
+        assert_eq!(results.iter().filter(|result| result.is_err()).count(), 1);
+        let failed_index = results.iter().position(Result::is_err).unwrap();
+        assert!(results[..failed_index]
+            .iter()
+            .all(|entry| entry.as_ref().unwrap().position() > failing_position));
+        assert!(results[failed_index + 1..]
+            .iter()
+            .all(|entry| entry.as_ref().unwrap().position() < failing_position));
+
+        // Few candidates are tested on the current thread
+        let small_ids = &ids[..PARALLEL_FILTER_MIN_CHUNK_SIZE - 1];
+        let small_set = FilterRevset {
+            candidates: Box::new(EagerRevset {
+                index_entries: small_ids.iter().rev().map(get_entry).collect(),
+            }),
+            predicate: parallel_predicate_fn(|entry: &IndexEntry| {
+                assert!(rayon::current_thread_index().is_none());
+                is_merge(entry)
+            }),
+        };
+        assert_eq!(
+            small_set.iter().map(Result::unwrap).collect_vec(),
+            [&small_ids[12], &small_ids[9], &small_ids[6], &small_ids[3]]
+                .map(get_entry)
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_build_file_matcher_excludes() {
+        let path = RepoPath::from_internal_string;
+        let excludes = [
+            FilePattern::PrefixPath(path("vendor")),
+            FilePattern::PrefixPath(path("third_party")),
+        ];
+        let matcher = build_file_matcher(None, &excludes).unwrap();
+        assert!(matcher.matches(&path("src/main.rs")));
+        assert!(!matcher.matches(&path("vendor/foo.rs")));
+        assert!(!matcher.matches(&path("third_party")));
+        // Excluded subtrees are skipped entirely
+        assert_eq!(
+            matcher.visit(&RepoPath::root()),
+            Visit::Specific {
+                dirs: VisitDirs::All,
+                files: VisitFiles::All,
+            }
+        );
+        assert_eq!(matcher.visit(&path("vendor")), Visit::Nothing);
+        assert_eq!(matcher.visit(&path("vendor/sub")), Visit::Nothing);
+        assert_eq!(matcher.visit(&path("src")), Visit::AllRecursively);
+
+        let includes = [FilePattern::PrefixPath(path("src"))];
+        let excludes = [FilePattern::Glob(path("src/**/*_test.rs"))];
+        let matcher = build_file_matcher(Some(&includes), &excludes).unwrap();
+        assert!(matcher.matches(&path("src/main.rs")));
+        assert!(!matcher.matches(&path("src/foo/bar_test.rs")));
+        assert!(!matcher.matches(&path("docs/index.md")));
+        assert_eq!(matcher.visit(&path("docs")), Visit::Nothing);
+    }
+
+    #[test]
+    fn test_string_matcher_ignoring_case() {
+        let new_matcher = |pattern: StringPattern| {
+            StringMatcher::new(&pattern, CaseSensitivity::Insensitive).unwrap()
+        };
+        let substring = |needle: &str| new_matcher(StringPattern::Substring(needle.to_owned()));
+        let regex = |needle: &str| new_matcher(StringPattern::Regex(needle.to_owned()));
+
+        let exact = |needle: &str| new_matcher(StringPattern::Exact(needle.to_owned()));
+
+        assert!(exact("").is_match(""));
+        assert!(exact("").is_match("\n"));
+        assert!(!exact("").is_match("foo"));
+        assert!(exact("wip").is_match("WIP\n"));
+        assert!(exact("wip\n").is_match("Wip"));
+        assert!(!exact("wip").is_match("WIP: foo"));
+        assert!(!exact("wip").is_match("WI"));
+
+        assert!(substring("").is_match(""));
+        assert!(substring("").is_match("foo"));
+        assert!(substring("wip").is_match("WIP: foo"));
+        assert!(substring("WIP").is_match("foo (wip)"));
+        assert!(substring("Wip").is_match("wIP"));
+        assert!(!substring("wip").is_match("WI"));
+        assert!(!substring("wip").is_match("w i p"));
+
+        // Latin-1 characters
+        assert!(substring("café").is_match("CAFÉ"));
+        assert!(substring("CAFÉ").is_match("un café"));
+        assert!(substring("Ñandú").is_match("ñANDÚ"));
+        assert!(substring("ÄÖÜ").is_match("xäöüx"));
+        assert!(!substring("café").is_match("CAFE"));
+        assert!(exact("Ñandú").is_match("ÑANDÚ\n"));
+        assert!(regex("^caf[é]$").is_match("CAFÉ"));
+        assert!(regex("ÄÖÜ").is_match("äöü"));
+    }
+
+    #[test]
+    fn test_string_matcher_word() {
+        let word = |needle: &str, case| {
+            StringMatcher::new(&StringPattern::Word(needle.to_owned()), case).unwrap()
+        };
+        let sensitive = |needle: &str| word(needle, CaseSensitivity::Sensitive);
+        let insensitive = |needle: &str| word(needle, CaseSensitivity::Insensitive);
+
+        assert!(sensitive("fix").is_match("fix"));
+        assert!(sensitive("fix").is_match("fix: foo"));
+        assert!(sensitive("fix").is_match("foo (fix)"));
+        assert!(sensitive("fix").is_match("prefix, fix"));
+        assert!(sensitive("fix bug").is_match("fix bug."));
+        assert!(!sensitive("fix").is_match("prefix"));
+        assert!(!sensitive("fix").is_match("fixup"));
+        assert!(!sensitive("fix").is_match("fix_up"));
+        assert!(!sensitive("fix").is_match("2fix"));
+        assert!(!sensitive("fix").is_match("FIX"));
+        assert!(!sensitive("fix").is_match(""));
+
+        assert!(insensitive("fix").is_match("FIX: foo"));
+        assert!(insensitive("FIX").is_match("a Fix"));
+        assert!(!insensitive("fix").is_match("PREFIX"));
+
+        // Word boundaries are determined by chars, not bytes
+        assert!(sensitive("café").is_match("un café."));
+        assert!(!sensitive("caf").is_match("café"));
+        assert!(!sensitive("fé").is_match("café"));
+        assert!(!sensitive("é").is_match("éé"));
+        assert!(sensitive("é").is_match("é é"));
+        assert!(insensitive("café").is_match("UN CAFÉ"));
+        assert!(!insensitive("caf").is_match("CAFÉ"));
+        assert!(insensitive("ñandú").is_match("¡ÑANDÚ!"));
+        assert!(!insensitive("ñand").is_match("ÑANDÚ"));
+    }
+
+    #[test]
+    fn test_evaluate_file_skips_commits_by_changed_path_filter() {
+        // None of these commits exist in the store, so evaluation would fail if
+        // the predicate tried to diff any of them.
+        let mut test_index = TestIndex::new();
+        let id_0 = CommitId::from_hex("000000");
+        let id_1 = CommitId::from_hex("111111");
+        let id_2 = CommitId::from_hex("222222");
+        test_index.add_commit(&id_0, &[]);
+        test_index.add_commit(&id_1, &[&id_0]);
+        test_index.add_commit(&id_2, &[&id_1]);
+        let path = RepoPath::from_internal_string;
+        for id in [&id_0, &id_1, &id_2] {
+            let filter = ChangedPathFilter::from_paths(&[path("dir/file")]);
+            test_index.index.set_changed_path_filter(id, filter);
+        }
+        assert!(test_index
+            .entry(&id_1)
+            .changed_path_filter()
+            .unwrap()
+            .might_contain(&path("dir")));
+
+        let file = |pattern: FilePattern| {
+            let expression = ResolvedExpression::FilterWithin {
+                candidates: Box::new(ResolvedExpression::Commits(vec![
+                    id_2.clone(),
+                    id_1.clone(),
+                ])),
+                predicate: ResolvedPredicateExpression::Filter(RevsetFilterPredicate::File {
+                    includes: Some(vec![pattern]),
+                    excludes: vec![],
+                }),
+            };
+            test_index.evaluate_ids(&expression)
+        };
+        assert_eq!(file(FilePattern::PrefixPath(path("other"))), vec![]);
+        assert_eq!(file(FilePattern::PrefixPath(path("dir/other"))), vec![]);
+    }
+
+    #[test]
+    fn test_build_predicate_fn_parallel_is_opt_in() {
+        let test_index = TestIndex::new();
+        let build = |parallel, predicate: &RevsetFilterPredicate| {
+            build_predicate_fn(
+                test_index.store.clone(),
+                &test_index.index,
+                test_index.index.as_composite(),
+                Arc::new(CommitCache::new(test_index.store.clone())),
+                parallel,
+                predicate,
+            )
+            .unwrap()
+        };
+        let file = RevsetFilterPredicate::File {
+            includes: None,
+            excludes: vec![],
+        };
+        assert!(build(false, &file).as_sync_predicate_fn().is_none());
+        assert!(build(true, &file).as_sync_predicate_fn().is_some());
+
+        // Index-only predicates are cheap enough to be tested sequentially
+        let parent_count = RevsetFilterPredicate::ParentCount(2..u32::MAX);
+        assert!(build(true, &parent_count).as_sync_predicate_fn().is_none());
+
+        // Extensions have to opt in
+        #[derive(Debug)]
+        struct TestExtension {
+            expensive: bool,
+        }
+        impl RevsetFilterExtension for TestExtension {
+            fn matches(
+                &self,
+                _store: &Arc<Store>,
+                _index: &dyn Index,
+                _commit_id: &CommitId,
+            ) -> Result<bool, RevsetEvaluationError> {
+                Ok(true)
+            }
+
+            fn is_expensive(&self) -> bool {
+                self.expensive
+            }
+        }
+        let extension =
+            |expensive| RevsetFilterPredicate::Extension(Arc::new(TestExtension { expensive }));
+        assert!(build(true, &extension(false))
+            .as_sync_predicate_fn()
+            .is_none());
+        assert!(build(true, &extension(true))
+            .as_sync_predicate_fn()
+            .is_some());
+    }
+
+    #[test]
+    fn test_flatten_union_and_intersection() {
+        let operands = (0..4)
+            .map(|i| ResolvedExpression::Commits(vec![CommitId::new(vec![i])]))
+            .collect_vec();
+        type Combinator =
+            fn(Box<ResolvedExpression>, Box<ResolvedExpression>) -> ResolvedExpression;
+        let left_leaning = |combine: Combinator| {
+            operands
+                .iter()
+                .cloned()
+                .reduce(|acc, expression| combine(Box::new(acc), Box::new(expression)))
+                .unwrap()
+        };
+        let right_leaning = |combine: Combinator| {
+            operands
+                .iter()
+                .cloned()
+                .rev()
+                .reduce(|acc, expression| combine(Box::new(expression), Box::new(acc)))
+                .unwrap()
+        };
+        let all_operands = operands.iter().collect_vec();
         assert_eq!(
-            id_index.resolve_prefix_with(&HexPrefix::new("0").unwrap(), |&v| v),
-            PrefixResolution::AmbiguousMatch,
+            flatten_union(&left_leaning(ResolvedExpression::Union)),
+            all_operands
         );
         assert_eq!(
-            id_index.resolve_prefix_with(&HexPrefix::new("00").unwrap(), |&v| v),
-            PrefixResolution::AmbiguousMatch,
+            flatten_union(&right_leaning(ResolvedExpression::Union)),
+            all_operands
         );
         assert_eq!(
-            id_index.resolve_prefix_with(&HexPrefix::new("000").unwrap(), |&v| v),
-            PrefixResolution::SingleMatch(vec![0]),
+            flatten_intersection(&left_leaning(ResolvedExpression::Intersection)),
+            all_operands
         );
         assert_eq!(
-            id_index.resolve_prefix_with(&HexPrefix::new("0001").unwrap(), |&v| v),
-            PrefixResolution::NoMatch,
+            flatten_intersection(&right_leaning(ResolvedExpression::Intersection)),
+            all_operands
         );
-        assert_eq!(
-            sorted(id_index.resolve_prefix_with(&HexPrefix::new("009").unwrap(), |&v| v)),
-            PrefixResolution::SingleMatch(vec![1, 2]),
+
+        // The other kind of node isn't flattened
+        let intersection = left_leaning(ResolvedExpression::Intersection);
+        let expression = ResolvedExpression::Union(
+            Box::new(intersection.clone()),
+            Box::new(operands[0].clone()),
         );
         assert_eq!(
-            id_index.resolve_prefix_with(&HexPrefix::new("0aa").unwrap(), |&v| v),
-            PrefixResolution::AmbiguousMatch,
+            flatten_union(&expression),
+            vec![&intersection, &operands[0]]
         );
+        assert_eq!(flatten_intersection(&expression), vec![&expression]);
+    }
+
+    #[test]
+    fn test_revset_iterator_seek() {
+        let mut test_index = TestIndex::new();
+        let ids = test_index.add_linear_history(40);
+        let make_set = |step: usize, offset: usize| -> Box<dyn InternalRevset> {
+            let index_entries = (offset..ids.len())
+                .step_by(step)
+                .rev()
+                .map(|i| test_index.entry(&ids[i]))
+                .collect();
+            Box::new(EagerRevset { index_entries })
+        };
+        let sets: Vec<(&str, Box<dyn InternalRevset>)> = vec![
+            ("eager", make_set(3, 0)),
+            ("empty", Box::new(EagerRevset::empty())),
+            (
+                "union",
+                Box::new(UnionRevset {
+                    sets: vec![make_set(5, 0), make_set(7, 1)],
+                }),
+            ),
+            (
+                "intersection",
+                Box::new(IntersectionRevset {
+                    sets: vec![make_set(2, 0), make_set(3, 0)],
+                }),
+            ),
+            (
+                "difference",
+                Box::new(DifferenceRevset {
+                    set1: make_set(2, 0),
+                    set2: make_set(3, 0),
+                }),
+            ),
+        ];
+
+        // Seeking after taking some entries must give the same result as
+        // visiting the entries one by one
+        fn positions<'index>(
+            iter: impl Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>>,
+        ) -> Vec<IndexPosition> {
+            iter.map(|entry| entry.unwrap().position()).collect()
+        }
+        let targets = ids
+            .iter()
+            .map(|id| test_index.entry(id).position())
+            .chain([IndexPosition::MAX])
+            .collect_vec();
+        for (name, set) in &sets {
+            let len = set.count().unwrap();
+            for num_taken in 0..=len.min(3) {
+                for &target in &targets {
+                    let mut iter = set.iter();
+                    let mut expected_iter = SequentialRevsetIterator(set.iter());
+                    iter.by_ref().take(num_taken).for_each(drop);
+                    expected_iter.by_ref().take(num_taken).for_each(drop);
+                    assert_eq!(
+                        iter.seek(target).map(|entry| entry.unwrap().position()),
+                        expected_iter
+                            .seek(target)
+                            .map(|entry| entry.unwrap().position()),
+                        "{name}: seek to {target:?} after {num_taken} entries"
+                    );
+                    assert_eq!(
+                        positions(iter),
+                        positions(expected_iter),
+                        "{name}: entries after seeking to {target:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_revset_intersect_commit_ids() {
+        let mut test_index = TestIndex::new();
+        let ids = test_index.add_linear_history(4);
+        let id_missing = CommitId::from_hex("999999");
+
+        let ancestors_of_2 = || {
+            let expression = ResolvedExpression::Ancestors {
+                heads: Box::new(ResolvedExpression::Commits(vec![ids[2].clone()])),
+                generation: GENERATION_RANGE_FULL,
+                generation_mode: GenerationMode::Any,
+            };
+            test_index.evaluate(&expression).unwrap()
+        };
+
+        let revset = ancestors_of_2().intersect_commit_ids(&[
+            ids[3].clone(),
+            ids[0].clone(),
+            id_missing.clone(),
+            ids[2].clone(),
+            ids[0].clone(),
+        ]);
         assert_eq!(
-            id_index.resolve_prefix_with(&HexPrefix::new("0aab").unwrap(), |&v| v),
-            PrefixResolution::SingleMatch(vec![4]),
+            revset.iter().map(Result::unwrap).collect_vec(),
+            vec![ids[2].clone(), ids[0].clone()]
         );
+        assert_eq!(revset.count().unwrap(), 2);
+
+        let revset = ancestors_of_2().intersect_commit_ids(&[id_missing]);
+        assert!(revset.is_empty());
+        let revset = ancestors_of_2().intersect_commit_ids(&[]);
+        assert!(revset.is_empty());
+    }
+
+    #[test]
+    fn test_evaluate_parents_same_as_ancestors_walk() {
+        let mut test_index = TestIndex::new();
+        // 4
+        // |\
+        // 2 3
+        // |/
+        // 1
+        // |
+        // 0
+        let id_0 = CommitId::from_hex("000000");
+        let id_1 = CommitId::from_hex("111111");
+        let id_2 = CommitId::from_hex("222222");
+        let id_3 = CommitId::from_hex("333333");
+        let id_4 = CommitId::from_hex("444444");
+        test_index.add_commit(&id_0, &[]);
+        test_index.add_commit(&id_1, &[&id_0]);
+        test_index.add_commit(&id_2, &[&id_1]);
+        test_index.add_commit(&id_3, &[&id_1]);
+        test_index.add_commit(&id_4, &[&id_2, &id_3]);
+
+        // The parents are looked up without walking the ancestors, which
+        // should give the same result
+        let parents = |ids: &[&CommitId]| {
+            let candidates = ResolvedExpression::Commits(ids.iter().copied().cloned().collect());
+            let expression = ResolvedExpression::Parents(Box::new(candidates.clone()));
+            let commit_ids = test_index.evaluate_ids(&expression);
+            let expression = ResolvedExpression::Ancestors {
+                heads: Box::new(candidates),
+                generation: 1..2,
+                generation_mode: GenerationMode::Any,
+            };
+            assert_eq!(test_index.evaluate_ids(&expression), commit_ids);
+            commit_ids
+        };
+
+        assert_eq!(parents(&[]), vec![]);
+        assert_eq!(parents(&[&id_0]), vec![]);
+        assert_eq!(parents(&[&id_4]), vec![id_3.clone(), id_2.clone()]);
+        assert_eq!(parents(&[&id_2, &id_3]), vec![id_1.clone()]);
         assert_eq!(
-            id_index.resolve_prefix_with(&HexPrefix::new("f").unwrap(), |&v| v),
-            PrefixResolution::NoMatch,
+            parents(&[&id_4, &id_3, &id_1]),
+            vec![id_3.clone(), id_2.clone(), id_1.clone(), id_0.clone()]
         );
     }
 
     #[test]
-    fn test_id_index_shortest_unique_prefix_len() {
-        // No crash if empty
-        let id_index = IdIndex::from_vec(vec![] as Vec<(ChangeId, ())>);
+    fn test_evaluate_dag_range_bounds() {
+        let mut test_index = TestIndex::new();
+        // 5
+        // |\
+        // 3 4
+        // | |
+        // 1 2
+        // |/
+        // 0
+        let id_0 = CommitId::from_hex("000000");
+        let id_1 = CommitId::from_hex("111111");
+        let id_2 = CommitId::from_hex("222222");
+        let id_3 = CommitId::from_hex("333333");
+        let id_4 = CommitId::from_hex("444444");
+        let id_5 = CommitId::from_hex("555555");
+        test_index.add_commit(&id_0, &[]);
+        test_index.add_commit(&id_1, &[&id_0]);
+        test_index.add_commit(&id_2, &[&id_0]);
+        test_index.add_commit(&id_3, &[&id_1]);
+        test_index.add_commit(&id_4, &[&id_2]);
+        test_index.add_commit(&id_5, &[&id_3, &id_4]);
+
+        let dag_range = |roots: &[&CommitId], heads: &[&CommitId]| {
+            let expression = ResolvedExpression::DagRange {
+                roots: Box::new(ResolvedExpression::Commits(
+                    roots.iter().copied().cloned().collect(),
+                )),
+                heads: Box::new(ResolvedExpression::Commits(
+                    heads.iter().copied().cloned().collect(),
+                )),
+                generation_from_roots: GENERATION_RANGE_FULL,
+            };
+            test_index.evaluate_ids(&expression)
+        };
+
+        assert_eq!(dag_range(&[], &[&id_5]), vec![]);
+        assert_eq!(dag_range(&[&id_0], &[]), vec![]);
+        // Root above the head
+        assert_eq!(dag_range(&[&id_3], &[&id_1]), vec![]);
+        assert_eq!(dag_range(&[&id_1], &[&id_1]), vec![id_1.clone()]);
         assert_eq!(
-            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("00")),
-            0
+            dag_range(&[&id_1, &id_2], &[&id_5]),
+            vec![
+                id_5.clone(),
+                id_4.clone(),
+                id_3.clone(),
+                id_2.clone(),
+                id_1.clone()
+            ]
         );
-
-        let id_index = IdIndex::from_vec(vec![
-            (ChangeId::from_hex("ab"), ()),
-            (ChangeId::from_hex("acd0"), ()),
-            (ChangeId::from_hex("acd0"), ()), // duplicated key is allowed
-        ]);
+        // Only one branch of the merge is reachable from the root, and the
+        // other head isn't a descendant of it
         assert_eq!(
-            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("acd0")),
-            2
+            dag_range(&[&id_1], &[&id_5, &id_4]),
+            vec![id_5.clone(), id_3.clone(), id_1.clone()]
         );
         assert_eq!(
-            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("ac")),
-            3
+            dag_range(&[&id_0, &id_4], &[&id_3, &id_4]),
+            vec![
+                id_4.clone(),
+                id_3.clone(),
+                id_2.clone(),
+                id_1.clone(),
+                id_0.clone()
+            ]
         );
+    }
 
-        let id_index = IdIndex::from_vec(vec![
-            (ChangeId::from_hex("ab"), ()),
-            (ChangeId::from_hex("acd0"), ()),
-            (ChangeId::from_hex("acf0"), ()),
-            (ChangeId::from_hex("a0"), ()),
-            (ChangeId::from_hex("ba"), ()),
-        ]);
+    #[test]
+    fn test_evaluate_repeated_subexpressions() {
+        let mut test_index = TestIndex::new();
+        let ids = test_index.add_linear_history(5);
+        let new_context = |expression: &ResolvedExpression| EvaluationContext {
+            repeated_expressions: repeated_subexpressions(expression),
+            ..EvaluationContext::new(
+                test_index.store.clone(),
+                &test_index.index,
+                test_index.index.as_composite(),
+            )
+        };
+        let commits = |indices: &[usize]| {
+            ResolvedExpression::Commits(indices.iter().map(|&i| ids[i].clone()).collect())
+        };
+        // (x & 2) | (x ~ 2)
+        let split_by_2 = |x: &ResolvedExpression| {
+            ResolvedExpression::Union(
+                Box::new(ResolvedExpression::Intersection(
+                    Box::new(x.clone()),
+                    Box::new(commits(&[2])),
+                )),
+                Box::new(ResolvedExpression::Difference(
+                    Box::new(x.clone()),
+                    Box::new(commits(&[2])),
+                )),
+            )
+        };
+        // Keys of the occurrences of x and 2
+        let operand_keys = |context: &EvaluationContext, expression: &ResolvedExpression| {
+            let operations = match expression {
+                ResolvedExpression::Union(operation1, operation2) => [operation1, operation2],
+                _ => panic!("unexpected expression: {expression:?}"),
+            };
+            operations
+                .into_iter()
+                .flat_map(|operation| match operation.as_ref() {
+                    ResolvedExpression::Intersection(x, y)
+                    | ResolvedExpression::Difference(x, y) => [x, y],
+                    _ => panic!("unexpected expression: {operation:?}"),
+                })
+                .map(|operand| {
+                    let operand: &ResolvedExpression = operand;
+                    context
+                        .repeated_expressions
+                        .get(&(operand as *const _))
+                        .copied()
+                })
+                .collect_vec()
+        };
 
+        // 1:4, which is evaluated eagerly
+        let dag_range = ResolvedExpression::DagRange {
+            roots: Box::new(commits(&[1])),
+            heads: Box::new(commits(&[4])),
+            generation_from_roots: GENERATION_RANGE_FULL,
+        };
+        let expression = split_by_2(&dag_range);
+        let context = new_context(&expression);
+        // The roots and heads of the range aren't repeated by themselves
+        assert_eq!(context.repeated_expressions.len(), 4);
         assert_eq!(
-            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("a0")),
-            2
-        );
-        assert_eq!(
-            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("ba")),
-            1
+            operand_keys(&context, &expression),
+            [Some(0), Some(1), Some(0), Some(1)]
         );
+        let revset = context.evaluate(&expression).unwrap();
         assert_eq!(
-            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("ab")),
-            2
+            revset
+                .iter()
+                .map_ok(|entry| entry.commit_id())
+                .try_collect::<_, Vec<_>, _>()
+                .unwrap(),
+            vec![
+                ids[4].clone(),
+                ids[3].clone(),
+                ids[2].clone(),
+                ids[1].clone()
+            ]
         );
+        // Both occurrences share the set evaluated for the first one
+        let shared_sets = context.shared_sets.borrow();
+        assert_eq!(shared_sets.len(), 2);
+        assert_eq!(Rc::strong_count(&shared_sets[&0]), 3);
+        assert_eq!(shared_sets[&0].index_entries.len(), 4);
+        drop(shared_sets);
+        drop(revset);
+        assert_eq!(Rc::strong_count(&context.shared_sets.borrow()[&0]), 1);
+
+        // ::3, which is walked lazily
+        let ancestors = ResolvedExpression::Ancestors {
+            heads: Box::new(commits(&[3])),
+            generation: GENERATION_RANGE_FULL,
+            generation_mode: GenerationMode::Any,
+        };
+        let expression = split_by_2(&ancestors);
+        let context = new_context(&expression);
+        assert_eq!(context.repeated_expressions.len(), 4);
         assert_eq!(
-            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("acd0")),
-            3
+            operand_keys(&context, &expression),
+            [Some(0), Some(1), Some(0), Some(1)]
         );
-        // If it were there, the length would be 1.
+        let revset = context.evaluate(&expression).unwrap();
         assert_eq!(
-            id_index.shortest_unique_prefix_len(&ChangeId::from_hex("c0")),
-            1
+            revset
+                .iter()
+                .map_ok(|entry| entry.commit_id())
+                .try_collect::<_, Vec<_>, _>()
+                .unwrap(),
+            ids[..4].iter().rev().cloned().collect_vec()
         );
+        // The walk isn't cached
+        assert!(!context.shared_sets.borrow().contains_key(&0));
+        assert!(context.shared_sets.borrow().contains_key(&1));
+
+        // Nothing is repeated
+        let expression = dag_range;
+        assert!(repeated_subexpressions(&expression).is_empty());
     }
 
-    /// Generator of unique 16-byte ChangeId excluding root id
-    fn change_id_generator() -> impl FnMut() -> ChangeId {
-        let mut iter = (1_u128..).map(|n| ChangeId::new(n.to_le_bytes().into()));
-        move || iter.next().unwrap()
+    #[test]
+    fn test_evaluate_change_id_prefixes_without_loading_commits() {
+        // None of these commits exist in the store, so evaluation would fail if
+        // the predicate tried to load any of them.
+        let mut test_index = TestIndex::new();
+        let id_0 = CommitId::from_hex("000000");
+        let id_1 = CommitId::from_hex("111111");
+        let id_2 = CommitId::from_hex("222222");
+        let id_3 = CommitId::from_hex("333333");
+        let mut add_commit = |id: &CommitId, change_id: &str, parent_ids: &[&CommitId]| {
+            let parent_ids = parent_ids.iter().copied().cloned().collect_vec();
+            test_index.index.add_commit_data(
+                id.clone(),
+                ChangeId::from_hex(change_id),
+                &parent_ids,
+            );
+        };
+        add_commit(&id_0, "0000", &[]);
+        add_commit(&id_1, "ab12", &[&id_0]);
+        add_commit(&id_2, "ab34", &[&id_0]);
+        // Divergent change
+        add_commit(&id_3, "ab12", &[&id_0]);
+
+        let change_id_prefixes = |prefixes: &[&str]| {
+            let prefixes = prefixes
+                .iter()
+                .map(|prefix| HexPrefix::new(prefix).unwrap())
+                .collect();
+            let expression = ResolvedExpression::FilterWithin {
+                candidates: Box::new(ResolvedExpression::Commits(vec![
+                    id_3.clone(),
+                    id_2.clone(),
+                    id_1.clone(),
+                    id_0.clone(),
+                ])),
+                predicate: ResolvedPredicateExpression::Filter(
+                    RevsetFilterPredicate::ChangeIdPrefixes(prefixes),
+                ),
+            };
+            test_index.evaluate_ids(&expression)
+        };
+
+        assert_eq!(change_id_prefixes(&["ff"]), vec![]);
+        assert_eq!(
+            change_id_prefixes(&["ab1"]),
+            vec![id_3.clone(), id_1.clone()]
+        );
+        assert_eq!(
+            change_id_prefixes(&["ab3", "0"]),
+            vec![id_2.clone(), id_0.clone()]
+        );
+        assert_eq!(
+            change_id_prefixes(&["ab"]),
+            vec![id_3.clone(), id_2.clone(), id_1.clone()]
+        );
     }
 
     #[test]
-    fn test_revset_combinator() {
-        let mut new_change_id = change_id_generator();
-        let mut index = MutableIndexImpl::full(3, 16);
+    fn test_evaluate_merges_without_loading_commits() {
+        // None of these commits exist in the store, so evaluation would fail if
+        // the predicate tried to load any of them.
+        let mut test_index = TestIndex::new();
         let id_0 = CommitId::from_hex("000000");
         let id_1 = CommitId::from_hex("111111");
         let id_2 = CommitId::from_hex("222222");
         let id_3 = CommitId::from_hex("333333");
         let id_4 = CommitId::from_hex("444444");
-        index.add_commit_data(id_0.clone(), new_change_id(), &[]);
-        index.add_commit_data(id_1.clone(), new_change_id(), &[id_0.clone()]);
-        index.add_commit_data(id_2.clone(), new_change_id(), &[id_1.clone()]);
-        index.add_commit_data(id_3.clone(), new_change_id(), &[id_2.clone()]);
-        index.add_commit_data(id_4.clone(), new_change_id(), &[id_3.clone()]);
+        test_index.add_commit(&id_0, &[]);
+        test_index.add_commit(&id_1, &[&id_0]);
+        test_index.add_commit(&id_2, &[&id_1]);
+        test_index.add_commit(&id_3, &[&id_0]);
+        test_index.add_commit(&id_4, &[&id_2, &id_3]);
 
-        let get_entry = |id: &CommitId| index.as_composite().entry_by_id(id).unwrap();
-        let make_entries = |ids: &[&CommitId]| ids.iter().map(|id| get_entry(id)).collect_vec();
-        let make_set = |ids: &[&CommitId]| -> Box<dyn InternalRevset> {
-            let index_entries = make_entries(ids);
-            Box::new(EagerRevset { index_entries })
+        let merges_in = |head_id: &CommitId| {
+            let expression = ResolvedExpression::FilterWithin {
+                candidates: Box::new(ResolvedExpression::Ancestors {
+                    heads: Box::new(ResolvedExpression::Commits(vec![head_id.clone()])),
+                    generation: GENERATION_RANGE_FULL,
+                    generation_mode: GenerationMode::Any,
+                }),
+                predicate: ResolvedPredicateExpression::Filter(RevsetFilterPredicate::ParentCount(
+                    2..u32::MAX,
+                )),
+            };
+            test_index.evaluate_ids(&expression)
         };
 
-        let set = make_set(&[&id_4, &id_3, &id_2, &id_0]);
-        let mut p = set.to_predicate_fn();
-        assert!(p(&get_entry(&id_4)));
-        assert!(p(&get_entry(&id_3)));
-        assert!(p(&get_entry(&id_2)));
-        assert!(!p(&get_entry(&id_1)));
-        assert!(p(&get_entry(&id_0)));
-        // Uninteresting entries can be skipped
-        let mut p = set.to_predicate_fn();
-        assert!(p(&get_entry(&id_3)));
-        assert!(!p(&get_entry(&id_1)));
-        assert!(p(&get_entry(&id_0)));
+        // Linear history
+        assert_eq!(merges_in(&id_2), vec![]);
+        // History containing a merge
+        assert_eq!(merges_in(&id_4), vec![id_4.clone()]);
+    }
 
-        let set = FilterRevset {
-            candidates: make_set(&[&id_4, &id_2, &id_0]),
-            predicate: pure_predicate_fn(|entry| entry.commit_id() != id_4),
-        };
-        assert_eq!(set.iter().collect_vec(), make_entries(&[&id_2, &id_0]));
-        let mut p = set.to_predicate_fn();
-        assert!(!p(&get_entry(&id_4)));
-        assert!(!p(&get_entry(&id_3)));
-        assert!(p(&get_entry(&id_2)));
-        assert!(!p(&get_entry(&id_1)));
-        assert!(p(&get_entry(&id_0)));
+    /// Counts commits read from the inner backend.
+    #[derive(Debug)]
+    struct CommitReadCountingBackend {
+        inner: Box<dyn Backend>,
+        read_count: Arc<AtomicUsize>,
+    }
 
-        // Intersection by FilterRevset
-        let set = FilterRevset {
-            candidates: make_set(&[&id_4, &id_2, &id_0]),
-            predicate: make_set(&[&id_3, &id_2, &id_1]),
+    impl Backend for CommitReadCountingBackend {
+        fn name(&self) -> &str {
+            self.inner.name()
+        }
+
+        fn commit_id_length(&self) -> usize {
+            self.inner.commit_id_length()
+        }
+
+        fn change_id_length(&self) -> usize {
+            self.inner.change_id_length()
+        }
+
+        fn git_repo(&self) -> Option<git2::Repository> {
+            self.inner.git_repo()
+        }
+
+        fn read_file(&self, path: &RepoPath, id: &FileId) -> BackendResult<Box<dyn Read>> {
+            self.inner.read_file(path, id)
+        }
+
+        fn write_file(&self, path: &RepoPath, contents: &mut dyn Read) -> BackendResult<FileId> {
+            self.inner.write_file(path, contents)
+        }
+
+        fn read_symlink(&self, path: &RepoPath, id: &SymlinkId) -> BackendResult<String> {
+            self.inner.read_symlink(path, id)
+        }
+
+        fn write_symlink(&self, path: &RepoPath, target: &str) -> BackendResult<SymlinkId> {
+            self.inner.write_symlink(path, target)
+        }
+
+        fn root_commit_id(&self) -> &CommitId {
+            self.inner.root_commit_id()
+        }
+
+        fn root_change_id(&self) -> &ChangeId {
+            self.inner.root_change_id()
+        }
+
+        fn empty_tree_id(&self) -> &TreeId {
+            self.inner.empty_tree_id()
+        }
+
+        fn read_tree(&self, path: &RepoPath, id: &TreeId) -> BackendResult<backend::Tree> {
+            self.inner.read_tree(path, id)
+        }
+
+        fn write_tree(&self, path: &RepoPath, contents: &backend::Tree) -> BackendResult<TreeId> {
+            self.inner.write_tree(path, contents)
+        }
+
+        fn read_conflict(&self, path: &RepoPath, id: &ConflictId) -> BackendResult<Conflict> {
+            self.inner.read_conflict(path, id)
+        }
+
+        fn write_conflict(
+            &self,
+            path: &RepoPath,
+            contents: &Conflict,
+        ) -> BackendResult<ConflictId> {
+            self.inner.write_conflict(path, contents)
+        }
+
+        fn read_commit(&self, id: &CommitId) -> BackendResult<backend::Commit> {
+            self.read_count.fetch_add(1, atomic::Ordering::Relaxed);
+            self.inner.read_commit(id)
+        }
+
+        fn write_commit(&self, contents: &backend::Commit) -> BackendResult<CommitId> {
+            self.inner.write_commit(contents)
+        }
+    }
+
+    #[test]
+    fn test_evaluate_filters_read_each_commit_once() {
+        let mut test_index = TestIndex::with_root_commit();
+        let commit_ids = ["foo", "bar", "foo bar"]
+            .map(|description| {
+                test_index.write_commit(|commit| {
+                    commit.description = description.to_owned();
+                    commit.author.name = "alice".to_owned();
+                    commit.committer.name = "bob".to_owned();
+                })
+            })
+            .to_vec();
+        let (store, read_count) = test_index.counting_store();
+        let index = &test_index.index;
+
+        // author("alice") & description("foo") & description("bar")
+        let filter = |candidates, predicate| ResolvedExpression::FilterWithin {
+            candidates: Box::new(candidates),
+            predicate: ResolvedPredicateExpression::Filter(predicate),
         };
-        assert_eq!(set.iter().collect_vec(), make_entries(&[&id_2]));
-        let mut p = set.to_predicate_fn();
-        assert!(!p(&get_entry(&id_4)));
-        assert!(!p(&get_entry(&id_3)));
-        assert!(p(&get_entry(&id_2)));
-        assert!(!p(&get_entry(&id_1)));
-        assert!(!p(&get_entry(&id_0)));
+        let substring = |needle: &str| StringPattern::Substring(needle.to_owned());
+        let expression = filter(
+            filter(
+                filter(
+                    ResolvedExpression::Commits(commit_ids.clone()),
+                    RevsetFilterPredicate::Author(substring("alice"), CaseSensitivity::Sensitive),
+                ),
+                RevsetFilterPredicate::Description(
+                    substring("foo"),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Full,
+                ),
+            ),
+            RevsetFilterPredicate::Description(
+                substring("bar"),
+                CaseSensitivity::Sensitive,
+                DescriptionScope::Full,
+            ),
+        );
+        let revset = evaluate(
+            &expression,
+            &store,
+            index,
+            index.as_composite(),
+            &RevsetEvaluationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            revset.iter().map(Result::unwrap).collect_vec(),
+            vec![commit_ids[2].clone()]
+        );
+        // Each commit is read from the backend once no matter how many
+        // predicates test it.
+        assert_eq!(read_count.load(atomic::Ordering::Relaxed), commit_ids.len());
 
-        let set = UnionRevset {
-            set1: make_set(&[&id_4, &id_2]),
-            set2: make_set(&[&id_3, &id_2, &id_1]),
+        // author("alice") & description("bar") & committer("bob") &
+        // ~adds_files(), tested as a single predicate tree with a fresh store.
+        // The tree-diff predicate runs in parallel and loads the parents too.
+        let (store, read_count) = test_index.counting_store();
+        let and = |predicate1, predicate2| {
+            ResolvedPredicateExpression::Intersection(Box::new(predicate1), Box::new(predicate2))
+        };
+        let predicate = and(
+            and(
+                and(
+                    ResolvedPredicateExpression::Filter(RevsetFilterPredicate::Author(
+                        substring("alice"),
+                        CaseSensitivity::Sensitive,
+                    )),
+                    ResolvedPredicateExpression::Filter(RevsetFilterPredicate::Description(
+                        substring("bar"),
+                        CaseSensitivity::Sensitive,
+                        DescriptionScope::Full,
+                    )),
+                ),
+                ResolvedPredicateExpression::Filter(RevsetFilterPredicate::Committer(
+                    substring("bob"),
+                    CaseSensitivity::Sensitive,
+                )),
+            ),
+            ResolvedPredicateExpression::NotIn(Box::new(ResolvedPredicateExpression::Filter(
+                RevsetFilterPredicate::AddsFiles {
+                    includes: None,
+                    excludes: vec![],
+                },
+            ))),
+        );
+        let expression = ResolvedExpression::FilterWithin {
+            candidates: Box::new(ResolvedExpression::Commits(commit_ids.clone())),
+            predicate,
         };
+        let revset = evaluate(
+            &expression,
+            &store,
+            index,
+            index.as_composite(),
+            &RevsetEvaluationOptions::default(),
+        )
+        .unwrap();
         assert_eq!(
-            set.iter().collect_vec(),
-            make_entries(&[&id_4, &id_3, &id_2, &id_1])
+            revset.iter().map(Result::unwrap).collect_vec(),
+            vec![commit_ids[2].clone(), commit_ids[1].clone()]
         );
-        let mut p = set.to_predicate_fn();
-        assert!(p(&get_entry(&id_4)));
-        assert!(p(&get_entry(&id_3)));
-        assert!(p(&get_entry(&id_2)));
-        assert!(p(&get_entry(&id_1)));
-        assert!(!p(&get_entry(&id_0)));
+        // The candidates and their shared parent, the root commit
+        assert_eq!(
+            read_count.load(atomic::Ordering::Relaxed),
+            commit_ids.len() + 1
+        );
+    }
 
-        let set = IntersectionRevset {
-            set1: make_set(&[&id_4, &id_2, &id_0]),
-            set2: make_set(&[&id_3, &id_2, &id_1]),
+    #[test]
+    fn test_commit_cache_evicts_least_recently_used() {
+        let mut test_index = TestIndex::with_root_commit();
+        let commit_ids = (0..3)
+            .map(|_| test_index.write_commit(|_| {}))
+            .collect_vec();
+        let (store, read_count) = test_index.counting_store();
+        let cache = CommitCache::with_capacity(store, 2);
+        let get = |id: &CommitId| cache.get(&test_index.entry(id)).unwrap();
+        let read_count = || read_count.load(atomic::Ordering::Relaxed);
+
+        get(&commit_ids[0]);
+        get(&commit_ids[1]);
+        assert_eq!(read_count(), 2);
+        // Cached
+        assert_eq!(get(&commit_ids[0]).id(), &commit_ids[0]);
+        assert_eq!(read_count(), 2);
+        // Evicts commit 1, which is the least recently used
+        get(&commit_ids[2]);
+        assert_eq!(read_count(), 3);
+        get(&commit_ids[0]);
+        assert_eq!(read_count(), 3);
+        get(&commit_ids[1]);
+        assert_eq!(read_count(), 4);
+    }
+
+    #[test]
+    fn test_build_commit_predicate_evaluates_set_once() {
+        let mut test_index = TestIndex::with_root_commit();
+        let commit_ids = ["foo", "bar", "foo bar"]
+            .map(|description| {
+                test_index.write_commit(|commit| commit.description = description.to_owned())
+            })
+            .to_vec();
+        let (store, read_count) = test_index.counting_store();
+        let index = &test_index.index;
+
+        // description("foo") within the commits, embedded as a set
+        let predicate =
+            ResolvedPredicateExpression::Set(Box::new(ResolvedExpression::FilterWithin {
+                candidates: Box::new(ResolvedExpression::Commits(commit_ids.clone())),
+                predicate: ResolvedPredicateExpression::Filter(RevsetFilterPredicate::Description(
+                    StringPattern::Substring("foo".to_owned()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Full,
+                )),
+            }));
+        let p = build_commit_predicate(&predicate, &store, index, index.as_composite()).unwrap();
+        assert_eq!(read_count.load(atomic::Ordering::Relaxed), 0);
+        let matches = commit_ids
+            .iter()
+            .map(|id| p(&test_index.store.get_commit(id).unwrap()).unwrap())
+            .collect_vec();
+        assert_eq!(matches, vec![true, false, true]);
+        // The set is evaluated on the first call, and reused by the others
+        assert_eq!(read_count.load(atomic::Ordering::Relaxed), commit_ids.len());
+    }
+
+    #[test]
+    fn test_evaluate_latest_reads_no_commits() {
+        let mut test_index = TestIndex::with_root_commit();
+        let commit_ids = [3_000, 1_000, 2_000]
+            .map(|timestamp| {
+                test_index.write_commit(|commit| {
+                    commit.committer.timestamp.timestamp = MillisSinceEpoch(timestamp);
+                })
+            })
+            .to_vec();
+        let (store, read_count) = test_index.counting_store();
+        let index = &test_index.index;
+
+        // latest(commits, 2)
+        let expression = ResolvedExpression::Latest {
+            candidates: Box::new(ResolvedExpression::Commits(commit_ids.clone())),
+            count: 2,
+            offset: 0,
+            group_by: None,
         };
-        assert_eq!(set.iter().collect_vec(), make_entries(&[&id_2]));
-        let mut p = set.to_predicate_fn();
-        assert!(!p(&get_entry(&id_4)));
-        assert!(!p(&get_entry(&id_3)));
-        assert!(p(&get_entry(&id_2)));
-        assert!(!p(&get_entry(&id_1)));
-        assert!(!p(&get_entry(&id_0)));
+        let revset = evaluate(
+            &expression,
+            &store,
+            index,
+            index.as_composite(),
+            &RevsetEvaluationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            revset.iter().map(Result::unwrap).collect_vec(),
+            vec![commit_ids[2].clone(), commit_ids[0].clone()]
+        );
+        // The timestamps are looked up in the index
+        assert_eq!(read_count.load(atomic::Ordering::Relaxed), 0);
+    }
 
-        let set = DifferenceRevset {
-            set1: make_set(&[&id_4, &id_2, &id_0]),
-            set2: make_set(&[&id_3, &id_2, &id_1]),
+    #[test]
+    fn test_evaluate_filters_propagate_store_error() {
+        let mut test_index = TestIndex::with_root_commit();
+        let set_author = |commit: &mut backend::Commit| commit.author.name = "alice".to_owned();
+        let id_1 = test_index.write_commit(set_author);
+        // Indexed, but the object is missing from the store
+        let id_missing = CommitId::new(vec![0x99; test_index.store.commit_id_length()]);
+        let root_commit_id = test_index.store.root_commit_id().clone();
+        test_index.add_commit(&id_missing, &[&root_commit_id]);
+        let id_3 = test_index.write_commit(set_author);
+
+        let filter = |predicate| ResolvedExpression::FilterWithin {
+            candidates: Box::new(ResolvedExpression::Commits(vec![
+                id_1.clone(),
+                id_missing.clone(),
+                id_3.clone(),
+            ])),
+            predicate: ResolvedPredicateExpression::Filter(predicate),
         };
-        assert_eq!(set.iter().collect_vec(), make_entries(&[&id_4, &id_0]));
-        let mut p = set.to_predicate_fn();
-        assert!(p(&get_entry(&id_4)));
-        assert!(!p(&get_entry(&id_3)));
-        assert!(!p(&get_entry(&id_2)));
-        assert!(!p(&get_entry(&id_1)));
-        assert!(p(&get_entry(&id_0)));
+        fn assert_store_error<T: fmt::Debug>(result: Result<T, RevsetEvaluationError>) {
+            assert_matches!(
+                result,
+                Err(RevsetEvaluationError::StoreError(
+                    BackendError::ObjectNotFound { .. }
+                ))
+            );
+        }
+
+        let expression = filter(RevsetFilterPredicate::Author(
+            StringPattern::Substring("alice".to_owned()),
+            CaseSensitivity::Sensitive,
+        ));
+        let revset = test_index.evaluate(&expression).unwrap();
+        // The commits around the failed one are still yielded.
+        let mut iter = revset.iter();
+        assert_eq!(iter.next().unwrap().unwrap(), id_3);
+        assert_store_error(iter.next().unwrap());
+        assert_eq!(iter.next().unwrap().unwrap(), id_1);
+        assert!(iter.next().is_none());
+
+        assert_store_error(revset.iter_reverse().next().unwrap());
+        assert_store_error(revset.count());
+        assert!(!revset.is_empty());
+        assert!(revset.contains(&id_1).unwrap());
+        assert_store_error(revset.contains(&id_missing));
+
+        let mut graph_iter = revset.iter_graph();
+        assert_eq!(graph_iter.next().unwrap().unwrap().0, id_3);
+        assert_store_error(graph_iter.next().unwrap());
+        assert_eq!(graph_iter.next().unwrap().unwrap().0, id_1);
+        assert!(graph_iter.next().is_none());
+
+        // Predicate tested in parallel. The other commits don't modify files.
+        let expression = filter(RevsetFilterPredicate::File {
+            includes: None,
+            excludes: vec![],
+        });
+        let revset = test_index.evaluate(&expression).unwrap();
+        let mut iter = revset.iter();
+        assert_store_error(iter.next().unwrap());
+        assert!(iter.next().is_none());
     }
 }
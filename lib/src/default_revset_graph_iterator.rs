@@ -15,10 +15,9 @@
 use std::cmp::min;
 use std::collections::{BTreeMap, HashSet};
 
-use crate::backend::CommitId;
 use crate::default_index_store::{IndexEntry, IndexPosition};
 use crate::nightly_shims::BTreeMapExt;
-use crate::revset::{RevsetGraphEdge, RevsetGraphEdgeType};
+use crate::revset::{RevsetEvaluationError, RevsetGraphEdge, RevsetGraphEdgeType, RevsetGraphItem};
 
 // Given an iterator over some set of revisions, yields the same revisions with
 // associated edge types.
@@ -86,8 +85,12 @@ use crate::revset::{RevsetGraphEdge, RevsetGraphEdgeType};
 // by stopping at "c" since we're only interested in edges that could lead to
 // "D", but that would require extra book-keeping to remember for later that the
 // edges from "f" and "H" are only partially computed.
+//
+// Errors yielded by the input iterator are forwarded as soon as the commit
+// being emitted is done. They don't affect the edges of the other commits.
 pub struct RevsetGraphIterator<'revset, 'index> {
-    input_set_iter: Box<dyn Iterator<Item = IndexEntry<'index>> + 'revset>,
+    input_set_iter:
+        Box<dyn Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>> + 'revset>,
     // Commits in the input set we had to take out of the iterator while walking external
     // edges. Does not necessarily include the commit we're currently about to emit.
     look_ahead: BTreeMap<IndexPosition, IndexEntry<'index>>,
@@ -98,11 +101,15 @@ pub struct RevsetGraphIterator<'revset, 'index> {
     // TODO: Remove unneeded entries here as we go (that's why it's an ordered map)?
     edges: BTreeMap<IndexPosition, HashSet<(IndexPosition, RevsetGraphEdge)>>,
     skip_transitive_edges: bool,
+    // Errors taken out of the iterator while walking external edges.
+    errors: Vec<RevsetEvaluationError>,
 }
 
 impl<'revset, 'index> RevsetGraphIterator<'revset, 'index> {
     pub fn new(
-        input_set_iter: Box<dyn Iterator<Item = IndexEntry<'index>> + 'revset>,
+        input_set_iter: Box<
+            dyn Iterator<Item = Result<IndexEntry<'index>, RevsetEvaluationError>> + 'revset,
+        >,
     ) -> RevsetGraphIterator<'revset, 'index> {
         RevsetGraphIterator {
             input_set_iter,
@@ -110,6 +117,7 @@ impl<'revset, 'index> RevsetGraphIterator<'revset, 'index> {
             min_position: IndexPosition::MAX,
             edges: Default::default(),
             skip_transitive_edges: true,
+            errors: vec![],
         }
     }
 
@@ -118,9 +126,12 @@ impl<'revset, 'index> RevsetGraphIterator<'revset, 'index> {
         self
     }
 
-    fn next_index_entry(&mut self) -> Option<IndexEntry<'index>> {
+    fn next_index_entry(&mut self) -> Option<Result<IndexEntry<'index>, RevsetEvaluationError>> {
+        if let Some(err) = self.errors.pop() {
+            return Some(Err(err));
+        }
         if let Some(index_entry) = self.look_ahead.pop_last_value() {
-            return Some(index_entry);
+            return Some(Ok(index_entry));
         }
         self.input_set_iter.next()
     }
@@ -259,6 +270,13 @@ impl<'revset, 'index> RevsetGraphIterator<'revset, 'index> {
     fn consume_to(&mut self, pos: IndexPosition) {
         while pos < self.min_position {
             if let Some(next_entry) = self.input_set_iter.next() {
+                let next_entry = match next_entry {
+                    Ok(next_entry) => next_entry,
+                    Err(err) => {
+                        self.errors.insert(0, err);
+                        continue;
+                    }
+                };
                 let next_position = next_entry.position();
                 self.look_ahead.insert(next_position, next_entry);
                 self.min_position = next_position;
@@ -267,13 +285,16 @@ impl<'revset, 'index> RevsetGraphIterator<'revset, 'index> {
             }
         }
     }
-}
 
-impl<'revset, 'index> Iterator for RevsetGraphIterator<'revset, 'index> {
-    type Item = (CommitId, Vec<RevsetGraphEdge>);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let index_entry = self.next_index_entry()?;
+    /// Same as `next()`, but yields the index entry instead of the commit id so
+    /// the caller doesn't have to look it up again.
+    pub fn next_with_index_entry(
+        &mut self,
+    ) -> Option<Result<(IndexEntry<'index>, Vec<RevsetGraphEdge>), RevsetEvaluationError>> {
+        let index_entry = match self.next_index_entry()? {
+            Ok(index_entry) => index_entry,
+            Err(err) => return Some(Err(err)),
+        };
         let mut edges = self.edges_from_internal_commit(&index_entry);
         if self.skip_transitive_edges {
             edges = self.remove_transitive_edges(edges);
@@ -281,6 +302,15 @@ impl<'revset, 'index> Iterator for RevsetGraphIterator<'revset, 'index> {
         let mut edges: Vec<_> = edges.into_iter().collect();
         edges.sort_by(|(target_pos1, _), (target_pos2, _)| target_pos2.cmp(target_pos1));
         let edges = edges.into_iter().map(|(_, edge)| edge).collect();
-        Some((index_entry.commit_id(), edges))
+        Some(Ok((index_entry, edges)))
+    }
+}
+
+impl<'revset, 'index> Iterator for RevsetGraphIterator<'revset, 'index> {
+    type Item = RevsetGraphItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.next_with_index_entry()?;
+        Some(item.map(|(index_entry, edges)| (index_entry.commit_id(), edges)))
     }
 }
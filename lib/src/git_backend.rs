@@ -24,8 +24,8 @@ use prost::Message;
 
 use crate::backend::{
     make_root_commit, Backend, BackendError, BackendResult, ChangeId, Commit, CommitId, Conflict,
-    ConflictId, ConflictTerm, FileId, MillisSinceEpoch, ObjectId, Signature, SymlinkId, Timestamp,
-    Tree, TreeId, TreeValue,
+    ConflictId, ConflictTerm, FileId, MillisSinceEpoch, ObjectId, SecureSig, Signature, SymlinkId,
+    Timestamp, Tree, TreeId, TreeValue,
 };
 use crate::repo_path::{RepoPath, RepoPathComponent};
 use crate::stacked_table::{ReadonlyTable, TableSegment, TableStore};
@@ -214,6 +214,16 @@ impl Backend for GitBackend {
         Ok(Box::new(Cursor::new(content)))
     }
 
+    fn file_size(&self, _path: &RepoPath, id: &FileId) -> BackendResult<u64> {
+        let git_blob_id = validate_git_object_id(id)?;
+        let locked_repo = self.repo.lock().unwrap();
+        let (size, _kind) = locked_repo
+            .odb()
+            .and_then(|odb| odb.read_header(git_blob_id))
+            .map_err(|err| map_not_found_err(err, id))?;
+        Ok(size as u64)
+    }
+
     fn write_file(&self, _path: &RepoPath, contents: &mut dyn Read) -> BackendResult<FileId> {
         let mut bytes = Vec::new();
         contents.read_to_end(&mut bytes).unwrap();
@@ -426,9 +436,18 @@ impl Backend for GitBackend {
             parents.push(self.root_commit_id.clone());
         };
         let tree_id = TreeId::from_bytes(commit.tree_id().as_bytes());
-        let description = commit.message().unwrap_or("<no message>").to_owned();
+        // Invalid UTF-8 sequences are replaced with U+FFFD
+        let description = String::from_utf8_lossy(commit.message_bytes()).into_owned();
         let author = signature_from_git(commit.author());
         let committer = signature_from_git(commit.committer());
+        // An unsigned commit has no signature to extract.
+        let secure_sig = locked_repo
+            .extract_signature(&git_commit_id, None)
+            .ok()
+            .map(|(sig, data)| SecureSig {
+                data: data.to_vec(),
+                sig: sig.to_vec(),
+            });
 
         let mut commit = Commit {
             parents,
@@ -438,6 +457,7 @@ impl Backend for GitBackend {
             description,
             author,
             committer,
+            secure_sig,
         };
 
         let table = {
@@ -753,6 +773,7 @@ mod tests {
             description: "".to_string(),
             author: create_signature(),
             committer: create_signature(),
+            secure_sig: None,
         };
 
         // No parents
@@ -820,6 +841,7 @@ mod tests {
             description: "initial".to_string(),
             author: signature.clone(),
             committer: signature,
+            secure_sig: None,
         };
         let commit_id = store.write_commit(&commit).unwrap();
         let git_refs = store
@@ -844,6 +866,7 @@ mod tests {
             description: "initial".to_string(),
             author: create_signature(),
             committer: create_signature(),
+            secure_sig: None,
         };
         let commit_id1 = store.write_commit(&commit1).unwrap();
         let mut commit2 = commit1;
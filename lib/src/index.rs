@@ -23,7 +23,7 @@ use crate::commit::Commit;
 use crate::default_index_store::RevWalk;
 use crate::op_store::OperationId;
 use crate::operation::Operation;
-use crate::revset::{ResolvedExpression, Revset, RevsetEvaluationError};
+use crate::revset::{ResolvedExpression, Revset, RevsetEvaluationError, RevsetEvaluationOptions};
 use crate::store::Store;
 
 #[derive(Debug, Error)]
@@ -70,6 +70,7 @@ pub trait Index: Send + Sync {
         &'index self,
         expression: &ResolvedExpression,
         store: &Arc<Store>,
+        options: &RevsetEvaluationOptions,
     ) -> Result<Box<dyn Revset<'index> + 'index>, RevsetEvaluationError>;
 }
 
@@ -91,7 +92,7 @@ pub trait MutableIndex: Any {
     fn merge_in(&mut self, other: &dyn ReadonlyIndex);
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct HexPrefix {
     // For odd-length prefix, lower 4 bits of the last byte is padded with 0
     min_prefix_bytes: Vec<u8>,
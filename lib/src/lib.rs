@@ -18,6 +18,7 @@
 mod content_hash;
 
 pub mod backend;
+pub mod changed_path_filter;
 pub mod commit;
 pub mod commit_builder;
 pub mod conflicts;
@@ -51,6 +52,7 @@ pub mod simple_op_heads_store;
 pub mod simple_op_store;
 pub mod stacked_table;
 pub mod store;
+pub mod trailer;
 pub mod transaction;
 pub mod tree;
 pub mod tree_builder;
@@ -157,6 +157,172 @@ impl Matcher for PrefixMatcher {
     }
 }
 
+/// Returns true if the `text` contains glob metacharacters.
+pub fn is_glob_pattern(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+/// Matches file paths against glob patterns. Unlike `PrefixMatcher`, a pattern
+/// matching a directory doesn't match the files in it.
+#[derive(Debug)]
+pub struct GlobMatcher {
+    patterns: Vec<glob::Pattern>,
+    // Literal directory prefixes of the patterns. 'is_file' is set at the
+    // prefix, below which any path may match.
+    prefix_tree: RepoPathTree,
+}
+
+impl GlobMatcher {
+    pub fn new(patterns: &[RepoPath]) -> Result<Self, glob::PatternError> {
+        let mut prefix_tree = RepoPathTree::new();
+        for pattern in patterns {
+            let components = pattern.components();
+            let literal_len = components
+                .iter()
+                .position(|name| is_glob_pattern(name.as_str()))
+                .unwrap_or(components.len().saturating_sub(1));
+            let prefix = RepoPath::from_components(components[..literal_len].to_vec());
+            let sub = prefix_tree.add(&prefix);
+            sub.is_dir = true;
+            sub.is_file = true;
+        }
+        let patterns = patterns
+            .iter()
+            .map(|pattern| glob::Pattern::new(&pattern.to_internal_file_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(GlobMatcher {
+            patterns,
+            prefix_tree,
+        })
+    }
+}
+
+impl Matcher for GlobMatcher {
+    fn matches(&self, file: &RepoPath) -> bool {
+        let options = glob::MatchOptions {
+            case_sensitive: true,
+            // "*" shouldn't match "/", but "**" should.
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let file = file.to_internal_file_string();
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.matches_with(&file, options))
+    }
+
+    fn visit(&self, dir: &RepoPath) -> Visit {
+        for (sub, tail_components) in self.prefix_tree.walk_to(dir) {
+            // 'dir' is at or below the literal prefix of patterns
+            if sub.is_file {
+                return Visit::Specific {
+                    dirs: VisitDirs::All,
+                    files: VisitFiles::All,
+                };
+            }
+            // 'dir' found, and is an ancestor of the literal prefixes
+            if tail_components.is_empty() {
+                return sub.to_visit_sets();
+            }
+        }
+        Visit::Nothing
+    }
+}
+
+/// Matches files by their extension, e.g. `rs` for `src/lib.rs`.
+#[derive(Debug)]
+pub struct ExtensionMatcher {
+    extensions: Vec<String>,
+}
+
+impl ExtensionMatcher {
+    pub fn new(extensions: &[String]) -> Self {
+        ExtensionMatcher {
+            extensions: extensions.to_vec(),
+        }
+    }
+}
+
+impl Matcher for ExtensionMatcher {
+    fn matches(&self, file: &RepoPath) -> bool {
+        let name = match file.components().last() {
+            Some(name) => name.as_str(),
+            None => return false,
+        };
+        self.extensions.iter().any(|ext| {
+            name.strip_suffix(ext.as_str())
+                .and_then(|name| name.strip_suffix('.'))
+                .map_or(false, |stem| !stem.is_empty())
+        })
+    }
+
+    fn visit(&self, _dir: &RepoPath) -> Visit {
+        if self.extensions.is_empty() {
+            Visit::Nothing
+        } else {
+            // Any directory may contain matching files, but not all files in
+            // it match.
+            Visit::Specific {
+                dirs: VisitDirs::All,
+                files: VisitFiles::All,
+            }
+        }
+    }
+}
+
+/// Matches paths that are matched by either of the input matchers.
+pub struct UnionMatcher<'input> {
+    input1: &'input dyn Matcher,
+    input2: &'input dyn Matcher,
+}
+
+impl<'input> UnionMatcher<'input> {
+    pub fn new(input1: &'input dyn Matcher, input2: &'input dyn Matcher) -> Self {
+        Self { input1, input2 }
+    }
+}
+
+impl Matcher for UnionMatcher<'_> {
+    fn matches(&self, file: &RepoPath) -> bool {
+        self.input1.matches(file) || self.input2.matches(file)
+    }
+
+    fn visit(&self, dir: &RepoPath) -> Visit {
+        match self.input1.visit(dir) {
+            Visit::AllRecursively => Visit::AllRecursively,
+            Visit::Nothing => self.input2.visit(dir),
+            Visit::Specific {
+                dirs: dirs1,
+                files: files1,
+            } => match self.input2.visit(dir) {
+                Visit::AllRecursively => Visit::AllRecursively,
+                Visit::Nothing => Visit::Specific {
+                    dirs: dirs1,
+                    files: files1,
+                },
+                Visit::Specific {
+                    dirs: dirs2,
+                    files: files2,
+                } => {
+                    let dirs = match (dirs1, dirs2) {
+                        (VisitDirs::All, _) | (_, VisitDirs::All) => VisitDirs::All,
+                        (VisitDirs::Set(dirs1), VisitDirs::Set(dirs2)) => {
+                            VisitDirs::Set(dirs1.union(&dirs2).cloned().collect())
+                        }
+                    };
+                    let files = match (files1, files2) {
+                        (VisitFiles::All, _) | (_, VisitFiles::All) => VisitFiles::All,
+                        (VisitFiles::Set(files1), VisitFiles::Set(files2)) => {
+                            VisitFiles::Set(files1.union(&files2).cloned().collect())
+                        }
+                    };
+                    Visit::Specific { dirs, files }
+                }
+            },
+        }
+    }
+}
+
 /// Matches paths that are matched by the first input matcher but not by the
 /// second.
 pub struct DifferenceMatcher<'input> {
@@ -546,6 +712,175 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_globmatcher() {
+        let m = GlobMatcher::new(&[
+            RepoPath::from_internal_string("**/*.rs"),
+            RepoPath::from_internal_string("docs/*.md"),
+        ])
+        .unwrap();
+
+        assert!(m.matches(&RepoPath::from_internal_string("main.rs")));
+        assert!(m.matches(&RepoPath::from_internal_string("foo/main.rs")));
+        assert!(m.matches(&RepoPath::from_internal_string("foo/bar/main.rs")));
+        assert!(!m.matches(&RepoPath::from_internal_string("main.rs/foo")));
+        assert!(m.matches(&RepoPath::from_internal_string("docs/index.md")));
+        // "*" doesn't match path separator
+        assert!(!m.matches(&RepoPath::from_internal_string("docs/foo/index.md")));
+        assert!(!m.matches(&RepoPath::from_internal_string("index.md")));
+
+        assert_eq!(
+            m.visit(&RepoPath::root()),
+            Visit::Specific {
+                dirs: VisitDirs::All,
+                files: VisitFiles::All,
+            }
+        );
+    }
+
+    #[test]
+    fn test_globmatcher_visit_literal_prefix() {
+        let m = GlobMatcher::new(&[
+            RepoPath::from_internal_string("foo/bar/**/*.rs"),
+            RepoPath::from_internal_string("foo/baz/*.md"),
+            RepoPath::from_internal_string("qux/README"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            m.visit(&RepoPath::root()),
+            Visit::sets(
+                hashset! {RepoPathComponent::from("foo"), RepoPathComponent::from("qux")},
+                hashset! {RepoPathComponent::from("qux")},
+            )
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("foo")),
+            Visit::sets(
+                hashset! {RepoPathComponent::from("bar"), RepoPathComponent::from("baz")},
+                hashset! {RepoPathComponent::from("bar"), RepoPathComponent::from("baz")},
+            )
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("foo/bar/sub")),
+            Visit::Specific {
+                dirs: VisitDirs::All,
+                files: VisitFiles::All,
+            }
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("qux")),
+            Visit::Specific {
+                dirs: VisitDirs::All,
+                files: VisitFiles::All,
+            }
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("foo/other")),
+            Visit::Nothing
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("bar")),
+            Visit::Nothing
+        );
+    }
+
+    #[test]
+    fn test_globmatcher_invalid_pattern() {
+        assert!(GlobMatcher::new(&[RepoPath::from_internal_string("[a-")]).is_err());
+    }
+
+    #[test]
+    fn test_extensionmatcher() {
+        let m = ExtensionMatcher::new(&["rs".to_string(), "tar.gz".to_string()]);
+        assert!(m.matches(&RepoPath::from_internal_string("lib.rs")));
+        assert!(m.matches(&RepoPath::from_internal_string("src/lib.rs")));
+        assert!(m.matches(&RepoPath::from_internal_string("foo.tar.gz")));
+        assert!(!m.matches(&RepoPath::from_internal_string("foo.gz")));
+        assert!(!m.matches(&RepoPath::from_internal_string("foo.brs")));
+        assert!(!m.matches(&RepoPath::from_internal_string("foors")));
+        // Hidden file without extension
+        assert!(!m.matches(&RepoPath::from_internal_string(".rs")));
+        assert!(!m.matches(&RepoPath::from_internal_string("rs")));
+
+        // Any directory may contain matching files
+        assert_eq!(
+            m.visit(&RepoPath::root()),
+            Visit::Specific {
+                dirs: VisitDirs::All,
+                files: VisitFiles::All,
+            }
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("src/lib.rs")),
+            Visit::Specific {
+                dirs: VisitDirs::All,
+                files: VisitFiles::All,
+            }
+        );
+        let m = ExtensionMatcher::new(&[]);
+        assert!(!m.matches(&RepoPath::from_internal_string("lib.rs")));
+        assert_eq!(m.visit(&RepoPath::root()), Visit::Nothing);
+    }
+
+    #[test]
+    fn test_unionmatcher_prefix_and_glob() {
+        let m1 = PrefixMatcher::new(&[RepoPath::from_internal_string("foo")]);
+        let m2 = GlobMatcher::new(&[RepoPath::from_internal_string("bar/*.rs")]).unwrap();
+        let m = UnionMatcher::new(&m1, &m2);
+
+        assert!(m.matches(&RepoPath::from_internal_string("foo")));
+        assert!(m.matches(&RepoPath::from_internal_string("foo/baz.txt")));
+        assert!(m.matches(&RepoPath::from_internal_string("bar/baz.rs")));
+        assert!(!m.matches(&RepoPath::from_internal_string("bar/baz.txt")));
+
+        assert_eq!(
+            m.visit(&RepoPath::root()),
+            Visit::sets(
+                hashset! {RepoPathComponent::from("foo"), RepoPathComponent::from("bar")},
+                hashset! {RepoPathComponent::from("foo"), RepoPathComponent::from("bar")},
+            )
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("foo")),
+            Visit::AllRecursively
+        );
+    }
+
+    #[test]
+    fn test_unionmatcher_prefixes() {
+        let m1 = PrefixMatcher::new(&[RepoPath::from_internal_string("foo/bar")]);
+        let m2 = PrefixMatcher::new(&[RepoPath::from_internal_string("baz")]);
+        let m = UnionMatcher::new(&m1, &m2);
+
+        assert!(m.matches(&RepoPath::from_internal_string("foo/bar")));
+        assert!(!m.matches(&RepoPath::from_internal_string("foo/baz")));
+        assert!(m.matches(&RepoPath::from_internal_string("baz/foo")));
+
+        assert_eq!(
+            m.visit(&RepoPath::root()),
+            Visit::sets(
+                hashset! {RepoPathComponent::from("foo"), RepoPathComponent::from("baz")},
+                hashset! {RepoPathComponent::from("baz")}
+            )
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("foo")),
+            Visit::sets(
+                hashset! {RepoPathComponent::from("bar")},
+                hashset! {RepoPathComponent::from("bar")}
+            )
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("baz")),
+            Visit::AllRecursively
+        );
+        assert_eq!(
+            m.visit(&RepoPath::from_internal_string("qux")),
+            Visit::Nothing
+        );
+    }
+
     #[test]
     fn test_differencematcher_remove_subdir() {
         let m1 = PrefixMatcher::new(&[
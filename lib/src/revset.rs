@@ -14,6 +14,7 @@
 
 use std::collections::HashMap;
 use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
 use std::ops::Range;
 use std::path::Path;
 use std::rc::Rc;
@@ -21,6 +22,7 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::{error, fmt};
 
+use chrono::DateTime;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use pest::iterators::{Pair, Pairs};
@@ -29,10 +31,11 @@ use pest::Parser;
 use pest_derive::Parser;
 use thiserror::Error;
 
-use crate::backend::{BackendError, BackendResult, ChangeId, CommitId, ObjectId};
+use crate::backend::{BackendError, ChangeId, CommitId, MillisSinceEpoch, ObjectId, Timestamp};
 use crate::commit::Commit;
 use crate::hex_util::to_forward_hex;
-use crate::index::{HexPrefix, PrefixResolution};
+use crate::index::{HexPrefix, Index, PrefixResolution};
+use crate::matchers::is_glob_pattern;
 use crate::op_store::WorkspaceId;
 use crate::repo::Repo;
 use crate::repo_path::{FsPathParseError, RepoPath};
@@ -92,6 +95,8 @@ pub enum RevsetParseErrorKind {
     },
     #[error("Invalid arguments to revset function \"{name}\": {message}")]
     InvalidFunctionArguments { name: String, message: String },
+    #[error("String pattern is only allowed as a function argument")]
+    UnexpectedStringPattern,
     #[error("Invalid file pattern: {0}")]
     FsPathParseError(#[source] FsPathParseError),
     #[error("Cannot resolve file pattern without workspace")]
@@ -195,6 +200,18 @@ impl error::Error for RevsetParseError {
 pub const GENERATION_RANGE_FULL: Range<u64> = 0..u64::MAX;
 pub const GENERATION_RANGE_EMPTY: Range<u64> = 0..0;
 
+/// How the generation of a commit is determined if the commit is reachable
+/// through multiple paths of different lengths.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum GenerationMode {
+    /// Commits reachable through any path of length in the generation range.
+    Any,
+    /// Commits whose shortest path is in the generation range.
+    Min,
+    /// Commits whose longest path is in the generation range.
+    Max,
+}
+
 /// Symbol or function to be resolved to `CommitId`s.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum RevsetCommitRef {
@@ -210,20 +227,318 @@ pub enum RevsetCommitRef {
     GitHead,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// Pattern to be tested against string property like commit description.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum StringPattern {
+    /// Matches strings exactly equal to the needle.
+    Exact(String),
+    /// Matches strings containing the needle.
+    Substring(String),
+    /// Matches strings containing the needle as a whole word, i.e. not
+    /// adjacent to alphanumeric characters or underscores.
+    Word(String),
+    /// Matches strings by the regular expression. The expression is compiled
+    /// at evaluation time.
+    Regex(String),
+}
+
+/// Pattern to be tested against file paths.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum FilePattern {
+    /// Matches the path and the files under it if it's a directory.
+    PrefixPath(RepoPath),
+    /// Matches file paths by the glob pattern. The pattern is compiled at
+    /// evaluation time.
+    Glob(RepoPath),
+    /// Matches files having the extension (without the leading dot) in any
+    /// directory.
+    Extension(String),
+}
+
+/// Whether a `StringPattern` should distinguish letter cases.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CaseSensitivity {
+    Sensitive,
+    Insensitive,
+}
+
+/// Part of the description to be matched by `Description`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DescriptionScope {
+    /// Text up to the first blank line.
+    Subject,
+    /// Text after the first blank line.
+    Body,
+    Full,
+}
+
+/// Property by which `latest()` groups the candidates.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LatestGroupKey {
+    /// Author email.
+    Author,
+}
+
+impl FromStr for LatestGroupKey {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "author" => Ok(LatestGroupKey::Author),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Kind of malformed author or committer signature.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SignatureCheck {
+    /// Name is empty or whitespace only.
+    EmptyName,
+    /// Email is empty or whitespace only.
+    EmptyEmail,
+    /// Non-empty email without `@`.
+    InvalidEmail,
+    /// Name or email is the placeholder used when the user isn't configured.
+    Placeholder,
+}
+
+impl SignatureCheck {
+    pub const ALL: [SignatureCheck; 4] = [
+        SignatureCheck::EmptyName,
+        SignatureCheck::EmptyEmail,
+        SignatureCheck::InvalidEmail,
+        SignatureCheck::Placeholder,
+    ];
+}
+
+impl FromStr for SignatureCheck {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "empty_name" => Ok(SignatureCheck::EmptyName),
+            "empty_email" => Ok(SignatureCheck::EmptyEmail),
+            "invalid_email" => Ok(SignatureCheck::InvalidEmail),
+            "placeholder" => Ok(SignatureCheck::Placeholder),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum RevsetFilterPredicate {
-    /// Commits with number of parents in the range.
+    /// Commits with number of parents in the range. `merges()` is spelled as
+    /// `ParentCount(2..u32::MAX)`. This can be tested without loading commits
+    /// from the store.
     ParentCount(Range<u32>),
-    /// Commits with description containing the needle.
-    Description(String),
-    /// Commits with author's name or email containing the needle.
-    Author(String),
-    /// Commits with committer's name or email containing the needle.
-    Committer(String),
-    /// Commits modifying the paths specified by the pattern.
-    File(Option<Vec<RepoPath>>), // TODO: embed matcher expression?
-    /// Commits with conflicts
-    HasConflict,
+    /// Commits with index generation number in the range. The generation
+    /// number is the length of the longest path from the root commit. This can
+    /// be tested without loading commits from the store.
+    Generation(Range<u32>),
+    /// Commits whose id starts with any of the prefixes. Unlike a symbol, a
+    /// prefix may match any number of commits.
+    CommitIdPrefixes(Vec<HexPrefix>),
+    /// Commits whose change id starts with any of the prefixes. This can be
+    /// tested without loading commits from the store.
+    ChangeIdPrefixes(Vec<HexPrefix>),
+    /// Commits with the `DescriptionScope` part of the description matching
+    /// the pattern.
+    Description(StringPattern, CaseSensitivity, DescriptionScope),
+    /// Commits with empty or whitespace-only description.
+    NoDescription,
+    /// Commits with the subject of the description matching the pattern. Same
+    /// as `Description` with `DescriptionScope::Subject`.
+    Subject(StringPattern, CaseSensitivity),
+    /// Commits having a trailer of the given key, and the value matching the
+    /// pattern.
+    Trailer(String, StringPattern, CaseSensitivity),
+    /// Commits with `Co-authored-by` trailer's name or email matching the
+    /// pattern. The email is matched case-insensitively.
+    Coauthor(StringPattern, CaseSensitivity),
+    /// Commits with author's name or email matching the pattern.
+    Author(StringPattern, CaseSensitivity),
+    /// Commits with author's name matching the pattern.
+    AuthorName(StringPattern, CaseSensitivity),
+    /// Commits with author's email matching the pattern.
+    AuthorEmail(StringPattern, CaseSensitivity),
+    /// Commits with the domain of author's email, the part after the last
+    /// `@`, equal to the string ignoring case. An email without `@` has no
+    /// domain.
+    AuthorEmailDomain(String),
+    /// Commits with committer's name or email matching the pattern.
+    Committer(StringPattern, CaseSensitivity),
+    /// Commits with committer's name matching the pattern.
+    CommitterName(StringPattern, CaseSensitivity),
+    /// Commits with committer's email matching the pattern.
+    CommitterEmail(StringPattern, CaseSensitivity),
+    /// Commits with author timestamp in the range. Use `MillisSinceEpoch(i64::MIN)`
+    /// or `MillisSinceEpoch(i64::MAX)` for an open-ended range.
+    AuthorDate(Range<MillisSinceEpoch>),
+    /// Commits with committer timestamp in the range.
+    CommitterDate(Range<MillisSinceEpoch>),
+    /// Commits whose committer has a different name or email than the author,
+    /// or, if the minimum delay in seconds is specified, committed at least
+    /// that long after the author timestamp.
+    CommitterDiffers(Option<u64>),
+    /// Commits carrying a cryptographic signature. The signature isn't
+    /// verified.
+    Signed,
+    /// Commits whose description contains control characters other than
+    /// newline and tab, or text that wasn't valid UTF-8.
+    DescriptionControlChars,
+    /// Commits whose author or committer fails any of the checks.
+    MalformedSignature(Vec<SignatureCheck>),
+    /// Commits modifying the paths specified by the `includes` patterns, or
+    /// any paths if `None`. Paths matching the `excludes` patterns are
+    /// ignored.
+    File {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits adding files specified in the same way as `File`.
+    AddsFiles {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits deleting files specified in the same way as `File`. A file
+    /// replaced by a directory, or a directory replaced by a file, counts as
+    /// deletion of the original entries.
+    DeletesFiles {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits flipping the executable bit of files specified in the same way
+    /// as `File`.
+    FileModeChanged {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits changing the executable bit or the kind (file or symlink) of
+    /// files specified in the same way as `File`, without changing the
+    /// content.
+    ModeOnlyChanged {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits adding, removing, or retargeting symlinks specified in the same
+    /// way as `File`.
+    TouchesSymlinks {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits adding, removing, or updating Git submodules specified in the
+    /// same way as `File`.
+    TouchesSubmodules {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits adding, modifying, or removing binary files specified in the
+    /// same way as `File`.
+    TouchesBinary {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits adding or modifying files of at least `min_size` bytes. The
+    /// files are specified in the same way as `File`.
+    AddsLargeFiles {
+        min_size: u64,
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits moving files, whose source or destination is specified in the
+    /// same way as `File`. Only exact content matches are detected.
+    Renames {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits whose diff from the parents adds or removes lines matching the
+    /// `text` pattern. The diff can be restricted to files in the same way as
+    /// `File`.
+    ///
+    /// This reads the file contents of each candidate, so it should be
+    /// intersected with cheaper filters such as `File` to narrow candidates.
+    DiffContains {
+        text: StringPattern,
+        case: CaseSensitivity,
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits whose number of added and removed lines from the parents is in
+    /// the range. Binary files don't count.
+    DiffLines(Range<u64>),
+    /// Commits whose tree is identical to the merged tree of their parents.
+    /// The root commit is empty if its tree is the empty tree.
+    Empty,
+    /// Commits changing only whitespace in text files. Since this has to read
+    /// all the changed files, the optimizer evaluates it after the other
+    /// filters of the same intersection.
+    WhitespaceOnly,
+    /// Commits with conflicts in the files specified in the same way as
+    /// `File`.
+    HasConflict {
+        includes: Option<Vec<FilePattern>>,
+        excludes: Vec<FilePattern>,
+    },
+    /// Commits whose number of conflicted paths is in the range.
+    ConflictCount(Range<usize>),
+    /// Commits matching the filter defined outside of this crate.
+    Extension(Arc<dyn RevsetFilterExtension>),
+}
+
+/// Filter predicate which can be plugged into `RevsetFilterPredicate` by
+/// downstream tools.
+pub trait RevsetFilterExtension: fmt::Debug + Send + Sync {
+    /// Tests if the indexed commit `commit_id` matches the predicate. An error
+    /// is reported to the caller iterating the revset.
+    fn matches(
+        &self,
+        store: &Arc<Store>,
+        index: &dyn Index,
+        commit_id: &CommitId,
+    ) -> Result<bool, RevsetEvaluationError>;
+
+    /// Whether testing a commit is expensive enough to be worth distributing
+    /// the candidates to the thread pool. Parallel evaluation is opt-in since
+    /// the extension may not be ready to be called from multiple threads at
+    /// once.
+    fn is_expensive(&self) -> bool {
+        false
+    }
+
+    /// Tests if the `commit`, which isn't in the index, matches the predicate.
+    /// Extensions which only look up the commit in the index don't support
+    /// this.
+    fn matches_commit(
+        &self,
+        _store: &Arc<Store>,
+        _index: &dyn Index,
+        commit: &Commit,
+    ) -> Result<bool, RevsetEvaluationError> {
+        Err(RevsetEvaluationError::Other(format!(
+            "Commit {} is not in the index",
+            commit.id().hex()
+        )))
+    }
+}
+
+/// Extensions are compared by identity since arbitrary predicates can't be
+/// compared structurally.
+impl PartialEq for dyn RevsetFilterExtension {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(
+            self as *const Self as *const u8,
+            other as *const Self as *const u8,
+        )
+    }
+}
+
+impl Eq for dyn RevsetFilterExtension {}
+
+impl Hash for dyn RevsetFilterExtension {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self as *const Self as *const u8, state);
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -257,13 +572,19 @@ pub enum RevsetExpression {
     Latest {
         candidates: Rc<RevsetExpression>,
         count: usize,
+        offset: usize,
+        /// If set, `count` and `offset` apply to each group.
+        group_by: Option<LatestGroupKey>,
     },
+    /// The commit in the set with the earliest author timestamp.
+    Earliest(Rc<RevsetExpression>),
     Filter(RevsetFilterPredicate),
     /// Marker for subtree that should be intersected as filter.
     AsFilter(Rc<RevsetExpression>),
     Present(Rc<RevsetExpression>),
     NotIn(Rc<RevsetExpression>),
     Union(Rc<RevsetExpression>, Rc<RevsetExpression>),
+    SymmetricDifference(Rc<RevsetExpression>, Rc<RevsetExpression>),
     Intersection(Rc<RevsetExpression>, Rc<RevsetExpression>),
     Difference(Rc<RevsetExpression>, Rc<RevsetExpression>),
 }
@@ -321,12 +642,44 @@ impl RevsetExpression {
     }
 
     pub fn latest(self: &Rc<RevsetExpression>, count: usize) -> Rc<RevsetExpression> {
+        self.latest_with_offset(count, 0)
+    }
+
+    /// Latest `count` commits after skipping the `offset` latest commits.
+    pub fn latest_with_offset(
+        self: &Rc<RevsetExpression>,
+        count: usize,
+        offset: usize,
+    ) -> Rc<RevsetExpression> {
+        Rc::new(RevsetExpression::Latest {
+            candidates: self.clone(),
+            count,
+            offset,
+            group_by: None,
+        })
+    }
+
+    /// Latest `count` commits in each group after skipping the `offset`
+    /// latest commits of the group.
+    pub fn latest_per_group(
+        self: &Rc<RevsetExpression>,
+        count: usize,
+        offset: usize,
+        group_by: LatestGroupKey,
+    ) -> Rc<RevsetExpression> {
         Rc::new(RevsetExpression::Latest {
             candidates: self.clone(),
             count,
+            offset,
+            group_by: Some(group_by),
         })
     }
 
+    /// The commit in `self` with the earliest author timestamp.
+    pub fn earliest(self: &Rc<RevsetExpression>) -> Rc<RevsetExpression> {
+        Rc::new(RevsetExpression::Earliest(self.clone()))
+    }
+
     pub fn filter(predicate: RevsetFilterPredicate) -> Rc<RevsetExpression> {
         Rc::new(RevsetExpression::Filter(predicate))
     }
@@ -416,6 +769,17 @@ impl RevsetExpression {
         Rc::new(RevsetExpression::Union(self.clone(), other.clone()))
     }
 
+    /// Commits that are in exactly one of `self` and `other`.
+    pub fn symmetric_difference(
+        self: &Rc<RevsetExpression>,
+        other: &Rc<RevsetExpression>,
+    ) -> Rc<RevsetExpression> {
+        Rc::new(RevsetExpression::SymmetricDifference(
+            self.clone(),
+            other.clone(),
+        ))
+    }
+
     /// Commits that are in `self` and in `other`.
     pub fn intersection(
         self: &Rc<RevsetExpression>,
@@ -449,7 +813,7 @@ impl RevsetExpression {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum ResolvedPredicateExpression {
     /// Pure filter predicate.
     Filter(RevsetFilterPredicate),
@@ -461,6 +825,10 @@ pub enum ResolvedPredicateExpression {
         Box<ResolvedPredicateExpression>,
         Box<ResolvedPredicateExpression>,
     ),
+    Intersection(
+        Box<ResolvedPredicateExpression>,
+        Box<ResolvedPredicateExpression>,
+    ),
 }
 
 /// Describes evaluation plan of revset expression.
@@ -469,32 +837,58 @@ pub enum ResolvedPredicateExpression {
 /// properties.
 ///
 /// Use `RevsetExpression` API to build a query programmatically.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum ResolvedExpression {
+    /// Empty set.
+    None,
+    /// All visible commits, which are the ancestors of `heads`.
+    All {
+        heads: Box<ResolvedExpression>,
+    },
     Commits(Vec<CommitId>),
     Ancestors {
         heads: Box<ResolvedExpression>,
         generation: Range<u64>,
+        generation_mode: GenerationMode,
     },
+    /// Parents of `candidates`. Same as `Ancestors` at generation `1..2`, but
+    /// the parents are looked up without walking the ancestors.
+    Parents(Box<ResolvedExpression>),
     /// Commits that are ancestors of `heads` but not ancestors of `roots`.
     Range {
         roots: Box<ResolvedExpression>,
         heads: Box<ResolvedExpression>,
         generation: Range<u64>,
     },
+    /// Descendants of `roots` within the `generation` range. Since the index
+    /// may contain hidden commits, the walk is bounded by `heads`, which are
+    /// usually the visible heads.
+    Descendants {
+        roots: Box<ResolvedExpression>,
+        heads: Box<ResolvedExpression>,
+        generation: Range<u64>,
+    },
     /// Commits that are descendants of `roots` and ancestors of `heads`.
     DagRange {
         roots: Box<ResolvedExpression>,
         heads: Box<ResolvedExpression>,
         generation_from_roots: Range<u64>,
     },
+    /// Commits in `candidates` plus the commits on the paths between them.
+    /// Same as `DagRange` of `candidates` to `candidates`, but `candidates` is
+    /// evaluated only once.
+    Connected(Box<ResolvedExpression>),
     Heads(Box<ResolvedExpression>),
     Roots(Box<ResolvedExpression>),
     Latest {
         candidates: Box<ResolvedExpression>,
         count: usize,
+        offset: usize,
+        group_by: Option<LatestGroupKey>,
     },
+    Earliest(Box<ResolvedExpression>),
     Union(Box<ResolvedExpression>, Box<ResolvedExpression>),
+    SymmetricDifference(Box<ResolvedExpression>, Box<ResolvedExpression>),
     /// Intersects `candidates` with `predicate` by filtering.
     FilterWithin {
         candidates: Box<ResolvedExpression>,
@@ -510,10 +904,26 @@ impl ResolvedExpression {
         &self,
         repo: &'index dyn Repo,
     ) -> Result<Box<dyn Revset<'index> + 'index>, RevsetEvaluationError> {
-        repo.index().evaluate_revset(self, repo.store())
+        self.evaluate_with_options(repo, &RevsetEvaluationOptions::default())
+    }
+
+    pub fn evaluate_with_options<'index>(
+        &self,
+        repo: &'index dyn Repo,
+        options: &RevsetEvaluationOptions,
+    ) -> Result<Box<dyn Revset<'index> + 'index>, RevsetEvaluationError> {
+        repo.index().evaluate_revset(self, repo.store(), options)
     }
 }
 
+/// Options to tune the evaluation of revset.
+#[derive(Clone, Debug, Default)]
+pub struct RevsetEvaluationOptions {
+    /// Whether expensive filters like `file()` may test the candidates on the
+    /// rayon thread pool.
+    pub parallel_filters: bool,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct RevsetAliasesMap {
     symbol_aliases: HashMap<String, String>,
@@ -626,6 +1036,7 @@ struct ParseState<'a> {
     aliases_map: &'a RevsetAliasesMap,
     aliases_expanding: &'a [RevsetAliasId<'a>],
     locals: &'a HashMap<&'a str, Rc<RevsetExpression>>,
+    user_email: &'a str,
     workspace_ctx: Option<&'a RevsetWorkspaceContext<'a>>,
 }
 
@@ -650,6 +1061,7 @@ impl ParseState<'_> {
             aliases_map: self.aliases_map,
             aliases_expanding: &aliases_expanding,
             locals,
+            user_email: self.user_email,
             workspace_ctx: self.workspace_ctx,
         };
         f(expanding_state).map_err(|e| {
@@ -708,6 +1120,7 @@ fn parse_expression_rule(
     static PRATT: Lazy<PrattParser<Rule>> = Lazy::new(|| {
         PrattParser::new()
             .op(Op::infix(Rule::union_op, Assoc::Left)
+                | Op::infix(Rule::symmetric_difference_op, Assoc::Left)
                 | Op::infix(Rule::compat_add_op, Assoc::Left))
             .op(Op::infix(Rule::intersection_op, Assoc::Left)
                 | Op::infix(Rule::difference_op, Assoc::Left)
@@ -739,6 +1152,7 @@ fn parse_expression_rule(
         })
         .map_infix(|lhs, op, rhs| match op.as_rule() {
             Rule::union_op => Ok(lhs?.union(&rhs?)),
+            Rule::symmetric_difference_op => Ok(lhs?.symmetric_difference(&rhs?)),
             Rule::compat_add_op => Err(not_infix_op(&op, "|", "union")),
             Rule::intersection_op => Ok(lhs?.intersection(&rhs?)),
             Rule::difference_op => Ok(lhs?.minus(&rhs?)),
@@ -763,6 +1177,10 @@ fn parse_primary_rule(
             let arguments_pair = pairs.next().unwrap();
             parse_function_expression(first, arguments_pair, state, span)
         }
+        Rule::string_pattern => Err(RevsetParseError::with_span(
+            RevsetParseErrorKind::UnexpectedStringPattern,
+            span,
+        )),
         Rule::symbol => parse_symbol_rule(first.into_inner(), state),
         _ => {
             panic!("unexpected revset parse rule: {:?}", first.as_str());
@@ -789,17 +1207,7 @@ fn parse_symbol_rule(
                 Ok(RevsetExpression::symbol(name.to_owned()))
             }
         }
-        Rule::literal_string => {
-            return Ok(RevsetExpression::symbol(
-                first
-                    .as_str()
-                    .strip_prefix('"')
-                    .unwrap()
-                    .strip_suffix('"')
-                    .unwrap()
-                    .to_owned(),
-            ));
-        }
+        Rule::literal_string => Ok(RevsetExpression::symbol(parse_literal_string_rule(first))),
         _ => {
             panic!("unexpected symbol parse rule: {:?}", first.as_str());
         }
@@ -944,15 +1352,31 @@ static BUILTIN_FUNCTION_MAP: Lazy<HashMap<&'static str, RevsetFunction>> = Lazy:
         expect_no_arguments(name, arguments_pair)?;
         Ok(RevsetExpression::git_head())
     });
+    map.insert("earliest", |name, arguments_pair, state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let candidates = parse_expression_rule(arg.into_inner(), state)?;
+        Ok(candidates.earliest())
+    });
     map.insert("latest", |name, arguments_pair, state| {
-        let ([candidates_arg], [count_opt_arg]) = expect_arguments(name, arguments_pair)?;
+        let ([candidates_arg], [count_opt_arg, offset_opt_arg, group_opt_arg]) =
+            expect_named_arguments(name, &["", "", "", "group"], arguments_pair)?;
         let candidates = parse_expression_rule(candidates_arg.into_inner(), state)?;
         let count = if let Some(count_arg) = count_opt_arg {
             parse_function_argument_as_literal("integer", name, count_arg, state)?
         } else {
             1
         };
-        Ok(candidates.latest(count))
+        let offset = if let Some(offset_arg) = offset_opt_arg {
+            parse_function_argument_as_literal("integer", name, offset_arg, state)?
+        } else {
+            0
+        };
+        if let Some(group_arg) = group_opt_arg {
+            let group_by = parse_function_argument_as_literal("group", name, group_arg, state)?;
+            Ok(candidates.latest_per_group(count, offset, group_by))
+        } else {
+            Ok(candidates.latest_with_offset(count, offset))
+        }
     });
     map.insert("merges", |name, arguments_pair, _state| {
         expect_no_arguments(name, arguments_pair)?;
@@ -960,71 +1384,343 @@ static BUILTIN_FUNCTION_MAP: Lazy<HashMap<&'static str, RevsetFunction>> = Lazy:
             RevsetFilterPredicate::ParentCount(2..u32::MAX),
         ))
     });
+    map.insert("generation", |name, arguments_pair, _state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let range = parse_function_argument_as_range(name, arg)?;
+        let to_u32 = |n: u64| u32::try_from(n).unwrap_or(u32::MAX);
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::Generation(
+            to_u32(range.start)..to_u32(range.end),
+        )))
+    });
     map.insert("description", |name, arguments_pair, state| {
         let arg = expect_one_argument(name, arguments_pair)?;
-        let needle = parse_function_argument_to_string(name, arg, state)?;
+        let (pattern, case, scope) =
+            parse_function_argument_to_description_pattern(name, arg, state)?;
         Ok(RevsetExpression::filter(
-            RevsetFilterPredicate::Description(needle),
+            RevsetFilterPredicate::Description(pattern, case, scope),
         ))
     });
+    map.insert("no_description", |name, arguments_pair, _state| {
+        expect_no_arguments(name, arguments_pair)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::NoDescription,
+        ))
+    });
+    map.insert("subject", |name, arguments_pair, state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let (pattern, case) = parse_function_argument_to_string_pattern(name, arg, state)?;
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::Subject(
+            pattern, case,
+        )))
+    });
+    map.insert("trailer", |name, arguments_pair, state| {
+        let ([key_arg], [value_opt_arg]) = expect_arguments(name, arguments_pair)?;
+        let key = parse_function_argument_to_string(name, key_arg, state)?;
+        let (pattern, case) = if let Some(value_arg) = value_opt_arg {
+            parse_function_argument_to_string_pattern(name, value_arg, state)?
+        } else {
+            (
+                StringPattern::Substring(String::new()),
+                CaseSensitivity::Sensitive,
+            )
+        };
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::Trailer(
+            key, pattern, case,
+        )))
+    });
+    map.insert("coauthor", |name, arguments_pair, state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let (pattern, case) = parse_function_argument_to_string_pattern(name, arg, state)?;
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::Coauthor(
+            pattern, case,
+        )))
+    });
     map.insert("author", |name, arguments_pair, state| {
         let arg = expect_one_argument(name, arguments_pair)?;
-        let needle = parse_function_argument_to_string(name, arg, state)?;
+        let (pattern, case) = parse_function_argument_to_string_pattern(name, arg, state)?;
         Ok(RevsetExpression::filter(RevsetFilterPredicate::Author(
-            needle,
+            pattern, case,
+        )))
+    });
+    map.insert("author_name", |name, arguments_pair, state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let (pattern, case) = parse_function_argument_to_string_pattern(name, arg, state)?;
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::AuthorName(
+            pattern, case,
         )))
     });
+    map.insert("author_email", |name, arguments_pair, state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let (pattern, case) = parse_function_argument_to_string_pattern(name, arg, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::AuthorEmail(pattern, case),
+        ))
+    });
+    map.insert("author_email_domain", |name, arguments_pair, state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let domain = parse_function_argument_to_string(name, arg, state)?;
+        // Allow `@example.com` as well as `example.com`
+        let domain = domain.strip_prefix('@').unwrap_or(&domain).to_owned();
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::AuthorEmailDomain(domain),
+        ))
+    });
     map.insert("committer", |name, arguments_pair, state| {
         let arg = expect_one_argument(name, arguments_pair)?;
-        let needle = parse_function_argument_to_string(name, arg, state)?;
+        let (pattern, case) = parse_function_argument_to_string_pattern(name, arg, state)?;
         Ok(RevsetExpression::filter(RevsetFilterPredicate::Committer(
-            needle,
+            pattern, case,
         )))
     });
-    map.insert("empty", |name, arguments_pair, _state| {
+    map.insert("committer_name", |name, arguments_pair, state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let (pattern, case) = parse_function_argument_to_string_pattern(name, arg, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::CommitterName(pattern, case),
+        ))
+    });
+    map.insert("committer_email", |name, arguments_pair, state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let (pattern, case) = parse_function_argument_to_string_pattern(name, arg, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::CommitterEmail(pattern, case),
+        ))
+    });
+    map.insert("author_date", |name, arguments_pair, state| {
+        let range = parse_date_range_arguments(name, arguments_pair, state)?;
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::AuthorDate(
+            range,
+        )))
+    });
+    map.insert("committer_date", |name, arguments_pair, state| {
+        let range = parse_date_range_arguments(name, arguments_pair, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::CommitterDate(range),
+        ))
+    });
+    map.insert("committer_differs", |name, arguments_pair, state| {
+        let ([], [min_delay_opt_arg]) = expect_arguments(name, arguments_pair)?;
+        let min_delay = if let Some(min_delay_arg) = min_delay_opt_arg {
+            Some(parse_function_argument_as_literal(
+                "integer",
+                name,
+                min_delay_arg,
+                state,
+            )?)
+        } else {
+            None
+        };
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::CommitterDiffers(min_delay),
+        ))
+    });
+    map.insert("malformed_signature", |name, arguments_pair, state| {
+        let checks = arguments_pair
+            .into_inner()
+            .map(|arg| parse_function_argument_as_literal("signature check", name, arg, state))
+            .collect::<Result<Vec<SignatureCheck>, _>>()?;
+        let checks = if checks.is_empty() {
+            SignatureCheck::ALL.to_vec()
+        } else {
+            checks
+        };
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::MalformedSignature(checks),
+        ))
+    });
+    map.insert(
+        "description_control_chars",
+        |name, arguments_pair, _state| {
+            expect_no_arguments(name, arguments_pair)?;
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::DescriptionControlChars,
+            ))
+        },
+    );
+    map.insert("signed", |name, arguments_pair, _state| {
         expect_no_arguments(name, arguments_pair)?;
-        Ok(RevsetExpression::filter(RevsetFilterPredicate::File(None)).negated())
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::Signed))
     });
-    map.insert("file", |name, arguments_pair, state| {
-        if let Some(ctx) = state.workspace_ctx {
-            let arguments_span = arguments_pair.as_span();
-            let paths: Vec<_> = arguments_pair
-                .into_inner()
-                .map(|arg| -> Result<_, RevsetParseError> {
-                    let span = arg.as_span();
-                    let needle = parse_function_argument_to_string(name, arg, state)?;
-                    let path = RepoPath::parse_fs_path(ctx.cwd, ctx.workspace_root, &needle)
-                        .map_err(|e| {
-                            RevsetParseError::with_span(
-                                RevsetParseErrorKind::FsPathParseError(e),
-                                span,
-                            )
-                        })?;
-                    Ok(path)
-                })
-                .try_collect()?;
-            if paths.is_empty() {
-                Err(RevsetParseError::with_span(
-                    RevsetParseErrorKind::InvalidFunctionArguments {
-                        name: name.to_owned(),
-                        message: "Expected at least 1 argument".to_string(),
-                    },
-                    arguments_span,
-                ))
-            } else {
-                Ok(RevsetExpression::filter(RevsetFilterPredicate::File(Some(
-                    paths,
-                ))))
-            }
+    map.insert("mine", |name, arguments_pair, state| {
+        expect_no_arguments(name, arguments_pair)?;
+        // Don't match commits with no author email if the user email isn't set.
+        if state.user_email.is_empty() {
+            Ok(RevsetExpression::none())
         } else {
-            Err(RevsetParseError::new(
-                RevsetParseErrorKind::FsPathWithoutWorkspace,
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::AuthorEmail(
+                    StringPattern::Exact(state.user_email.to_owned()),
+                    CaseSensitivity::Insensitive,
+                ),
             ))
         }
     });
-    map.insert("conflict", |name, arguments_pair, _state| {
+    map.insert("empty", |name, arguments_pair, _state| {
+        expect_no_arguments(name, arguments_pair)?;
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::Empty))
+    });
+    map.insert("whitespace_only", |name, arguments_pair, _state| {
         expect_no_arguments(name, arguments_pair)?;
-        Ok(RevsetExpression::filter(RevsetFilterPredicate::HasConflict))
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::WhitespaceOnly,
+        ))
+    });
+    map.insert("file", |name, arguments_pair, state| {
+        let arguments_span = arguments_pair.as_span();
+        let args = arguments_pair.into_inner();
+        if args.peek().is_none() {
+            return Err(RevsetParseError::with_span(
+                RevsetParseErrorKind::InvalidFunctionArguments {
+                    name: name.to_owned(),
+                    message: "Expected at least 1 argument".to_string(),
+                },
+                arguments_span,
+            ));
+        }
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::File {
+            includes,
+            excludes,
+        }))
+    });
+    map.insert("present_ids", |name, arguments_pair, state| {
+        let prefixes =
+            parse_function_arguments_to_hex_prefixes(name, arguments_pair, state, "commit id")?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::CommitIdPrefixes(prefixes),
+        ))
+    });
+    map.insert("present_change_ids", |name, arguments_pair, state| {
+        let prefixes =
+            parse_function_arguments_to_hex_prefixes(name, arguments_pair, state, "change id")?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::ChangeIdPrefixes(prefixes),
+        ))
+    });
+    map.insert("adds_files", |name, arguments_pair, state| {
+        let args = arguments_pair.into_inner();
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::AddsFiles {
+            includes,
+            excludes,
+        }))
+    });
+    map.insert("deletes_files", |name, arguments_pair, state| {
+        let args = arguments_pair.into_inner();
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::DeletesFiles { includes, excludes },
+        ))
+    });
+    map.insert("file_mode_changed", |name, arguments_pair, state| {
+        let args = arguments_pair.into_inner();
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::FileModeChanged { includes, excludes },
+        ))
+    });
+    map.insert("mode_only_changed", |name, arguments_pair, state| {
+        let args = arguments_pair.into_inner();
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::ModeOnlyChanged { includes, excludes },
+        ))
+    });
+    map.insert("touches_symlinks", |name, arguments_pair, state| {
+        let args = arguments_pair.into_inner();
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::TouchesSymlinks { includes, excludes },
+        ))
+    });
+    map.insert("touches_submodules", |name, arguments_pair, state| {
+        let args = arguments_pair.into_inner();
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::TouchesSubmodules { includes, excludes },
+        ))
+    });
+    map.insert("touches_binary", |name, arguments_pair, state| {
+        let args = arguments_pair.into_inner();
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::TouchesBinary { includes, excludes },
+        ))
+    });
+    map.insert("adds_large_files", |name, arguments_pair, state| {
+        let arguments_span = arguments_pair.as_span();
+        let mut args = arguments_pair.into_inner();
+        let size_arg = args.next().ok_or_else(|| {
+            RevsetParseError::with_span(
+                RevsetParseErrorKind::InvalidFunctionArguments {
+                    name: name.to_owned(),
+                    message: "Expected at least 1 argument".to_string(),
+                },
+                arguments_span,
+            )
+        })?;
+        let min_size = parse_function_argument_as_byte_size(name, size_arg, state)?;
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::AddsLargeFiles {
+                min_size,
+                includes,
+                excludes,
+            },
+        ))
+    });
+    map.insert("renames", |name, arguments_pair, state| {
+        let args = arguments_pair.into_inner();
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::Renames {
+            includes,
+            excludes,
+        }))
+    });
+    map.insert("diff_contains", |name, arguments_pair, state| {
+        let arguments_span = arguments_pair.as_span();
+        let mut args = arguments_pair.into_inner();
+        let text_arg = args.next().ok_or_else(|| {
+            RevsetParseError::with_span(
+                RevsetParseErrorKind::InvalidFunctionArguments {
+                    name: name.to_owned(),
+                    message: "Expected at least 1 argument".to_string(),
+                },
+                arguments_span,
+            )
+        })?;
+        let (text, case) = parse_function_argument_to_string_pattern(name, text_arg, state)?;
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::DiffContains {
+                text,
+                case,
+                includes,
+                excludes,
+            },
+        ))
+    });
+    map.insert("diff_lines", |name, arguments_pair, _state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let range = parse_function_argument_as_range(name, arg)?;
+        Ok(RevsetExpression::filter(RevsetFilterPredicate::DiffLines(
+            range,
+        )))
+    });
+    map.insert("conflicts", |name, arguments_pair, _state| {
+        let arg = expect_one_argument(name, arguments_pair)?;
+        let range = parse_function_argument_as_range(name, arg)?;
+        let to_usize = |n: u64| usize::try_from(n).unwrap_or(usize::MAX);
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::ConflictCount(to_usize(range.start)..to_usize(range.end)),
+        ))
+    });
+    map.insert("conflict", |name, arguments_pair, state| {
+        let args = arguments_pair.into_inner();
+        let (includes, excludes) = parse_function_arguments_to_file_patterns(name, args, state)?;
+        Ok(RevsetExpression::filter(
+            RevsetFilterPredicate::HasConflict { includes, excludes },
+        ))
     });
     map.insert("present", |name, arguments_pair, state| {
         let arg = expect_one_argument(name, arguments_pair)?;
@@ -1164,23 +1860,370 @@ fn parse_function_argument_to_string(
     parse_function_argument_as_literal("string", name, pair, state)
 }
 
-fn parse_function_argument_as_literal<T: FromStr>(
-    type_name: &str,
+fn parse_function_argument_to_string_pattern(
     name: &str,
     pair: Pair<Rule>,
     state: ParseState,
-) -> Result<T, RevsetParseError> {
-    let span = pair.as_span();
-    let make_error = || {
-        RevsetParseError::with_span(
+) -> Result<(StringPattern, CaseSensitivity), RevsetParseError> {
+    if let Some(pattern_pair) = as_string_pattern_rule(pair.clone().into_inner()) {
+        parse_string_pattern_rule(name, pattern_pair)
+    } else {
+        let needle = parse_function_argument_to_string(name, pair, state)?;
+        Ok((StringPattern::Substring(needle), CaseSensitivity::Sensitive))
+    }
+}
+
+/// Parses one or more arguments into hex prefixes of `id_kind` ids. Empty and
+/// non-hex prefixes are rejected.
+fn parse_function_arguments_to_hex_prefixes(
+    name: &str,
+    arguments_pair: Pair<Rule>,
+    state: ParseState,
+    id_kind: &str,
+) -> Result<Vec<HexPrefix>, RevsetParseError> {
+    let arguments_span = arguments_pair.as_span();
+    let args = arguments_pair.into_inner();
+    if args.peek().is_none() {
+        return Err(RevsetParseError::with_span(
             RevsetParseErrorKind::InvalidFunctionArguments {
-                name: name.to_string(),
-                message: format!("Expected function argument of type {type_name}"),
+                name: name.to_owned(),
+                message: "Expected at least 1 argument".to_string(),
             },
-            span,
+            arguments_span,
+        ));
+    }
+    args.map(|arg| {
+        let span = arg.as_span();
+        let text = parse_function_argument_to_string(name, arg, state)?;
+        HexPrefix::new(&text)
+            .filter(|_| !text.is_empty())
+            .ok_or_else(|| {
+                RevsetParseError::with_span(
+                    RevsetParseErrorKind::InvalidFunctionArguments {
+                        name: name.to_owned(),
+                        message: format!(r#"Invalid {id_kind} prefix "{text}""#),
+                    },
+                    span,
+                )
+            })
+    })
+    .try_collect()
+}
+
+/// Parses `description()` argument, which may be prefixed with `subject:`,
+/// `body:`, or `full:` in place of the string pattern kind to select the part
+/// to be searched.
+fn parse_function_argument_to_description_pattern(
+    name: &str,
+    pair: Pair<Rule>,
+    state: ParseState,
+) -> Result<(StringPattern, CaseSensitivity, DescriptionScope), RevsetParseError> {
+    if let Some(pattern_pair) = as_string_pattern_rule(pair.clone().into_inner()) {
+        let mut pairs = pattern_pair.into_inner();
+        let scope = match pairs.next().unwrap().as_str() {
+            "subject" => Some(DescriptionScope::Subject),
+            "body" => Some(DescriptionScope::Body),
+            "full" => Some(DescriptionScope::Full),
+            _ => None,
+        };
+        if let Some(scope) = scope {
+            let needle = parse_literal_string_rule(pairs.next().unwrap());
+            let pattern = StringPattern::Substring(needle);
+            return Ok((pattern, CaseSensitivity::Sensitive, scope));
+        }
+    }
+    let (pattern, case) = parse_function_argument_to_string_pattern(name, pair, state)?;
+    Ok((pattern, case, DescriptionScope::Full))
+}
+
+/// Parses file pattern arguments into included and excluded patterns. If only
+/// exclusions are specified, the included patterns are `None`, which matches
+/// everything but them.
+fn parse_function_arguments_to_file_patterns(
+    name: &str,
+    pairs: Pairs<Rule>,
+    state: ParseState,
+) -> Result<(Option<Vec<FilePattern>>, Vec<FilePattern>), RevsetParseError> {
+    if pairs.peek().is_none() {
+        return Ok((None, vec![]));
+    }
+    let ctx = state
+        .workspace_ctx
+        .ok_or_else(|| RevsetParseError::new(RevsetParseErrorKind::FsPathWithoutWorkspace))?;
+    let mut includes = vec![];
+    let mut excludes = vec![];
+    for pair in pairs {
+        match parse_function_argument_to_file_pattern(name, pair, state, ctx)? {
+            (false, pattern) => includes.push(pattern),
+            (true, pattern) => excludes.push(pattern),
+        }
+    }
+    let includes = (!includes.is_empty()).then_some(includes);
+    Ok((includes, excludes))
+}
+
+/// Parses `file()` argument. Returns `true` if the pattern is negated by `~`.
+fn parse_function_argument_to_file_pattern(
+    name: &str,
+    pair: Pair<Rule>,
+    state: ParseState,
+    ctx: &RevsetWorkspaceContext,
+) -> Result<(bool, FilePattern), RevsetParseError> {
+    let span = pair.as_span();
+    let parse_path = |input: &str| {
+        RepoPath::parse_fs_path(ctx.cwd, ctx.workspace_root, input).map_err(|e| {
+            RevsetParseError::with_span(RevsetParseErrorKind::FsPathParseError(e), span)
+        })
+    };
+    let mut pairs = pair.into_inner();
+    let negated = pairs
+        .peek()
+        .map_or(false, |pair| pair.as_rule() == Rule::negate_op);
+    if negated {
+        pairs.next();
+    }
+    let pattern = if let Some(pattern_pair) = as_string_pattern_rule(pairs.clone()) {
+        let mut pairs = pattern_pair.into_inner();
+        let kind = pairs.next().unwrap();
+        let needle = parse_literal_string_rule(pairs.next().unwrap());
+        match kind.as_str() {
+            "glob" => FilePattern::Glob(parse_path(&needle)?),
+            "ext" => {
+                if needle.is_empty() || needle.starts_with('.') || needle.contains('/') {
+                    return Err(RevsetParseError::with_span(
+                        RevsetParseErrorKind::InvalidFunctionArguments {
+                            name: name.to_owned(),
+                            message: format!(r#"Invalid file extension "{needle}""#),
+                        },
+                        span,
+                    ));
+                }
+                FilePattern::Extension(needle)
+            }
+            kind => {
+                return Err(RevsetParseError::with_span(
+                    RevsetParseErrorKind::InvalidFunctionArguments {
+                        name: name.to_owned(),
+                        message: format!(r#"Invalid file pattern kind "{kind}""#),
+                    },
+                    span,
+                ));
+            }
+        }
+    } else {
+        let needle: String = parse_expression_as_literal("string", name, pairs, span, state)?;
+        let path = parse_path(&needle)?;
+        // Paths containing glob metacharacters are matched as globs for
+        // compatibility.
+        if is_glob_pattern(&needle) {
+            FilePattern::Glob(path)
+        } else {
+            FilePattern::PrefixPath(path)
+        }
+    };
+    Ok((negated, pattern))
+}
+
+/// Extracts `kind:"needle"` node if the argument `pairs` consist of it alone.
+fn as_string_pattern_rule(mut pairs: Pairs<Rule>) -> Option<Pair<Rule>> {
+    let primary = pairs.next().filter(|p| p.as_rule() == Rule::primary)?;
+    if pairs.next().is_some() {
+        return None;
+    }
+    let first = primary.into_inner().next().unwrap();
+    (first.as_rule() == Rule::string_pattern).then_some(first)
+}
+
+fn parse_string_pattern_rule(
+    name: &str,
+    pair: Pair<Rule>,
+) -> Result<(StringPattern, CaseSensitivity), RevsetParseError> {
+    let span = pair.as_span();
+    let mut pairs = pair.into_inner();
+    let kind = pairs.next().unwrap();
+    let needle = parse_literal_string_rule(pairs.next().unwrap());
+    let (kind, case) = match kind.as_str().strip_suffix("-i") {
+        Some(kind) => (kind, CaseSensitivity::Insensitive),
+        None => (kind.as_str(), CaseSensitivity::Sensitive),
+    };
+    let pattern = match kind {
+        "exact" => StringPattern::Exact(needle),
+        "substring" => StringPattern::Substring(needle),
+        "word" => StringPattern::Word(needle),
+        "regex" => StringPattern::Regex(needle),
+        kind => {
+            return Err(RevsetParseError::with_span(
+                RevsetParseErrorKind::InvalidFunctionArguments {
+                    name: name.to_owned(),
+                    message: format!(r#"Invalid string pattern kind "{kind}""#),
+                },
+                span,
+            ));
+        }
+    };
+    Ok((pattern, case))
+}
+
+fn parse_literal_string_rule(pair: Pair<Rule>) -> String {
+    assert_eq!(pair.as_rule(), Rule::literal_string);
+    pair.as_str()
+        .strip_prefix('"')
+        .unwrap()
+        .strip_suffix('"')
+        .unwrap()
+        .to_owned()
+}
+
+/// Parses `after`/`before` arguments into a range of timestamps. An omitted
+/// bound is unlimited.
+fn parse_date_range_arguments(
+    name: &str,
+    arguments_pair: Pair<Rule>,
+    state: ParseState,
+) -> Result<Range<MillisSinceEpoch>, RevsetParseError> {
+    let arguments_span = arguments_pair.as_span();
+    let ([], [after_opt_arg, before_opt_arg]) =
+        expect_named_arguments(name, &["after", "before"], arguments_pair)?;
+    if after_opt_arg.is_none() && before_opt_arg.is_none() {
+        return Err(RevsetParseError::with_span(
+            RevsetParseErrorKind::InvalidFunctionArguments {
+                name: name.to_owned(),
+                message: "Expected at least one of \"after\" or \"before\"".to_owned(),
+            },
+            arguments_span,
+        ));
+    }
+    let start = if let Some(after_arg) = after_opt_arg {
+        parse_function_argument_to_timestamp(name, after_arg, state)?
+    } else {
+        MillisSinceEpoch(i64::MIN)
+    };
+    let end = if let Some(before_arg) = before_opt_arg {
+        parse_function_argument_to_timestamp(name, before_arg, state)?
+    } else {
+        MillisSinceEpoch(i64::MAX)
+    };
+    Ok(start..end)
+}
+
+fn parse_function_argument_to_timestamp(
+    name: &str,
+    pair: Pair<Rule>,
+    state: ParseState,
+) -> Result<MillisSinceEpoch, RevsetParseError> {
+    let span = pair.as_span();
+    let text = parse_function_argument_to_string(name, pair, state)?;
+    // A date without time is the midnight in UTC.
+    DateTime::parse_from_rfc3339(&text)
+        .or_else(|_| DateTime::parse_from_rfc3339(&format!("{text}T00:00:00Z")))
+        .map(|datetime| Timestamp::from_datetime(datetime).timestamp)
+        .map_err(|_| {
+            RevsetParseError::with_span(
+                RevsetParseErrorKind::InvalidFunctionArguments {
+                    name: name.to_owned(),
+                    message: format!(
+                        r#"Invalid date "{text}", expected "YYYY-MM-DD" or RFC 3339 timestamp"#
+                    ),
+                },
+                span,
+            )
+        })
+}
+
+fn parse_function_argument_as_literal<T: FromStr>(
+    type_name: &str,
+    name: &str,
+    pair: Pair<Rule>,
+    state: ParseState,
+) -> Result<T, RevsetParseError> {
+    let span = pair.as_span();
+    parse_expression_as_literal(type_name, name, pair.into_inner(), span, state)
+}
+
+/// Parses a number of bytes with an optional `B`, `KiB`, `MiB`, or `GiB`
+/// unit, such as `10MiB`.
+fn parse_function_argument_as_byte_size(
+    name: &str,
+    pair: Pair<Rule>,
+    state: ParseState,
+) -> Result<u64, RevsetParseError> {
+    let span = pair.as_span();
+    let text: String = parse_function_argument_as_literal("size", name, pair, state)?;
+    parse_byte_size(&text).ok_or_else(|| {
+        RevsetParseError::with_span(
+            RevsetParseErrorKind::InvalidFunctionArguments {
+                name: name.to_string(),
+                message: "Expected function argument of type size".to_string(),
+            },
+            span,
+        )
+    })
+}
+
+fn parse_byte_size(text: &str) -> Option<u64> {
+    let unit_start = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(unit_start);
+    let multiplier = match unit {
+        "" | "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses `n`, `start..`, `..end`, or `start..end` as a half-open range of
+/// non-negative integers.
+fn parse_function_argument_as_range(
+    name: &str,
+    pair: Pair<Rule>,
+) -> Result<Range<u64>, RevsetParseError> {
+    let span = pair.as_span();
+    let make_error = || {
+        RevsetParseError::with_span(
+            RevsetParseErrorKind::InvalidFunctionArguments {
+                name: name.to_string(),
+                message: "Expected function argument of type range".to_string(),
+            },
+            span,
+        )
+    };
+    let parse_bound = |text: &str, default: u64| {
+        if text.is_empty() {
+            Ok(default)
+        } else {
+            text.parse().map_err(|_| make_error())
+        }
+    };
+    match pair.as_str().split_once("..") {
+        Some((start, end)) => Ok(parse_bound(start, 0)?..parse_bound(end, u64::MAX)?),
+        None => {
+            let n: u64 = pair.as_str().parse().map_err(|_| make_error())?;
+            Ok(n..n.saturating_add(1))
+        }
+    }
+}
+
+fn parse_expression_as_literal<T: FromStr>(
+    type_name: &str,
+    name: &str,
+    pairs: Pairs<Rule>,
+    span: pest::Span<'_>,
+    state: ParseState,
+) -> Result<T, RevsetParseError> {
+    let make_error = || {
+        RevsetParseError::with_span(
+            RevsetParseErrorKind::InvalidFunctionArguments {
+                name: name.to_string(),
+                message: format!("Expected function argument of type {type_name}"),
+            },
+            span,
         )
     };
-    let expression = parse_expression_rule(pair.into_inner(), state)?;
+    let expression = parse_expression_rule(pairs, state)?;
     match expression.as_ref() {
         RevsetExpression::CommitRef(RevsetCommitRef::Symbol(symbol)) => {
             symbol.parse().map_err(|_| make_error())
@@ -1191,14 +2234,14 @@ fn parse_function_argument_as_literal<T: FromStr>(
 
 pub fn parse(
     revset_str: &str,
-    aliases_map: &RevsetAliasesMap,
-    workspace_ctx: Option<&RevsetWorkspaceContext>,
+    context: &RevsetParseContext,
 ) -> Result<Rc<RevsetExpression>, RevsetParseError> {
     let state = ParseState {
-        aliases_map,
+        aliases_map: context.aliases_map,
         aliases_expanding: &[],
         locals: &HashMap::new(),
-        workspace_ctx,
+        user_email: context.user_email,
+        workspace_ctx: context.workspace.as_ref(),
     };
     parse_program(revset_str, state)
 }
@@ -1273,11 +2316,20 @@ fn try_transform_expression<E>(
             RevsetExpression::Roots(candidates) => {
                 transform_rec(candidates, pre, post)?.map(RevsetExpression::Roots)
             }
-            RevsetExpression::Latest { candidates, count } => transform_rec(candidates, pre, post)?
-                .map(|candidates| RevsetExpression::Latest {
-                    candidates,
-                    count: *count,
-                }),
+            RevsetExpression::Latest {
+                candidates,
+                count,
+                offset,
+                group_by,
+            } => transform_rec(candidates, pre, post)?.map(|candidates| RevsetExpression::Latest {
+                candidates,
+                count: *count,
+                offset: *offset,
+                group_by: *group_by,
+            }),
+            RevsetExpression::Earliest(candidates) => {
+                transform_rec(candidates, pre, post)?.map(RevsetExpression::Earliest)
+            }
             RevsetExpression::Filter(_) => None,
             RevsetExpression::AsFilter(candidates) => {
                 transform_rec(candidates, pre, post)?.map(RevsetExpression::AsFilter)
@@ -1293,6 +2345,13 @@ fn try_transform_expression<E>(
                     |(expression1, expression2)| RevsetExpression::Union(expression1, expression2),
                 )
             }
+            RevsetExpression::SymmetricDifference(expression1, expression2) => {
+                transform_rec_pair((expression1, expression2), pre, post)?.map(
+                    |(expression1, expression2)| {
+                        RevsetExpression::SymmetricDifference(expression1, expression2)
+                    },
+                )
+            }
             RevsetExpression::Intersection(expression1, expression2) => {
                 transform_rec_pair((expression1, expression2), pre, post)?.map(
                     |(expression1, expression2)| {
@@ -1369,6 +2428,14 @@ fn internalize_filter(expression: &Rc<RevsetExpression>) -> TransformedExpressio
         is_filter(expression) || as_filter_intersection(expression).is_some()
     }
 
+    // Filter which should be tested after the other filters.
+    fn is_deferred_filter(expression: &RevsetExpression) -> bool {
+        matches!(
+            expression,
+            RevsetExpression::Filter(RevsetFilterPredicate::WhitespaceOnly)
+        )
+    }
+
     // Extracts 'c & f' from intersect_down()-ed node.
     fn as_filter_intersection(
         expression: &RevsetExpression,
@@ -1390,6 +2457,19 @@ fn internalize_filter(expression: &Rc<RevsetExpression>) -> TransformedExpressio
     ) -> TransformedExpression {
         let recurse = |e1, e2| intersect_down(e1, e2).unwrap_or_else(|| e1.intersection(e2));
         match (expression1.as_ref(), expression2.as_ref()) {
+            // f1 & f2 -> f2 & f1 if f1 is deferred
+            (e1, e2) if is_deferred_filter(e1) && is_filter(e2) && !is_deferred_filter(e2) => {
+                Some(expression2.intersection(expression1))
+            }
+            // (c1 & f1) & f2 -> (c1 & f2) & f1 if f1 is deferred
+            (e1, e2) if is_filter(e2) && !is_deferred_filter(e2) => {
+                match as_filter_intersection(e1) {
+                    Some((c1, f1)) if is_deferred_filter(f1) => {
+                        Some(recurse(c1, expression2).intersection(f1))
+                    }
+                    _ => None,
+                }
+            }
             // Don't reorder 'f1 & f2'
             (_, e2) if is_filter(e2) => None,
             // f1 & e2 -> e2 & f1
@@ -1847,7 +2927,7 @@ impl VisibilityResolutionContext<'_> {
     /// Resolves expression tree as set.
     fn resolve(&self, expression: &RevsetExpression) -> ResolvedExpression {
         match expression {
-            RevsetExpression::None => ResolvedExpression::Commits(vec![]),
+            RevsetExpression::None => ResolvedExpression::None,
             RevsetExpression::All => self.resolve_all(),
             RevsetExpression::Commits(commit_ids) => {
                 ResolvedExpression::Commits(commit_ids.clone())
@@ -1855,15 +2935,21 @@ impl VisibilityResolutionContext<'_> {
             RevsetExpression::CommitRef(_) => {
                 panic!("Expression '{expression:?}' should have been resolved by caller");
             }
+            RevsetExpression::Ancestors { heads, generation } if generation == &(1..2) => {
+                ResolvedExpression::Parents(self.resolve(heads).into())
+            }
             RevsetExpression::Ancestors { heads, generation } => ResolvedExpression::Ancestors {
                 heads: self.resolve(heads).into(),
                 generation: generation.clone(),
+                generation_mode: GenerationMode::Any,
             },
-            RevsetExpression::Descendants { roots, generation } => ResolvedExpression::DagRange {
-                roots: self.resolve(roots).into(),
-                heads: self.resolve_visible_heads().into(),
-                generation_from_roots: generation.clone(),
-            },
+            RevsetExpression::Descendants { roots, generation } => {
+                ResolvedExpression::Descendants {
+                    roots: self.resolve(roots).into(),
+                    heads: self.resolve_visible_heads().into(),
+                    generation: generation.clone(),
+                }
+            }
             RevsetExpression::Range {
                 roots,
                 heads,
@@ -1873,6 +2959,9 @@ impl VisibilityResolutionContext<'_> {
                 heads: self.resolve(heads).into(),
                 generation: generation.clone(),
             },
+            RevsetExpression::DagRange { roots, heads } if roots == heads => {
+                ResolvedExpression::Connected(self.resolve(roots).into())
+            }
             RevsetExpression::DagRange { roots, heads } => ResolvedExpression::DagRange {
                 roots: self.resolve(roots).into(),
                 heads: self.resolve(heads).into(),
@@ -1884,10 +2973,20 @@ impl VisibilityResolutionContext<'_> {
             RevsetExpression::Roots(candidates) => {
                 ResolvedExpression::Roots(self.resolve(candidates).into())
             }
-            RevsetExpression::Latest { candidates, count } => ResolvedExpression::Latest {
+            RevsetExpression::Latest {
+                candidates,
+                count,
+                offset,
+                group_by,
+            } => ResolvedExpression::Latest {
                 candidates: self.resolve(candidates).into(),
                 count: *count,
+                offset: *offset,
+                group_by: *group_by,
             },
+            RevsetExpression::Earliest(candidates) => {
+                ResolvedExpression::Earliest(self.resolve(candidates).into())
+            }
             RevsetExpression::Filter(_) | RevsetExpression::AsFilter(_) => {
                 // Top-level filter without intersection: e.g. "~author(_)" is represented as
                 // `AsFilter(NotIn(Filter(Author(_))))`.
@@ -1907,6 +3006,12 @@ impl VisibilityResolutionContext<'_> {
                 self.resolve(expression1).into(),
                 self.resolve(expression2).into(),
             ),
+            RevsetExpression::SymmetricDifference(expression1, expression2) => {
+                ResolvedExpression::SymmetricDifference(
+                    self.resolve(expression1).into(),
+                    self.resolve(expression2).into(),
+                )
+            }
             RevsetExpression::Intersection(expression1, expression2) => {
                 match expression2.as_ref() {
                     RevsetExpression::Filter(_) | RevsetExpression::AsFilter(_) => {
@@ -1938,9 +3043,8 @@ impl VisibilityResolutionContext<'_> {
         // (and `remote_branches()`) specified in the revset expression. Alternatively,
         // some optimization rules could be removed, but that means `author(_) & x`
         // would have to test `:visble_heads() & x`.
-        ResolvedExpression::Ancestors {
+        ResolvedExpression::All {
             heads: self.resolve_visible_heads().into(),
-            generation: GENERATION_RANGE_FULL,
         }
     }
 
@@ -1964,7 +3068,8 @@ impl VisibilityResolutionContext<'_> {
             | RevsetExpression::DagRange { .. }
             | RevsetExpression::Heads(_)
             | RevsetExpression::Roots(_)
-            | RevsetExpression::Latest { .. } => {
+            | RevsetExpression::Latest { .. }
+            | RevsetExpression::Earliest(_) => {
                 ResolvedPredicateExpression::Set(self.resolve(expression).into())
             }
             RevsetExpression::Filter(predicate) => {
@@ -1982,9 +3087,17 @@ impl VisibilityResolutionContext<'_> {
                 let predicate2 = self.resolve_predicate(expression2);
                 ResolvedPredicateExpression::Union(predicate1.into(), predicate2.into())
             }
-            // Intersection of filters should have been substituted by optimize().
-            // If it weren't, just fall back to the set evaluation path.
-            RevsetExpression::Intersection(..) | RevsetExpression::Difference(..) => {
+            // An intersection within a filter tree (e.g. '(f1 & f2) | f3') is
+            // tested as a predicate so that it doesn't have to walk all().
+            RevsetExpression::Intersection(expression1, expression2) => {
+                let predicate1 = self.resolve_predicate(expression1);
+                let predicate2 = self.resolve_predicate(expression2);
+                ResolvedPredicateExpression::Intersection(predicate1.into(), predicate2.into())
+            }
+            // Symmetric difference is always evaluated as a set. Difference should
+            // have been unfolded by optimize(). If it weren't, just fall back to the
+            // set evaluation path.
+            RevsetExpression::SymmetricDifference(..) | RevsetExpression::Difference(..) => {
                 ResolvedPredicateExpression::Set(self.resolve(expression).into())
             }
         }
@@ -1993,13 +3106,61 @@ impl VisibilityResolutionContext<'_> {
 
 pub trait Revset<'index>: fmt::Debug {
     /// Iterate in topological order with children before parents.
-    fn iter(&self) -> Box<dyn Iterator<Item = CommitId> + '_>;
+    ///
+    /// An error is yielded in place of a commit if a filter predicate failed
+    /// to load it from the store. The iteration may continue past the error.
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<CommitId, RevsetEvaluationError>> + '_>;
+
+    /// Iterate in reverse topological order with parents before children.
+    ///
+    /// This collects the whole set before yielding the first commit. If that
+    /// fails, only the error is yielded.
+    fn iter_reverse(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<CommitId, RevsetEvaluationError>> + '_>;
+
+    /// Same as `iter()`, but also yields the properties which can be looked up
+    /// from the index without loading the commits.
+    fn iter_entries(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<RevsetEntry, RevsetEvaluationError>> + '_>;
+
+    fn iter_graph(&self) -> Box<dyn Iterator<Item = RevsetGraphItem> + '_>;
 
-    fn iter_graph(&self) -> Box<dyn Iterator<Item = (CommitId, Vec<RevsetGraphEdge>)> + '_>;
+    /// Same as `iter_graph()`, but also yields the properties which can be
+    /// looked up from the index without loading the commits.
+    fn iter_graph_entries(&self) -> Box<dyn Iterator<Item = RevsetGraphEntryItem> + '_>;
 
     fn change_id_index(&self) -> Box<dyn ChangeIdIndex + 'index>;
 
+    /// Returns true if the set has no commits. A commit which failed to
+    /// evaluate counts as non-empty.
     fn is_empty(&self) -> bool;
+
+    /// Number of commits in the set.
+    fn count(&self) -> Result<usize, RevsetEvaluationError> {
+        itertools::process_results(self.iter(), |commit_ids| commit_ids.count())
+    }
+
+    /// Returns true if the set contains the commit. Commits unknown to the
+    /// index are not contained.
+    ///
+    /// Each call tests the commit from scratch, so testing many commits this
+    /// way may be slower than iterating over the set once.
+    fn contains(&self, id: &CommitId) -> Result<bool, RevsetEvaluationError>;
+
+    /// Limits the set to the first `n` commits in iteration order. The set is
+    /// still evaluated lazily. An error counts as one of the `n` commits.
+    fn take(self: Box<Self>, n: usize) -> Box<dyn Revset<'index> + 'index>;
+}
+
+/// Commit in a revset along with its properties stored in the index.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct RevsetEntry {
+    pub commit_id: CommitId,
+    pub change_id: ChangeId,
+    /// Length of the longest path from the root commit.
+    pub generation_number: u32,
 }
 
 pub trait ChangeIdIndex: Send + Sync {
@@ -2023,6 +3184,12 @@ pub trait ChangeIdIndex: Send + Sync {
     fn shortest_unique_prefix_len(&self, change_id: &ChangeId) -> usize;
 }
 
+/// Commit yielded by `Revset::iter_graph()` along with its edges.
+pub type RevsetGraphItem = Result<(CommitId, Vec<RevsetGraphEdge>), RevsetEvaluationError>;
+
+/// Commit yielded by `Revset::iter_graph_entries()` along with its edges.
+pub type RevsetGraphEntryItem = Result<(RevsetEntry, Vec<RevsetGraphEdge>), RevsetEvaluationError>;
+
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct RevsetGraphEdge {
     pub target: CommitId,
@@ -2062,7 +3229,10 @@ pub trait RevsetIteratorExt<'index, I> {
     fn reversed(self) -> ReverseRevsetIterator;
 }
 
-impl<'index, I: Iterator<Item = CommitId>> RevsetIteratorExt<'index, I> for I {
+impl<'index, I> RevsetIteratorExt<'index, I> for I
+where
+    I: Iterator<Item = Result<CommitId, RevsetEvaluationError>>,
+{
     fn commits(self, store: &Arc<Store>) -> RevsetCommitIterator<I> {
         RevsetCommitIterator {
             iter: self,
@@ -2082,28 +3252,42 @@ pub struct RevsetCommitIterator<I> {
     iter: I,
 }
 
-impl<I: Iterator<Item = CommitId>> Iterator for RevsetCommitIterator<I> {
-    type Item = BackendResult<Commit>;
+impl<I> Iterator for RevsetCommitIterator<I>
+where
+    I: Iterator<Item = Result<CommitId, RevsetEvaluationError>>,
+{
+    type Item = Result<Commit, RevsetEvaluationError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.iter
-            .next()
-            .map(|commit_id| self.store.get_commit(&commit_id))
+        self.iter.next().map(|commit_id| {
+            self.store
+                .get_commit(&commit_id?)
+                .map_err(RevsetEvaluationError::StoreError)
+        })
     }
 }
 
 pub struct ReverseRevsetIterator {
-    entries: Vec<CommitId>,
+    entries: Vec<Result<CommitId, RevsetEvaluationError>>,
 }
 
 impl Iterator for ReverseRevsetIterator {
-    type Item = CommitId;
+    type Item = Result<CommitId, RevsetEvaluationError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.entries.pop()
     }
 }
 
+/// Information needed to parse revset expression.
+#[derive(Clone, Debug)]
+pub struct RevsetParseContext<'a> {
+    pub aliases_map: &'a RevsetAliasesMap,
+    /// Email address of the current user, which `mine()` will select.
+    pub user_email: &'a str,
+    pub workspace: Option<RevsetWorkspaceContext<'a>>,
+}
+
 /// Workspace information needed to evaluate revset expression.
 #[derive(Clone, Debug)]
 pub struct RevsetWorkspaceContext<'a> {
@@ -2117,12 +3301,15 @@ pub struct ReverseRevsetGraphIterator {
 }
 
 impl ReverseRevsetGraphIterator {
+    /// Collects the whole `input`. Fails with the first error yielded by the
+    /// `input`.
     pub fn new<'revset>(
-        input: Box<dyn Iterator<Item = (CommitId, Vec<RevsetGraphEdge>)> + 'revset>,
-    ) -> Self {
+        input: Box<dyn Iterator<Item = RevsetGraphItem> + 'revset>,
+    ) -> Result<Self, RevsetEvaluationError> {
         let mut entries = vec![];
         let mut reverse_edges: HashMap<CommitId, Vec<RevsetGraphEdge>> = HashMap::new();
-        for (commit_id, edges) in input {
+        for item in input {
+            let (commit_id, edges) = item?;
             for RevsetGraphEdge { target, edge_type } in edges {
                 reverse_edges
                     .entry(target)
@@ -2140,7 +3327,7 @@ impl ReverseRevsetGraphIterator {
             let edges = reverse_edges.get(&commit_id).cloned().unwrap_or_default();
             items.push((commit_id, edges));
         }
-        Self { items }
+        Ok(Self { items })
     }
 }
 
@@ -2168,14 +3355,18 @@ mod tests {
         for (decl, defn) in aliases {
             aliases_map.insert(decl, defn).unwrap();
         }
-        // Set up pseudo context to resolve file(path)
-        let workspace_ctx = RevsetWorkspaceContext {
-            cwd: Path::new("/"),
-            workspace_id: &WorkspaceId::default(),
-            workspace_root: Path::new("/"),
+        // Set up pseudo context to resolve file(path) and mine()
+        let context = RevsetParseContext {
+            aliases_map: &aliases_map,
+            user_email: "test.user@example.com",
+            workspace: Some(RevsetWorkspaceContext {
+                cwd: Path::new("/"),
+                workspace_id: &WorkspaceId::default(),
+                workspace_root: Path::new("/"),
+            }),
         };
         // Map error to comparable object
-        super::parse(revset_str, &aliases_map, Some(&workspace_ctx)).map_err(|e| e.kind)
+        super::parse(revset_str, &context).map_err(|e| e.kind)
     }
 
     #[test]
@@ -2335,6 +3526,11 @@ mod tests {
         assert_eq!(parse("foo | bar"), Ok(foo_symbol.union(&bar_symbol)));
         // Parse the "difference" operator
         assert_eq!(parse("foo ~ bar"), Ok(foo_symbol.minus(&bar_symbol)));
+        // Parse the "symmetric difference" operator
+        assert_eq!(
+            parse("foo ^ bar"),
+            Ok(foo_symbol.symmetric_difference(&bar_symbol))
+        );
         // Parentheses are allowed before suffix operators
         assert_eq!(parse("(@)-"), Ok(wc_symbol.parents()));
         // Space is allowed around expressions
@@ -2347,16 +3543,19 @@ mod tests {
         // Space is allowed around infix operators and function arguments
         assert_eq!(
             parse("   description(  arg1 ) ~    file(  arg1 ,   arg2 )  ~ visible_heads(  )  "),
-            Ok(
-                RevsetExpression::filter(RevsetFilterPredicate::Description("arg1".to_string()))
-                    .minus(&RevsetExpression::filter(RevsetFilterPredicate::File(
-                        Some(vec![
-                            RepoPath::from_internal_string("arg1"),
-                            RepoPath::from_internal_string("arg2"),
-                        ])
-                    )))
-                    .minus(&RevsetExpression::visible_heads())
-            )
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Description(
+                StringPattern::Substring("arg1".to_string()),
+                CaseSensitivity::Sensitive,
+                DescriptionScope::Full
+            ))
+            .minus(&RevsetExpression::filter(RevsetFilterPredicate::File {
+                includes: Some(vec![
+                    FilePattern::PrefixPath(RepoPath::from_internal_string("arg1")),
+                    FilePattern::PrefixPath(RepoPath::from_internal_string("arg2")),
+                ]),
+                excludes: vec![],
+            }))
+            .minus(&RevsetExpression::visible_heads()))
         );
         // Space is allowed around keyword arguments
         assert_eq!(
@@ -2414,6 +3613,15 @@ mod tests {
                 description: "parents".to_owned(),
             })
         );
+        // Postfix "^" isn't confused with the infix operator
+        assert_eq!(
+            parse("foo^ ^ bar"),
+            Err(RevsetParseErrorKind::NotPostfixOperator {
+                op: "^".to_owned(),
+                similar_op: "-".to_owned(),
+                description: "parents".to_owned(),
+            })
+        );
         assert_eq!(
             parse("foo + bar"),
             Err(RevsetParseErrorKind::NotInfixOperator {
@@ -2455,6 +3663,9 @@ mod tests {
         assert_eq!(parse("x&y|z").unwrap(), parse("(x&y)|z").unwrap());
         assert_eq!(parse("x|y&z").unwrap(), parse("x|(y&z)").unwrap());
         assert_eq!(parse("x|y~z").unwrap(), parse("x|(y~z)").unwrap());
+        assert_eq!(parse("x ^ y|z").unwrap(), parse("(x ^ y)|z").unwrap());
+        assert_eq!(parse("x|y ^ z").unwrap(), parse("(x|y) ^ z").unwrap());
+        assert_eq!(parse("x ^ y&z").unwrap(), parse("x ^ (y&z)").unwrap());
         // Parse repeated "ancestors"/"descendants"/"dag range"/"range" operators
         assert_eq!(parse(":foo:"), Err(RevsetParseErrorKind::SyntaxError));
         assert_eq!(parse("::foo"), Err(RevsetParseErrorKind::SyntaxError));
@@ -2495,13 +3706,161 @@ mod tests {
         assert_eq!(
             parse(r#"description("")"#),
             Ok(RevsetExpression::filter(
-                RevsetFilterPredicate::Description("".to_string())
+                RevsetFilterPredicate::Description(
+                    StringPattern::Substring("".to_string()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Full
+                )
+            ))
+        );
+        assert_eq!(
+            parse(r#"description(subject:"foo")"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::Description(
+                    StringPattern::Substring("foo".to_string()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Subject
+                )
+            ))
+        );
+        assert_eq!(
+            parse(r#"description(body:"foo")"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::Description(
+                    StringPattern::Substring("foo".to_string()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Body
+                )
+            ))
+        );
+        assert_eq!(
+            parse("subject(foo)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Subject(
+                StringPattern::Substring("foo".to_string()),
+                CaseSensitivity::Sensitive
+            )))
+        );
+        assert_eq!(
+            parse(r#"trailer("Signed-off-by", alice)"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Trailer(
+                "Signed-off-by".to_string(),
+                StringPattern::Substring("alice".to_string()),
+                CaseSensitivity::Sensitive
+            )))
+        );
+        assert_eq!(
+            parse(r#"trailer("Signed-off-by")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Trailer(
+                "Signed-off-by".to_string(),
+                StringPattern::Substring("".to_string()),
+                CaseSensitivity::Sensitive
+            )))
+        );
+        assert!(parse("trailer()").is_err());
+        assert_eq!(
+            parse(r#"present_ids("abc1", 9f0e)"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::CommitIdPrefixes(vec![
+                    HexPrefix::new("abc1").unwrap(),
+                    HexPrefix::new("9f0e").unwrap(),
+                ])
+            ))
+        );
+        assert_eq!(
+            parse("present_ids()"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "present_ids".to_string(),
+                message: "Expected at least 1 argument".to_string()
+            })
+        );
+        assert_eq!(
+            parse("present_ids(xyz)"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "present_ids".to_string(),
+                message: r#"Invalid commit id prefix "xyz""#.to_string()
+            })
+        );
+        assert_eq!(
+            parse(r#"present_ids("")"#),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "present_ids".to_string(),
+                message: r#"Invalid commit id prefix """#.to_string()
+            })
+        );
+        assert_eq!(
+            parse("present_change_ids(abc1, 9f0e)"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::ChangeIdPrefixes(vec![
+                    HexPrefix::new("abc1").unwrap(),
+                    HexPrefix::new("9f0e").unwrap(),
+                ])
+            ))
+        );
+        assert_eq!(
+            parse("present_change_ids()"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "present_change_ids".to_string(),
+                message: "Expected at least 1 argument".to_string()
+            })
+        );
+        assert_eq!(
+            parse("present_change_ids(abc, xyz)"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "present_change_ids".to_string(),
+                message: r#"Invalid change id prefix "xyz""#.to_string()
+            })
+        );
+        assert_eq!(
+            parse(r#"present_change_ids("")"#),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "present_change_ids".to_string(),
+                message: r#"Invalid change id prefix """#.to_string()
+            })
+        );
+        assert_eq!(
+            parse("author_name(foo)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::AuthorName(
+                StringPattern::Substring("foo".to_string()),
+                CaseSensitivity::Sensitive
+            )))
+        );
+        assert_eq!(
+            parse("author_email_domain(example.com)"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::AuthorEmailDomain("example.com".to_string())
+            ))
+        );
+        assert_eq!(
+            parse(r#"author_email_domain("@Example.com")"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::AuthorEmailDomain("Example.com".to_string())
+            ))
+        );
+        assert!(parse("author_email_domain()").is_err());
+        assert_eq!(
+            parse("committer_email(foo)"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::CommitterEmail(
+                    StringPattern::Substring("foo".to_string()),
+                    CaseSensitivity::Sensitive
+                )
             ))
         );
+        assert_eq!(
+            parse("coauthor(alice)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Coauthor(
+                StringPattern::Substring("alice".to_string()),
+                CaseSensitivity::Sensitive
+            )))
+        );
         assert_eq!(
             parse("description(foo)"),
             Ok(RevsetExpression::filter(
-                RevsetFilterPredicate::Description("foo".to_string())
+                RevsetFilterPredicate::Description(
+                    StringPattern::Substring("foo".to_string()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Full
+                )
             ))
         );
         assert_eq!(
@@ -2512,41 +3871,487 @@ mod tests {
             })
         );
         assert_eq!(
-            parse("description((foo))"),
+            parse("description((foo))"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::Description(
+                    StringPattern::Substring("foo".to_string()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Full
+                )
+            ))
+        );
+        assert_eq!(
+            parse("description(\"(foo)\")"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::Description(
+                    StringPattern::Substring("(foo)".to_string()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Full
+                )
+            ))
+        );
+        assert_eq!(
+            parse(r#"description(substring:"foo")"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::Description(
+                    StringPattern::Substring("foo".to_string()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Full
+                )
+            ))
+        );
+        assert_eq!(
+            parse(r#"author(regex:"^foo.*bar$")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Author(
+                StringPattern::Regex("^foo.*bar$".to_string()),
+                CaseSensitivity::Sensitive
+            )))
+        );
+        assert_eq!(
+            parse(r#"committer(regex:"foo")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Committer(
+                StringPattern::Regex("foo".to_string()),
+                CaseSensitivity::Sensitive
+            )))
+        );
+        assert_eq!(
+            parse(r#"description(exact:"")"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::Description(
+                    StringPattern::Exact("".to_string()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Full
+                )
+            ))
+        );
+        assert_eq!(
+            parse(r#"author(exact-i:"Foo")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Author(
+                StringPattern::Exact("Foo".to_string()),
+                CaseSensitivity::Insensitive
+            )))
+        );
+        assert_eq!(
+            parse(r#"author(substring-i:"Foo")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Author(
+                StringPattern::Substring("Foo".to_string()),
+                CaseSensitivity::Insensitive
+            )))
+        );
+        assert_eq!(
+            parse(r#"description(word:"fix")"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::Description(
+                    StringPattern::Word("fix".to_string()),
+                    CaseSensitivity::Sensitive,
+                    DescriptionScope::Full
+                )
+            ))
+        );
+        assert_eq!(
+            parse(r#"author(word-i:"Foo")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Author(
+                StringPattern::Word("Foo".to_string()),
+                CaseSensitivity::Insensitive
+            )))
+        );
+        assert_eq!(
+            parse(r#"description(regex-i:"^wip")"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::Description(
+                    StringPattern::Regex("^wip".to_string()),
+                    CaseSensitivity::Insensitive,
+                    DescriptionScope::Full
+                )
+            ))
+        );
+        assert_eq!(
+            parse(r#"regex:"foo""#),
+            Err(RevsetParseErrorKind::UnexpectedStringPattern)
+        );
+        assert_eq!(
+            parse(r#"description(regex:"foo" | bar)"#),
+            Err(RevsetParseErrorKind::UnexpectedStringPattern)
+        );
+        assert_eq!(
+            parse("empty()"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Empty))
+        );
+        assert!(parse("empty(foo)").is_err());
+        assert_eq!(
+            parse("whitespace_only()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::WhitespaceOnly
+            ))
+        );
+        assert_eq!(
+            parse("no_description()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::NoDescription
+            ))
+        );
+        assert!(parse("no_description(foo)").is_err());
+        assert_eq!(
+            parse("mine()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::AuthorEmail(
+                    StringPattern::Exact("test.user@example.com".to_string()),
+                    CaseSensitivity::Insensitive
+                )
+            ))
+        );
+        assert!(parse("mine(foo)").is_err());
+        // Empty user email shouldn't match commits without author email
+        let context = RevsetParseContext {
+            aliases_map: &RevsetAliasesMap::new(),
+            user_email: "",
+            workspace: None,
+        };
+        assert_eq!(
+            super::parse("mine()", &context).unwrap(),
+            RevsetExpression::none()
+        );
+        assert!(parse("file()").is_err());
+        assert_eq!(
+            parse("file(foo)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::File {
+                includes: Some(vec![FilePattern::PrefixPath(
+                    RepoPath::from_internal_string("foo")
+                )]),
+                excludes: vec![],
+            }))
+        );
+        assert_eq!(
+            parse("file(foo, bar, baz)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::File {
+                includes: Some(vec![
+                    FilePattern::PrefixPath(RepoPath::from_internal_string("foo")),
+                    FilePattern::PrefixPath(RepoPath::from_internal_string("bar")),
+                    FilePattern::PrefixPath(RepoPath::from_internal_string("baz")),
+                ]),
+                excludes: vec![],
+            }))
+        );
+        assert_eq!(
+            parse(r#"file(glob:"**/*.rs", "*.md")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::File {
+                includes: Some(vec![
+                    FilePattern::Glob(RepoPath::from_internal_string("**/*.rs")),
+                    FilePattern::Glob(RepoPath::from_internal_string("*.md")),
+                ]),
+                excludes: vec![],
+            }))
+        );
+        assert_eq!(
+            parse(r#"file(~vendor, ~ glob:"*.lock")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::File {
+                includes: None,
+                excludes: vec![
+                    FilePattern::PrefixPath(RepoPath::from_internal_string("vendor")),
+                    FilePattern::Glob(RepoPath::from_internal_string("*.lock")),
+                ],
+            }))
+        );
+        assert_eq!(
+            parse(r#"file(~"src/vendor", src)"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::File {
+                includes: Some(vec![FilePattern::PrefixPath(
+                    RepoPath::from_internal_string("src")
+                )]),
+                excludes: vec![FilePattern::PrefixPath(RepoPath::from_internal_string(
+                    "src/vendor"
+                ))],
+            }))
+        );
+        assert_eq!(
+            parse(r#"file(ext:"rs", ~ext:"md")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::File {
+                includes: Some(vec![FilePattern::Extension("rs".to_string())]),
+                excludes: vec![FilePattern::Extension("md".to_string())],
+            }))
+        );
+        assert_eq!(
+            parse(r#"file(ext:".rs")"#),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "file".to_string(),
+                message: r#"Invalid file extension ".rs""#.to_string()
+            })
+        );
+        assert_eq!(
+            parse("file(~~foo)"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "file".to_string(),
+                message: "Expected function argument of type string".to_string()
+            })
+        );
+        assert_eq!(
+            parse("adds_files()"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::AddsFiles {
+                includes: None,
+                excludes: vec![],
+            }))
+        );
+        assert_eq!(
+            parse("adds_files(foo, ~bar)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::AddsFiles {
+                includes: Some(vec![FilePattern::PrefixPath(
+                    RepoPath::from_internal_string("foo")
+                )]),
+                excludes: vec![FilePattern::PrefixPath(RepoPath::from_internal_string(
+                    "bar"
+                ))],
+            }))
+        );
+        assert_eq!(
+            parse("deletes_files(foo)"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::DeletesFiles {
+                    includes: Some(vec![FilePattern::PrefixPath(
+                        RepoPath::from_internal_string("foo")
+                    )]),
+                    excludes: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse("file_mode_changed()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::FileModeChanged {
+                    includes: None,
+                    excludes: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse("mode_only_changed()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::ModeOnlyChanged {
+                    includes: None,
+                    excludes: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse("touches_symlinks()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::TouchesSymlinks {
+                    includes: None,
+                    excludes: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse("touches_submodules()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::TouchesSubmodules {
+                    includes: None,
+                    excludes: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse("touches_binary()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::TouchesBinary {
+                    includes: None,
+                    excludes: vec![],
+                }
+            ))
+        );
+        assert!(parse("adds_large_files()").is_err());
+        assert_eq!(
+            parse("adds_large_files(10MiB)"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::AddsLargeFiles {
+                    min_size: 10 * 1024 * 1024,
+                    includes: None,
+                    excludes: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse(r#"adds_large_files("512", foo)"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::AddsLargeFiles {
+                    min_size: 512,
+                    includes: Some(vec![FilePattern::PrefixPath(
+                        RepoPath::from_internal_string("foo")
+                    )]),
+                    excludes: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse("adds_large_files(10MB)"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "adds_large_files".to_string(),
+                message: "Expected function argument of type size".to_string()
+            })
+        );
+        assert_eq!(
+            parse("renames()"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Renames {
+                includes: None,
+                excludes: vec![],
+            }))
+        );
+        assert!(parse("diff_contains()").is_err());
+        assert_eq!(
+            parse(r#"diff_contains("TODO")"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::DiffContains {
+                    text: StringPattern::Substring("TODO".to_string()),
+                    case: CaseSensitivity::Sensitive,
+                    includes: None,
+                    excludes: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse(r#"diff_contains(regex-i:"^todo", foo, ~"foo/bar")"#),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::DiffContains {
+                    text: StringPattern::Regex("^todo".to_string()),
+                    case: CaseSensitivity::Insensitive,
+                    includes: Some(vec![FilePattern::PrefixPath(
+                        RepoPath::from_internal_string("foo")
+                    )]),
+                    excludes: vec![FilePattern::PrefixPath(RepoPath::from_internal_string(
+                        "foo/bar"
+                    ))],
+                }
+            ))
+        );
+        assert!(parse("diff_lines()").is_err());
+        assert_eq!(
+            parse("diff_lines(3)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::DiffLines(
+                3..4
+            )))
+        );
+        assert_eq!(
+            parse("diff_lines(..10)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::DiffLines(
+                0..10
+            )))
+        );
+        assert_eq!(
+            parse("diff_lines(5..)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::DiffLines(
+                5..u64::MAX
+            )))
+        );
+        assert_eq!(
+            parse("diff_lines(5..10)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::DiffLines(
+                5..10
+            )))
+        );
+        assert_eq!(
+            parse("diff_lines(x..)"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "diff_lines".to_string(),
+                message: "Expected function argument of type range".to_string()
+            })
+        );
+        assert!(parse("generation()").is_err());
+        assert_eq!(
+            parse("generation(10..20)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Generation(
+                10..20
+            )))
+        );
+        assert_eq!(
+            parse("generation(3)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Generation(
+                3..4
+            )))
+        );
+        assert_eq!(
+            parse("generation(5..)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Generation(
+                5..u32::MAX
+            )))
+        );
+        assert!(parse("conflicts()").is_err());
+        assert_eq!(
+            parse("conflicts(5..)"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::ConflictCount(5..usize::MAX)
+            ))
+        );
+        assert_eq!(
+            parse("conflicts(1..3)"),
             Ok(RevsetExpression::filter(
-                RevsetFilterPredicate::Description("foo".to_string())
+                RevsetFilterPredicate::ConflictCount(1..3)
             ))
         );
         assert_eq!(
-            parse("description(\"(foo)\")"),
+            parse("conflict()"),
             Ok(RevsetExpression::filter(
-                RevsetFilterPredicate::Description("(foo)".to_string())
+                RevsetFilterPredicate::HasConflict {
+                    includes: None,
+                    excludes: vec![],
+                }
             ))
         );
         assert_eq!(
-            parse("empty()"),
-            Ok(RevsetExpression::filter(RevsetFilterPredicate::File(None)).negated())
+            parse("conflict(foo, ~glob:\"foo/*.lock\")"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::HasConflict {
+                    includes: Some(vec![FilePattern::PrefixPath(
+                        RepoPath::from_internal_string("foo")
+                    )]),
+                    excludes: vec![FilePattern::Glob(RepoPath::from_internal_string(
+                        "foo/*.lock"
+                    ))],
+                }
+            ))
         );
-        assert!(parse("empty(foo)").is_err());
-        assert!(parse("file()").is_err());
         assert_eq!(
-            parse("file(foo)"),
-            Ok(RevsetExpression::filter(RevsetFilterPredicate::File(Some(
-                vec![RepoPath::from_internal_string("foo")]
-            ))))
+            parse(r#"file(exact:"foo")"#),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "file".to_string(),
+                message: r#"Invalid file pattern kind "exact""#.to_string()
+            })
         );
         assert_eq!(
-            parse("file(foo, bar, baz)"),
-            Ok(RevsetExpression::filter(RevsetFilterPredicate::File(Some(
-                vec![
-                    RepoPath::from_internal_string("foo"),
-                    RepoPath::from_internal_string("bar"),
-                    RepoPath::from_internal_string("baz"),
-                ]
-            ))))
+            parse(r#"description(glob:"foo")"#),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "description".to_string(),
+                message: r#"Invalid string pattern kind "glob""#.to_string()
+            })
+        );
+        assert_eq!(
+            parse(r#"author(subject:"foo")"#),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "author".to_string(),
+                message: r#"Invalid string pattern kind "subject""#.to_string()
+            })
         );
     }
 
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("0"), Some(0));
+        assert_eq!(parse_byte_size("100"), Some(100));
+        assert_eq!(parse_byte_size("100B"), Some(100));
+        assert_eq!(parse_byte_size("2KiB"), Some(2048));
+        assert_eq!(parse_byte_size("10MiB"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_byte_size("3GiB"), Some(3 * 1024 * 1024 * 1024));
+        assert_eq!(parse_byte_size(""), None);
+        assert_eq!(parse_byte_size("MiB"), None);
+        assert_eq!(parse_byte_size("10 MiB"), None);
+        assert_eq!(parse_byte_size("10mib"), None);
+        assert_eq!(parse_byte_size("10MB"), None);
+        assert_eq!(parse_byte_size("-1"), None);
+        assert_eq!(parse_byte_size("1.5MiB"), None);
+        // Overflow
+        assert_eq!(parse_byte_size("18446744073709551615"), Some(u64::MAX));
+        assert_eq!(parse_byte_size("18446744073709551616"), None);
+        assert_eq!(parse_byte_size("18446744073709551615KiB"), None);
+    }
+
     #[test]
     fn test_parse_revset_keyword_arguments() {
         assert_eq!(
@@ -2589,6 +4394,134 @@ mod tests {
             message: "Unexpected keyword argument \"unknown\"",
         }
         "###);
+        assert_eq!(
+            parse("latest(foo, group=author)").unwrap(),
+            RevsetExpression::symbol("foo".to_owned()).latest_per_group(
+                1,
+                0,
+                LatestGroupKey::Author
+            )
+        );
+        assert_eq!(
+            parse("latest(foo, 2, 3, author)").unwrap(),
+            parse("latest(foo, 2, 3, group=author)").unwrap(),
+        );
+        insta::assert_debug_snapshot!(
+            parse(r#"latest(foo, group=committer)"#).unwrap_err(),
+            @r###"
+        InvalidFunctionArguments {
+            name: "latest",
+            message: "Expected function argument of type group",
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_parse_revset_date_range_arguments() {
+        // 2023-01-01T00:00:00Z, 2023-06-01T00:00:00Z
+        let jan = MillisSinceEpoch(1672531200000);
+        let jun = MillisSinceEpoch(1685577600000);
+        assert_eq!(
+            parse(r#"author_date(after="2023-01-01", before="2023-06-01")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::AuthorDate(
+                jan.clone()..jun.clone()
+            )))
+        );
+        assert_eq!(
+            parse("author_date(2023-01-01, 2023-06-01)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::AuthorDate(
+                jan.clone()..jun.clone()
+            )))
+        );
+        assert_eq!(
+            parse("author_date(after=2023-01-01)"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::AuthorDate(
+                jan..MillisSinceEpoch(i64::MAX)
+            )))
+        );
+        assert_eq!(
+            parse(r#"author_date(before="2023-06-01T09:00:00+09:00")"#),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::AuthorDate(
+                MillisSinceEpoch(i64::MIN)..jun.clone()
+            )))
+        );
+        assert_eq!(
+            parse("committer_date(before=2023-06-01)"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::CommitterDate(MillisSinceEpoch(i64::MIN)..jun.clone())
+            ))
+        );
+        assert_eq!(
+            parse("committer_differs()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::CommitterDiffers(None)
+            ))
+        );
+        assert_eq!(
+            parse("committer_differs(604800)"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::CommitterDiffers(Some(604800))
+            ))
+        );
+        assert_eq!(
+            parse("signed()"),
+            Ok(RevsetExpression::filter(RevsetFilterPredicate::Signed))
+        );
+        assert_eq!(
+            parse("malformed_signature()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::MalformedSignature(SignatureCheck::ALL.to_vec())
+            ))
+        );
+        assert_eq!(
+            parse("malformed_signature(placeholder, empty_name)"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::MalformedSignature(vec![
+                    SignatureCheck::Placeholder,
+                    SignatureCheck::EmptyName,
+                ])
+            ))
+        );
+        assert_eq!(
+            parse("malformed_signature(empty_email, foo)"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "malformed_signature".to_string(),
+                message: "Expected function argument of type signature check".to_string()
+            })
+        );
+        assert_eq!(
+            parse("description_control_chars()"),
+            Ok(RevsetExpression::filter(
+                RevsetFilterPredicate::DescriptionControlChars
+            ))
+        );
+        assert_eq!(
+            parse("signed(foo)"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "signed".to_string(),
+                message: "Expected 0 arguments".to_string()
+            })
+        );
+        assert_eq!(
+            parse("committer_differs(week)"),
+            Err(RevsetParseErrorKind::InvalidFunctionArguments {
+                name: "committer_differs".to_string(),
+                message: "Expected function argument of type integer".to_string()
+            })
+        );
+        insta::assert_debug_snapshot!(parse("author_date()").unwrap_err(), @r###"
+        InvalidFunctionArguments {
+            name: "author_date",
+            message: "Expected at least one of \"after\" or \"before\"",
+        }
+        "###);
+        insta::assert_debug_snapshot!(
+            parse("author_date(after=yesterday)").unwrap_err(), @r###"
+        InvalidFunctionArguments {
+            name: "author_date",
+            message: "Invalid date \"yesterday\", expected \"YYYY-MM-DD\" or RFC 3339 timestamp",
+        }
+        "###);
     }
 
     #[test]
@@ -2790,6 +4723,14 @@ mod tests {
             optimize(parse("latest(branches() & all(), 2)").unwrap()),
             RevsetExpression::branches("".to_owned()).latest(2)
         );
+        assert_eq!(
+            optimize(parse("latest(branches() & all(), 2, 3)").unwrap()),
+            RevsetExpression::branches("".to_owned()).latest_with_offset(2, 3)
+        );
+        assert_eq!(
+            optimize(parse("earliest(branches() & all())").unwrap()),
+            RevsetExpression::branches("".to_owned()).earliest()
+        );
 
         assert_eq!(
             optimize(parse("present(foo ~ bar)").unwrap()),
@@ -3102,12 +5043,10 @@ mod tests {
 
     #[test]
     fn test_optimize_filter_difference() {
-        // '~empty()' -> '~~file(*)' -> 'file(*)'
-        insta::assert_debug_snapshot!(optimize(parse("~empty()").unwrap()), @r###"
+        // '~~empty()' -> 'empty()'
+        insta::assert_debug_snapshot!(optimize(parse("~~empty()").unwrap()), @r###"
         Filter(
-            File(
-                None,
-            ),
+            Empty,
         )
         "###);
 
@@ -3129,7 +5068,10 @@ mod tests {
             ),
             Filter(
                 Author(
-                    "foo",
+                    Substring(
+                        "foo",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3148,7 +5090,10 @@ mod tests {
             ),
             Filter(
                 Author(
-                    "bar",
+                    Substring(
+                        "bar",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3167,7 +5112,10 @@ mod tests {
                 Union(
                     Filter(
                         Author(
-                            "bar",
+                            Substring(
+                                "bar",
+                            ),
+                            Sensitive,
                         ),
                     ),
                     CommitRef(
@@ -3193,7 +5141,10 @@ mod tests {
             ),
             Filter(
                 Author(
-                    "foo",
+                    Substring(
+                        "foo",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3205,7 +5156,10 @@ mod tests {
         insta::assert_debug_snapshot!(optimize(parse("author(foo)").unwrap()), @r###"
         Filter(
             Author(
-                "foo",
+                Substring(
+                    "foo",
+                ),
+                Sensitive,
             ),
         )
         "###);
@@ -3219,7 +5173,11 @@ mod tests {
             ),
             Filter(
                 Description(
-                    "bar",
+                    Substring(
+                        "bar",
+                    ),
+                    Sensitive,
+                    Full,
                 ),
             ),
         )
@@ -3233,7 +5191,10 @@ mod tests {
             ),
             Filter(
                 Author(
-                    "foo",
+                    Substring(
+                        "foo",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3243,12 +5204,18 @@ mod tests {
         Intersection(
             Filter(
                 Author(
-                    "foo",
+                    Substring(
+                        "foo",
+                    ),
+                    Sensitive,
                 ),
             ),
             Filter(
                 Committer(
-                    "bar",
+                    Substring(
+                        "bar",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3265,13 +5232,20 @@ mod tests {
                 ),
                 Filter(
                     Description(
-                        "bar",
+                        Substring(
+                            "bar",
+                        ),
+                        Sensitive,
+                        Full,
                     ),
                 ),
             ),
             Filter(
                 Author(
-                    "baz",
+                    Substring(
+                        "baz",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3287,13 +5261,19 @@ mod tests {
                 ),
                 Filter(
                     Committer(
-                        "foo",
+                        Substring(
+                            "foo",
+                        ),
+                        Sensitive,
                     ),
                 ),
             ),
             Filter(
                 Author(
-                    "baz",
+                    Substring(
+                        "baz",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3309,18 +5289,24 @@ mod tests {
                 ),
                 Filter(
                     Committer(
-                        "foo",
+                        Substring(
+                            "foo",
+                        ),
+                        Sensitive,
                     ),
                 ),
             ),
             Filter(
-                File(
-                    Some(
+                File {
+                    includes: Some(
                         [
-                            "bar",
+                            PrefixPath(
+                                "bar",
+                            ),
                         ],
                     ),
-                ),
+                    excludes: [],
+                },
             ),
         )
         "###);
@@ -3330,22 +5316,31 @@ mod tests {
             Intersection(
                 Filter(
                     Committer(
-                        "foo",
+                        Substring(
+                            "foo",
+                        ),
+                        Sensitive,
                     ),
                 ),
                 Filter(
-                    File(
-                        Some(
+                    File {
+                        includes: Some(
                             [
-                                "bar",
+                                PrefixPath(
+                                    "bar",
+                                ),
                             ],
                         ),
-                    ),
+                        excludes: [],
+                    },
                 ),
             ),
             Filter(
                 Author(
-                    "baz",
+                    Substring(
+                        "baz",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3365,13 +5360,16 @@ mod tests {
                 ),
             ),
             Filter(
-                File(
-                    Some(
+                File {
+                    includes: Some(
                         [
-                            "bar",
+                            PrefixPath(
+                                "bar",
+                            ),
                         ],
                     ),
-                ),
+                    excludes: [],
+                },
             ),
         )
         "###);
@@ -3394,13 +5392,20 @@ mod tests {
                 ),
                 Filter(
                     Description(
-                        "bar",
+                        Substring(
+                            "bar",
+                        ),
+                        Sensitive,
+                        Full,
                     ),
                 ),
             ),
             Filter(
                 Author(
-                    "baz",
+                    Substring(
+                        "baz",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3418,7 +5423,10 @@ mod tests {
                     Ancestors {
                         heads: Filter(
                             Author(
-                                "baz",
+                                Substring(
+                                    "baz",
+                                ),
+                                Sensitive,
                             ),
                         ),
                         generation: 1..2,
@@ -3432,7 +5440,11 @@ mod tests {
             ),
             Filter(
                 Description(
-                    "bar",
+                    Substring(
+                        "bar",
+                    ),
+                    Sensitive,
+                    Full,
                 ),
             ),
         )
@@ -3455,7 +5467,10 @@ mod tests {
                         ),
                         Filter(
                             Author(
-                                "baz",
+                                Substring(
+                                    "baz",
+                                ),
+                                Sensitive,
                             ),
                         ),
                     ),
@@ -3464,7 +5479,11 @@ mod tests {
             ),
             Filter(
                 Description(
-                    "bar",
+                    Substring(
+                        "bar",
+                    ),
+                    Sensitive,
+                    Full,
                 ),
             ),
         )
@@ -3497,19 +5516,28 @@ mod tests {
                     ),
                     Filter(
                         Author(
-                            "A",
+                            Substring(
+                                "A",
+                            ),
+                            Sensitive,
                         ),
                     ),
                 ),
                 Filter(
                     Author(
-                        "B",
+                        Substring(
+                            "B",
+                        ),
+                        Sensitive,
                     ),
                 ),
             ),
             Filter(
                 Author(
-                    "C",
+                    Substring(
+                        "C",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3548,19 +5576,28 @@ mod tests {
                     ),
                     Filter(
                         Author(
-                            "A",
+                            Substring(
+                                "A",
+                            ),
+                            Sensitive,
                         ),
                     ),
                 ),
                 Filter(
                     Author(
-                        "B",
+                        Substring(
+                            "B",
+                        ),
+                        Sensitive,
                     ),
                 ),
             ),
             Filter(
                 Author(
-                    "C",
+                    Substring(
+                        "C",
+                    ),
+                    Sensitive,
                 ),
             ),
         )
@@ -3579,15 +5616,95 @@ mod tests {
                 ),
                 Filter(
                     Description(
-                        "bar",
+                        Substring(
+                            "bar",
+                        ),
+                        Sensitive,
+                        Full,
                     ),
                 ),
             ),
             Filter(
                 Author(
-                    "baz",
+                    Substring(
+                        "baz",
+                    ),
+                    Sensitive,
+                ),
+            ),
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_optimize_deferred_filter() {
+        insta::assert_debug_snapshot!(
+            optimize(parse("whitespace_only() & author(foo)").unwrap()), @r###"
+        Intersection(
+            Filter(
+                Author(
+                    Substring(
+                        "foo",
+                    ),
+                    Sensitive,
+                ),
+            ),
+            Filter(
+                WhitespaceOnly,
+            ),
+        )
+        "###);
+        insta::assert_debug_snapshot!(
+            optimize(parse("author(foo) & whitespace_only() & bar & empty()").unwrap()), @r###"
+        Intersection(
+            Intersection(
+                Intersection(
+                    CommitRef(
+                        Symbol(
+                            "bar",
+                        ),
+                    ),
+                    Filter(
+                        Author(
+                            Substring(
+                                "foo",
+                            ),
+                            Sensitive,
+                        ),
+                    ),
+                ),
+                Filter(
+                    Empty,
+                ),
+            ),
+            Filter(
+                WhitespaceOnly,
+            ),
+        )
+        "###);
+        insta::assert_debug_snapshot!(
+            optimize(parse("whitespace_only() & (foo | author(bar))").unwrap()), @r###"
+        Intersection(
+            AsFilter(
+                Union(
+                    CommitRef(
+                        Symbol(
+                            "foo",
+                        ),
+                    ),
+                    Filter(
+                        Author(
+                            Substring(
+                                "bar",
+                            ),
+                            Sensitive,
+                        ),
+                    ),
                 ),
             ),
+            Filter(
+                WhitespaceOnly,
+            ),
         )
         "###);
     }
@@ -3606,7 +5723,10 @@ mod tests {
                 Union(
                     Filter(
                         Author(
-                            "foo",
+                            Substring(
+                                "foo",
+                            ),
+                            Sensitive,
                         ),
                     ),
                     CommitRef(
@@ -3637,7 +5757,10 @@ mod tests {
                         ),
                         Filter(
                             Committer(
-                                "bar",
+                                Substring(
+                                    "bar",
+                                ),
+                                Sensitive,
                             ),
                         ),
                     ),
@@ -3645,7 +5768,11 @@ mod tests {
             ),
             Filter(
                 Description(
-                    "baz",
+                    Substring(
+                        "baz",
+                    ),
+                    Sensitive,
+                    Full,
                 ),
             ),
         )
@@ -3673,7 +5800,10 @@ mod tests {
                                         ),
                                         Filter(
                                             Author(
-                                                "foo",
+                                                Substring(
+                                                    "foo",
+                                                ),
+                                                Sensitive,
                                             ),
                                         ),
                                     ),
@@ -3722,7 +5852,10 @@ mod tests {
                         Union(
                             Filter(
                                 Author(
-                                    "A",
+                                    Substring(
+                                        "A",
+                                    ),
+                                    Sensitive,
                                 ),
                             ),
                             CommitRef(
@@ -3737,7 +5870,10 @@ mod tests {
                     Union(
                         Filter(
                             Author(
-                                "B",
+                                Substring(
+                                    "B",
+                                ),
+                                Sensitive,
                             ),
                         ),
                         CommitRef(
@@ -3752,7 +5888,10 @@ mod tests {
                 Union(
                     Filter(
                         Author(
-                            "C",
+                            Substring(
+                                "C",
+                            ),
+                            Sensitive,
                         ),
                     ),
                     CommitRef(
@@ -172,7 +172,8 @@ impl<'settings, 'repo> DescendantRebaser<'settings, 'repo> {
             .evaluate(mut_repo)
             .unwrap()
             .iter()
-            .collect();
+            .try_collect()
+            .unwrap();
 
         let to_visit_expression = old_commits_expression.descendants();
         let to_visit_revset = to_visit_expression
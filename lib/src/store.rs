@@ -76,6 +76,18 @@ impl Store {
         Ok(Commit::new(self.clone(), id.clone(), data))
     }
 
+    /// Like `get_commit()`, but doesn't add the commit to the cache if it has to
+    /// be read from the backend. Useful for bulk reads which are cached by the
+    /// caller.
+    pub fn get_commit_uncached(self: &Arc<Self>, id: &CommitId) -> BackendResult<Commit> {
+        let cached = self.commit_cache.read().unwrap().get(id).cloned();
+        let data = match cached {
+            Some(data) => data,
+            None => Arc::new(self.backend.read_commit(id)?),
+        };
+        Ok(Commit::new(self.clone(), id.clone(), data))
+    }
+
     fn get_backend_commit(&self, id: &CommitId) -> BackendResult<Arc<backend::Commit>> {
         {
             let read_locked_cached = self.commit_cache.read().unwrap();
@@ -130,6 +142,10 @@ impl Store {
         self.backend.read_file(path, id)
     }
 
+    pub fn file_size(&self, path: &RepoPath, id: &FileId) -> BackendResult<u64> {
+        self.backend.file_size(path, id)
+    }
+
     pub fn write_file(&self, path: &RepoPath, contents: &mut dyn Read) -> BackendResult<FileId> {
         self.backend.write_file(path, contents)
     }
@@ -0,0 +1,165 @@
+// Copyright 2023 The Jujutsu Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing of git-style trailers such as `Signed-off-by: Alice <alice@...>`.
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Trailer {
+    pub key: String,
+    pub value: String,
+}
+
+/// Parses the trailer block at the end of the `description`.
+///
+/// The trailer block is the last paragraph of the description, which consists
+/// of `Key: value` lines only. A line starting with whitespace continues the
+/// value of the preceding trailer. The first paragraph is the subject, so a
+/// description made of a single paragraph has no trailers.
+pub fn parse_description_trailers(description: &str) -> Vec<Trailer> {
+    // Fast path: no trailer can exist without a separator
+    if !description.contains(':') {
+        return vec![];
+    }
+    let lines = description.trim_end().lines().collect::<Vec<_>>();
+    let is_blank = |line: &&str| line.trim().is_empty();
+    let block_start = match lines.iter().rposition(is_blank) {
+        Some(pos) if !lines[..pos].iter().all(is_blank) => pos + 1,
+        _ => return vec![],
+    };
+
+    let mut trailers: Vec<Trailer> = vec![];
+    for line in &lines[block_start..] {
+        if line.starts_with(|c: char| c.is_ascii_whitespace()) {
+            match trailers.last_mut() {
+                Some(trailer) => {
+                    trailer.value.push(' ');
+                    trailer.value.push_str(line.trim());
+                }
+                None => return vec![],
+            }
+        } else if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim_end();
+            if key.is_empty() || key.contains(|c: char| c.is_whitespace()) {
+                return vec![];
+            }
+            trailers.push(Trailer {
+                key: key.to_owned(),
+                value: value.trim().to_owned(),
+            });
+        } else {
+            return vec![];
+        }
+    }
+    trailers
+}
+
+/// Splits trailer value of the form `Name <email>` into name and email. The
+/// email is empty if the value has no `<...>` part.
+pub fn split_name_and_email(value: &str) -> (&str, &str) {
+    match value.rsplit_once('<') {
+        Some((name, rest)) if rest.ends_with('>') => (name.trim_end(), rest.trim_end_matches('>')),
+        _ => (value, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trailer(key: &str, value: &str) -> Trailer {
+        Trailer {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_parse_description_trailers() {
+        // Empty description
+        assert_eq!(parse_description_trailers(""), vec![]);
+        assert_eq!(parse_description_trailers("\n"), vec![]);
+
+        // Subject only
+        assert_eq!(parse_description_trailers("subject\n"), vec![]);
+        assert_eq!(parse_description_trailers("subject: foo\n"), vec![]);
+
+        // Trailer-only description is the subject
+        assert_eq!(parse_description_trailers("Signed-off-by: Alice\n"), vec![]);
+        assert_eq!(
+            parse_description_trailers("\n\nSigned-off-by: Alice\n"),
+            vec![]
+        );
+
+        // Subject and trailers
+        assert_eq!(
+            parse_description_trailers(
+                "subject\n\nSigned-off-by: Alice <alice@example.com>\nReviewed-by:Bob\n"
+            ),
+            vec![
+                trailer("Signed-off-by", "Alice <alice@example.com>"),
+                trailer("Reviewed-by", "Bob"),
+            ]
+        );
+
+        // Body paragraphs before trailers, trailing blank lines
+        assert_eq!(
+            parse_description_trailers(
+                "subject\n\nbody: not a trailer\nmore body\n\nAcked-by: Alice\n\n \n"
+            ),
+            vec![trailer("Acked-by", "Alice")]
+        );
+        // Paragraphs are separated by whitespace-only lines too
+        assert_eq!(
+            parse_description_trailers("subject\n  \nAcked-by: Alice"),
+            vec![trailer("Acked-by", "Alice")]
+        );
+
+        // Folded value
+        assert_eq!(
+            parse_description_trailers("subject\n\nFixes: a long\n  description\n\tof bug\n"),
+            vec![trailer("Fixes", "a long description of bug")]
+        );
+
+        // Last paragraph containing a non-trailer line
+        assert_eq!(
+            parse_description_trailers("subject\n\nAcked-by: Alice\nthanks\n"),
+            vec![]
+        );
+        assert_eq!(
+            parse_description_trailers("subject\n\nNot a key: Alice\n"),
+            vec![]
+        );
+        assert_eq!(parse_description_trailers("subject\n\n: Alice\n"), vec![]);
+        // Continuation line without preceding trailer
+        assert_eq!(
+            parse_description_trailers("subject\n\n  Acked-by: Alice\n"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_split_name_and_email() {
+        assert_eq!(
+            split_name_and_email("Alice <alice@example.com>"),
+            ("Alice", "alice@example.com")
+        );
+        assert_eq!(
+            split_name_and_email("<alice@example.com>"),
+            ("", "alice@example.com")
+        );
+        assert_eq!(split_name_and_email("Alice"), ("Alice", ""));
+        assert_eq!(split_name_and_email("Alice <alice"), ("Alice <alice", ""));
+        assert_eq!(split_name_and_email(""), ("", ""));
+    }
+}
@@ -225,7 +225,14 @@ impl Tree {
     }
 
     pub fn has_conflict(&self) -> bool {
-        !self.conflicts().is_empty()
+        self.has_conflict_matching(&EverythingMatcher)
+    }
+
+    /// Whether any conflicted path matches the `matcher`. Stops walking the
+    /// tree at the first match.
+    pub fn has_conflict_matching(&self, matcher: &dyn Matcher) -> bool {
+        self.entries_matching(matcher)
+            .any(|(_, value)| matches!(value, TreeValue::Conflict(_)))
     }
 }
 
@@ -240,7 +247,6 @@ pub struct TreeEntriesIterator<'matcher> {
 impl<'matcher> TreeEntriesIterator<'matcher> {
     fn new(tree: Tree, matcher: &'matcher dyn Matcher) -> Self {
         let tree = Box::pin(tree);
-        // TODO: Restrict walk according to Matcher::visit()
         let entry_iterator = tree.entries_non_recursive();
         let entry_iterator: TreeEntriesNonRecursiveIterator<'static> =
             unsafe { std::mem::transmute(entry_iterator) };
@@ -268,6 +274,11 @@ impl Iterator for TreeEntriesIterator<'_> {
             let entry = self.entry_iterator.next()?;
             match entry.value() {
                 TreeValue::Tree(id) => {
+                    // Don't load the subtree if nothing in it can match
+                    let subdir = self.tree.dir().join(entry.name());
+                    if self.matcher.visit(&subdir).is_nothing() {
+                        continue;
+                    }
                     let subtree = self.tree.known_sub_tree(entry.name(), id);
                     self.subdir_iterator =
                         Some(Box::new(TreeEntriesIterator::new(subtree, self.matcher)));
@@ -462,9 +473,10 @@ impl Iterator for TreeDiffIterator<'_> {
             let (name, before, after) = self.entry_iterator.next()?;
             let tree_before = matches!(before, Some(TreeValue::Tree(_)));
             let tree_after = matches!(after, Some(TreeValue::Tree(_)));
-            if tree_before || tree_after {
+            let subdir_path = self.dir.join(&name);
+            // Don't load the subtrees if nothing in them can match
+            if (tree_before || tree_after) && !self.matcher.visit(&subdir_path).is_nothing() {
                 let subdir = &name;
-                let subdir_path = self.dir.join(subdir);
                 let before_tree = match before {
                     Some(TreeValue::Tree(id_before)) => {
                         self.tree1.known_sub_tree(subdir, id_before)
@@ -17,7 +17,7 @@ use jujutsu_lib::commit::Commit;
 use jujutsu_lib::default_index_store::ReadonlyIndexImpl;
 use jujutsu_lib::default_revset_engine::{evaluate, RevsetImpl};
 use jujutsu_lib::repo::Repo;
-use jujutsu_lib::revset::{ResolvedExpression, RevsetGraphEdge};
+use jujutsu_lib::revset::{ResolvedExpression, Revset, RevsetEvaluationOptions, RevsetGraphEdge};
 use test_case::test_case;
 use testutils::{CommitGraphBuilder, TestRepo};
 
@@ -29,7 +29,14 @@ fn revset_for_commits<'index>(repo: &'index dyn Repo, commits: &[&Commit]) -> Re
         .unwrap();
     let expression =
         ResolvedExpression::Commits(commits.iter().map(|commit| commit.id().clone()).collect());
-    evaluate(&expression, repo.store(), index, index.as_composite()).unwrap()
+    evaluate(
+        &expression,
+        repo.store(),
+        index,
+        index.as_composite(),
+        &RevsetEvaluationOptions::default(),
+    )
+    .unwrap()
 }
 
 fn direct(commit: &Commit) -> RevsetGraphEdge {
@@ -72,6 +79,7 @@ fn test_graph_iterator_linearized(skip_transitive_edges: bool) {
     let commits = revset
         .iter_graph_impl()
         .set_skip_transitive_edges(skip_transitive_edges)
+        .map(Result::unwrap)
         .collect_vec();
     assert_eq!(commits.len(), 2);
     assert_eq!(commits[0].0, *commit_d.id());
@@ -112,6 +120,7 @@ fn test_graph_iterator_virtual_octopus(skip_transitive_edges: bool) {
     let commits = revset
         .iter_graph_impl()
         .set_skip_transitive_edges(skip_transitive_edges)
+        .map(Result::unwrap)
         .collect_vec();
     assert_eq!(commits.len(), 4);
     assert_eq!(commits[0].0, *commit_f.id());
@@ -163,6 +172,7 @@ fn test_graph_iterator_simple_fork(skip_transitive_edges: bool) {
     let commits = revset
         .iter_graph_impl()
         .set_skip_transitive_edges(skip_transitive_edges)
+        .map(Result::unwrap)
         .collect_vec();
     assert_eq!(commits.len(), 3);
     assert_eq!(commits[0].0, *commit_e.id());
@@ -204,6 +214,7 @@ fn test_graph_iterator_multiple_missing(skip_transitive_edges: bool) {
     let commits = revset
         .iter_graph_impl()
         .set_skip_transitive_edges(skip_transitive_edges)
+        .map(Result::unwrap)
         .collect_vec();
     assert_eq!(commits.len(), 2);
     assert_eq!(commits[0].0, *commit_f.id());
@@ -248,6 +259,7 @@ fn test_graph_iterator_edge_to_ancestor(skip_transitive_edges: bool) {
     let commits = revset
         .iter_graph_impl()
         .set_skip_transitive_edges(skip_transitive_edges)
+        .map(Result::unwrap)
         .collect_vec();
     assert_eq!(commits.len(), 3);
     assert_eq!(commits[0].0, *commit_f.id());
@@ -307,6 +319,7 @@ fn test_graph_iterator_edge_escapes_from_(skip_transitive_edges: bool) {
     let commits = revset
         .iter_graph_impl()
         .set_skip_transitive_edges(skip_transitive_edges)
+        .map(Result::unwrap)
         .collect_vec();
     assert_eq!(commits.len(), 5);
     assert_eq!(commits[0].0, *commit_j.id());
@@ -328,3 +341,51 @@ fn test_graph_iterator_edge_escapes_from_(skip_transitive_edges: bool) {
     assert_eq!(commits[3].1, vec![indirect(&commit_a)]);
     assert_eq!(commits[4].1, vec![missing(&root_commit)]);
 }
+
+#[test]
+fn test_graph_iterator_entries() {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(true);
+    let repo = &test_repo.repo;
+
+    // Tests that the entries carry the same edges as the plain graph iterator:
+    // D
+    // |\        D
+    // b C   =>  |
+    // |/        C
+    // A         |
+    // |         A
+    // root      ~
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut graph_builder = CommitGraphBuilder::new(&settings, tx.mut_repo());
+    let commit_a = graph_builder.initial_commit();
+    let commit_b = graph_builder.commit_with_parents(&[&commit_a]);
+    let commit_c = graph_builder.commit_with_parents(&[&commit_a]);
+    let commit_d = graph_builder.commit_with_parents(&[&commit_b, &commit_c]);
+    let repo = tx.commit();
+    let root_commit = repo.store().root_commit();
+
+    let revset = revset_for_commits(repo.as_ref(), &[&commit_a, &commit_c, &commit_d]);
+    let entries = revset
+        .iter_graph_entries()
+        .map(Result::unwrap)
+        .collect_vec();
+    assert_eq!(
+        entries
+            .iter()
+            .map(|(entry, edges)| (entry.commit_id.clone(), edges.clone()))
+            .collect_vec(),
+        revset.iter_graph().map(Result::unwrap).collect_vec()
+    );
+    assert_eq!(entries.len(), 3);
+    for ((entry, _), commit) in entries.iter().zip([&commit_d, &commit_c, &commit_a]) {
+        assert_eq!(entry.commit_id, *commit.id());
+        assert_eq!(entry.change_id, *commit.change_id());
+    }
+    assert_eq!(entries[0].0.generation_number, 3);
+    assert_eq!(entries[1].0.generation_number, 2);
+    assert_eq!(entries[2].0.generation_number, 1);
+    assert_eq!(entries[0].1, vec![direct(&commit_c)]);
+    assert_eq!(entries[1].1, vec![direct(&commit_a)]);
+    assert_eq!(entries[2].1, vec![missing(&root_commit)]);
+}
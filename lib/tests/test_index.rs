@@ -20,10 +20,13 @@ use jujutsu_lib::commit_builder::CommitBuilder;
 use jujutsu_lib::default_index_store::{MutableIndexImpl, ReadonlyIndexImpl};
 use jujutsu_lib::index::Index;
 use jujutsu_lib::repo::{MutableRepo, ReadonlyRepo, Repo};
+use jujutsu_lib::repo_path::RepoPath;
+use jujutsu_lib::revset::GenerationMode;
 use jujutsu_lib::settings::UserSettings;
 use test_case::test_case;
 use testutils::{
-    create_random_commit, load_repo_at_head, write_random_commit, CommitGraphBuilder, TestRepo,
+    create_random_commit, create_tree, load_repo_at_head, write_random_commit, CommitGraphBuilder,
+    TestRepo,
 };
 
 fn child_commit<'repo>(
@@ -229,14 +232,14 @@ fn test_index_commits_criss_cross(use_git: bool) {
     assert_eq!(
         index
             .walk_revs(&[left_commits[num_generations - 1].id().clone()], &[])
-            .filter_by_generation(0..(num_generations + 1) as u32)
+            .filter_by_generation(0..(num_generations + 1) as u32, GenerationMode::Any)
             .count(),
         2 * num_generations
     );
     assert_eq!(
         index
             .walk_revs(&[right_commits[num_generations - 1].id().clone()], &[])
-            .filter_by_generation(0..(num_generations + 1) as u32)
+            .filter_by_generation(0..(num_generations + 1) as u32, GenerationMode::Any)
             .count(),
         2 * num_generations
     );
@@ -246,7 +249,7 @@ fn test_index_commits_criss_cross(use_git: bool) {
                 &[left_commits[num_generations - 1].id().clone()],
                 &[left_commits[num_generations - 2].id().clone()]
             )
-            .filter_by_generation(0..(num_generations + 1) as u32)
+            .filter_by_generation(0..(num_generations + 1) as u32, GenerationMode::Any)
             .count(),
         2
     );
@@ -256,7 +259,7 @@ fn test_index_commits_criss_cross(use_git: bool) {
                 &[right_commits[num_generations - 1].id().clone()],
                 &[right_commits[num_generations - 2].id().clone()]
             )
-            .filter_by_generation(0..(num_generations + 1) as u32)
+            .filter_by_generation(0..(num_generations + 1) as u32, GenerationMode::Any)
             .count(),
         2
     );
@@ -538,6 +541,136 @@ fn test_index_commits_incremental_squashed(use_git: bool) {
     assert_eq!(commits_by_level(&repo), vec![71, 20]);
 }
 
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_index_commits_committer_timestamp(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    // The second segment is squashed into the first one
+    let repo = create_n_commits(&settings, repo, 1);
+    let repo = create_n_commits(&settings, &repo, 1);
+    assert_eq!(commits_by_level(&repo), vec![3]);
+    let repo = create_n_commits(&settings, &repo, 1);
+    assert_eq!(commits_by_level(&repo), vec![3, 1]);
+
+    let index = as_readonly_impl(&repo).as_composite();
+    for commit_id in repo.view().heads() {
+        let commit = repo.store().get_commit(commit_id).unwrap();
+        assert_eq!(
+            index.entry_by_id(commit_id).unwrap().committer_timestamp(),
+            commit.committer().timestamp.timestamp
+        );
+    }
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_index_commits_changed_path_filter(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let path = RepoPath::from_internal_string;
+    let tree1 = create_tree(repo, &[(&path("dir/file"), "1")]);
+    let tree2 = create_tree(repo, &[(&path("dir/file"), "1"), (&path("other"), "2")]);
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let root_commit_id = repo.store().root_commit_id().clone();
+    let commit1 = mut_repo
+        .new_commit(&settings, vec![root_commit_id], tree1.id().clone())
+        .write()
+        .unwrap();
+    let commit2 = mut_repo
+        .new_commit(&settings, vec![commit1.id().clone()], tree2.id().clone())
+        .write()
+        .unwrap();
+    let commit3 = mut_repo
+        .new_commit(&settings, vec![commit2.id().clone()], tree2.id().clone())
+        .write()
+        .unwrap();
+    let commit4 = mut_repo
+        .new_commit(
+            &settings,
+            vec![commit1.id().clone(), commit3.id().clone()],
+            tree2.id().clone(),
+        )
+        .write()
+        .unwrap();
+    tx.commit();
+
+    // The filters are built when the commits are indexed, and persisted in the
+    // index file
+    let repo = load_repo_at_head(&settings, repo.repo_path());
+    let index = as_readonly_impl(&repo).as_composite();
+    let filter = |commit: &Commit| {
+        index
+            .entry_by_id(commit.id())
+            .unwrap()
+            .changed_path_filter()
+            .unwrap()
+    };
+    let filter1 = filter(&commit1);
+    assert!(filter1.might_contain(&path("dir")));
+    assert!(filter1.might_contain(&path("dir/file")));
+    assert!(!filter1.might_contain(&path("other")));
+    let filter2 = filter(&commit2);
+    assert!(!filter2.might_contain(&path("dir/file")));
+    assert!(filter2.might_contain(&path("other")));
+    let filter3 = filter(&commit3);
+    assert!(!filter3.might_contain(&path("dir/file")));
+    assert!(!filter3.might_contain(&path("other")));
+    // The merge commit changes "other" compared to its first parent
+    let filter4 = filter(&commit4);
+    assert!(!filter4.might_contain(&path("dir/file")));
+    assert!(filter4.might_contain(&path("other")));
+
+    // The filters are carried over when the index segments are squashed
+    let repo = create_n_commits(&settings, &repo, 10);
+    assert_eq!(commits_by_level(&repo), vec![15]);
+    let index = as_readonly_impl(&repo).as_composite();
+    assert_eq!(
+        index
+            .entry_by_id(commit2.id())
+            .unwrap()
+            .changed_path_filter(),
+        Some(filter2)
+    );
+}
+
+/// Test that index segments written in an older file format are rebuilt instead
+/// of being misread.
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_index_commits_old_format_is_rebuilt(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let repo = create_n_commits(&settings, repo, 3);
+    let repo = create_n_commits(&settings, &repo, 1);
+    assert_eq!(commits_by_level(&repo), vec![4, 1]);
+
+    // Strip the format version from all segment files, which is how they were
+    // written before the version was recorded.
+    let index_dir = repo.repo_path().join("index");
+    for entry in std::fs::read_dir(&index_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file() && path.file_name().unwrap() != "type" {
+            let data = std::fs::read(&path).unwrap();
+            std::fs::write(&path, &data[4..]).unwrap();
+        }
+    }
+
+    let repo = load_repo_at_head(&settings, repo.repo_path());
+    let index = as_readonly_impl(&repo);
+    assert_eq!(index.num_commits(), 5);
+    for commit_id in repo.view().heads() {
+        assert!(index.has_id(commit_id));
+    }
+}
+
 /// Test that .jj/repo/index/type is created when the repo is created, and that
 /// it is created when an old repo is loaded.
 #[test_case(false ; "local backend")]
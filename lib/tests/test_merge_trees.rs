@@ -15,6 +15,7 @@
 use assert_matches::assert_matches;
 use itertools::Itertools;
 use jujutsu_lib::backend::{ConflictTerm, TreeValue};
+use jujutsu_lib::matchers::{EverythingMatcher, NothingMatcher, PrefixMatcher};
 use jujutsu_lib::repo::Repo;
 use jujutsu_lib::repo_path::{RepoPath, RepoPathComponent};
 use jujutsu_lib::rewrite::rebase_commit;
@@ -264,6 +265,32 @@ fn test_subtrees(use_git: bool) {
     assert_eq!(entries, expected_entries);
 }
 
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_has_conflict_matching(use_git: bool) {
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+    let store = repo.store();
+
+    let path = RepoPath::from_internal_string;
+    let write_tree = |contents: &str| -> Tree {
+        testutils::create_tree(repo, &[(&path("d1/f1"), contents), (&path("d2/f1"), "0")])
+    };
+    let base_tree = write_tree("0");
+    let side1_tree = write_tree("1");
+    let side2_tree = write_tree("2");
+
+    let merged_tree_id = tree::merge_trees(&side1_tree, &base_tree, &side2_tree).unwrap();
+    let merged_tree = store.get_tree(&RepoPath::root(), &merged_tree_id).unwrap();
+    assert!(merged_tree.has_conflict());
+    assert!(merged_tree.has_conflict_matching(&EverythingMatcher));
+    assert!(merged_tree.has_conflict_matching(&PrefixMatcher::new(&[path("d1")])));
+    assert!(merged_tree.has_conflict_matching(&PrefixMatcher::new(&[path("d1/f1")])));
+    assert!(!merged_tree.has_conflict_matching(&PrefixMatcher::new(&[path("d2")])));
+    assert!(!merged_tree.has_conflict_matching(&NothingMatcher));
+    assert!(!base_tree.has_conflict());
+}
+
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
 fn test_subtree_becomes_empty(use_git: bool) {
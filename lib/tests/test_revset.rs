@@ -12,23 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::ops::Range;
 use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use assert_matches::assert_matches;
 use itertools::Itertools;
-use jujutsu_lib::backend::{ChangeId, CommitId, MillisSinceEpoch, ObjectId, Signature, Timestamp};
+use jujutsu_lib::backend::{
+    ChangeId, CommitId, MillisSinceEpoch, ObjectId, Signature, Timestamp, TreeValue,
+};
 use jujutsu_lib::commit::Commit;
+use jujutsu_lib::default_index_store::ReadonlyIndexImpl;
+use jujutsu_lib::default_revset_engine::build_commit_predicate;
 use jujutsu_lib::git;
-use jujutsu_lib::index::{HexPrefix, PrefixResolution};
+use jujutsu_lib::index::{HexPrefix, Index, PrefixResolution};
 use jujutsu_lib::op_store::{RefTarget, WorkspaceId};
 use jujutsu_lib::repo::Repo;
 use jujutsu_lib::repo_path::RepoPath;
 use jujutsu_lib::revset::{
-    optimize, parse, resolve_symbol, ReverseRevsetGraphIterator, Revset, RevsetAliasesMap,
-    RevsetExpression, RevsetFilterPredicate, RevsetGraphEdge, RevsetResolutionError,
-    RevsetWorkspaceContext,
+    optimize, parse, resolve_symbol, CaseSensitivity, DescriptionScope, FilePattern,
+    GenerationMode, ResolvedExpression, ResolvedPredicateExpression, ReverseRevsetGraphIterator,
+    Revset, RevsetAliasesMap, RevsetEntry, RevsetEvaluationError, RevsetExpression,
+    RevsetFilterExtension, RevsetFilterPredicate, RevsetGraphEdge, RevsetParseContext,
+    RevsetResolutionError, RevsetWorkspaceContext, StringPattern, GENERATION_RANGE_FULL,
 };
-use jujutsu_lib::settings::GitSettings;
+use jujutsu_lib::settings::{GitSettings, UserSettings};
+use jujutsu_lib::store::Store;
 use jujutsu_lib::tree::merge_trees;
 use jujutsu_lib::workspace::Workspace;
 use test_case::test_case;
@@ -174,7 +184,7 @@ fn test_resolve_symbol_commit_id() {
     // Test present() suppresses only NoSuchRevision error
     assert_eq!(resolve_commit_ids(repo.as_ref(), "present(foo)"), []);
     assert_matches!(
-        optimize(parse("present(04)", &RevsetAliasesMap::new(), None).unwrap()).resolve(repo.as_ref()),
+        parse_optimized("present(04)", None).resolve(repo.as_ref()),
         Err(RevsetResolutionError::AmbiguousIdPrefix(s)) if s == "04"
     );
     assert_eq!(
@@ -502,10 +512,28 @@ fn test_resolve_symbol_git_refs() {
     );
 }
 
+fn parse_optimized(
+    revset_str: &str,
+    workspace_ctx: Option<RevsetWorkspaceContext>,
+) -> Rc<RevsetExpression> {
+    let settings = testutils::user_settings();
+    let context = RevsetParseContext {
+        aliases_map: &RevsetAliasesMap::new(),
+        user_email: &settings.user_email(),
+        workspace: workspace_ctx,
+    };
+    optimize(parse(revset_str, &context).unwrap())
+}
+
 fn resolve_commit_ids(repo: &dyn Repo, revset_str: &str) -> Vec<CommitId> {
-    let expression = optimize(parse(revset_str, &RevsetAliasesMap::new(), None).unwrap());
+    let expression = parse_optimized(revset_str, None);
     let expression = expression.resolve(repo).unwrap();
-    expression.evaluate(repo).unwrap().iter().collect()
+    expression
+        .evaluate(repo)
+        .unwrap()
+        .iter()
+        .try_collect()
+        .unwrap()
 }
 
 fn resolve_commit_ids_in_workspace(
@@ -519,12 +547,16 @@ fn resolve_commit_ids_in_workspace(
         workspace_id: workspace.workspace_id(),
         workspace_root: workspace.workspace_root(),
     };
-    let expression =
-        optimize(parse(revset_str, &RevsetAliasesMap::new(), Some(&workspace_ctx)).unwrap());
+    let expression = parse_optimized(revset_str, Some(workspace_ctx.clone()));
     let expression = expression
         .resolve_in_workspace(repo, &workspace_ctx)
         .unwrap();
-    expression.evaluate(repo).unwrap().iter().collect()
+    expression
+        .evaluate(repo)
+        .unwrap()
+        .iter()
+        .try_collect()
+        .unwrap()
 }
 
 #[test_case(false ; "local backend")]
@@ -612,6 +644,37 @@ fn test_evaluate_expression_heads(use_git: bool) {
     );
 }
 
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_roots_of_merges_with_unsorted_parents(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.initial_commit();
+    let unrelated_commit = graph_builder.initial_commit();
+    let merge1 = graph_builder.commit_with_parents(&[&commit1, &commit2]);
+    let mut chain_head = merge1.clone();
+    for _ in 0..80 {
+        chain_head = graph_builder.commit_with_parents(&[&chain_head]);
+    }
+    // The first parent has a lower index position than the second parent, so
+    // the parents of the candidates aren't in index order
+    let merge2 = graph_builder.commit_with_parents(&[&unrelated_commit, &chain_head]);
+
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!("roots({} | {})", merge1.id().hex(), merge2.id().hex())
+        ),
+        vec![merge1.id().clone()]
+    );
+}
+
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
 fn test_evaluate_expression_roots(use_git: bool) {
@@ -626,6 +689,8 @@ fn test_evaluate_expression_roots(use_git: bool) {
     let commit1 = graph_builder.initial_commit();
     let commit2 = graph_builder.commit_with_parents(&[&commit1]);
     let commit3 = graph_builder.commit_with_parents(&[&commit2]);
+    let commit4 = graph_builder.initial_commit();
+    let commit5 = graph_builder.commit_with_parents(&[&commit3, &commit4]);
 
     // Roots of an empty set is an empty set
     assert_eq!(resolve_commit_ids(mut_repo, "roots(none())"), vec![]);
@@ -661,6 +726,30 @@ fn test_evaluate_expression_roots(use_git: bool) {
         vec![commit1.id().clone()]
     );
 
+    // Roots of unrelated commits are both of them
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!("roots({} | {})", commit2.id().hex(), commit4.id().hex())
+        ),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+
+    // A merge is a descendant of both of the roots, even though none of its
+    // parents are in the set
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                "roots({} | {} | {})",
+                commit1.id().hex(),
+                commit4.id().hex(),
+                commit5.id().hex()
+            )
+        ),
+        vec![commit4.id().clone(), commit1.id().clone()]
+    );
+
     // Roots of all commits is the root commit
     assert_eq!(
         resolve_commit_ids(mut_repo, "roots(all())"),
@@ -1078,6 +1167,7 @@ fn test_evaluate_expression_connected(use_git: bool) {
     let commit3 = graph_builder.commit_with_parents(&[&commit2]);
     let commit4 = graph_builder.commit_with_parents(&[&commit1]);
     let commit5 = graph_builder.commit_with_parents(&[&commit3, &commit4]);
+    let commit6 = graph_builder.commit_with_parents(&[&commit4]);
 
     // Connecting an empty set yields an empty set
     assert_eq!(resolve_commit_ids(mut_repo, "connected(none())"), vec![]);
@@ -1137,6 +1227,26 @@ fn test_evaluate_expression_connected(use_git: bool) {
             commit2.id().clone(),
         ]
     );
+
+    // Disconnected clusters aren't bridged through their common ancestor
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                "connected({} | {} | {} | {})",
+                commit2.id().hex(),
+                commit3.id().hex(),
+                commit4.id().hex(),
+                commit6.id().hex()
+            )
+        ),
+        vec![
+            commit6.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+        ]
+    );
 }
 
 #[test_case(false ; "local backend")]
@@ -1241,6 +1351,158 @@ fn test_evaluate_expression_descendants(use_git: bool) {
     );
 }
 
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_descendants_with_generation(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let root_commit_id = repo.store().root_commit_id().clone();
+    let commit1 = write_random_commit(mut_repo, &settings);
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .write()
+        .unwrap();
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .write()
+        .unwrap();
+    let commit4 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone(), commit3.id().clone()])
+        .write()
+        .unwrap();
+    let mut_repo = &*mut_repo;
+
+    let evaluate = |expression: &ResolvedExpression| -> Vec<CommitId> {
+        expression
+            .evaluate(mut_repo)
+            .unwrap()
+            .iter()
+            .try_collect()
+            .unwrap()
+    };
+    let roots = Box::new(ResolvedExpression::Commits(vec![commit1.id().clone()]));
+    let heads = Box::new(ResolvedExpression::Commits(
+        mut_repo.view().heads().iter().cloned().collect(),
+    ));
+    let descendants = |generation| ResolvedExpression::Descendants {
+        roots: roots.clone(),
+        heads: heads.clone(),
+        generation,
+    };
+
+    // Direct children, which should match the children walk
+    let children = evaluate(&descendants(1..2));
+    assert_eq!(children, vec![commit3.id().clone(), commit2.id().clone()]);
+    assert_eq!(
+        evaluate(&ResolvedExpression::DagRange {
+            roots: roots.clone(),
+            heads: heads.clone(),
+            generation_from_roots: 1..2,
+        }),
+        children
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, &format!("{}+", commit1.id().hex())),
+        children
+    );
+
+    assert_eq!(
+        evaluate(&descendants(0..2)),
+        vec![
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone(),
+        ]
+    );
+    assert_eq!(evaluate(&descendants(2..3)), vec![commit4.id().clone()]);
+    assert_eq!(evaluate(&descendants(3..4)), vec![]);
+    assert_eq!(
+        evaluate(&descendants(GENERATION_RANGE_FULL)),
+        resolve_commit_ids(mut_repo, &format!("{}:", commit1.id().hex()))
+    );
+
+    // Root commit is a descendant only of itself
+    assert_eq!(
+        evaluate(&ResolvedExpression::Descendants {
+            roots: Box::new(ResolvedExpression::Commits(vec![root_commit_id.clone()])),
+            heads,
+            generation: 0..1,
+        }),
+        vec![root_commit_id]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_dag_range_with_long_generation(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    // Chain of 70 commits, where commits[10] is also a child of commits[0], so
+    // commits[k] (k >= 10) is reachable from commits[0] in k and k - 9
+    // generations.
+    let mut commits = vec![write_random_commit(mut_repo, &settings)];
+    for k in 1..70 {
+        let mut parents = vec![commits[k - 1].id().clone()];
+        if k == 10 {
+            parents.push(commits[0].id().clone());
+        }
+        let commit = create_random_commit(mut_repo, &settings)
+            .set_parents(parents)
+            .write()
+            .unwrap();
+        commits.push(commit);
+    }
+    let mut_repo = &*mut_repo;
+
+    let evaluate = |generation_from_roots| -> Vec<CommitId> {
+        let expression = ResolvedExpression::DagRange {
+            roots: Box::new(ResolvedExpression::Commits(vec![commits[0].id().clone()])),
+            heads: Box::new(ResolvedExpression::Commits(vec![commits[69].id().clone()])),
+            generation_from_roots,
+        };
+        expression
+            .evaluate(mut_repo)
+            .unwrap()
+            .iter()
+            .try_collect()
+            .unwrap()
+    };
+    let expected = |generation: Range<u64>| -> Vec<CommitId> {
+        (0..commits.len())
+            .rev()
+            .filter(|&k| {
+                let k = k as u64;
+                generation.contains(&k) || (k >= 10 && generation.contains(&(k - 9)))
+            })
+            .map(|k| commits[k].id().clone())
+            .collect()
+    };
+
+    assert_eq!(
+        evaluate(1..2),
+        vec![commits[10].id().clone(), commits[1].id().clone()]
+    );
+    assert_eq!(evaluate(1..2), expected(1..2));
+    assert_eq!(evaluate(0..1), expected(0..1));
+    assert_eq!(evaluate(2..4), expected(2..4));
+    assert_eq!(evaluate(5..5), vec![]);
+    // Ranges ending within and beyond the generation bit set
+    assert_eq!(evaluate(58..64), expected(58..64));
+    assert_eq!(evaluate(60..65), expected(60..65));
+    assert_eq!(evaluate(64..66), expected(64..66));
+    assert_eq!(evaluate(0..100), expected(0..100));
+}
+
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
 fn test_evaluate_expression_none(use_git: bool) {
@@ -1279,97 +1541,466 @@ fn test_evaluate_expression_all(use_git: bool) {
     );
 }
 
-#[test_case(false ; "local backend")]
-#[test_case(true ; "git backend")]
-fn test_evaluate_expression_visible_heads(use_git: bool) {
+#[test]
+fn test_evaluate_expression_all_excludes_hidden() {
     let settings = testutils::user_settings();
-    let test_repo = TestRepo::init(use_git);
+    let test_repo = TestRepo::init(false);
     let repo = &test_repo.repo;
 
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
+    let root_commit_id = repo.store().root_commit_id().clone();
     let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
     let commit1 = graph_builder.initial_commit();
-    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
-    let commit3 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit2 = graph_builder.initial_commit();
+    mut_repo.remove_head(commit2.id());
 
+    let evaluate = |revset_str: &str| {
+        parse_optimized(revset_str, None)
+            .resolve(mut_repo)
+            .unwrap()
+            .evaluate(mut_repo)
+            .unwrap()
+    };
+    let revset = evaluate("all()");
     assert_eq!(
-        resolve_commit_ids(mut_repo, "visible_heads()"),
-        vec![commit3.id().clone(), commit2.id().clone()]
+        revset.iter().try_collect::<_, Vec<_>, _>().unwrap(),
+        vec![commit1.id().clone(), root_commit_id.clone()]
     );
+    assert!(!revset.is_empty());
+    assert!(revset.contains(commit1.id()).unwrap());
+    assert!(revset.contains(&root_commit_id).unwrap());
+    assert!(!revset.contains(commit2.id()).unwrap());
+
+    let revset = evaluate("none()");
+    assert!(revset.is_empty());
+    assert_eq!(revset.count().unwrap(), 0);
+    assert!(!revset.contains(&root_commit_id).unwrap());
 }
 
-#[test_case(false ; "local backend")]
-#[test_case(true ; "git backend")]
-fn test_evaluate_expression_git_refs(use_git: bool) {
+#[test]
+fn test_evaluate_expression_union_of_many_sets() {
     let settings = testutils::user_settings();
-    let test_repo = TestRepo::init(use_git);
+    let test_repo = TestRepo::init(false);
     let repo = &test_repo.repo;
 
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let mut commits = vec![graph_builder.initial_commit()];
+    while commits.len() < 32 {
+        let commit = graph_builder.commit_with_parents(&[commits.last().unwrap()]);
+        commits.push(commit);
+    }
 
-    let commit1 = write_random_commit(mut_repo, &settings);
-    let commit2 = write_random_commit(mut_repo, &settings);
-    let commit3 = write_random_commit(mut_repo, &settings);
-    let commit4 = write_random_commit(mut_repo, &settings);
+    // 16-way union of overlapping sets in both left- and right-leaning trees.
+    // The expressions are built directly since deeply nested parentheses are
+    // slow to parse.
+    let operands = (0..16)
+        .map(|i| {
+            RevsetExpression::commits(vec![commits[i].id().clone(), commits[i * 2].id().clone()])
+        })
+        .collect_vec();
+    let left_leaning = operands
+        .iter()
+        .cloned()
+        .reduce(|acc, operand| acc.union(&operand))
+        .unwrap();
+    let right_leaning = operands
+        .iter()
+        .rev()
+        .cloned()
+        .reduce(|acc, operand| operand.union(&acc))
+        .unwrap();
+    let expected_ids = (0..16)
+        .chain((0..16).map(|i| i * 2))
+        .sorted()
+        .rev()
+        .dedup()
+        .map(|i| commits[i].id().clone())
+        .collect_vec();
+    for expression in [&left_leaning, &right_leaning] {
+        let revset = optimize(expression.clone())
+            .resolve(mut_repo)
+            .unwrap()
+            .evaluate(mut_repo)
+            .unwrap();
+        assert_eq!(
+            revset.iter().try_collect::<_, Vec<_>, _>().unwrap(),
+            expected_ids
+        );
+        assert!(revset.contains(commits[30].id()).unwrap());
+        assert!(!revset.contains(commits[31].id()).unwrap());
+    }
+}
 
-    // Can get git refs when there are none
-    assert_eq!(resolve_commit_ids(mut_repo, "git_refs()"), vec![]);
-    // Can get a mix of git refs
-    mut_repo.set_git_ref(
-        "refs/heads/branch1".to_string(),
-        RefTarget::Normal(commit1.id().clone()),
-    );
-    mut_repo.set_git_ref(
-        "refs/tags/tag1".to_string(),
-        RefTarget::Normal(commit2.id().clone()),
+#[test]
+fn test_evaluate_expression_intersection_of_many_sets() {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(false);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let mut commits = vec![graph_builder.initial_commit()];
+    while commits.len() < 32 {
+        let commit = graph_builder.commit_with_parents(&[commits.last().unwrap()]);
+        commits.push(commit);
+    }
+
+    let multiples_of = |k: usize| {
+        let hexes = (0..32).step_by(k).map(|i| commits[i].id().hex());
+        format!("({})", hexes.format(" | "))
+    };
+    let left_leaning = |operands: &[String]| operands.join(" & ");
+    let right_leaning = |operands: &[String]| {
+        operands
+            .iter()
+            .rev()
+            .cloned()
+            .reduce(|acc, operand| format!("{operand} & ({acc})"))
+            .unwrap()
+    };
+    let ids = |indices: &[usize]| {
+        indices
+            .iter()
+            .map(|&i| commits[i].id().clone())
+            .collect_vec()
+    };
+
+    // Operands of different sizes and strides
+    let operands = [1, 2, 3, 4].map(multiples_of);
+    let expected_ids = ids(&[24, 12, 0]);
+    assert_eq!(
+        resolve_commit_ids(mut_repo, &left_leaning(&operands)),
+        expected_ids
     );
     assert_eq!(
-        resolve_commit_ids(mut_repo, "git_refs()"),
-        vec![commit2.id().clone(), commit1.id().clone()]
+        resolve_commit_ids(mut_repo, &right_leaning(&operands)),
+        expected_ids
     );
-    // Two refs pointing to the same commit does not result in a duplicate in the
-    // revset
-    mut_repo.set_git_ref(
-        "refs/tags/tag2".to_string(),
-        RefTarget::Normal(commit2.id().clone()),
+
+    // One of the operands is empty
+    for empty_index in 0..3 {
+        let mut operands = [2, 3, 6].map(multiples_of);
+        operands[empty_index] = "none()".to_owned();
+        assert_eq!(
+            resolve_commit_ids(mut_repo, &left_leaning(&operands)),
+            vec![]
+        );
+        assert_eq!(
+            resolve_commit_ids(mut_repo, &right_leaning(&operands)),
+            vec![]
+        );
+    }
+
+    // All operands are identical
+    let operands = vec![multiples_of(5); 4];
+    let expected_ids = ids(&[30, 25, 20, 15, 10, 5, 0]);
+    assert_eq!(
+        resolve_commit_ids(mut_repo, &left_leaning(&operands)),
+        expected_ids
     );
     assert_eq!(
-        resolve_commit_ids(mut_repo, "git_refs()"),
-        vec![commit2.id().clone(), commit1.id().clone()]
+        resolve_commit_ids(mut_repo, &right_leaning(&operands)),
+        expected_ids
     );
-    // Can get git refs when there are conflicted refs
-    mut_repo.set_git_ref(
-        "refs/heads/branch1".to_string(),
-        RefTarget::Conflict {
-            removes: vec![commit1.id().clone()],
-            adds: vec![commit2.id().clone(), commit3.id().clone()],
-        },
+
+    // Membership in the intersection requires membership in every operand
+    let revset = parse_optimized(&left_leaning(&[2, 3].map(multiples_of)), None)
+        .resolve(mut_repo)
+        .unwrap()
+        .evaluate(mut_repo)
+        .unwrap();
+    assert!(revset.contains(commits[6].id()).unwrap());
+    assert!(!revset.contains(commits[4].id()).unwrap());
+    assert!(!revset.contains(commits[9].id()).unwrap());
+}
+
+#[test]
+fn test_evaluate_expression_unknown_commit_id() {
+    let test_repo = TestRepo::init(false);
+    let repo = &test_repo.repo;
+
+    let root_commit_id = repo.store().root_commit_id().clone();
+    let missing_id = CommitId::new(vec![0x99; repo.store().commit_id_length()]);
+    let evaluate = |expression: &ResolvedExpression| {
+        expression
+            .evaluate(repo.as_ref())
+            .map(|revset| revset.iter().try_collect::<_, Vec<_>, _>().unwrap())
+    };
+
+    assert_matches!(
+        evaluate(&ResolvedExpression::Commits(vec![
+            root_commit_id.clone(),
+            missing_id.clone(),
+        ])),
+        Err(RevsetEvaluationError::Other(message)) if message.contains(&missing_id.hex())
     );
-    mut_repo.set_git_ref(
-        "refs/tags/tag1".to_string(),
-        RefTarget::Conflict {
-            removes: vec![commit2.id().clone()],
-            adds: vec![commit3.id().clone(), commit4.id().clone()],
-        },
+    assert_matches!(
+        evaluate(&ResolvedExpression::Ancestors {
+            heads: Box::new(ResolvedExpression::Commits(vec![missing_id])),
+            generation: GENERATION_RANGE_FULL,
+            generation_mode: GenerationMode::Any,
+        }),
+        Err(RevsetEvaluationError::Other(_))
     );
-    mut_repo.remove_git_ref("refs/tags/tag2");
     assert_eq!(
-        resolve_commit_ids(mut_repo, "git_refs()"),
-        vec![
-            commit4.id().clone(),
-            commit3.id().clone(),
-            commit2.id().clone()
-        ]
+        evaluate(&ResolvedExpression::Commits(vec![root_commit_id.clone()])).unwrap(),
+        vec![root_commit_id]
     );
 }
 
-#[test_case(false ; "local backend")]
-#[test_case(true ; "git backend")]
-fn test_evaluate_expression_git_head(use_git: bool) {
+#[test]
+fn test_revset_take() {
     let settings = testutils::user_settings();
-    let test_repo = TestRepo::init(use_git);
+    let test_repo = TestRepo::init(false);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let root_commit_id = repo.store().root_commit_id().clone();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.commit_with_parents(&[&commit2]);
+
+    let ancestors_of_2 = || {
+        RevsetExpression::commit(commit2.id().clone())
+            .ancestors()
+            .resolve(mut_repo)
+            .unwrap()
+            .evaluate(mut_repo)
+            .unwrap()
+    };
+
+    let revset = ancestors_of_2().take(2);
+    assert_eq!(
+        revset.iter().try_collect::<_, Vec<_>, _>().unwrap(),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        revset.iter_reverse().try_collect::<_, Vec<_>, _>().unwrap(),
+        vec![commit1.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(revset.count().unwrap(), 2);
+    assert!(revset.contains(commit2.id()).unwrap());
+    assert!(revset.contains(commit1.id()).unwrap());
+    assert!(!revset.contains(&root_commit_id).unwrap());
+    assert!(!revset.contains(commit3.id()).unwrap());
+
+    // Taking more than the set has
+    let revset = ancestors_of_2().take(10);
+    assert_eq!(revset.count().unwrap(), 3);
+    assert!(revset.contains(&root_commit_id).unwrap());
+
+    let revset = ancestors_of_2().take(0);
+    assert!(revset.is_empty());
+    assert!(!revset.contains(commit2.id()).unwrap());
+}
+
+#[test]
+fn test_revset_contains() {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(false);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let root_commit_id = repo.store().root_commit_id().clone();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.commit_with_parents(&[&commit2]);
+    let missing_id = CommitId::new(vec![0x99; repo.store().commit_id_length()]);
+
+    let revset_str = format!(":{} ~ {}", commit2.id().hex(), commit1.id().hex());
+    let revset = parse_optimized(&revset_str, None)
+        .resolve(mut_repo)
+        .unwrap()
+        .evaluate(mut_repo)
+        .unwrap();
+    // Test in arbitrary order
+    assert!(revset.contains(&root_commit_id).unwrap());
+    assert!(revset.contains(commit2.id()).unwrap());
+    assert!(!revset.contains(commit1.id()).unwrap());
+    assert!(!revset.contains(commit3.id()).unwrap());
+    assert!(!revset.contains(&missing_id).unwrap());
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_count(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.commit_with_parents(&[&commit1]);
+    graph_builder.commit_with_parents(&[&commit2, &commit3]);
+
+    let count = |revset_str: &str| {
+        let expression = parse_optimized(revset_str, None);
+        let revset = expression
+            .resolve(mut_repo)
+            .unwrap()
+            .evaluate(mut_repo)
+            .unwrap();
+        assert_eq!(revset.count().unwrap(), revset.iter().count());
+        revset.count().unwrap()
+    };
+    assert_eq!(count("none()"), 0);
+    assert_eq!(count("all()"), 5);
+    assert_eq!(count("heads(all())"), 1);
+    assert_eq!(
+        count(&format!("{} | {}", commit2.id().hex(), commit3.id().hex())),
+        2
+    );
+    assert_eq!(count("~merges()"), 4);
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_iter_entries(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.initial_commit();
+    let commit4 = graph_builder.commit_with_parents(&[&commit2, &commit3]);
+
+    let expression = parse_optimized("~root", None);
+    let revset = expression
+        .resolve(mut_repo)
+        .unwrap()
+        .evaluate(mut_repo)
+        .unwrap();
+    let entry = |commit: &Commit, generation_number| RevsetEntry {
+        commit_id: commit.id().clone(),
+        change_id: commit.change_id().clone(),
+        generation_number,
+    };
+    assert_eq!(
+        revset.iter_entries().try_collect::<_, Vec<_>, _>().unwrap(),
+        vec![
+            entry(&commit4, 3),
+            entry(&commit3, 1),
+            entry(&commit2, 2),
+            entry(&commit1, 1),
+        ]
+    );
+    // Same order as iter()
+    assert_eq!(
+        revset
+            .iter_entries()
+            .map_ok(|entry| entry.commit_id)
+            .try_collect::<_, Vec<_>, _>()
+            .unwrap(),
+        revset.iter().try_collect::<_, Vec<_>, _>().unwrap()
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_visible_heads(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.commit_with_parents(&[&commit1]);
+
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "visible_heads()"),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_git_refs(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let commit1 = write_random_commit(mut_repo, &settings);
+    let commit2 = write_random_commit(mut_repo, &settings);
+    let commit3 = write_random_commit(mut_repo, &settings);
+    let commit4 = write_random_commit(mut_repo, &settings);
+
+    // Can get git refs when there are none
+    assert_eq!(resolve_commit_ids(mut_repo, "git_refs()"), vec![]);
+    // Can get a mix of git refs
+    mut_repo.set_git_ref(
+        "refs/heads/branch1".to_string(),
+        RefTarget::Normal(commit1.id().clone()),
+    );
+    mut_repo.set_git_ref(
+        "refs/tags/tag1".to_string(),
+        RefTarget::Normal(commit2.id().clone()),
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "git_refs()"),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    // Two refs pointing to the same commit does not result in a duplicate in the
+    // revset
+    mut_repo.set_git_ref(
+        "refs/tags/tag2".to_string(),
+        RefTarget::Normal(commit2.id().clone()),
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "git_refs()"),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    // Can get git refs when there are conflicted refs
+    mut_repo.set_git_ref(
+        "refs/heads/branch1".to_string(),
+        RefTarget::Conflict {
+            removes: vec![commit1.id().clone()],
+            adds: vec![commit2.id().clone(), commit3.id().clone()],
+        },
+    );
+    mut_repo.set_git_ref(
+        "refs/tags/tag1".to_string(),
+        RefTarget::Conflict {
+            removes: vec![commit2.id().clone()],
+            adds: vec![commit3.id().clone(), commit4.id().clone()],
+        },
+    );
+    mut_repo.remove_git_ref("refs/tags/tag2");
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "git_refs()"),
+        vec![
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone()
+        ]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_git_head(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
     let repo = &test_repo.repo;
 
     let mut tx = repo.start_transaction(&settings, "test");
@@ -1616,10 +2247,15 @@ fn test_evaluate_expression_latest(use_git: bool) {
         vec![commit1_t3.id().clone()],
     );
 
-    // Tie-breaking: pick the later entry in position
+    // Tie-breaking: pick the entry with the greater change id
+    let later_t2 = if commit2_t2.change_id() > commit3_t2.change_id() {
+        &commit2_t2
+    } else {
+        &commit3_t2
+    };
     assert_eq!(
         resolve_commit_ids(mut_repo, "latest(all(), 2)"),
-        vec![commit3_t2.id().clone(), commit1_t3.id().clone()],
+        vec![later_t2.id().clone(), commit1_t3.id().clone()],
     );
 
     assert_eq!(
@@ -1662,39 +2298,93 @@ fn test_evaluate_expression_latest(use_git: bool) {
             commit1_t3.id().clone(),
         ],
     );
+
+    // Skip the latest entries by offset
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "latest(~root, 1, 0)"),
+        vec![commit1_t3.id().clone()],
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "latest(~root, 2, 1)"),
+        vec![commit3_t2.id().clone(), commit2_t2.id().clone()],
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "latest(~root, 5, 3)"),
+        vec![commit4_t1.id().clone()],
+    );
+    assert_eq!(resolve_commit_ids(mut_repo, "latest(~root, 0, 1)"), vec![]);
+
+    // Should not panic if offset is larger than the candidates size
+    assert_eq!(resolve_commit_ids(mut_repo, "latest(~root, 1, 4)"), vec![]);
+    assert_eq!(resolve_commit_ids(mut_repo, "latest(~root, 2, 10)"), vec![]);
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!("latest(~root, {}, {})", usize::MAX, usize::MAX)
+        ),
+        vec![]
+    );
 }
 
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
-fn test_evaluate_expression_merges(use_git: bool) {
+fn test_evaluate_expression_earliest(use_git: bool) {
     let settings = testutils::user_settings();
     let test_repo = TestRepo::init(use_git);
     let repo = &test_repo.repo;
 
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
-    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
-    let commit1 = graph_builder.initial_commit();
-    let commit2 = graph_builder.initial_commit();
-    let commit3 = graph_builder.initial_commit();
-    let commit4 = graph_builder.commit_with_parents(&[&commit1, &commit2]);
-    let commit5 = graph_builder.commit_with_parents(&[&commit1, &commit2, &commit3]);
 
-    // Finds all merges by default
+    let mut write_commit_with_author_timestamp = |msec, committer_msec| {
+        let builder = create_random_commit(mut_repo, &settings);
+        let mut author = builder.author().clone();
+        author.timestamp.timestamp = MillisSinceEpoch(msec);
+        let mut committer = builder.committer().clone();
+        committer.timestamp.timestamp = MillisSinceEpoch(committer_msec);
+        builder
+            .set_author(author)
+            .set_committer(committer)
+            .write()
+            .unwrap()
+    };
+    let commit1_t3 = write_commit_with_author_timestamp(3000, 1000);
+    let commit2_t2 = write_commit_with_author_timestamp(2000, 5000);
+    let commit3_t4 = write_commit_with_author_timestamp(4000, 2000);
+
+    // Picks by author timestamp, not by committer timestamp or position
     assert_eq!(
-        resolve_commit_ids(mut_repo, "merges()"),
-        vec![commit5.id().clone(), commit4.id().clone(),]
+        resolve_commit_ids(mut_repo, "earliest(~root)"),
+        vec![commit2_t2.id().clone()],
     );
-    // Searches only among candidates if specified
     assert_eq!(
-        resolve_commit_ids(mut_repo, &format!(":{} & merges()", commit5.id().hex())),
-        vec![commit5.id().clone()]
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                "earliest({} | {})",
+                commit1_t3.id().hex(),
+                commit3_t4.id().hex()
+            )
+        ),
+        vec![commit1_t3.id().clone()],
+    );
+    // The root commit has zero timestamp
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "earliest(all())"),
+        vec![repo.store().root_commit_id().clone()],
+    );
+
+    // Should not panic with empty set
+    assert_eq!(resolve_commit_ids(mut_repo, "earliest(none())"), vec![]);
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "earliest(~root & description(nothing))"),
+        vec![]
     );
 }
 
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
-fn test_evaluate_expression_description(use_git: bool) {
+fn test_evaluate_expression_latest_same_timestamp(use_git: bool) {
     let settings = testutils::user_settings();
     let test_repo = TestRepo::init(use_git);
     let repo = &test_repo.repo;
@@ -1702,45 +2392,188 @@ fn test_evaluate_expression_description(use_git: bool) {
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
 
-    let commit1 = create_random_commit(mut_repo, &settings)
-        .set_description("commit 1")
-        .write()
-        .unwrap();
-    let commit2 = create_random_commit(mut_repo, &settings)
-        .set_parents(vec![commit1.id().clone()])
-        .set_description("commit 2")
-        .write()
-        .unwrap();
-    let commit3 = create_random_commit(mut_repo, &settings)
-        .set_parents(vec![commit2.id().clone()])
-        .set_description("commit 3")
-        .write()
-        .unwrap();
+    let mut write_commit_with_change_id = |change_id: &str| {
+        let builder = create_random_commit(mut_repo, &settings);
+        let mut committer = builder.committer().clone();
+        committer.timestamp.timestamp = MillisSinceEpoch(1);
+        builder
+            .set_committer(committer)
+            .set_change_id(ChangeId::from_hex(change_id))
+            .write()
+            .unwrap()
+    };
+    // Change ids are not in index order
+    let commit1 = write_commit_with_change_id("33333333333333333333333333333333");
+    let commit2 = write_commit_with_change_id("11111111111111111111111111111111");
+    let commit3 = write_commit_with_change_id("22222222222222222222222222222222");
+    let commit4 = write_commit_with_change_id("44444444444444444444444444444444");
+    let _commit5 = write_commit_with_change_id("00000000000000000000000000000000");
 
-    // Can find multiple matches
+    // Ties are broken by change id, not by index position
     assert_eq!(
-        resolve_commit_ids(mut_repo, "description(commit)"),
+        resolve_commit_ids(mut_repo, "latest(~root)"),
+        vec![commit4.id().clone()],
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "latest(~root, 2)"),
+        vec![commit4.id().clone(), commit1.id().clone()],
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "latest(~root, 3)"),
         vec![
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit1.id().clone(),
+        ],
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "latest(~root, 4)"),
+        vec![
+            commit4.id().clone(),
             commit3.id().clone(),
             commit2.id().clone(),
-            commit1.id().clone()
-        ]
+            commit1.id().clone(),
+        ],
     );
-    // Can find a unique match
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_latest_per_author(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let mut write_commit = |email: &str, msec| {
+        let builder = create_random_commit(mut_repo, &settings);
+        let mut author = builder.author().clone();
+        author.email = email.to_owned();
+        let mut committer = builder.committer().clone();
+        committer.timestamp.timestamp = MillisSinceEpoch(msec);
+        builder
+            .set_author(author)
+            .set_committer(committer)
+            .write()
+            .unwrap()
+    };
+    let commit1 = write_commit("a@example.com", 3);
+    let commit2 = write_commit("b@example.com", 1);
+    let commit3 = write_commit("a@example.com", 1);
+    let commit4 = write_commit("b@example.com", 2);
+    let commit5 = write_commit("c@example.com", 1);
+    let commit6 = write_commit("a@example.com", 2);
+
+    // Latest commit of each author, sorted in index order
     assert_eq!(
-        resolve_commit_ids(mut_repo, "description(\"commit 2\")"),
-        vec![commit2.id().clone()]
+        resolve_commit_ids(mut_repo, "latest(~root, group=author)"),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit1.id().clone(),
+        ],
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "latest(~root, 2, group=author)"),
+        vec![
+            commit6.id().clone(),
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone(),
+        ],
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "latest(~root, 1, 1, author)"),
+        vec![commit6.id().clone(), commit2.id().clone()],
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "latest(~root, 0, group=author)"),
+        vec![],
+    );
+    // Only the candidates are grouped
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                "latest({} | {}, group=author)",
+                commit3.id().hex(),
+                commit5.id().hex()
+            )
+        ),
+        vec![commit5.id().clone(), commit3.id().clone()],
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_merges(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.initial_commit();
+    let commit3 = graph_builder.initial_commit();
+    let commit4 = graph_builder.commit_with_parents(&[&commit1, &commit2]);
+    let commit5 = graph_builder.commit_with_parents(&[&commit1, &commit2, &commit3]);
+
+    // Finds all merges by default
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "merges()"),
+        vec![commit5.id().clone(), commit4.id().clone(),]
     );
     // Searches only among candidates if specified
     assert_eq!(
-        resolve_commit_ids(mut_repo, "visible_heads() & description(\"commit 2\")"),
-        vec![]
+        resolve_commit_ids(mut_repo, &format!(":{} & merges()", commit5.id().hex())),
+        vec![commit5.id().clone()]
     );
 }
 
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
-fn test_evaluate_expression_author(use_git: bool) {
+fn test_evaluate_expression_generation(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.commit_with_parents(&[&commit2]);
+    let commit4 = graph_builder.initial_commit();
+    // Generation is the length of the longest path from the root
+    let commit5 = graph_builder.commit_with_parents(&[&commit3, &commit4]);
+
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "generation(0)"),
+        vec![mut_repo.store().root_commit_id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "generation(1)"),
+        vec![commit4.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "generation(2..4)"),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "generation(4..)"),
+        vec![commit5.id().clone()]
+    );
+    assert_eq!(resolve_commit_ids(mut_repo, "generation(5..)"), vec![]);
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_description(use_git: bool) {
     let settings = testutils::user_settings();
     let test_repo = TestRepo::init(use_git);
     let repo = &test_repo.repo;
@@ -1748,73 +2581,76 @@ fn test_evaluate_expression_author(use_git: bool) {
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
 
-    let timestamp = Timestamp {
-        timestamp: MillisSinceEpoch(0),
-        tz_offset: 0,
-    };
     let commit1 = create_random_commit(mut_repo, &settings)
-        .set_author(Signature {
-            name: "name1".to_string(),
-            email: "email1".to_string(),
-            timestamp: timestamp.clone(),
-        })
+        .set_description("commit 1")
         .write()
         .unwrap();
     let commit2 = create_random_commit(mut_repo, &settings)
         .set_parents(vec![commit1.id().clone()])
-        .set_author(Signature {
-            name: "name2".to_string(),
-            email: "email2".to_string(),
-            timestamp: timestamp.clone(),
-        })
+        .set_description("commit 2")
         .write()
         .unwrap();
     let commit3 = create_random_commit(mut_repo, &settings)
         .set_parents(vec![commit2.id().clone()])
-        .set_author(Signature {
-            name: "name3".to_string(),
-            email: "email3".to_string(),
-            timestamp,
-        })
+        .set_description("commit 3")
         .write()
         .unwrap();
 
     // Can find multiple matches
     assert_eq!(
-        resolve_commit_ids(mut_repo, "author(name)"),
+        resolve_commit_ids(mut_repo, "description(commit)"),
         vec![
             commit3.id().clone(),
             commit2.id().clone(),
             commit1.id().clone()
         ]
     );
-    // Can find a unique match by either name or email
+    // Can find a unique match
     assert_eq!(
-        resolve_commit_ids(mut_repo, "author(\"name2\")"),
+        resolve_commit_ids(mut_repo, "description(\"commit 2\")"),
         vec![commit2.id().clone()]
     );
-    assert_eq!(
-        resolve_commit_ids(mut_repo, "author(\"name3\")"),
-        vec![commit3.id().clone()]
-    );
     // Searches only among candidates if specified
     assert_eq!(
-        resolve_commit_ids(mut_repo, "visible_heads() & author(\"name2\")"),
+        resolve_commit_ids(mut_repo, "visible_heads() & description(\"commit 2\")"),
         vec![]
     );
-    // Filter by union of pure predicate and set
+    // Can match by regex
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            &format!("root.. & (author(name1) | {})", commit3.id().hex())
-        ),
+        resolve_commit_ids(mut_repo, r#"description(regex:"commit [13]")"#),
         vec![commit3.id().clone(), commit1.id().clone()]
     );
+    // Can match case-insensitively
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"description(substring-i:"Commit 2")"#),
+        vec![commit2.id().clone()]
+    );
+    // Can match the whole description
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"description(exact:"commit")"#),
+        vec![]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"description(exact:"commit 2")"#),
+        vec![commit2.id().clone()]
+    );
+    // Explicit substring pattern doesn't interpret metacharacters
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"description(substring:"commit [13]")"#),
+        vec![]
+    );
+    // Invalid regex is reported at evaluation time
+    let expression = parse_optimized(r#"description(regex:"commit (")"#, None);
+    let expression = expression.resolve(mut_repo).unwrap();
+    assert_matches!(
+        expression.evaluate(mut_repo).map(|_| ()),
+        Err(RevsetEvaluationError::Other(_))
+    );
 }
 
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
-fn test_evaluate_expression_committer(use_git: bool) {
+fn test_evaluate_expression_description_scope(use_git: bool) {
     let settings = testutils::user_settings();
     let test_repo = TestRepo::init(use_git);
     let repo = &test_repo.repo;
@@ -1822,65 +2658,68 @@ fn test_evaluate_expression_committer(use_git: bool) {
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
 
-    let timestamp = Timestamp {
-        timestamp: MillisSinceEpoch(0),
-        tz_offset: 0,
-    };
     let commit1 = create_random_commit(mut_repo, &settings)
-        .set_committer(Signature {
-            name: "name1".to_string(),
-            email: "email1".to_string(),
-            timestamp: timestamp.clone(),
-        })
+        .set_description("fix foo\n\nThis fixes bar.\n\nFixes: baz\n")
         .write()
         .unwrap();
     let commit2 = create_random_commit(mut_repo, &settings)
         .set_parents(vec![commit1.id().clone()])
-        .set_committer(Signature {
-            name: "name2".to_string(),
-            email: "email2".to_string(),
-            timestamp: timestamp.clone(),
-        })
+        .set_description("fix bar\nin two lines\n")
         .write()
         .unwrap();
     let commit3 = create_random_commit(mut_repo, &settings)
         .set_parents(vec![commit2.id().clone()])
-        .set_committer(Signature {
-            name: "name3".to_string(),
-            email: "email3".to_string(),
-            timestamp,
-        })
+        .set_description("")
         .write()
         .unwrap();
 
-    // Can find multiple matches
+    let resolve = |revset_str: &str| resolve_commit_ids(mut_repo, revset_str);
+
+    // Description without blank line is all subject
     assert_eq!(
-        resolve_commit_ids(mut_repo, "committer(name)"),
-        vec![
-            commit3.id().clone(),
-            commit2.id().clone(),
-            commit1.id().clone()
-        ]
+        resolve(r#"description(subject:"bar")"#),
+        vec![commit2.id().clone()]
     );
-    // Can find a unique match by either name or email
     assert_eq!(
-        resolve_commit_ids(mut_repo, "committer(\"name2\")"),
+        resolve(r#"description(subject:"two lines")"#),
         vec![commit2.id().clone()]
     );
     assert_eq!(
-        resolve_commit_ids(mut_repo, "committer(\"name3\")"),
-        vec![commit3.id().clone()]
+        resolve(r#"description(body:"bar")"#),
+        vec![commit1.id().clone()]
     );
-    // Searches only among candidates if specified
     assert_eq!(
-        resolve_commit_ids(mut_repo, "visible_heads() & committer(\"name2\")"),
-        vec![]
+        resolve(r#"description(body:"Fixes: baz")"#),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"description(full:"bar")"#),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"description(full:"bar")"#),
+        resolve("description(bar)")
+    );
+
+    // Empty description matches only empty needle
+    assert_eq!(
+        resolve(r#"description(subject:"") & description(body:"")"#),
+        vec![
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone(),
+            repo.store().root_commit_id().clone(),
+        ]
+    );
+    assert_eq!(
+        resolve(r#"description(subject:"fix")"#),
+        vec![commit2.id().clone(), commit1.id().clone()]
     );
 }
 
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
-fn test_evaluate_expression_union(use_git: bool) {
+fn test_evaluate_expression_present_ids(use_git: bool) {
     let settings = testutils::user_settings();
     let test_repo = TestRepo::init(use_git);
     let repo = &test_repo.repo;
@@ -1892,195 +2731,190 @@ fn test_evaluate_expression_union(use_git: bool) {
     let commit1 = graph_builder.initial_commit();
     let commit2 = graph_builder.commit_with_parents(&[&commit1]);
     let commit3 = graph_builder.commit_with_parents(&[&commit2]);
-    let commit4 = graph_builder.commit_with_parents(&[&commit3]);
-    let commit5 = graph_builder.commit_with_parents(&[&commit2]);
+    let all_commits = [&commit3, &commit2, &commit1, &root_commit];
 
-    // Union between ancestors
     assert_eq!(
         resolve_commit_ids(
             mut_repo,
-            &format!(":{} | :{}", commit4.id().hex(), commit5.id().hex())
+            &format!(
+                "present_ids({}, {})",
+                commit1.id().hex(),
+                &commit3.id().hex()[..8]
+            )
         ),
-        vec![
-            commit5.id().clone(),
-            commit4.id().clone(),
-            commit3.id().clone(),
-            commit2.id().clone(),
-            commit1.id().clone(),
-            root_commit.id().clone()
-        ]
+        vec![commit3.id().clone(), commit1.id().clone()]
     );
-
-    // Unioning can add back commits removed by difference
+    // Ambiguous prefix matches all of the commits
+    let prefix = &commit2.id().hex()[..1];
+    assert_eq!(
+        resolve_commit_ids(mut_repo, &format!("present_ids({prefix})")),
+        all_commits
+            .iter()
+            .filter(|commit| commit.id().hex().starts_with(prefix))
+            .map(|commit| commit.id().clone())
+            .collect_vec()
+    );
+    // Filters the other set
     assert_eq!(
         resolve_commit_ids(
             mut_repo,
             &format!(
-                "(:{} ~ :{}) | :{}",
-                commit4.id().hex(),
-                commit2.id().hex(),
-                commit5.id().hex()
+                "present_ids({}, {}) & {}..",
+                commit1.id().hex(),
+                commit3.id().hex(),
+                commit1.id().hex()
             )
         ),
-        vec![
-            commit5.id().clone(),
-            commit4.id().clone(),
-            commit3.id().clone(),
-            commit2.id().clone(),
-            commit1.id().clone(),
-            root_commit.id().clone(),
-        ]
+        vec![commit3.id().clone()]
     );
-
-    // Unioning of disjoint sets
+    // Missing prefix contributes nothing
+    let missing_id = "f".repeat(commit1.id().hex().len());
     assert_eq!(
         resolve_commit_ids(
             mut_repo,
-            &format!(
-                "(:{} ~ :{}) | {}",
-                commit4.id().hex(),
-                commit2.id().hex(),
-                commit5.id().hex(),
-            )
+            &format!("present_ids({missing_id}, {})", commit2.id().hex())
         ),
-        vec![
-            commit5.id().clone(),
-            commit4.id().clone(),
-            commit3.id().clone()
-        ]
+        vec![commit2.id().clone()]
     );
 }
 
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
-fn test_evaluate_expression_intersection(use_git: bool) {
+fn test_evaluate_expression_present_change_ids(use_git: bool) {
     let settings = testutils::user_settings();
     let test_repo = TestRepo::init(use_git);
     let repo = &test_repo.repo;
 
-    let root_commit = repo.store().root_commit();
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
-    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
-    let commit1 = graph_builder.initial_commit();
-    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
-    let commit3 = graph_builder.commit_with_parents(&[&commit2]);
-    let commit4 = graph_builder.commit_with_parents(&[&commit3]);
-    let commit5 = graph_builder.commit_with_parents(&[&commit2]);
 
-    // Intersection between ancestors
+    let mut write_commit_with_change_id = |change_id: &str| {
+        create_random_commit(mut_repo, &settings)
+            .set_change_id(ChangeId::from_hex(change_id))
+            .write()
+            .unwrap()
+    };
+    let commit1 = write_commit_with_change_id("ab120000000000000000000000000000");
+    let commit2 = write_commit_with_change_id("ab340000000000000000000000000000");
+    // Divergent change
+    let commit3 = write_commit_with_change_id("ab120000000000000000000000000000");
+
+    // All visible commits of the change match
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            &format!(":{} & :{}", commit4.id().hex(), commit5.id().hex())
-        ),
+        resolve_commit_ids(mut_repo, "present_change_ids(ab12)"),
+        vec![commit3.id().clone(), commit1.id().clone()]
+    );
+    // Ambiguous prefix matches all of the commits
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "present_change_ids(ab)"),
         vec![
+            commit3.id().clone(),
             commit2.id().clone(),
-            commit1.id().clone(),
-            root_commit.id().clone()
+            commit1.id().clone()
         ]
     );
-
-    // Intersection of disjoint sets
+    // Missing prefix contributes nothing
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "present_change_ids(ffff, ab34)"),
+        vec![commit2.id().clone()]
+    );
+    // Filters the other set
     assert_eq!(
         resolve_commit_ids(
             mut_repo,
-            &format!("{} & {}", commit4.id().hex(), commit2.id().hex())
+            &format!("present_change_ids(ab) & ~{}", commit3.id().hex())
         ),
-        vec![]
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+
+    // Hidden commits don't match
+    mut_repo.record_abandoned_commit(commit1.id().clone());
+    mut_repo.rebase_descendants(&settings).unwrap();
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "present_change_ids(ab12)"),
+        vec![commit3.id().clone()]
     );
 }
 
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
-fn test_evaluate_expression_difference(use_git: bool) {
+fn test_evaluate_expression_subject(use_git: bool) {
     let settings = testutils::user_settings();
     let test_repo = TestRepo::init(use_git);
     let repo = &test_repo.repo;
 
-    let root_commit = repo.store().root_commit();
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
-    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
-    let commit1 = graph_builder.initial_commit();
-    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
-    let commit3 = graph_builder.commit_with_parents(&[&commit2]);
-    let commit4 = graph_builder.commit_with_parents(&[&commit3]);
-    let commit5 = graph_builder.commit_with_parents(&[&commit2]);
 
-    // Difference from all
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_description("fix foo\n\nThis reverts \"add bar\".\n")
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_description("add bar")
+        .write()
+        .unwrap();
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone()])
+        .set_description("\n")
+        .write()
+        .unwrap();
+    let commit4 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit3.id().clone()])
+        .set_description("")
+        .write()
+        .unwrap();
+    let commit5 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit4.id().clone()])
+        .set_description("fix baz\nand qux\n\nBody.\n")
+        .write()
+        .unwrap();
+
+    // Only the subject is searched
     assert_eq!(
-        resolve_commit_ids(mut_repo, &format!("~:{}", commit5.id().hex())),
-        vec![commit4.id().clone(), commit3.id().clone()]
+        resolve_commit_ids(mut_repo, "subject(bar)"),
+        vec![commit2.id().clone()]
     );
-
-    // Difference between ancestors
+    // The subject spans up to the first blank line, as in description()
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            &format!(":{} ~ :{}", commit4.id().hex(), commit5.id().hex())
-        ),
-        vec![commit4.id().clone(), commit3.id().clone()]
+        resolve_commit_ids(mut_repo, "subject(qux)"),
+        vec![commit5.id().clone()]
     );
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            &format!(":{} ~ :{}", commit5.id().hex(), commit4.id().hex())
-        ),
+        resolve_commit_ids(mut_repo, r#"description(subject:"qux")"#),
         vec![commit5.id().clone()]
     );
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            &format!("~:{} & :{}", commit4.id().hex(), commit5.id().hex())
-        ),
+        resolve_commit_ids(mut_repo, "subject(exact:\"fix baz\nand qux\")"),
         vec![commit5.id().clone()]
     );
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            &format!(":{} ~ :{}", commit4.id().hex(), commit2.id().hex())
-        ),
-        vec![commit4.id().clone(), commit3.id().clone()]
+        resolve_commit_ids(mut_repo, "description(bar)"),
+        vec![commit2.id().clone(), commit1.id().clone()]
     );
-
-    // Associativity
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            &format!(
-                ":{} ~ {} ~ {}",
-                commit4.id().hex(),
-                commit2.id().hex(),
-                commit3.id().hex()
-            )
-        ),
+        resolve_commit_ids(mut_repo, r#"subject(exact:"fix foo")"#),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"subject(regex:"^(add|fix) ")"#),
         vec![
-            commit4.id().clone(),
-            commit1.id().clone(),
-            root_commit.id().clone(),
+            commit5.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone()
         ]
     );
-
-    // Subtracting a difference does not add back any commits
+    // Empty or newline-only description has empty subject
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            &format!(
-                "(:{} ~ :{}) ~ (:{} ~ :{})",
-                commit4.id().hex(),
-                commit1.id().hex(),
-                commit3.id().hex(),
-                commit1.id().hex(),
-            )
-        ),
-        vec![commit4.id().clone()]
+        resolve_commit_ids(mut_repo, r#"subject(exact:"") ~ root"#),
+        vec![commit4.id().clone(), commit3.id().clone()]
     );
 }
 
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
-fn test_evaluate_expression_filter_combinator(use_git: bool) {
+fn test_evaluate_expression_trailer(use_git: bool) {
     let settings = testutils::user_settings();
     let test_repo = TestRepo::init(use_git);
     let repo = &test_repo.repo;
@@ -2088,169 +2922,2733 @@ fn test_evaluate_expression_filter_combinator(use_git: bool) {
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
 
-    let root_commit_id = repo.store().root_commit_id();
     let commit1 = create_random_commit(mut_repo, &settings)
-        .set_description("commit 1")
+        .set_description("commit 1\n\nSigned-off-by: Alice <alice@example.com>\nReviewed-by: Bob\n")
         .write()
         .unwrap();
     let commit2 = create_random_commit(mut_repo, &settings)
         .set_parents(vec![commit1.id().clone()])
-        .set_description("commit 2")
+        .set_description("commit 2\n\nSigned-off-by: Bob <bob@example.com>\n")
         .write()
         .unwrap();
     let commit3 = create_random_commit(mut_repo, &settings)
         .set_parents(vec![commit2.id().clone()])
-        .set_description("commit 3")
+        .set_description("Signed-off-by: Alice <alice@example.com>\n")
         .write()
         .unwrap();
 
-    // Not intersected with a set node
     assert_eq!(
-        resolve_commit_ids(mut_repo, "~description(1)"),
-        vec![
-            commit3.id().clone(),
-            commit2.id().clone(),
-            root_commit_id.clone(),
-        ],
+        resolve_commit_ids(mut_repo, r#"trailer("Signed-off-by")"#),
+        vec![commit2.id().clone(), commit1.id().clone()]
     );
     assert_eq!(
-        resolve_commit_ids(mut_repo, "description(1) | description(2)"),
-        vec![commit2.id().clone(), commit1.id().clone()],
+        resolve_commit_ids(mut_repo, r#"trailer("Signed-off-by", "alice@example.com")"#),
+        vec![commit1.id().clone()]
     );
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            "description(commit) ~ (description(2) | description(3))",
-        ),
-        vec![commit1.id().clone()],
+        resolve_commit_ids(mut_repo, r#"trailer("Reviewed-by", bob)"#),
+        vec![]
     );
-
-    // Intersected with a set node
     assert_eq!(
-        resolve_commit_ids(mut_repo, "root.. & ~description(1)"),
-        vec![commit3.id().clone(), commit2.id().clone()],
+        resolve_commit_ids(mut_repo, r#"trailer("Reviewed-by", substring-i:"bob")"#),
+        vec![commit1.id().clone()]
     );
+    // Key must match exactly
     assert_eq!(
-        resolve_commit_ids(
-            mut_repo,
-            &format!(
-                "{}.. & (description(1) | description(2))",
-                commit1.id().hex(),
-            )
-        ),
-        vec![commit2.id().clone()],
+        resolve_commit_ids(mut_repo, r#"trailer("signed-off-by")"#),
+        vec![]
     );
+    // Description consisting of trailer only has no trailer
+    assert!(!resolve_commit_ids(mut_repo, r#"trailer("Signed-off-by")"#).contains(commit3.id()));
 }
 
 #[test_case(false ; "local backend")]
 #[test_case(true ; "git backend")]
-fn test_evaluate_expression_file(use_git: bool) {
+fn test_evaluate_expression_coauthor(use_git: bool) {
     let settings = testutils::user_settings();
-    let test_workspace = TestWorkspace::init(&settings, use_git);
-    let repo = &test_workspace.repo;
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
 
     let mut tx = repo.start_transaction(&settings, "test");
     let mut_repo = tx.mut_repo();
 
-    let added_clean_clean = RepoPath::from_internal_string("added_clean_clean");
-    let added_modified_clean = RepoPath::from_internal_string("added_modified_clean");
-    let added_modified_removed = RepoPath::from_internal_string("added_modified_removed");
-    let tree1 = testutils::create_tree(
-        repo,
-        &[
-            (&added_clean_clean, "1"),
-            (&added_modified_clean, "1"),
-            (&added_modified_removed, "1"),
-        ],
-    );
-    let tree2 = testutils::create_tree(
-        repo,
-        &[
-            (&added_clean_clean, "1"),
-            (&added_modified_clean, "2"),
-            (&added_modified_removed, "2"),
-        ],
-    );
-    let tree3 = testutils::create_tree(
-        repo,
-        &[
-            (&added_clean_clean, "1"),
-            (&added_modified_clean, "2"),
-            // added_modified_removed,
-        ],
-    );
-    let commit1 = mut_repo
-        .new_commit(
-            &settings,
-            vec![repo.store().root_commit_id().clone()],
-            tree1.id().clone(),
+    let timestamp = Timestamp {
+        timestamp: MillisSinceEpoch(0),
+        tz_offset: 0,
+    };
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_description(
+            "commit 1\n\nCo-authored-by: Alice <alice@example.com>\nCo-authored-by: Bob \
+             <Bob@Example.com>\n",
         )
         .write()
         .unwrap();
-    let commit2 = mut_repo
-        .new_commit(&settings, vec![commit1.id().clone()], tree2.id().clone())
-        .write()
-        .unwrap();
-    let commit3 = mut_repo
-        .new_commit(&settings, vec![commit2.id().clone()], tree3.id().clone())
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_description("commit 2\n\nCo-authored-by: Carol <carol@example.com>\n")
         .write()
         .unwrap();
-    let commit4 = mut_repo
-        .new_commit(&settings, vec![commit3.id().clone()], tree3.id().clone())
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone()])
+        .set_author(Signature {
+            name: "Alice".to_string(),
+            email: "alice@example.com".to_string(),
+            timestamp,
+        })
+        .set_description("commit 3\n\nmentions Alice <alice@example.com>\n")
         .write()
         .unwrap();
 
-    let resolve = |file_path: &RepoPath| -> Vec<CommitId> {
-        let mut_repo = &*mut_repo;
-        let expression =
-            RevsetExpression::filter(RevsetFilterPredicate::File(Some(vec![file_path.clone()])));
-        let revset = expression
-            .resolve(mut_repo)
-            .unwrap()
-            .evaluate(mut_repo)
-            .unwrap();
-        revset.iter().collect()
-    };
-
-    assert_eq!(resolve(&added_clean_clean), vec![commit1.id().clone()]);
+    // Multiple co-authors
     assert_eq!(
-        resolve(&added_modified_clean),
+        resolve_commit_ids(mut_repo, "coauthor(Alice)"),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "coauthor(Bob)"),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"coauthor("example.com")"#),
         vec![commit2.id().clone(), commit1.id().clone()]
     );
+    // Email is matched case-insensitively, but name isn't
     assert_eq!(
-        resolve(&added_modified_removed),
-        vec![
-            commit3.id().clone(),
-            commit2.id().clone(),
-            commit1.id().clone()
+        resolve_commit_ids(mut_repo, r#"coauthor("bob@example.com")"#),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "coauthor(carol)"),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"coauthor("Carol ")"#),
+        vec![]
+    );
+    // Author without trailer doesn't count
+    assert!(!resolve_commit_ids(mut_repo, "coauthor(alice)").contains(commit3.id()));
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_no_description(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let mut write_commit_with_description = |description: &str| {
+        create_random_commit(mut_repo, &settings)
+            .set_description(description)
+            .write()
+            .unwrap()
+    };
+    let commit1 = write_commit_with_description("");
+    let commit2 = write_commit_with_description("\n");
+    let commit3 = write_commit_with_description(" \n\t\n");
+    let _commit4 = write_commit_with_description("description\n");
+    let _commit5 = write_commit_with_description("\n\nbody\n");
+
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "~root & no_description()"),
+        vec![
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone(),
+        ]
+    );
+    // The root commit has no description
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "root & no_description()"),
+        vec![mut_repo.store().root_commit_id().clone()]
+    );
+
+    let commit6 = create_random_commit(mut_repo, &settings)
+        .set_author(Signature {
+            name: "name".to_string(),
+            email: "someone@example.com".to_string(),
+            timestamp: Timestamp {
+                timestamp: MillisSinceEpoch(0),
+                tz_offset: 0,
+            },
+        })
+        .set_description("")
+        .write()
+        .unwrap();
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "no_description() & ~mine()"),
+        vec![
+            commit6.id().clone(),
+            mut_repo.store().root_commit_id().clone()
+        ]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "mine() & no_description()"),
+        vec![
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone(),
+        ]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_description_regex_multi_line(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_description("cli: add option\n")
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_description("lib: fix bug\n\ncli: is also affected\n")
+        .write()
+        .unwrap();
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone()])
+        .set_description("docs: mention cli: prefix\n")
+        .write()
+        .unwrap();
+
+    // `^` matches at the start of any line
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"description(regex:"^cli:")"#),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    // `$` matches at the end of any line
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"description(regex:"bug$")"#),
+        vec![commit2.id().clone()]
+    );
+    // `\A` still anchors at the start of the whole description
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"description(regex:"\A(lib|docs):")"#),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_author(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let timestamp = Timestamp {
+        timestamp: MillisSinceEpoch(0),
+        tz_offset: 0,
+    };
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_author(Signature {
+            name: "name1".to_string(),
+            email: "email1".to_string(),
+            timestamp: timestamp.clone(),
+        })
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_author(Signature {
+            name: "name2".to_string(),
+            email: "email2".to_string(),
+            timestamp: timestamp.clone(),
+        })
+        .write()
+        .unwrap();
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone()])
+        .set_author(Signature {
+            name: "name3".to_string(),
+            email: "email3".to_string(),
+            timestamp,
+        })
+        .write()
+        .unwrap();
+
+    // Can find multiple matches
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author(name)"),
+        vec![
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone()
+        ]
+    );
+    // Can find a unique match by either name or email
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author(\"name2\")"),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author(\"name3\")"),
+        vec![commit3.id().clone()]
+    );
+    // Searches only among candidates if specified
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "visible_heads() & author(\"name2\")"),
+        vec![]
+    );
+    // Filter by union of pure predicate and set
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!("root.. & (author(name1) | {})", commit3.id().hex())
+        ),
+        vec![commit3.id().clone(), commit1.id().clone()]
+    );
+    // Filter by union of intersections of pure predicates
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            "(author(name) & author(2)) | (author(email) & ~author(1))"
+        ),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    // Can match name or email by regex
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"author(regex:"^name[12]$")"#),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"author(regex:"^email3$")"#),
+        vec![commit3.id().clone()]
+    );
+    // Can match case-insensitively
+    assert_eq!(resolve_commit_ids(mut_repo, r#"author("NAME2")"#), vec![]);
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"author(substring-i:"NAME2")"#),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"author(regex-i:"^Email[13]$")"#),
+        vec![commit3.id().clone(), commit1.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_mine(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let timestamp = Timestamp {
+        timestamp: MillisSinceEpoch(0),
+        tz_offset: 0,
+    };
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_author(Signature {
+            name: "name1".to_string(),
+            email: "email1".to_string(),
+            timestamp: timestamp.clone(),
+        })
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_author(Signature {
+            name: "name2".to_string(),
+            email: settings.user_email().to_ascii_uppercase(),
+            timestamp: timestamp.clone(),
+        })
+        .write()
+        .unwrap();
+    // Can find a unique match by email only, case-insensitively
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "mine()"),
+        vec![commit2.id().clone()]
+    );
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone()])
+        .set_author(Signature {
+            name: "name3".to_string(),
+            email: settings.user_email(),
+            timestamp: timestamp.clone(),
+        })
+        .write()
+        .unwrap();
+    // Email containing the user email isn't matched
+    create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit3.id().clone()])
+        .set_author(Signature {
+            name: settings.user_email(),
+            email: format!("x.{}", settings.user_email()),
+            timestamp,
+        })
+        .write()
+        .unwrap();
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "mine()"),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_committer(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let timestamp = Timestamp {
+        timestamp: MillisSinceEpoch(0),
+        tz_offset: 0,
+    };
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_committer(Signature {
+            name: "name1".to_string(),
+            email: "email1".to_string(),
+            timestamp: timestamp.clone(),
+        })
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_committer(Signature {
+            name: "name2".to_string(),
+            email: "email2".to_string(),
+            timestamp: timestamp.clone(),
+        })
+        .write()
+        .unwrap();
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone()])
+        .set_committer(Signature {
+            name: "name3".to_string(),
+            email: "email3".to_string(),
+            timestamp,
+        })
+        .write()
+        .unwrap();
+
+    // Can find multiple matches
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "committer(name)"),
+        vec![
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone()
+        ]
+    );
+    // Can find a unique match by either name or email
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "committer(\"name2\")"),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "committer(\"name3\")"),
+        vec![commit3.id().clone()]
+    );
+    // Searches only among candidates if specified
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "visible_heads() & committer(\"name2\")"),
+        vec![]
+    );
+    // Can match name or email by regex
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"committer(regex:"^(name1|email3)$")"#),
+        vec![commit3.id().clone(), commit1.id().clone()]
+    );
+}
+
+#[test]
+fn test_evaluate_expression_author_committer_name_email() {
+    let settings = testutils::user_settings();
+    // The Git backend doesn't support signatures with empty name or email
+    let test_repo = TestRepo::init(false);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let signature = |name: &str, email: &str| Signature {
+        name: name.to_string(),
+        email: email.to_string(),
+        timestamp: Timestamp {
+            timestamp: MillisSinceEpoch(0),
+            tz_offset: 0,
+        },
+    };
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_author(signature("Alan", ""))
+        .set_committer(signature("", "alan@example.com"))
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_author(signature("", "dan@example.com"))
+        .set_committer(signature("Dan", ""))
+        .write()
+        .unwrap();
+
+    // The combined predicates match either field
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author(an)"),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "committer(an)"),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author_name(an)"),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author_email(an)"),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "committer_name(an)"),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "committer_email(an)"),
+        vec![commit1.id().clone()]
+    );
+    // Empty fields
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"root.. & author_name(exact:"")"#),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"root.. & author_email(exact:"")"#),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"root.. & committer_name(exact:"")"#),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"root.. & committer_email(exact:"")"#),
+        vec![commit2.id().clone()]
+    );
+}
+
+#[test]
+fn test_evaluate_expression_author_email_domain() {
+    let settings = testutils::user_settings();
+    // The Git backend doesn't support signatures with empty email
+    let test_repo = TestRepo::init(false);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let mut commits = vec![];
+    for (name, email) in [
+        ("Example", "alice@other.org"),
+        ("Bob", "bob@Example.COM"),
+        ("Carol", "carol@sub.example.com"),
+        ("Dan", "dan@example.com@other.org"),
+        ("Eve", "eve@other.org@example.com"),
+        ("Frank", "example.com"),
+        ("Grace", ""),
+    ] {
+        let commit = create_random_commit(mut_repo, &settings)
+            .set_author(Signature {
+                name: name.to_string(),
+                email: email.to_string(),
+                timestamp: Timestamp {
+                    timestamp: MillisSinceEpoch(0),
+                    tz_offset: 0,
+                },
+            })
+            .write()
+            .unwrap();
+        commits.push(commit);
+    }
+
+    // The domain is compared exactly but ignoring case, and only the part
+    // after the last `@` is the domain
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author_email_domain(example.com)"),
+        vec![commits[4].id().clone(), commits[1].id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author_email_domain(@EXAMPLE.com)"),
+        vec![commits[4].id().clone(), commits[1].id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author_email_domain(other.org)"),
+        vec![commits[3].id().clone(), commits[0].id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author_email_domain(example)"),
+        vec![]
+    );
+    // Email with no domain
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"author_email_domain("")"#),
+        vec![]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_author_committer_regex(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let timestamp = Timestamp {
+        timestamp: MillisSinceEpoch(0),
+        tz_offset: 0,
+    };
+    let signature1 = Signature {
+        name: "Fan of example.com".to_string(),
+        email: "fan@example.org".to_string(),
+        timestamp: timestamp.clone(),
+    };
+    let signature2 = Signature {
+        name: "Employee".to_string(),
+        email: "employee@example.com".to_string(),
+        timestamp,
+    };
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_author(signature1.clone())
+        .set_committer(signature2.clone())
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_author(signature2)
+        .set_committer(signature1)
+        .write()
+        .unwrap();
+
+    // Substring matches the name as well as the email
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "author(example.com)"),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    // Anchored regex is applied to the name and the email separately
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"author(regex:"@example\.com$")"#),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"committer(regex:"@example\.com$")"#),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"committer(regex:"^Fan .*\.com$")"#),
+        vec![commit2.id().clone()]
+    );
+
+    // Invalid regex is reported with the offending pattern
+    let expression = parse_optimized(r#"committer(regex:"[a-")"#, None);
+    let expression = expression.resolve(mut_repo).unwrap();
+    let err = expression.evaluate(mut_repo).map(|_| ()).unwrap_err();
+    assert_matches!(
+        err,
+        RevsetEvaluationError::Other(message) if message.contains(r#""[a-""#)
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_author_committer_date(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let signature_at = |name: &str, millis: i64| Signature {
+        name: name.to_string(),
+        email: format!("{name}@example.com"),
+        timestamp: Timestamp {
+            timestamp: MillisSinceEpoch(millis),
+            tz_offset: 0,
+        },
+    };
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_author(signature_at("name1", 1000))
+        .set_committer(signature_at("name1", 4000))
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_author(signature_at("name2", 2000))
+        .set_committer(signature_at("name1", 5000))
+        .write()
+        .unwrap();
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone()])
+        .set_author(signature_at("name1", 3000))
+        .set_committer(signature_at("name2", 6000))
+        .write()
+        .unwrap();
+
+    let resolve = |expression: Rc<RevsetExpression>| -> Vec<CommitId> {
+        let mut_repo = &*mut_repo;
+        let expression = optimize(expression);
+        let revset = expression
+            .resolve(mut_repo)
+            .unwrap()
+            .evaluate(mut_repo)
+            .unwrap();
+        revset.iter().try_collect().unwrap()
+    };
+    let author_date = |range: Range<i64>| {
+        RevsetExpression::filter(RevsetFilterPredicate::AuthorDate(
+            MillisSinceEpoch(range.start)..MillisSinceEpoch(range.end),
+        ))
+    };
+    let committer_date = |range: Range<i64>| {
+        RevsetExpression::filter(RevsetFilterPredicate::CommitterDate(
+            MillisSinceEpoch(range.start)..MillisSinceEpoch(range.end),
+        ))
+    };
+
+    // Lower bound is inclusive, upper bound is exclusive
+    assert_eq!(
+        resolve(author_date(1000..3000)),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve(committer_date(5000..6001)),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    // Open-ended ranges
+    assert_eq!(
+        resolve(author_date(2000..i64::MAX)),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    // The root commit has zero timestamps
+    let root_commit_id = repo.store().root_commit_id().clone();
+    assert_eq!(
+        resolve(committer_date(i64::MIN..5000)),
+        vec![commit1.id().clone(), root_commit_id.clone()]
+    );
+    assert_eq!(resolve(author_date(i64::MIN..1000)), vec![root_commit_id]);
+    // Can be specified by date strings
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            r#"author_date(after="1970-01-01T00:00:02Z", before="1970-01-01T00:00:03Z")"#
+        ),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, r#"author_date(after="1970-01-01T00:00:02Z")"#),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    // Author and committer dates are independent
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            r#"author_date(before="1970-01-01T00:00:03Z")
+               & committer_date(after="1970-01-01T00:00:05Z")"#
+        ),
+        vec![commit2.id().clone()]
+    );
+    // Can be combined with other filters on the same commit
+    let name1 = RevsetExpression::filter(RevsetFilterPredicate::Author(
+        StringPattern::Substring("name1".to_string()),
+        CaseSensitivity::Sensitive,
+    ));
+    assert_eq!(
+        resolve(author_date(2000..i64::MAX).intersection(&name1)),
+        vec![commit3.id().clone()]
+    );
+    assert_eq!(
+        resolve(committer_date(4000..6000).minus(&name1)),
+        vec![commit2.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_committer_differs(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let signature_at = |name: &str, email: &str, secs: i64| Signature {
+        name: name.to_string(),
+        email: email.to_string(),
+        timestamp: Timestamp {
+            timestamp: MillisSinceEpoch(secs * 1000),
+            tz_offset: 0,
+        },
+    };
+    let mut write_commit = |author: Signature, committer: Signature| {
+        create_random_commit(mut_repo, &settings)
+            .set_author(author)
+            .set_committer(committer)
+            .write()
+            .unwrap()
+    };
+    let week = 7 * 24 * 3600;
+    let commit1 = write_commit(
+        signature_at("name1", "name1@example.com", 1),
+        signature_at("name1", "name1@example.com", 1),
+    );
+    let commit2 = write_commit(
+        signature_at("name1", "name1@example.com", 1),
+        signature_at("name2", "name1@example.com", 1),
+    );
+    let commit3 = write_commit(
+        signature_at("name1", "name1@example.com", 1),
+        signature_at("name1", "name2@example.com", 1),
+    );
+    let commit4 = write_commit(
+        signature_at("name1", "name1@example.com", 1),
+        signature_at("name1", "name1@example.com", 1 + week + 1),
+    );
+    let commit5 = write_commit(
+        signature_at("name1", "name1@example.com", 1),
+        signature_at("name1", "name1@example.com", 61),
+    );
+    // Committer timestamp earlier than author timestamp
+    let _commit6 = write_commit(
+        signature_at("name1", "name1@example.com", 61),
+        signature_at("name1", "name1@example.com", 1),
+    );
+
+    // Compares name and email by default
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "committer_differs()"),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    // Minimum delay is inclusive
+    assert_eq!(
+        resolve_commit_ids(mut_repo, &format!("committer_differs({week})")),
+        vec![
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+        ]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "committer_differs(60)"),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+        ]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "committer_differs(61)"),
+        vec![
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+        ]
+    );
+    // Zero delay matches commits committed at or after the author timestamp
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "~root & committer_differs(0)"),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone(),
+        ]
+    );
+    // Should not overflow
+    assert_eq!(
+        resolve_commit_ids(mut_repo, &format!("committer_differs({})", u64::MAX)),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_description_control_chars(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let mut write_commit = |description: &str| {
+        create_random_commit(mut_repo, &settings)
+            .set_description(description)
+            .write()
+            .unwrap()
+    };
+    let _commit1 = write_commit("subject\n\n\tindented body\n");
+    let commit2 = write_commit("\x1b[31mred\x1b[0m\n");
+    let commit3 = write_commit("bell\x07\n");
+    let commit4 = write_commit("crlf\r\n");
+    // C1 control character
+    let commit5 = write_commit("next\u{85}line\n");
+    let commit6 = write_commit("invalid \u{fffd}\n");
+
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "description_control_chars()"),
+        vec![
+            commit6.id().clone(),
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+        ]
+    );
+}
+
+#[test]
+fn test_evaluate_expression_description_control_chars_invalid_utf8() {
+    let settings = testutils::user_settings();
+    let git_settings = GitSettings::default();
+    let test_repo = TestRepo::init(true);
+    let repo = &test_repo.repo;
+
+    // Write commit objects directly since git2 only accepts UTF-8 messages
+    let git_repo = repo.store().git_repo().unwrap();
+    let empty_tree_id = git_repo.treebuilder(None).unwrap().write().unwrap();
+    let write_git_commit = |message: &[u8]| {
+        let mut data = format!(
+            "tree {empty_tree_id}\nauthor Someone <someone@example.com> 0 +0000\ncommitter \
+             Someone <someone@example.com> 0 +0000\n\n"
+        )
+        .into_bytes();
+        data.extend_from_slice(message);
+        git_repo
+            .odb()
+            .unwrap()
+            .write(git2::ObjectType::Commit, &data)
+            .unwrap()
+    };
+    let git_commit1 = write_git_commit(b"valid\n");
+    let git_commit2 = write_git_commit(b"invalid \xff\n");
+    for (name, git_commit_id) in [("valid", git_commit1), ("invalid", git_commit2)] {
+        git_repo
+            .reference(&format!("refs/heads/{name}"), git_commit_id, false, "")
+            .unwrap();
+    }
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    git::import_refs(tx.mut_repo(), &git_repo, &git_settings).unwrap();
+    let mut_repo = tx.mut_repo();
+
+    let commit2 = mut_repo
+        .store()
+        .get_commit(&CommitId::from_bytes(git_commit2.as_bytes()))
+        .unwrap();
+    assert_eq!(commit2.description(), "invalid \u{fffd}\n");
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "description_control_chars()"),
+        vec![commit2.id().clone()]
+    );
+}
+
+#[test]
+fn test_evaluate_expression_malformed_signature() {
+    let settings = testutils::user_settings();
+    // Git can't store signatures with empty name or email
+    let test_repo = TestRepo::init(false);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let signature = |name: &str, email: &str| Signature {
+        name: name.to_string(),
+        email: email.to_string(),
+        timestamp: Timestamp {
+            timestamp: MillisSinceEpoch(0),
+            tz_offset: 0,
+        },
+    };
+    let mut write_commit = |author: Signature, committer: Signature| {
+        create_random_commit(mut_repo, &settings)
+            .set_author(author)
+            .set_committer(committer)
+            .write()
+            .unwrap()
+    };
+    let good = || signature("name", "name@example.com");
+    let _commit1 = write_commit(good(), good());
+    let commit2 = write_commit(signature(" ", "name@example.com"), good());
+    let commit3 = write_commit(good(), signature("name", ""));
+    let commit4 = write_commit(signature("name", "name.example.com"), good());
+    let commit5 = write_commit(
+        good(),
+        signature(UserSettings::user_name_placeholder(), "name@example.com"),
+    );
+    let commit6 = write_commit(
+        signature("name", UserSettings::user_email_placeholder()),
+        good(),
+    );
+
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "malformed_signature() ~ root"),
+        vec![
+            commit6.id().clone(),
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+        ]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "malformed_signature(empty_name) ~ root"),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "malformed_signature(empty_email) ~ root"),
+        vec![commit3.id().clone()]
+    );
+    // The placeholder email has no "@"
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "malformed_signature(invalid_email)"),
+        vec![commit6.id().clone(), commit4.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "malformed_signature(placeholder)"),
+        vec![commit6.id().clone(), commit5.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            "malformed_signature(empty_name, empty_email) ~ root"
+        ),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+}
+
+#[test]
+fn test_evaluate_expression_signed() {
+    let settings = testutils::user_settings();
+    let git_settings = GitSettings::default();
+    // Only the git backend can store signatures
+    let test_repo = TestRepo::init(true);
+    let repo = &test_repo.repo;
+
+    let git_repo = repo.store().git_repo().unwrap();
+    let empty_tree_id = git_repo.treebuilder(None).unwrap().write().unwrap();
+    let git_tree = git_repo.find_tree(empty_tree_id).unwrap();
+    let git_signature =
+        git2::Signature::new("Someone", "someone@example.com", &git2::Time::new(0, 0)).unwrap();
+    let write_git_commit = |message: &str, parents: &[&git2::Commit], signed: bool| {
+        if signed {
+            let buffer = git_repo
+                .commit_create_buffer(&git_signature, &git_signature, message, &git_tree, parents)
+                .unwrap();
+            git_repo
+                .commit_signed(buffer.as_str().unwrap(), "fake signature", None)
+                .unwrap()
+        } else {
+            git_repo
+                .commit(
+                    None,
+                    &git_signature,
+                    &git_signature,
+                    message,
+                    &git_tree,
+                    parents,
+                )
+                .unwrap()
+        }
+    };
+    let git_commit1 = write_git_commit("signed", &[], true);
+    let git_commit2 = write_git_commit("unsigned", &[], false);
+    let git_commit3 = write_git_commit(
+        "signed merge",
+        &[
+            &git_repo.find_commit(git_commit1).unwrap(),
+            &git_repo.find_commit(git_commit2).unwrap(),
+        ],
+        true,
+    );
+    let git_commit4 = write_git_commit(
+        "unsigned merge",
+        &[
+            &git_repo.find_commit(git_commit1).unwrap(),
+            &git_repo.find_commit(git_commit2).unwrap(),
+        ],
+        false,
+    );
+    git_repo
+        .reference("refs/heads/main", git_commit3, false, "")
+        .unwrap();
+    git_repo
+        .reference("refs/heads/other", git_commit4, false, "")
+        .unwrap();
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    git::import_refs(tx.mut_repo(), &git_repo, &git_settings).unwrap();
+    let mut_repo = tx.mut_repo();
+    let commit_id = |git_commit_id: git2::Oid| CommitId::from_bytes(git_commit_id.as_bytes());
+
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "signed()"),
+        vec![commit_id(git_commit3), commit_id(git_commit1)]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "~signed() ~ root"),
+        vec![commit_id(git_commit4), commit_id(git_commit2)]
+    );
+
+    // Rewriting a commit drops the signature
+    let commit1 = mut_repo
+        .store()
+        .get_commit(&commit_id(git_commit1))
+        .unwrap();
+    let rewritten_commit1 = mut_repo
+        .rewrite_commit(&settings, &commit1)
+        .set_description("rewritten")
+        .write()
+        .unwrap();
+    assert!(rewritten_commit1.store_commit().secure_sig.is_none());
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!("signed() & {}", rewritten_commit1.id().hex())
+        ),
+        vec![]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_union(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let root_commit = repo.store().root_commit();
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.commit_with_parents(&[&commit2]);
+    let commit4 = graph_builder.commit_with_parents(&[&commit3]);
+    let commit5 = graph_builder.commit_with_parents(&[&commit2]);
+
+    // Union between ancestors
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(":{} | :{}", commit4.id().hex(), commit5.id().hex())
+        ),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone(),
+            root_commit.id().clone()
+        ]
+    );
+
+    // Unioning can add back commits removed by difference
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                "(:{} ~ :{}) | :{}",
+                commit4.id().hex(),
+                commit2.id().hex(),
+                commit5.id().hex()
+            )
+        ),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone(),
+            root_commit.id().clone(),
+        ]
+    );
+
+    // Unioning of disjoint sets
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                "(:{} ~ :{}) | {}",
+                commit4.id().hex(),
+                commit2.id().hex(),
+                commit5.id().hex(),
+            )
+        ),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone()
+        ]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_symmetric_difference(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.commit_with_parents(&[&commit2]);
+    let commit4 = graph_builder.commit_with_parents(&[&commit3]);
+    let commit5 = graph_builder.commit_with_parents(&[&commit2]);
+
+    // Commits unique to either branch
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(":{} ^ :{}", commit4.id().hex(), commit5.id().hex())
+        ),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone()
+        ]
+    );
+
+    // Symmetric difference with itself
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(":{} ^ :{}", commit4.id().hex(), commit4.id().hex())
+        ),
+        vec![]
+    );
+
+    // Symmetric difference of disjoint sets is the union
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!("{} ^ {}", commit4.id().hex(), commit2.id().hex())
+        ),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+
+    // Symmetric difference with a filter
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                "({}:) & ({}: ^ merges())",
+                commit1.id().hex(),
+                commit3.id().hex()
+            )
+        ),
+        vec![commit4.id().clone(), commit3.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_intersection(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let root_commit = repo.store().root_commit();
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.commit_with_parents(&[&commit2]);
+    let commit4 = graph_builder.commit_with_parents(&[&commit3]);
+    let commit5 = graph_builder.commit_with_parents(&[&commit2]);
+
+    // Intersection between ancestors
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(":{} & :{}", commit4.id().hex(), commit5.id().hex())
+        ),
+        vec![
+            commit2.id().clone(),
+            commit1.id().clone(),
+            root_commit.id().clone()
+        ]
+    );
+
+    // Intersection of disjoint sets
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!("{} & {}", commit4.id().hex(), commit2.id().hex())
+        ),
+        vec![]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_difference(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let root_commit = repo.store().root_commit();
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+    let mut graph_builder = CommitGraphBuilder::new(&settings, mut_repo);
+    let commit1 = graph_builder.initial_commit();
+    let commit2 = graph_builder.commit_with_parents(&[&commit1]);
+    let commit3 = graph_builder.commit_with_parents(&[&commit2]);
+    let commit4 = graph_builder.commit_with_parents(&[&commit3]);
+    let commit5 = graph_builder.commit_with_parents(&[&commit2]);
+
+    // Difference from all
+    assert_eq!(
+        resolve_commit_ids(mut_repo, &format!("~:{}", commit5.id().hex())),
+        vec![commit4.id().clone(), commit3.id().clone()]
+    );
+
+    // Difference between ancestors
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(":{} ~ :{}", commit4.id().hex(), commit5.id().hex())
+        ),
+        vec![commit4.id().clone(), commit3.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(":{} ~ :{}", commit5.id().hex(), commit4.id().hex())
+        ),
+        vec![commit5.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!("~:{} & :{}", commit4.id().hex(), commit5.id().hex())
+        ),
+        vec![commit5.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(":{} ~ :{}", commit4.id().hex(), commit2.id().hex())
+        ),
+        vec![commit4.id().clone(), commit3.id().clone()]
+    );
+
+    // Associativity
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                ":{} ~ {} ~ {}",
+                commit4.id().hex(),
+                commit2.id().hex(),
+                commit3.id().hex()
+            )
+        ),
+        vec![
+            commit4.id().clone(),
+            commit1.id().clone(),
+            root_commit.id().clone(),
+        ]
+    );
+
+    // Subtracting a difference does not add back any commits
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                "(:{} ~ :{}) ~ (:{} ~ :{})",
+                commit4.id().hex(),
+                commit1.id().hex(),
+                commit3.id().hex(),
+                commit1.id().hex(),
+            )
+        ),
+        vec![commit4.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_filter_combinator(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let root_commit_id = repo.store().root_commit_id();
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_description("commit 1")
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_description("commit 2")
+        .write()
+        .unwrap();
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone()])
+        .set_description("commit 3")
+        .write()
+        .unwrap();
+
+    // Not intersected with a set node
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "~description(1)"),
+        vec![
+            commit3.id().clone(),
+            commit2.id().clone(),
+            root_commit_id.clone(),
+        ],
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "description(1) | description(2)"),
+        vec![commit2.id().clone(), commit1.id().clone()],
+    );
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            "description(commit) ~ (description(2) | description(3))",
+        ),
+        vec![commit1.id().clone()],
+    );
+
+    // Intersected with a set node
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "root.. & ~description(1)"),
+        vec![commit3.id().clone(), commit2.id().clone()],
+    );
+    assert_eq!(
+        resolve_commit_ids(
+            mut_repo,
+            &format!(
+                "{}.. & (description(1) | description(2))",
+                commit1.id().hex(),
+            )
+        ),
+        vec![commit2.id().clone()],
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_file(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let added_clean_clean = RepoPath::from_internal_string("added_clean_clean");
+    let added_modified_clean = RepoPath::from_internal_string("added_modified_clean");
+    let added_modified_removed = RepoPath::from_internal_string("added_modified_removed");
+    let tree1 = testutils::create_tree(
+        repo,
+        &[
+            (&added_clean_clean, "1"),
+            (&added_modified_clean, "1"),
+            (&added_modified_removed, "1"),
+        ],
+    );
+    let tree2 = testutils::create_tree(
+        repo,
+        &[
+            (&added_clean_clean, "1"),
+            (&added_modified_clean, "2"),
+            (&added_modified_removed, "2"),
+        ],
+    );
+    let tree3 = testutils::create_tree(
+        repo,
+        &[
+            (&added_clean_clean, "1"),
+            (&added_modified_clean, "2"),
+            // added_modified_removed,
+        ],
+    );
+    let commit1 = mut_repo
+        .new_commit(
+            &settings,
+            vec![repo.store().root_commit_id().clone()],
+            tree1.id().clone(),
+        )
+        .write()
+        .unwrap();
+    let commit2 = mut_repo
+        .new_commit(&settings, vec![commit1.id().clone()], tree2.id().clone())
+        .write()
+        .unwrap();
+    let commit3 = mut_repo
+        .new_commit(&settings, vec![commit2.id().clone()], tree3.id().clone())
+        .write()
+        .unwrap();
+    let commit4 = mut_repo
+        .new_commit(&settings, vec![commit3.id().clone()], tree3.id().clone())
+        .write()
+        .unwrap();
+
+    let resolve = |file_path: &RepoPath| -> Vec<CommitId> {
+        let mut_repo = &*mut_repo;
+        let expression = RevsetExpression::filter(RevsetFilterPredicate::File {
+            includes: Some(vec![FilePattern::PrefixPath(file_path.clone())]),
+            excludes: vec![],
+        });
+        let revset = expression
+            .resolve(mut_repo)
+            .unwrap()
+            .evaluate(mut_repo)
+            .unwrap();
+        revset.iter().try_collect().unwrap()
+    };
+
+    assert_eq!(resolve(&added_clean_clean), vec![commit1.id().clone()]);
+    assert_eq!(
+        resolve(&added_modified_clean),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve(&added_modified_removed),
+        vec![
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone()
+        ]
+    );
+
+    // file() revset:
+    assert_eq!(
+        resolve_commit_ids_in_workspace(
+            mut_repo,
+            r#"file("repo/added_clean_clean")"#,
+            &test_workspace.workspace,
+            Some(test_workspace.workspace.workspace_root().parent().unwrap()),
+        ),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids_in_workspace(
+            mut_repo,
+            &format!(r#"{}: & file("added_modified_clean")"#, commit2.id().hex()),
+            &test_workspace.workspace,
+            Some(test_workspace.workspace.workspace_root()),
+        ),
+        vec![commit2.id().clone()]
+    );
+
+    // empty() revset, which is identical to ~file(".")
+    assert_eq!(
+        resolve_commit_ids(mut_repo, &format!("{}: & empty()", commit1.id().hex())),
+        vec![commit4.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_file_glob(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let main_rs = RepoPath::from_internal_string("src/main.rs");
+    let mod_rs = RepoPath::from_internal_string("src/lib/mod.rs");
+    let index_md = RepoPath::from_internal_string("docs/index.md");
+    let readme_md = RepoPath::from_internal_string("README.md");
+    let tree1 = testutils::create_tree(repo, &[(&main_rs, "1"), (&index_md, "1")]);
+    let tree2 = testutils::create_tree(repo, &[(&main_rs, "1"), (&index_md, "1"), (&mod_rs, "1")]);
+    let tree3 = testutils::create_tree(
+        repo,
+        &[
+            (&main_rs, "1"),
+            (&index_md, "1"),
+            (&mod_rs, "1"),
+            (&readme_md, "1"),
+        ],
+    );
+    let commit1 = mut_repo
+        .new_commit(
+            &settings,
+            vec![repo.store().root_commit_id().clone()],
+            tree1.id().clone(),
+        )
+        .write()
+        .unwrap();
+    let commit2 = mut_repo
+        .new_commit(&settings, vec![commit1.id().clone()], tree2.id().clone())
+        .write()
+        .unwrap();
+    let commit3 = mut_repo
+        .new_commit(&settings, vec![commit2.id().clone()], tree3.id().clone())
+        .write()
+        .unwrap();
+
+    let workspace_root = test_workspace.workspace.workspace_root();
+    let resolve = |revset_str: &str, cwd: &Path| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, Some(cwd))
+    };
+
+    // "**" matches any number of directories
+    assert_eq!(
+        resolve(r#"file("**/*.rs")"#, workspace_root),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"file("**/*.md")"#, workspace_root),
+        vec![commit3.id().clone(), commit1.id().clone()]
+    );
+    // "*" doesn't match path separator
+    assert_eq!(
+        resolve(r#"file("*.md")"#, workspace_root),
+        vec![commit3.id().clone()]
+    );
+    // Glob is relative to the current directory
+    assert_eq!(
+        resolve(r#"file("*.rs")"#, &workspace_root.join("src")),
+        vec![commit1.id().clone()]
+    );
+    // Can be mixed with prefix paths
+    assert_eq!(
+        resolve(r#"file("src/lib", "*.md")"#, workspace_root),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    // Glob can be specified explicitly
+    assert_eq!(
+        resolve(r#"file(glob:"src/**/*.rs")"#, workspace_root),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"file(glob:"lib/*.rs")"#, &workspace_root.join("src")),
+        vec![commit2.id().clone()]
+    );
+    // Extension matches files in any directory
+    assert_eq!(
+        resolve(r#"file(ext:"rs")"#, workspace_root),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"file(ext:"md")"#, &workspace_root.join("src")),
+        vec![commit3.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"file(ext:"md", ~docs)"#, workspace_root),
+        vec![commit3.id().clone()]
+    );
+    assert_eq!(resolve(r#"file(ext:"txt")"#, workspace_root), vec![]);
+    // Glob matching nothing
+    assert_eq!(resolve(r#"file(glob:"*.txt")"#, workspace_root), vec![]);
+    assert_eq!(
+        resolve(r#"file(glob:"nonexistent/**")"#, workspace_root),
+        vec![]
+    );
+
+    // Invalid glob is reported at evaluation time
+    let workspace_ctx = RevsetWorkspaceContext {
+        cwd: workspace_root,
+        workspace_id: test_workspace.workspace.workspace_id(),
+        workspace_root,
+    };
+    for revset_str in [r#"file("[a-")"#, r#"file(glob:"src/[a-")"#] {
+        let expression = parse_optimized(revset_str, Some(workspace_ctx.clone()));
+        let expression = expression
+            .resolve_in_workspace(mut_repo, &workspace_ctx)
+            .unwrap();
+        assert_matches!(
+            expression.evaluate(mut_repo).map(|_| ()),
+            Err(RevsetEvaluationError::Other(_))
+        );
+    }
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_file_excludes(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let main_rs = RepoPath::from_internal_string("src/main.rs");
+    let vendor_rs = RepoPath::from_internal_string("vendor/lib.rs");
+    let third_party_rs = RepoPath::from_internal_string("third_party/lib.rs");
+    let tree1 = testutils::create_tree(repo, &[(&main_rs, "1")]);
+    let tree2 = testutils::create_tree(repo, &[(&main_rs, "1"), (&vendor_rs, "1")]);
+    let tree3 = testutils::create_tree(
+        repo,
+        &[(&main_rs, "1"), (&vendor_rs, "1"), (&third_party_rs, "1")],
+    );
+    let tree4 = testutils::create_tree(
+        repo,
+        &[(&main_rs, "2"), (&vendor_rs, "2"), (&third_party_rs, "1")],
+    );
+    let commit1 = mut_repo
+        .new_commit(
+            &settings,
+            vec![repo.store().root_commit_id().clone()],
+            tree1.id().clone(),
+        )
+        .write()
+        .unwrap();
+    let commit2 = mut_repo
+        .new_commit(&settings, vec![commit1.id().clone()], tree2.id().clone())
+        .write()
+        .unwrap();
+    let commit3 = mut_repo
+        .new_commit(&settings, vec![commit2.id().clone()], tree3.id().clone())
+        .write()
+        .unwrap();
+    let commit4 = mut_repo
+        .new_commit(&settings, vec![commit3.id().clone()], tree4.id().clone())
+        .write()
+        .unwrap();
+
+    let workspace_root = test_workspace.workspace.workspace_root();
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(
+            mut_repo,
+            revset_str,
+            &test_workspace.workspace,
+            Some(workspace_root),
+        )
+    };
+
+    // Commits touching anything except the excluded directories
+    assert_eq!(
+        resolve("file(~vendor, ~third_party)"),
+        vec![commit4.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve("file(~vendor)"),
+        vec![
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit1.id().clone()
+        ]
+    );
+    // Exclusions are subtracted from the included paths
+    assert_eq!(
+        resolve("file(vendor, third_party, ~vendor)"),
+        vec![commit3.id().clone()]
+    );
+    assert_eq!(resolve(r#"file(~glob:"**/*.rs")"#), vec![]);
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_diff_contains(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("dir/file2");
+    let binary_path = RepoPath::from_internal_string("binary");
+    let tree1 = testutils::create_tree(repo, &[(&file_path1, "foo\nbar\n")]);
+    // Modifies "bar" line, adds file2
+    let tree2 = testutils::create_tree(
+        repo,
+        &[(&file_path1, "foo\nbaz\n"), (&file_path2, "hello\n")],
+    );
+    // Removes file2
+    let tree3 = testutils::create_tree(repo, &[(&file_path1, "foo\nbaz\n")]);
+    // Adds binary file
+    let tree4 = testutils::create_tree(
+        repo,
+        &[(&file_path1, "foo\nbaz\n"), (&binary_path, "\0hello\n")],
+    );
+    let commit1 = mut_repo
+        .new_commit(
+            &settings,
+            vec![repo.store().root_commit_id().clone()],
+            tree1.id().clone(),
+        )
+        .write()
+        .unwrap();
+    let commit2 = mut_repo
+        .new_commit(&settings, vec![commit1.id().clone()], tree2.id().clone())
+        .write()
+        .unwrap();
+    let commit3 = mut_repo
+        .new_commit(&settings, vec![commit2.id().clone()], tree3.id().clone())
+        .write()
+        .unwrap();
+    let commit4 = mut_repo
+        .new_commit(&settings, vec![commit3.id().clone()], tree4.id().clone())
+        .write()
+        .unwrap();
+
+    let workspace_root = test_workspace.workspace.workspace_root();
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(
+            mut_repo,
+            revset_str,
+            &test_workspace.workspace,
+            Some(workspace_root),
+        )
+    };
+
+    // Added and removed lines are searched, unchanged lines are not
+    assert_eq!(
+        resolve(r#"diff_contains("bar")"#),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"diff_contains("foo")"#),
+        vec![commit1.id().clone()]
+    );
+    // Lines of added and removed files are searched, but binary files are not
+    assert_eq!(
+        resolve(r#"diff_contains("hello")"#),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    // The commit only adding binary file has no lines to search
+    assert_eq!(
+        resolve(r#"diff_contains("")"#),
+        vec![
+            commit3.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone()
+        ]
+    );
+    assert_eq!(resolve(r#"file(binary)"#), vec![commit4.id().clone()]);
+    // String patterns are matched line by line
+    assert_eq!(
+        resolve(r#"diff_contains(exact:"baz")"#),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"diff_contains(regex:"^ba[rz]$")"#),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    // Restricted to files
+    assert_eq!(
+        resolve(r#"diff_contains("hello", dir)"#),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(resolve(r#"diff_contains("hello", file1)"#), vec![]);
+    assert_eq!(
+        resolve(r#"diff_contains("ba", ~dir)"#),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_adds_files(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("dir/file2");
+    let file_path3 = RepoPath::from_internal_string("dir/file3");
+    let tree1 = testutils::create_tree(repo, &[(&file_path1, "1")]);
+    // Modifies file1, adds file2
+    let tree2 = testutils::create_tree(repo, &[(&file_path1, "2"), (&file_path2, "2")]);
+    // Removes file2
+    let tree3 = testutils::create_tree(repo, &[(&file_path1, "2")]);
+    // Adds file3
+    let tree4 = testutils::create_tree(repo, &[(&file_path1, "2"), (&file_path3, "4")]);
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(
+        vec![repo.store().root_commit_id().clone()],
+        tree1.id().clone(),
+    );
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2.id().clone());
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3.id().clone());
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4.id().clone());
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    assert_eq!(
+        resolve("adds_files()"),
+        vec![
+            commit4.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone()
+        ]
+    );
+    assert_eq!(
+        resolve("adds_files(dir)"),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(resolve("adds_files(file1)"), vec![commit1.id().clone()]);
+    assert_eq!(
+        resolve(r#"adds_files(~"dir/file3")"#),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    // Modifying or removing files doesn't count
+    assert_eq!(
+        resolve("file(file1) & adds_files(file1)"),
+        vec![commit1.id().clone()]
+    );
+    assert!(!resolve("adds_files()").contains(commit3.id()));
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_deletes_files(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("dir/file2");
+    let file_path3 = RepoPath::from_internal_string("dir/file3");
+    let file_path4 = RepoPath::from_internal_string("file1/file4");
+    let tree1 = testutils::create_tree(
+        repo,
+        &[(&file_path1, "1"), (&file_path2, "1"), (&file_path3, "1")],
+    );
+    // Modifies file1, removes file2
+    let tree2 = testutils::create_tree(repo, &[(&file_path1, "2"), (&file_path3, "1")]);
+    // Replaces file1 by a directory
+    let tree3 = testutils::create_tree(repo, &[(&file_path4, "3"), (&file_path3, "1")]);
+    // Replaces directory "dir" by a file
+    let dir_path = RepoPath::from_internal_string("dir");
+    let tree4 = testutils::create_tree(repo, &[(&file_path4, "3"), (&dir_path, "4")]);
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(
+        vec![repo.store().root_commit_id().clone()],
+        tree1.id().clone(),
+    );
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2.id().clone());
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3.id().clone());
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4.id().clone());
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    assert_eq!(
+        resolve("deletes_files()"),
+        vec![
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone()
+        ]
+    );
+    assert_eq!(
+        resolve("deletes_files(dir)"),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(resolve("deletes_files(file1)"), vec![commit3.id().clone()]);
+    assert_eq!(
+        resolve(r#"deletes_files("dir/file3")"#),
+        vec![commit4.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"deletes_files(~"dir/file2")"#),
+        vec![commit4.id().clone(), commit3.id().clone()]
+    );
+    // Adding or modifying files doesn't count
+    assert!(!resolve("deletes_files()").contains(commit1.id()));
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_file_mode_changed(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("dir/file2");
+    let create_tree = |files: &[(&RepoPath, &str, bool)]| {
+        let store = repo.store();
+        let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+        for &(path, contents, executable) in files {
+            if executable {
+                testutils::write_executable_file(&mut tree_builder, path, contents);
+            } else {
+                testutils::write_normal_file(&mut tree_builder, path, contents);
+            }
+        }
+        tree_builder.write_tree()
+    };
+    let tree1 = create_tree(&[(&file_path1, "1", false), (&file_path2, "1", false)]);
+    // Makes file1 executable
+    let tree2 = create_tree(&[(&file_path1, "1", true), (&file_path2, "1", false)]);
+    // Modifies file1 content only
+    let tree3 = create_tree(&[(&file_path1, "2", true), (&file_path2, "1", false)]);
+    // Makes file1 non-executable with modification, makes file2 executable
+    let tree4 = create_tree(&[(&file_path1, "3", false), (&file_path2, "1", true)]);
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(vec![repo.store().root_commit_id().clone()], tree1);
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2);
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3);
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4);
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    // Adding files or modifying content doesn't count
+    assert_eq!(
+        resolve("file_mode_changed()"),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve("file_mode_changed(file1)"),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve("file_mode_changed(dir)"),
+        vec![commit4.id().clone()]
+    );
+    assert_eq!(
+        resolve("file_mode_changed(~dir)"),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+    assert!(!resolve("file_mode_changed()").contains(commit3.id()));
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_mode_only_changed(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("dir/file2");
+    let link_path = RepoPath::from_internal_string("link");
+    // (path, contents, kind) where kind is "file", "exec", or "link"
+    let create_tree = |files: &[(&RepoPath, &str, &str)]| {
+        let store = repo.store();
+        let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+        for &(path, contents, kind) in files {
+            match kind {
+                "file" => testutils::write_normal_file(&mut tree_builder, path, contents),
+                "exec" => testutils::write_executable_file(&mut tree_builder, path, contents),
+                "link" => testutils::write_symlink(&mut tree_builder, path, contents),
+                _ => panic!("unexpected kind {kind}"),
+            }
+        }
+        tree_builder.write_tree()
+    };
+    let tree1 = create_tree(&[
+        (&file_path1, "1", "file"),
+        (&file_path2, "1", "file"),
+        (&link_path, "target", "file"),
+    ]);
+    // Makes file1 executable
+    let tree2 = create_tree(&[
+        (&file_path1, "1", "exec"),
+        (&file_path2, "1", "file"),
+        (&link_path, "target", "file"),
+    ]);
+    // Modifies file1 content only
+    let tree3 = create_tree(&[
+        (&file_path1, "2", "exec"),
+        (&file_path2, "1", "file"),
+        (&link_path, "target", "file"),
+    ]);
+    // Modifies both content and mode of file1 and file2
+    let tree4 = create_tree(&[
+        (&file_path1, "3", "file"),
+        (&file_path2, "2", "exec"),
+        (&link_path, "target", "file"),
+    ]);
+    // Turns the file into a symlink to its content
+    let tree5 = create_tree(&[
+        (&file_path1, "3", "file"),
+        (&file_path2, "2", "exec"),
+        (&link_path, "target", "link"),
+    ]);
+    // Retargets the symlink while turning it into a file
+    let tree6 = create_tree(&[
+        (&file_path1, "3", "file"),
+        (&file_path2, "2", "exec"),
+        (&link_path, "other", "file"),
+    ]);
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(vec![repo.store().root_commit_id().clone()], tree1);
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2);
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3);
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4);
+    let commit5 = create_commit(vec![commit4.id().clone()], tree5);
+    let _commit6 = create_commit(vec![commit5.id().clone()], tree6);
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    assert_eq!(
+        resolve("mode_only_changed()"),
+        vec![commit5.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve("mode_only_changed(file1)"),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve("mode_only_changed(link)"),
+        vec![commit5.id().clone()]
+    );
+    assert_eq!(resolve("mode_only_changed(dir)"), vec![]);
+    // Changing both the mode and the content counts as a mode change only for
+    // file_mode_changed()
+    assert!(resolve("file_mode_changed()").contains(commit4.id()));
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_touches_symlinks(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path = RepoPath::from_internal_string("file");
+    let link_path1 = RepoPath::from_internal_string("link1");
+    let link_path2 = RepoPath::from_internal_string("dir/link2");
+    // Each item is (path, content or target, is_symlink)
+    let create_tree = |entries: &[(&RepoPath, &str, bool)]| {
+        let store = repo.store();
+        let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+        for &(path, contents, is_symlink) in entries {
+            if is_symlink {
+                testutils::write_symlink(&mut tree_builder, path, contents);
+            } else {
+                testutils::write_normal_file(&mut tree_builder, path, contents);
+            }
+        }
+        tree_builder.write_tree()
+    };
+    // Adds link1
+    let tree1 = create_tree(&[(&file_path, "1", false), (&link_path1, "file", true)]);
+    // Modifies file only
+    let tree2 = create_tree(&[(&file_path, "2", false), (&link_path1, "file", true)]);
+    // Retargets link1
+    let tree3 = create_tree(&[(&file_path, "2", false), (&link_path1, "dir", true)]);
+    // Adds dir/link2
+    let tree4 = create_tree(&[
+        (&file_path, "2", false),
+        (&link_path1, "dir", true),
+        (&link_path2, "../file", true),
+    ]);
+    // Replaces link1 with regular file
+    let tree5 = create_tree(&[
+        (&file_path, "2", false),
+        (&link_path1, "dir", false),
+        (&link_path2, "../file", true),
+    ]);
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(vec![repo.store().root_commit_id().clone()], tree1);
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2);
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3);
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4);
+    let commit5 = create_commit(vec![commit4.id().clone()], tree5);
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    assert_eq!(
+        resolve("touches_symlinks()"),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit1.id().clone(),
+        ]
+    );
+    assert_eq!(resolve("touches_symlinks(dir)"), vec![commit4.id().clone()]);
+    assert_eq!(
+        resolve("touches_symlinks(~dir)"),
+        vec![
+            commit5.id().clone(),
+            commit3.id().clone(),
+            commit1.id().clone(),
+        ]
+    );
+    assert_eq!(resolve("touches_symlinks(file)"), vec![]);
+}
+
+#[test]
+fn test_evaluate_expression_touches_submodules() {
+    let settings = testutils::user_settings();
+    // Submodules can't be stored in the local backend
+    let test_workspace = TestWorkspace::init(&settings, true);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path = RepoPath::from_internal_string("vendor/lib/file");
+    let submodule_path1 = RepoPath::from_internal_string("sub1");
+    let submodule_path2 = RepoPath::from_internal_string("vendor/sub2");
+    let target1 = CommitId::from_hex("1111111111111111111111111111111111111111");
+    let target2 = CommitId::from_hex("2222222222222222222222222222222222222222");
+    // Each item is (path, file content or submodule target)
+    let create_tree = |files: &[(&RepoPath, &str)], submodules: &[(&RepoPath, &CommitId)]| {
+        let store = repo.store();
+        let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+        for &(path, contents) in files {
+            testutils::write_normal_file(&mut tree_builder, path, contents);
+        }
+        for &(path, target) in submodules {
+            tree_builder.set(path.clone(), TreeValue::GitSubmodule(target.clone()));
+        }
+        tree_builder.write_tree()
+    };
+    // Adds sub1
+    let tree1 = create_tree(&[(&file_path, "1")], &[(&submodule_path1, &target1)]);
+    // Modifies vendored file only
+    let tree2 = create_tree(&[(&file_path, "2")], &[(&submodule_path1, &target1)]);
+    // Bumps sub1
+    let tree3 = create_tree(&[(&file_path, "2")], &[(&submodule_path1, &target2)]);
+    // Adds vendor/sub2
+    let tree4 = create_tree(
+        &[(&file_path, "2")],
+        &[(&submodule_path1, &target2), (&submodule_path2, &target1)],
+    );
+    // Removes sub1
+    let tree5 = create_tree(&[(&file_path, "2")], &[(&submodule_path2, &target1)]);
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(vec![repo.store().root_commit_id().clone()], tree1);
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2);
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3);
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4);
+    let commit5 = create_commit(vec![commit4.id().clone()], tree5);
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    assert_eq!(
+        resolve("touches_submodules()"),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit1.id().clone(),
+        ]
+    );
+    assert_eq!(
+        resolve("touches_submodules(vendor)"),
+        vec![commit4.id().clone()]
+    );
+    assert_eq!(
+        resolve("touches_submodules(sub1)"),
+        vec![
+            commit5.id().clone(),
+            commit3.id().clone(),
+            commit1.id().clone(),
+        ]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_touches_binary(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let text_path = RepoPath::from_internal_string("text");
+    let binary_path1 = RepoPath::from_internal_string("binary1");
+    let binary_path2 = RepoPath::from_internal_string("assets/binary2");
+    // NUL byte beyond the detection limit doesn't make the file binary
+    let long_text = format!("{}\0", "a".repeat(8 * 1024));
+    let create_tree =
+        |files: &[(&RepoPath, &str)]| testutils::create_tree(repo, files).id().clone();
+    // Adds text only
+    let tree1 = create_tree(&[(&text_path, "1")]);
+    // Adds binary1
+    let tree2 = create_tree(&[(&text_path, "1"), (&binary_path1, "a\0b")]);
+    // Modifies text only
+    let tree3 = create_tree(&[(&text_path, &long_text), (&binary_path1, "a\0b")]);
+    // Adds assets/binary2
+    let tree4 = create_tree(&[
+        (&text_path, &long_text),
+        (&binary_path1, "a\0b"),
+        (&binary_path2, "\0"),
+    ]);
+    // Removes binary1
+    let tree5 = create_tree(&[(&text_path, &long_text), (&binary_path2, "\0")]);
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(vec![repo.store().root_commit_id().clone()], tree1);
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2);
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3);
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4);
+    let commit5 = create_commit(vec![commit4.id().clone()], tree5);
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    assert_eq!(
+        resolve("touches_binary()"),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit2.id().clone(),
+        ]
+    );
+    assert_eq!(
+        resolve("touches_binary(assets)"),
+        vec![commit4.id().clone()]
+    );
+    assert_eq!(
+        resolve("touches_binary() ~ file(assets)"),
+        vec![commit5.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(resolve("touches_binary(text)"), vec![]);
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_adds_large_files(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let small_path = RepoPath::from_internal_string("small");
+    let large_path1 = RepoPath::from_internal_string("large1");
+    let large_path2 = RepoPath::from_internal_string("assets/large2");
+    let small = "a".repeat(1023);
+    let large = "a".repeat(1024);
+    let create_tree =
+        |files: &[(&RepoPath, &str)]| testutils::create_tree(repo, files).id().clone();
+    // Adds small file only
+    let tree1 = create_tree(&[(&small_path, &small)]);
+    // Adds large1
+    let tree2 = create_tree(&[(&small_path, &small), (&large_path1, &large)]);
+    // Shrinks large1
+    let tree3 = create_tree(&[(&small_path, &small), (&large_path1, "a")]);
+    // Grows large1 and adds assets/large2
+    let tree4 = create_tree(&[
+        (&small_path, &small),
+        (&large_path1, &large),
+        (&large_path2, &large),
+    ]);
+    // Removes large1 and assets/large2
+    let tree5 = create_tree(&[(&small_path, &small)]);
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(vec![repo.store().root_commit_id().clone()], tree1);
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2);
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3);
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4);
+    let _commit5 = create_commit(vec![commit4.id().clone()], tree5);
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    // The size threshold is inclusive
+    assert_eq!(
+        resolve("adds_large_files(1KiB)"),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(resolve("adds_large_files(1025)"), vec![]);
+    assert_eq!(
+        resolve("adds_large_files(1023B)"),
+        vec![
+            commit4.id().clone(),
+            commit2.id().clone(),
+            commit1.id().clone(),
+        ]
+    );
+    assert_eq!(
+        resolve("adds_large_files(1KiB, assets)"),
+        vec![commit4.id().clone()]
+    );
+    assert_eq!(
+        resolve(r#"adds_large_files(1KiB, ~"assets")"#),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(resolve("adds_large_files(1KiB, small)"), vec![]);
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_renames(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("file2");
+    let file_path3 = RepoPath::from_internal_string("dir/file3");
+    let file_path4 = RepoPath::from_internal_string("dir/file4");
+    let file_path5 = RepoPath::from_internal_string("file5");
+    let tree1 = testutils::create_tree(repo, &[(&file_path1, "1"), (&file_path2, "2")]);
+    // Moves file1 to dir/file3
+    let tree2 = testutils::create_tree(repo, &[(&file_path3, "1"), (&file_path2, "2")]);
+    // Moves dir/file3 to dir/file4 with modification
+    let tree3 = testutils::create_tree(repo, &[(&file_path4, "3"), (&file_path2, "2")]);
+    // Copies file2 to file1
+    let tree4 = testutils::create_tree(
+        repo,
+        &[(&file_path4, "3"), (&file_path2, "2"), (&file_path1, "2")],
+    );
+    // Moves dir/file4 to file5
+    let tree5 = testutils::create_tree(
+        repo,
+        &[(&file_path1, "2"), (&file_path2, "2"), (&file_path5, "3")],
+    );
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(
+        vec![repo.store().root_commit_id().clone()],
+        tree1.id().clone(),
+    );
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2.id().clone());
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3.id().clone());
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4.id().clone());
+    let commit5 = create_commit(vec![commit4.id().clone()], tree5.id().clone());
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    // Modified or copied files aren't detected as moved
+    assert_eq!(
+        resolve("renames()"),
+        vec![commit5.id().clone(), commit2.id().clone()]
+    );
+    // Either source or destination should match
+    assert_eq!(
+        resolve("renames(dir)"),
+        vec![commit5.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(resolve("renames(file1)"), vec![commit2.id().clone()]);
+    assert_eq!(resolve("renames(file2)"), vec![]);
+    assert_eq!(resolve("renames(file5)"), vec![commit5.id().clone()]);
+    assert_eq!(
+        resolve(r#"renames(dir, ~"dir/file3")"#),
+        vec![commit5.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_diff_lines(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("dir/file2");
+    let binary_path = RepoPath::from_internal_string("binary");
+    // Adds 2 lines
+    let tree1 = testutils::create_tree(repo, &[(&file_path1, "foo\nbar\n")]);
+    // Modifies "bar" line, adds file2 (3 lines)
+    let tree2 = testutils::create_tree(
+        repo,
+        &[(&file_path1, "foo\nbaz\n"), (&file_path2, "hello\n")],
+    );
+    // Removes file2 (1 line)
+    let tree3 = testutils::create_tree(repo, &[(&file_path1, "foo\nbaz\n")]);
+    // Adds binary file (0 lines)
+    let tree4 = testutils::create_tree(
+        repo,
+        &[(&file_path1, "foo\nbaz\n"), (&binary_path, "\0hello\n")],
+    );
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(
+        vec![repo.store().root_commit_id().clone()],
+        tree1.id().clone(),
+    );
+    let commit2 = create_commit(vec![commit1.id().clone()], tree2.id().clone());
+    let commit3 = create_commit(vec![commit2.id().clone()], tree3.id().clone());
+    let commit4 = create_commit(vec![commit3.id().clone()], tree4.id().clone());
+    // Empty commit (0 lines)
+    let commit5 = create_commit(vec![commit4.id().clone()], tree4.id().clone());
+
+    let root_commit_id = repo.store().root_commit_id().clone();
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "diff_lines(0)"),
+        vec![commit5.id().clone(), commit4.id().clone(), root_commit_id]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "diff_lines(2)"),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "diff_lines(1..3) & ~root"),
+        vec![commit3.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "diff_lines(..3) & ~root"),
+        vec![
+            commit5.id().clone(),
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit1.id().clone()
+        ]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "diff_lines(2..)"),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_empty(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("file2");
+    let tree1 = testutils::create_tree(repo, &[(&file_path1, "1")]);
+    let tree2 = testutils::create_tree(repo, &[(&file_path2, "1")]);
+    let tree3 = testutils::create_tree(repo, &[(&file_path1, "1"), (&file_path2, "1")]);
+    let root_commit_id = repo.store().root_commit_id().clone();
+    let commit1 = mut_repo
+        .new_commit(&settings, vec![root_commit_id.clone()], tree1.id().clone())
+        .write()
+        .unwrap();
+    // No-op commit on top of commit1
+    let commit2 = mut_repo
+        .new_commit(&settings, vec![commit1.id().clone()], tree1.id().clone())
+        .write()
+        .unwrap();
+    let commit3 = mut_repo
+        .new_commit(&settings, vec![root_commit_id.clone()], tree2.id().clone())
+        .write()
+        .unwrap();
+    // Merge commit without user modifications
+    let commit4 = mut_repo
+        .new_commit(
+            &settings,
+            vec![commit2.id().clone(), commit3.id().clone()],
+            tree3.id().clone(),
+        )
+        .write()
+        .unwrap();
+    // Merge commit with user modifications
+    let commit5 = mut_repo
+        .new_commit(
+            &settings,
+            vec![commit2.id().clone(), commit3.id().clone()],
+            tree1.id().clone(),
+        )
+        .write()
+        .unwrap();
+
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "empty()"),
+        vec![
+            commit4.id().clone(),
+            commit2.id().clone(),
+            root_commit_id.clone(),
+        ]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "~empty()"),
+        vec![
+            commit5.id().clone(),
+            commit3.id().clone(),
+            commit1.id().clone(),
         ]
     );
+}
 
-    // file() revset:
-    assert_eq!(
-        resolve_commit_ids_in_workspace(
-            mut_repo,
-            r#"file("repo/added_clean_clean")"#,
-            &test_workspace.workspace,
-            Some(test_workspace.workspace.workspace_root().parent().unwrap()),
-        ),
-        vec![commit1.id().clone()]
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_whitespace_only(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("file2");
+    let file_path3 = RepoPath::from_internal_string("file3");
+    let binary_path = RepoPath::from_internal_string("binary");
+    let tree1 = testutils::create_tree(repo, &[(&file_path1, "a b\nc\n")]);
+    // Reformats file1
+    let tree2 = testutils::create_tree(repo, &[(&file_path1, "a  b\n\n  c\n")]);
+    // Modifies file1 content
+    let tree3 = testutils::create_tree(repo, &[(&file_path1, "a  b\n\n  d\n")]);
+    // Reformats file1 and adds non-empty file2
+    let tree4 = testutils::create_tree(repo, &[(&file_path1, "ab d"), (&file_path2, " x ")]);
+    // Reformats file1 and file2, and adds empty file3
+    let tree5 = testutils::create_tree(
+        repo,
+        &[
+            (&file_path1, "ab d\n"),
+            (&file_path2, "x\n"),
+            (&file_path3, ""),
+        ],
     );
-    assert_eq!(
-        resolve_commit_ids_in_workspace(
-            mut_repo,
-            &format!(r#"{}: & file("added_modified_clean")"#, commit2.id().hex()),
-            &test_workspace.workspace,
-            Some(test_workspace.workspace.workspace_root()),
-        ),
-        vec![commit2.id().clone()]
+    // Adds binary file
+    let tree6 = testutils::create_tree(
+        repo,
+        &[
+            (&file_path1, "ab d\n"),
+            (&file_path2, "x\n"),
+            (&file_path3, ""),
+            (&binary_path, "\0"),
+        ],
+    );
+    // Modifies binary file by whitespace
+    let tree7 = testutils::create_tree(
+        repo,
+        &[
+            (&file_path1, "ab d\n"),
+            (&file_path2, "x\n"),
+            (&file_path3, ""),
+            (&binary_path, " \0"),
+        ],
     );
 
-    // empty() revset, which is identical to ~file(".")
+    let mut parent_id = repo.store().root_commit_id().clone();
+    let mut commit_ids = vec![];
+    for tree in [&tree1, &tree2, &tree3, &tree4, &tree5, &tree6, &tree7] {
+        let commit = mut_repo
+            .new_commit(&settings, vec![parent_id], tree.id().clone())
+            .write()
+            .unwrap();
+        parent_id = commit.id().clone();
+        commit_ids.push(parent_id.clone());
+    }
+    // No-op commit
+    let commit8 = mut_repo
+        .new_commit(&settings, vec![parent_id], tree7.id().clone())
+        .write()
+        .unwrap();
+
     assert_eq!(
-        resolve_commit_ids(mut_repo, &format!("{}: & empty()", commit1.id().hex())),
-        vec![commit4.id().clone()]
+        resolve_commit_ids(mut_repo, "whitespace_only()"),
+        vec![commit_ids[4].clone(), commit_ids[1].clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "whitespace_only() & empty()"),
+        vec![]
     );
+    assert!(!resolve_commit_ids(mut_repo, "whitespace_only()").contains(commit8.id()));
 }
 
 #[test_case(false ; "local backend")]
@@ -2296,6 +5694,118 @@ fn test_evaluate_expression_conflict(use_git: bool) {
     );
 }
 
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_conflict_in_files(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_workspace = TestWorkspace::init(&settings, use_git);
+    let repo = &test_workspace.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    // Create a conflict in `dir1/file` in commit2 and in `dir2/file` in commit3
+    let file_path1 = RepoPath::from_internal_string("dir1/file");
+    let file_path2 = RepoPath::from_internal_string("dir2/file");
+    let base = testutils::create_tree(repo, &[(&file_path1, "0"), (&file_path2, "0")]);
+    let tree1 = testutils::create_tree(repo, &[(&file_path1, "1"), (&file_path2, "0")]);
+    let tree2 = testutils::create_tree(repo, &[(&file_path1, "2"), (&file_path2, "0")]);
+    let tree3 = testutils::create_tree(repo, &[(&file_path1, "0"), (&file_path2, "1")]);
+    let tree4 = testutils::create_tree(repo, &[(&file_path1, "0"), (&file_path2, "2")]);
+    let tree_id_conflict1 = merge_trees(&tree1, &base, &tree2).unwrap();
+    let tree_id_conflict2 = merge_trees(&tree3, &base, &tree4).unwrap();
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(
+        vec![repo.store().root_commit_id().clone()],
+        base.id().clone(),
+    );
+    let commit2 = create_commit(vec![commit1.id().clone()], tree_id_conflict1);
+    let commit3 = create_commit(vec![commit1.id().clone()], tree_id_conflict2);
+
+    let resolve = |revset_str: &str| -> Vec<CommitId> {
+        resolve_commit_ids_in_workspace(mut_repo, revset_str, &test_workspace.workspace, None)
+    };
+
+    assert_eq!(
+        resolve("conflict()"),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(resolve("conflict(dir1)"), vec![commit2.id().clone()]);
+    assert_eq!(
+        resolve(r#"conflict("dir2/file")"#),
+        vec![commit3.id().clone()]
+    );
+    assert_eq!(resolve("conflict(~dir1)"), vec![commit3.id().clone()]);
+    assert_eq!(resolve(r#"conflict(glob:"*/file")"#).len(), 2);
+    assert_eq!(resolve("conflict(dir3)"), vec![]);
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_conflict_count(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    // Create trees with one conflict and with two conflicts
+    let file_path1 = RepoPath::from_internal_string("file1");
+    let file_path2 = RepoPath::from_internal_string("dir/file2");
+    let create_tree = |contents1: &str, contents2: &str| {
+        testutils::create_tree(repo, &[(&file_path1, contents1), (&file_path2, contents2)])
+    };
+    let base = create_tree("0", "0");
+    let tree_id_conflict1 =
+        merge_trees(&create_tree("1", "0"), &base, &create_tree("2", "0")).unwrap();
+    let tree_id_conflict2 =
+        merge_trees(&create_tree("1", "1"), &base, &create_tree("2", "2")).unwrap();
+
+    let mut create_commit = |parent_ids, tree_id| {
+        mut_repo
+            .new_commit(&settings, parent_ids, tree_id)
+            .write()
+            .unwrap()
+    };
+    let commit1 = create_commit(
+        vec![repo.store().root_commit_id().clone()],
+        base.id().clone(),
+    );
+    let commit2 = create_commit(vec![commit1.id().clone()], tree_id_conflict1);
+    let commit3 = create_commit(vec![commit2.id().clone()], tree_id_conflict2);
+
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "~root & conflicts(0)"),
+        vec![commit1.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "conflicts(1)"),
+        vec![commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "conflicts(1..)"),
+        vec![commit3.id().clone(), commit2.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "conflicts(2..)"),
+        vec![commit3.id().clone()]
+    );
+    assert_eq!(
+        resolve_commit_ids(mut_repo, "~root & conflicts(..2)"),
+        vec![commit2.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(resolve_commit_ids(mut_repo, "conflicts(3..)"), vec![]);
+    // Empty range
+    assert_eq!(resolve_commit_ids(mut_repo, "conflicts(..0)"), vec![]);
+}
+
 #[test]
 fn test_reverse_graph_iterator() {
     let settings = testutils::user_settings();
@@ -2332,7 +5842,9 @@ fn test_reverse_graph_iterator() {
         repo.as_ref(),
         &[&commit_a, &commit_c, &commit_d, &commit_e, &commit_f],
     );
-    let commits = ReverseRevsetGraphIterator::new(revset.iter_graph()).collect_vec();
+    let commits = ReverseRevsetGraphIterator::new(revset.iter_graph())
+        .unwrap()
+        .collect_vec();
     assert_eq!(commits.len(), 5);
     assert_eq!(commits[0].0, *commit_a.id());
     assert_eq!(commits[1].0, *commit_c.id());
@@ -2466,3 +5978,286 @@ fn test_change_id_index() {
     );
     assert_eq!(resolve_prefix("a"), PrefixResolution::AmbiguousMatch);
 }
+
+#[test]
+fn test_change_id_index_divergent_order() {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(true);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+
+    let root_commit = repo.store().root_commit();
+    let change_id = ChangeId::from_hex("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+    let mut commits = vec![];
+    for i in 0..5 {
+        let commit = tx
+            .mut_repo()
+            .new_commit(
+                &settings,
+                vec![root_commit.id().clone()],
+                root_commit.tree_id().clone(),
+            )
+            .set_change_id(change_id.clone())
+            .set_description(format!("commit {i}"))
+            .write()
+            .unwrap();
+        commits.push(commit);
+    }
+
+    // The newest commit should come first regardless of the input order
+    let revset = revset_for_commits(
+        tx.repo(),
+        &[
+            &commits[2],
+            &commits[0],
+            &commits[4],
+            &commits[1],
+            &commits[3],
+        ],
+    );
+    let change_id_index = revset.change_id_index();
+    assert_eq!(
+        change_id_index.resolve_prefix(&HexPrefix::new("aa").unwrap()),
+        PrefixResolution::SingleMatch(commits.iter().rev().map(|c| c.id().clone()).collect())
+    );
+}
+
+/// Toy extension predicate matching commits with even-length description.
+#[derive(Debug)]
+struct EvenLengthDescription;
+
+impl RevsetFilterExtension for EvenLengthDescription {
+    fn matches(
+        &self,
+        store: &Arc<Store>,
+        _index: &dyn Index,
+        commit_id: &CommitId,
+    ) -> Result<bool, RevsetEvaluationError> {
+        let commit = store
+            .get_commit(commit_id)
+            .map_err(RevsetEvaluationError::StoreError)?;
+        Ok(commit.description().len() % 2 == 0)
+    }
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_evaluate_expression_filter_extension(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let mut tx = repo.start_transaction(&settings, "test");
+    let mut_repo = tx.mut_repo();
+
+    let root_commit_id = repo.store().root_commit_id().clone();
+    let commit1 = create_random_commit(mut_repo, &settings)
+        .set_description("a")
+        .write()
+        .unwrap();
+    let commit2 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit1.id().clone()])
+        .set_description("ab")
+        .write()
+        .unwrap();
+    let commit3 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit2.id().clone()])
+        .set_description("abc")
+        .write()
+        .unwrap();
+    let commit4 = create_random_commit(mut_repo, &settings)
+        .set_parents(vec![commit3.id().clone()])
+        .set_description("abcd")
+        .write()
+        .unwrap();
+    let mut_repo = &*mut_repo;
+
+    let extension = RevsetExpression::filter(RevsetFilterPredicate::Extension(Arc::new(
+        EvenLengthDescription,
+    )));
+    let evaluate = |expression: &Rc<RevsetExpression>| -> Vec<CommitId> {
+        optimize(expression.clone())
+            .resolve(mut_repo)
+            .unwrap()
+            .evaluate(mut_repo)
+            .unwrap()
+            .iter()
+            .try_collect()
+            .unwrap()
+    };
+
+    // The root commit has an empty description
+    assert_eq!(
+        evaluate(&extension),
+        vec![
+            commit4.id().clone(),
+            commit2.id().clone(),
+            root_commit_id.clone(),
+        ]
+    );
+    assert_eq!(
+        evaluate(&extension.negated()),
+        vec![commit3.id().clone(), commit1.id().clone()]
+    );
+    assert_eq!(
+        evaluate(
+            &extension.intersection(&RevsetExpression::commit(commit2.id().clone()).descendants())
+        ),
+        vec![commit4.id().clone(), commit2.id().clone()]
+    );
+    let description_c = RevsetExpression::filter(RevsetFilterPredicate::Description(
+        StringPattern::Substring("c".to_owned()),
+        CaseSensitivity::Sensitive,
+        DescriptionScope::Full,
+    ));
+    assert_eq!(
+        evaluate(&extension.intersection(&description_c)),
+        vec![commit4.id().clone()]
+    );
+    assert_eq!(
+        evaluate(&extension.union(&description_c)),
+        vec![
+            commit4.id().clone(),
+            commit3.id().clone(),
+            commit2.id().clone(),
+            root_commit_id,
+        ]
+    );
+    // Extensions are compared by identity
+    assert_eq!(extension, extension.clone());
+    assert_ne!(
+        RevsetFilterPredicate::Extension(Arc::new(EvenLengthDescription)),
+        RevsetFilterPredicate::Extension(Arc::new(EvenLengthDescription))
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_build_commit_predicate_for_unindexed_commit(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let src_file = RepoPath::from_internal_string("src/main.rs");
+    let doc_file = RepoPath::from_internal_string("docs/index.md");
+    let mut tx = repo.start_transaction(&settings, "test");
+    let tree1 = testutils::create_tree(repo, &[(&doc_file, "1")]);
+    let commit1 = tx
+        .mut_repo()
+        .new_commit(
+            &settings,
+            vec![repo.store().root_commit_id().clone()],
+            tree1.id().clone(),
+        )
+        .write()
+        .unwrap();
+    let repo = tx.commit();
+
+    // The commit is written to the store, but the index of `repo` doesn't know
+    // about it.
+    let mut tx = repo.start_transaction(&settings, "test");
+    let tree2 = testutils::create_tree(&repo, &[(&doc_file, "1"), (&src_file, "1")]);
+    let working_copy = tx
+        .mut_repo()
+        .new_commit(&settings, vec![commit1.id().clone()], tree2.id().clone())
+        .set_description("wip")
+        .write()
+        .unwrap();
+    drop(tx);
+    assert!(!repo.index().has_id(working_copy.id()));
+
+    let index = repo
+        .index()
+        .as_any()
+        .downcast_ref::<ReadonlyIndexImpl>()
+        .unwrap();
+    let matches = |predicate: &ResolvedPredicateExpression, commit: &Commit| {
+        build_commit_predicate(predicate, repo.store(), index, index.as_composite()).unwrap()(
+            commit,
+        )
+    };
+    let filter = ResolvedPredicateExpression::Filter;
+    let author = |needle: &str| {
+        filter(RevsetFilterPredicate::Author(
+            StringPattern::Substring(needle.to_owned()),
+            CaseSensitivity::Sensitive,
+        ))
+    };
+    let file = |path: &RepoPath| {
+        filter(RevsetFilterPredicate::File {
+            includes: Some(vec![FilePattern::PrefixPath(path.clone())]),
+            excludes: vec![],
+        })
+    };
+    let intersection = |predicate1, predicate2| {
+        ResolvedPredicateExpression::Intersection(Box::new(predicate1), Box::new(predicate2))
+    };
+    let union = |predicate1, predicate2| {
+        ResolvedPredicateExpression::Union(Box::new(predicate1), Box::new(predicate2))
+    };
+    let commit1_set =
+        ResolvedPredicateExpression::Set(Box::new(ResolvedExpression::Commits(vec![commit1
+            .id()
+            .clone()])));
+
+    // author("test.user") & file("src")
+    let src_dir = RepoPath::from_internal_string("src");
+    assert!(matches(
+        &intersection(author("test.user"), file(&src_dir)),
+        &working_copy
+    )
+    .unwrap());
+    assert!(!matches(
+        &intersection(author("nobody"), file(&src_dir)),
+        &working_copy
+    )
+    .unwrap());
+    assert!(!matches(
+        &intersection(author("test.user"), file(&doc_file)),
+        &working_copy
+    )
+    .unwrap());
+    assert!(matches(
+        &ResolvedPredicateExpression::NotIn(Box::new(file(&doc_file))),
+        &working_copy
+    )
+    .unwrap());
+    // Predicates otherwise answered by the index
+    assert!(matches(
+        &filter(RevsetFilterPredicate::ParentCount(1..2)),
+        &working_copy
+    )
+    .unwrap());
+    assert!(matches(
+        &filter(RevsetFilterPredicate::Generation(2..3)),
+        &working_copy
+    )
+    .unwrap());
+    assert!(!matches(
+        &filter(RevsetFilterPredicate::Generation(1..2)),
+        &working_copy
+    )
+    .unwrap());
+    // A set embedded in the predicate contains indexed commits only
+    let commit1_or_wip = union(
+        commit1_set.clone(),
+        filter(RevsetFilterPredicate::Description(
+            StringPattern::Exact("wip".to_owned()),
+            CaseSensitivity::Sensitive,
+            DescriptionScope::Full,
+        )),
+    );
+    assert!(matches(&commit1_or_wip, &working_copy).unwrap());
+    assert!(matches(&commit1_or_wip, &commit1).unwrap());
+    assert!(!matches(&union(author("nobody"), commit1_set), &working_copy).unwrap());
+    // Extensions can only test indexed commits by default
+    let even_length = filter(RevsetFilterPredicate::Extension(Arc::new(
+        EvenLengthDescription,
+    )));
+    assert!(matches(&even_length, &commit1).unwrap());
+    assert_matches!(
+        matches(&even_length, &working_copy),
+        Err(RevsetEvaluationError::Other(_))
+    );
+}
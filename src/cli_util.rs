@@ -45,7 +45,8 @@ use jujutsu_lib::repo::{
 use jujutsu_lib::repo_path::{FsPathParseError, RepoPath};
 use jujutsu_lib::revset::{
     Revset, RevsetAliasesMap, RevsetEvaluationError, RevsetExpression, RevsetIteratorExt,
-    RevsetParseError, RevsetParseErrorKind, RevsetResolutionError, RevsetWorkspaceContext,
+    RevsetParseContext, RevsetParseError, RevsetParseErrorKind, RevsetResolutionError,
+    RevsetWorkspaceContext,
 };
 use jujutsu_lib::settings::UserSettings;
 use jujutsu_lib::transaction::Transaction;
@@ -860,11 +861,9 @@ impl WorkspaceCommandHelper {
         &self,
         revision_str: &str,
     ) -> Result<Rc<RevsetExpression>, RevsetParseError> {
-        let expression = revset::parse(
-            revision_str,
-            &self.revset_aliases_map,
-            Some(&self.revset_context()),
-        )?;
+        let user_email = self.settings.user_email();
+        let context = self.revset_parse_context(&user_email);
+        let expression = revset::parse(revision_str, &context)?;
         Ok(revset::optimize(expression))
     }
 
@@ -877,8 +876,15 @@ impl WorkspaceCommandHelper {
         Ok(revset_expression.evaluate(self.repo.as_ref())?)
     }
 
-    pub(crate) fn revset_aliases_map(&self) -> &RevsetAliasesMap {
-        &self.revset_aliases_map
+    pub(crate) fn revset_parse_context<'a>(
+        &'a self,
+        user_email: &'a str,
+    ) -> RevsetParseContext<'a> {
+        RevsetParseContext {
+            aliases_map: &self.revset_aliases_map,
+            user_email,
+            workspace: Some(self.revset_context()),
+        }
     }
 
     pub(crate) fn revset_context(&self) -> RevsetWorkspaceContext {
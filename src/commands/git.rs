@@ -24,7 +24,7 @@ use crate::cli_util::{
     user_error_with_hint, CommandError, CommandHelper, RevisionArg, WorkspaceCommandHelper,
 };
 use crate::commands::make_branch_term;
-use crate::progress::Progress;
+use crate::progress::{Progress, DEFAULT_RATE_TIME_WINDOW};
 use crate::ui::Ui;
 
 /// Commands for working with the underlying Git repo
@@ -498,7 +498,13 @@ fn with_remote_callbacks<T>(ui: &mut Ui, f: impl FnOnce(git::RemoteCallbacks<'_>
     let mut ui = Mutex::new(ui);
     let mut callback = None;
     if ui.get_mut().unwrap().use_progress_indicator() {
-        let mut progress = Progress::new(Instant::now());
+        let ui_ref = ui.get_mut().unwrap();
+        let mut progress = Progress::new(
+            Instant::now(),
+            ui_ref.progress_update_interval(),
+            ui_ref.progress_decimal_units(),
+            DEFAULT_RATE_TIME_WINDOW,
+        );
         let ui = &ui;
         callback = Some(move |x: &git::Progress| {
             _ = progress.update(Instant::now(), x, *ui.lock().unwrap());
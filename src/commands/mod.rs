@@ -38,8 +38,9 @@ use jujutsu_lib::op_store::{RefTarget, WorkspaceId};
 use jujutsu_lib::repo::{ReadonlyRepo, Repo};
 use jujutsu_lib::repo_path::RepoPath;
 use jujutsu_lib::revset::{
-    ReverseRevsetGraphIterator, RevsetAliasesMap, RevsetExpression, RevsetFilterPredicate,
-    RevsetGraphEdge, RevsetGraphEdgeType, RevsetIteratorExt,
+    FilePattern, ReverseRevsetGraphIterator, RevsetAliasesMap, RevsetExpression,
+    RevsetFilterPredicate, RevsetGraphEdgeType, RevsetGraphItem, RevsetIteratorExt,
+    RevsetParseContext,
 };
 use jujutsu_lib::rewrite::{back_out_commit, merge_commit_trees, rebase_commit, DescendantRebaser};
 use jujutsu_lib::settings::UserSettings;
@@ -1472,14 +1473,16 @@ fn cmd_log(ui: &mut Ui, command: &CommandHelper, args: &LogArgs) -> Result<(), C
     let repo = workspace_command.repo();
     let wc_commit_id = workspace_command.get_wc_commit_id();
     let revset_expression = if !args.paths.is_empty() {
-        let repo_paths: Vec<_> = args
+        let patterns: Vec<_> = args
             .paths
             .iter()
             .map(|path_arg| workspace_command.parse_file_path(path_arg))
+            .map_ok(FilePattern::PrefixPath)
             .try_collect()?;
-        revset_expression.intersection(&RevsetExpression::filter(RevsetFilterPredicate::File(
-            Some(repo_paths),
-        )))
+        revset_expression.intersection(&RevsetExpression::filter(RevsetFilterPredicate::File {
+            includes: Some(patterns),
+            excludes: vec![],
+        }))
     } else {
         revset_expression
     };
@@ -1505,13 +1508,13 @@ fn cmd_log(ui: &mut Ui, command: &CommandHelper, args: &LogArgs) -> Result<(), C
         if !args.no_graph {
             let mut graph = get_graphlog(command.settings(), formatter.raw());
             let default_node_symbol = graph.default_node_symbol().to_owned();
-            let iter: Box<dyn Iterator<Item = (CommitId, Vec<RevsetGraphEdge>)>> = if args.reversed
-            {
-                Box::new(ReverseRevsetGraphIterator::new(revset.iter_graph()))
+            let iter: Box<dyn Iterator<Item = RevsetGraphItem>> = if args.reversed {
+                Box::new(ReverseRevsetGraphIterator::new(revset.iter_graph())?.map(Ok))
             } else {
                 revset.iter_graph()
             };
-            for (commit_id, edges) in iter {
+            for item in iter {
+                let (commit_id, edges) = item?;
                 let mut graphlog_edges = vec![];
                 // TODO: Should we update RevsetGraphIterator to yield this flag instead of all
                 // the missing edges since we don't care about where they point here
@@ -1569,7 +1572,7 @@ fn cmd_log(ui: &mut Ui, command: &CommandHelper, args: &LogArgs) -> Result<(), C
                 )?;
             }
         } else {
-            let iter: Box<dyn Iterator<Item = CommitId>> = if args.reversed {
+            let iter: Box<dyn Iterator<Item = Result<CommitId, _>>> = if args.reversed {
                 Box::new(revset.iter().reversed())
             } else {
                 Box::new(revset.iter())
@@ -1603,7 +1606,15 @@ fn cmd_log(ui: &mut Ui, command: &CommandHelper, args: &LogArgs) -> Result<(), C
                  the working copy commit, pass -r '@' instead."
             )?;
         } else if revset.is_empty()
-            && revset::parse(only_path, &RevsetAliasesMap::new(), None).is_ok()
+            && revset::parse(
+                only_path,
+                &RevsetParseContext {
+                    aliases_map: &RevsetAliasesMap::new(),
+                    user_email: "",
+                    workspace: None,
+                },
+            )
+            .is_ok()
         {
             writeln!(
                 ui.warning(),
@@ -2061,6 +2072,7 @@ fn cmd_new(ui: &mut Ui, command: &CommandHelper, args: &NewArgs) -> Result<(), C
             .evaluate(tx.repo())?
             .iter()
             .next()
+            .transpose()?
         {
             return Err(user_error(format!(
                 "Refusing to create a loop: commit {} would be both an ancestor and a descendant \
@@ -3194,11 +3206,9 @@ fn cmd_debug_revset(
     let workspace_ctx = workspace_command.revset_context();
     let repo = workspace_command.repo().as_ref();
 
-    let expression = revset::parse(
-        &args.revision,
-        workspace_command.revset_aliases_map(),
-        Some(&workspace_ctx),
-    )?;
+    let user_email = command.settings().user_email();
+    let context = workspace_command.revset_parse_context(&user_email);
+    let expression = revset::parse(&args.revision, &context)?;
     writeln!(ui, "-- Parsed:")?;
     writeln!(ui, "{expression:#?}")?;
     writeln!(ui)?;
@@ -3220,7 +3230,7 @@ fn cmd_debug_revset(
 
     writeln!(ui, "-- Commit IDs:")?;
     for commit_id in revset.iter() {
-        writeln!(ui, "{}", commit_id.hex())?;
+        writeln!(ui, "{}", commit_id?.hex())?;
     }
     Ok(())
 }
@@ -1,6 +1,7 @@
 use std::io;
 use std::time::{Duration, Instant};
 
+use crossterm::style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor};
 use crossterm::terminal::{Clear, ClearType};
 use jujutsu_lib::git;
 
@@ -8,18 +9,34 @@ use crate::cleanup_guard::CleanupGuard;
 use crate::ui::Ui;
 
 pub struct Progress {
+    start: Instant,
     next_print: Instant,
+    update_interval: Duration,
+    decimal_units: bool,
     rate: RateEstimate,
-    buffer: String,
+    /// Last downloaded size that differed from the previous one, and when it
+    /// was reported
+    last_transfer: (u64, Instant),
     guard: Option<CleanupGuard>,
 }
 
 impl Progress {
-    pub fn new(now: Instant) -> Self {
+    /// Creates progress indicator which averages the transfer rate over
+    /// `rate_time_window`. A longer window gives a steadier rate on bursty
+    /// connections, but it takes longer to follow actual changes.
+    pub fn new(
+        now: Instant,
+        update_interval: Duration,
+        decimal_units: bool,
+        rate_time_window: Duration,
+    ) -> Self {
         Self {
+            start: now,
             next_print: now + INITIAL_DELAY,
-            rate: RateEstimate::new(),
-            buffer: String::new(),
+            update_interval,
+            decimal_units,
+            rate: RateEstimate::new(rate_time_window),
+            last_transfer: (0, now),
             guard: None,
         }
     }
@@ -30,16 +47,27 @@ impl Progress {
         progress: &git::Progress,
         ui: &mut Ui,
     ) -> io::Result<()> {
-        use std::fmt::Write as _;
-
+        let term_width = match ui.progress_term_width() {
+            Some(width) => usize::from(width),
+            None => {
+                // Not a terminal we can redraw in place (e.g. a CI log), so
+                // print plain lines now and then instead.
+                if let Some(line) = self.update_plain(now, progress) {
+                    writeln!(ui, "{line}")?;
+                    ui.flush()?;
+                }
+                return Ok(());
+            }
+        };
         if progress.overall == 1.0 {
-            write!(ui, "\r{}", Clear(ClearType::CurrentLine))?;
+            // Nothing to clean up if the progress wasn't drawn yet
+            if self.guard.is_some() {
+                write!(ui, "\r{}", Clear(ClearType::CurrentLine))?;
+            }
             return Ok(());
         }
 
-        let rate = progress
-            .bytes_downloaded
-            .and_then(|x| self.rate.update(now, x));
+        let rate = self.record_transfer(now, progress);
         if now < self.next_print {
             return Ok(());
         }
@@ -51,33 +79,141 @@ impl Progress {
             _ = write!(ui, "{}", crossterm::cursor::Hide);
             self.guard = Some(guard);
         }
-        self.next_print = now.min(self.next_print + Duration::from_secs(1) / UPDATE_HZ);
+        self.next_print = now.min(self.next_print + self.update_interval);
+
+        let bar_color = ui.color().then(|| {
+            let stalled_for = match progress.bytes_downloaded {
+                Some(_) => now - self.last_transfer.1,
+                None => Duration::ZERO,
+            };
+            bar_color(stalled_for)
+        });
+        let line = self.render_line(now, progress, rate, Some(term_width), bar_color);
+        write!(ui, "\r{}{line}", Clear(ClearType::CurrentLine))?;
+        ui.flush()?;
+        Ok(())
+    }
+
+    /// Non-interactive counterpart of `update()`. Returns the plain line to be
+    /// printed, if it's time to print one.
+    fn update_plain(&mut self, now: Instant, progress: &git::Progress) -> Option<String> {
+        if progress.overall == 1.0 {
+            return None;
+        }
+        let rate = self.record_transfer(now, progress);
+        if now < self.next_print {
+            return None;
+        }
+        self.next_print = now + NON_INTERACTIVE_UPDATE_INTERVAL;
+        let line = self.render_line(now, progress, rate, None, None);
+        (!line.is_empty()).then_some(line)
+    }
 
-        self.buffer.clear();
-        write!(self.buffer, "\r{}", Clear(ClearType::CurrentLine)).unwrap();
-        let control_chars = self.buffer.len();
-        write!(self.buffer, "{: >3.0}% ", 100.0 * progress.overall).unwrap();
+    /// Updates the rate estimate and the time of the last transfer, and
+    /// returns the estimated rate.
+    fn record_transfer(&mut self, now: Instant, progress: &git::Progress) -> Option<f32> {
+        let rate = progress
+            .bytes_downloaded
+            .and_then(|x| self.rate.update(now, x));
         if let Some(total) = progress.bytes_downloaded {
-            let (scaled, prefix) = binary_prefix(total as f32);
-            write!(self.buffer, "{scaled: >5.1} {prefix}B ").unwrap();
+            if total != self.last_transfer.0 {
+                self.last_transfer = (total, now);
+            }
         }
-        if let Some(estimate) = rate {
-            let (scaled, prefix) = binary_prefix(estimate);
-            write!(self.buffer, "at {scaled: >5.1} {prefix}B/s ").unwrap();
+        rate
+    }
+
+    /// Formats the progress line to be printed at `now`, without the control
+    /// characters to redraw it in place.
+    ///
+    /// If `width` is specified, the line is laid out for a terminal of that
+    /// width, with a spinner or a progress bar. Otherwise, a plain line
+    /// without trailing whitespace is returned.
+    ///
+    /// If `bar_color` is specified, the progress bar is drawn in that color on
+    /// a dim background.
+    fn render_line(
+        &self,
+        now: Instant,
+        progress: &git::Progress,
+        rate: Option<f32>,
+        width: Option<usize>,
+        bar_color: Option<Color>,
+    ) -> String {
+        use std::fmt::Write as _;
+
+        let mut line = String::new();
+        let width = match width {
+            Some(width) => width,
+            None => {
+                if progress.overall > 0.0 {
+                    write!(line, "{:.0}% ", 100.0 * progress.overall).unwrap();
+                }
+                self.write_transfer_status(progress, rate, &mut line);
+                line.truncate(line.trim_end().len());
+                return line;
+            }
+        };
+
+        // The total is unknown until the remote finishes counting objects
+        let indeterminate = progress.overall == 0.0;
+        if indeterminate {
+            draw_spinner(now - self.start, &mut line);
+            line.push(' ');
+        } else {
+            write!(line, "{: >3.0}% ", 100.0 * progress.overall).unwrap();
+        }
+        self.write_transfer_status(progress, rate, &mut line);
+
+        if !indeterminate {
+            let bar_width = width.saturating_sub(line.chars().count() + 2);
+            line.push('[');
+            if let Some(color) = bar_color {
+                write!(
+                    line,
+                    "{}{}",
+                    SetForegroundColor(color),
+                    SetBackgroundColor(Color::DarkGrey)
+                )
+                .unwrap();
+            }
+            draw_progress(progress.overall, &mut line, bar_width);
+            if bar_color.is_some() {
+                write!(line, "{ResetColor}").unwrap();
+            }
+            line.push(']');
         }
+        line
+    }
 
-        let bar_width = ui
-            .term_width()
-            .map(usize::from)
-            .unwrap_or(0)
-            .saturating_sub(self.buffer.len() - control_chars + 2);
-        self.buffer.push('[');
-        draw_progress(progress.overall, &mut self.buffer, bar_width);
-        self.buffer.push(']');
+    /// Appends the downloaded size, transfer rate, and remaining time to the
+    /// buffer.
+    fn write_transfer_status(
+        &self,
+        progress: &git::Progress,
+        rate: Option<f32>,
+        buffer: &mut String,
+    ) {
+        use std::fmt::Write as _;
 
-        write!(ui, "{}", self.buffer)?;
-        ui.flush()?;
-        Ok(())
+        let unit_prefix = if self.decimal_units {
+            decimal_prefix
+        } else {
+            binary_prefix
+        };
+        if let Some(total) = progress.bytes_downloaded {
+            let (scaled, prefix) = unit_prefix(total as f32);
+            write!(buffer, "{scaled: >5.1} {prefix}B ").unwrap();
+        }
+        if let Some(estimate) = rate {
+            let (scaled, prefix) = unit_prefix(estimate);
+            write!(buffer, "at {scaled: >5.1} {prefix}B/s ").unwrap();
+        }
+        if let (Some(total), Some(estimate)) = (progress.bytes_downloaded, rate) {
+            if let Some(eta) = estimate_remaining_time(progress.overall, total, estimate) {
+                write!(buffer, "eta {} ", format_duration(eta)).unwrap();
+            }
+        }
     }
 }
 
@@ -98,36 +234,105 @@ fn draw_progress(progress: f32, buffer: &mut String, width: usize) {
     }
 }
 
-const UPDATE_HZ: u32 = 30;
+/// Picks the color of the progress bar depending on how long no data has been
+/// received.
+fn bar_color(stalled_for: Duration) -> Color {
+    if stalled_for >= STALL_ERROR_DELAY {
+        Color::Red
+    } else if stalled_for >= STALL_WARNING_DELAY {
+        Color::Yellow
+    } else {
+        Color::Green
+    }
+}
+
+fn draw_spinner(elapsed: Duration, buffer: &mut String) {
+    const CHARS: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+    const FRAME_DURATION: Duration = Duration::from_millis(100);
+    let frame = elapsed.as_millis() / FRAME_DURATION.as_millis();
+    buffer.push(CHARS[(frame % CHARS.len() as u128) as usize]);
+}
+
+/// Default number of progress updates per second
+pub const DEFAULT_UPDATE_HZ: u32 = 30;
+/// Default time window over which the transfer rate is averaged
+pub const DEFAULT_RATE_TIME_WINDOW: Duration = Duration::from_secs(2);
 const INITIAL_DELAY: Duration = Duration::from_millis(250);
+/// Time without received data after which the progress bar turns yellow
+const STALL_WARNING_DELAY: Duration = Duration::from_secs(3);
+/// Time without received data after which the progress bar turns red
+const STALL_ERROR_DELAY: Duration = Duration::from_secs(10);
+/// Interval between progress lines when the output isn't an interactive
+/// terminal
+const NON_INTERACTIVE_UPDATE_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Find the smallest binary prefix with which the whole part of `x` is at most
 /// three digits, and return the scaled `x` and that prefix.
 fn binary_prefix(x: f32) -> (f32, &'static str) {
     const TABLE: [&str; 9] = ["", "Ki", "Mi", "Gi", "Ti", "Pi", "Ei", "Zi", "Yi"];
+    scale_by_prefix(x, 1024.0, &TABLE)
+}
+
+/// Find the smallest decimal (SI) prefix with which the whole part of `x` is at
+/// most three digits, and return the scaled `x` and that prefix.
+fn decimal_prefix(x: f32) -> (f32, &'static str) {
+    const TABLE: [&str; 9] = ["", "k", "M", "G", "T", "P", "E", "Z", "Y"];
+    scale_by_prefix(x, 1000.0, &TABLE)
+}
 
+fn scale_by_prefix(x: f32, base: f32, table: &[&'static str]) -> (f32, &'static str) {
     let mut i = 0;
     let mut scaled = x;
-    while scaled.abs() >= 1000.0 && i < TABLE.len() - 1 {
+    while scaled.abs() >= 1000.0 && i < table.len() - 1 {
         i += 1;
-        scaled /= 1024.0;
+        scaled /= base;
+    }
+    (scaled, table[i])
+}
+
+/// Estimates the time to download the remaining bytes, assuming the bytes
+/// downloaded so far account for the `overall` fraction of the work.
+fn estimate_remaining_time(overall: f32, downloaded: u64, rate: f32) -> Option<Duration> {
+    let has_partial_progress = overall > 0.0 && overall < 1.0;
+    let is_rate_stable = rate.is_finite() && rate > 0.0;
+    if !has_partial_progress || !is_rate_stable {
+        return None;
+    }
+    let remaining = downloaded as f32 * (1.0 - overall) / overall;
+    // Float to int conversion saturates, so this can't overflow
+    Some(Duration::from_secs((remaining / rate).round() as u64))
+}
+
+/// Formats duration like `1h02m03s`, `1m23s`, or `5s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}h{minutes:02}m{secs:02}s")
+    } else if minutes > 0 {
+        format!("{minutes}m{secs:02}s")
+    } else {
+        format!("{secs}s")
     }
-    (scaled, TABLE[i])
 }
 
 struct RateEstimate {
     state: Option<RateEstimateState>,
+    time_window: Duration,
 }
 
 impl RateEstimate {
-    fn new() -> Self {
-        RateEstimate { state: None }
+    fn new(time_window: Duration) -> Self {
+        RateEstimate {
+            state: None,
+            time_window,
+        }
     }
 
     /// Compute smoothed rate from an update
     fn update(&mut self, now: Instant, total: u64) -> Option<f32> {
         if let Some(ref mut state) = self.state {
-            return Some(state.update(now, total));
+            return state.update(now, total, self.time_window);
         }
 
         self.state = Some(RateEstimateState {
@@ -146,21 +351,30 @@ struct RateEstimateState {
 }
 
 impl RateEstimateState {
-    fn update(&mut self, now: Instant, total: u64) -> f32 {
-        let delta = total - self.total;
-        self.total = total;
+    /// Returns `None` if the `total` went backwards, in which case the
+    /// estimate starts over from the new `total`.
+    fn update(&mut self, now: Instant, total: u64, time_window: Duration) -> Option<f32> {
         let dt = now - self.last_sample;
         self.last_sample = now;
+        let delta = match total.checked_sub(self.total) {
+            Some(delta) => delta,
+            None => {
+                // The transfer restarted (e.g. a new fetch phase)
+                self.total = total;
+                self.avg_rate = None;
+                return None;
+            }
+        };
+        self.total = total;
         let sample = delta as f32 / dt.as_secs_f32();
         match self.avg_rate {
-            None => *self.avg_rate.insert(sample),
+            None => Some(*self.avg_rate.insert(sample)),
             Some(ref mut avg_rate) => {
                 // From Algorithms for Unevenly Spaced Time Series: Moving
                 // Averages and Other Rolling Operators (Andreas Eckner, 2019)
-                const TIME_WINDOW: f32 = 2.0;
-                let alpha = 1.0 - (-dt.as_secs_f32() / TIME_WINDOW).exp();
+                let alpha = 1.0 - (-dt.as_secs_f32() / time_window.as_secs_f32()).exp();
                 *avg_rate += alpha * (sample - *avg_rate);
-                *avg_rate
+                Some(*avg_rate)
             }
         }
     }
@@ -186,4 +400,251 @@ mod tests {
         assert_eq!(buf, "█████▍    ");
         buf.clear();
     }
+
+    #[test]
+    fn test_render_line() {
+        let start = Instant::now();
+        let progress = Progress::new(
+            start,
+            Duration::from_millis(100),
+            false,
+            DEFAULT_RATE_TIME_WINDOW,
+        );
+        let mib = 1024.0 * 1024.0;
+        let git_progress = |overall, bytes: Option<f32>| git::Progress {
+            bytes_downloaded: bytes.map(|bytes| (bytes * mib) as u64),
+            overall,
+        };
+
+        assert_eq!(
+            progress.render_line(
+                start,
+                &git_progress(0.42, Some(1.5)),
+                Some(3.2 * mib),
+                Some(44),
+                None
+            ),
+            " 42%   1.5 MiB at   3.2 MiB/s eta 1s [██▏  ]"
+        );
+        assert_eq!(
+            progress.render_line(start, &git_progress(0.5, None), None, Some(17), None),
+            " 50% [█████     ]"
+        );
+        // Indeterminate progress has a spinner instead of a bar
+        assert_eq!(
+            progress.render_line(
+                start + Duration::from_millis(100),
+                &git_progress(0.0, Some(1.5)),
+                None,
+                Some(40),
+                None
+            ),
+            "⠙   1.5 MiB "
+        );
+        // The bar is omitted if the terminal is too narrow
+        assert_eq!(
+            progress.render_line(start, &git_progress(0.5, None), None, Some(4), None),
+            " 50% []"
+        );
+        // Only the bar is colored
+        assert_eq!(
+            progress.render_line(
+                start,
+                &git_progress(0.5, None),
+                None,
+                Some(17),
+                Some(Color::Green)
+            ),
+            format!(
+                " 50% [{}{}█████     {ResetColor}]",
+                SetForegroundColor(Color::Green),
+                SetBackgroundColor(Color::DarkGrey)
+            )
+        );
+        assert_eq!(
+            progress.render_line(
+                start,
+                &git_progress(0.0, Some(1.5)),
+                None,
+                Some(40),
+                Some(Color::Green)
+            ),
+            "⠋   1.5 MiB "
+        );
+
+        // Plain line for non-interactive output
+        assert_eq!(
+            progress.render_line(
+                start,
+                &git_progress(0.42, Some(1.5)),
+                Some(3.2 * mib),
+                None,
+                None
+            ),
+            "42%   1.5 MiB at   3.2 MiB/s eta 1s"
+        );
+        assert_eq!(
+            progress.render_line(start, &git_progress(0.0, None), None, None, None),
+            ""
+        );
+
+        // Decimal units
+        let progress = Progress::new(
+            start,
+            Duration::from_millis(100),
+            true,
+            DEFAULT_RATE_TIME_WINDOW,
+        );
+        assert_eq!(
+            progress.render_line(start, &git_progress(0.5, Some(1.5)), None, None, None),
+            "50%   1.6 MB"
+        );
+    }
+
+    #[test]
+    fn test_update_plain() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut progress = Progress::new(
+            start,
+            Duration::from_millis(100),
+            false,
+            DEFAULT_RATE_TIME_WINDOW,
+        );
+        let git_progress = |overall, bytes| git::Progress {
+            bytes_downloaded: Some(bytes),
+            overall,
+        };
+
+        // Nothing is printed before the initial delay
+        assert_eq!(progress.update_plain(at(0), &git_progress(0.1, 0)), None);
+        assert_eq!(
+            progress.update_plain(at(250), &git_progress(0.2, 2048)),
+            Some("20%   2.0 KiB at   8.0 KiB/s eta 1s".to_owned())
+        );
+        // Lines are printed much less often than the bar is redrawn, but the rate
+        // is estimated from all updates
+        assert_eq!(
+            progress.update_plain(at(350), &git_progress(0.3, 4096)),
+            None
+        );
+        assert_eq!(
+            progress.update_plain(at(5000), &git_progress(0.4, 4096)),
+            None
+        );
+        assert_eq!(
+            progress.update_plain(at(5250), &git_progress(0.5, 4096)),
+            Some("50%   4.0 KiB at 758.6 B/s eta 5s".to_owned())
+        );
+        // Nothing to clean up when done
+        assert_eq!(
+            progress.update_plain(at(20000), &git_progress(1.0, 8192)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_bar_color() {
+        assert_eq!(bar_color(Duration::ZERO), Color::Green);
+        assert_eq!(bar_color(Duration::from_millis(2999)), Color::Green);
+        assert_eq!(bar_color(Duration::from_secs(3)), Color::Yellow);
+        assert_eq!(bar_color(Duration::from_millis(9999)), Color::Yellow);
+        assert_eq!(bar_color(Duration::from_secs(10)), Color::Red);
+    }
+
+    #[test]
+    fn test_rate_estimate_reset() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut rate = RateEstimate::new(DEFAULT_RATE_TIME_WINDOW);
+        assert_eq!(rate.update(at(0), 1000), None);
+        assert_eq!(rate.update(at(1), 2000), Some(1000.0));
+        // Decreasing total starts over without a bogus rate
+        assert_eq!(rate.update(at(2), 500), None);
+        assert_eq!(rate.update(at(3), 100), None);
+        // The rate is estimated from the new total
+        assert_eq!(rate.update(at(4), 200), Some(100.0));
+        let estimate = rate.update(at(5), 400).unwrap();
+        assert!(estimate > 100.0 && estimate < 200.0, "{estimate}");
+    }
+
+    #[test]
+    fn test_rate_estimate_time_window() {
+        // Bursty transfer alternating between 0 and 20000 bytes/s
+        let variance = |time_window| {
+            let start = Instant::now();
+            let mut rate = RateEstimate::new(time_window);
+            let mut total = 0;
+            let estimates = (0..3000)
+                .filter_map(|i| {
+                    total += if i % 2 == 0 { 0 } else { 2000 };
+                    rate.update(start + Duration::from_millis(i * 100), total)
+                })
+                .skip(1500) // let the estimate settle
+                .collect::<Vec<_>>();
+            let mean = estimates.iter().sum::<f32>() / estimates.len() as f32;
+            estimates.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / estimates.len() as f32
+        };
+        let short = variance(Duration::from_secs(1));
+        let default = variance(DEFAULT_RATE_TIME_WINDOW);
+        let long = variance(Duration::from_secs(10));
+        assert!(short > default, "{short} > {default}");
+        assert!(default > long, "{default} > {long}");
+    }
+
+    #[test]
+    fn test_unit_prefix() {
+        assert_eq!(binary_prefix(999.0), (999.0, ""));
+        assert_eq!(decimal_prefix(999.0), (999.0, ""));
+        let (scaled, prefix) = binary_prefix(1500.0);
+        assert!((scaled - 1.4648).abs() < 0.001);
+        assert_eq!(prefix, "Ki");
+        assert_eq!(decimal_prefix(1500.0), (1.5, "k"));
+        assert_eq!(decimal_prefix(2.5e9), (2.5, "G"));
+        // The largest prefix is used for out-of-range values
+        assert_eq!(decimal_prefix(1e30).1, "Y");
+    }
+
+    #[test]
+    fn test_estimate_remaining_time() {
+        // Half of the work done at 10 bytes/s
+        assert_eq!(
+            estimate_remaining_time(0.5, 100, 10.0),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            estimate_remaining_time(0.8, 400, 20.0),
+            Some(Duration::from_secs(5))
+        );
+        // Unknown total or no progress
+        assert_eq!(estimate_remaining_time(0.0, 100, 10.0), None);
+        assert_eq!(estimate_remaining_time(1.0, 100, 10.0), None);
+        // Stalled or unstable rate
+        assert_eq!(estimate_remaining_time(0.5, 100, 0.0), None);
+        assert_eq!(estimate_remaining_time(0.5, 100, f32::NAN), None);
+        assert_eq!(estimate_remaining_time(0.5, 100, f32::INFINITY), None);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+        assert_eq!(format_duration(Duration::from_millis(5900)), "5s");
+        assert_eq!(format_duration(Duration::from_secs(83)), "1m23s");
+        assert_eq!(format_duration(Duration::from_secs(3600 + 62)), "1h01m02s");
+    }
+
+    #[test]
+    fn test_spinner() {
+        let draw = |millis| {
+            let mut buf = String::new();
+            draw_spinner(Duration::from_millis(millis), &mut buf);
+            buf
+        };
+        assert_eq!(draw(0), "⠋");
+        assert_eq!(draw(99), "⠋");
+        assert_eq!(draw(100), "⠙");
+        assert_eq!(draw(950), "⠏");
+        // Cycles back to the first glyph
+        assert_eq!(draw(1000), "⠋");
+    }
 }
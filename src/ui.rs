@@ -15,6 +15,7 @@
 use std::io::{Stderr, Stdout, Write};
 use std::process::{Child, ChildStdin, Stdio};
 use std::str::FromStr;
+use std::time::Duration;
 use std::{env, fmt, io, mem};
 
 use crossterm::tty::IsTty;
@@ -22,12 +23,15 @@ use crossterm::tty::IsTty;
 use crate::cli_util::CommandError;
 use crate::config::CommandNameAndArgs;
 use crate::formatter::{Formatter, FormatterFactory, LabeledWriter};
+use crate::progress;
 
 pub struct Ui {
     color: bool,
     pager_cmd: CommandNameAndArgs,
     paginate: PaginationChoice,
     progress_indicator: bool,
+    progress_refresh_rate: u32,
+    progress_decimal_units: bool,
     formatter_factory: FormatterFactory,
     output: UiOutput,
 }
@@ -36,6 +40,29 @@ fn progress_indicator_setting(config: &config::Config) -> bool {
     config.get_bool("ui.progress-indicator").unwrap_or(true)
 }
 
+fn progress_decimal_units_setting(config: &config::Config) -> bool {
+    config
+        .get_bool("ui.progress-decimal-units")
+        .unwrap_or(false)
+}
+
+fn progress_refresh_rate_setting(config: &config::Config) -> Result<u32, CommandError> {
+    match config.get_int("ui.progress-refresh-rate") {
+        Ok(rate) => u32::try_from(rate)
+            .ok()
+            .filter(|&rate| rate > 0)
+            .ok_or_else(|| {
+                CommandError::ConfigError(format!(
+                    "Invalid `ui.progress-refresh-rate`: {rate} is not a positive integer"
+                ))
+            }),
+        Err(config::ConfigError::NotFound(_)) => Ok(progress::DEFAULT_UPDATE_HZ),
+        Err(err) => Err(CommandError::ConfigError(format!(
+            "Invalid `ui.progress-refresh-rate`: {err}"
+        ))),
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub enum ColorChoice {
     Always,
@@ -105,12 +132,16 @@ impl Ui {
         let sanitize = io::stdout().is_tty();
         let formatter_factory = FormatterFactory::prepare(config, color, sanitize)?;
         let progress_indicator = progress_indicator_setting(config);
+        let progress_refresh_rate = progress_refresh_rate_setting(config)?;
+        let progress_decimal_units = progress_decimal_units_setting(config);
         Ok(Ui {
             color,
             formatter_factory,
             pager_cmd: pager_setting(config)?,
             paginate: PaginationChoice::Auto,
             progress_indicator,
+            progress_refresh_rate,
+            progress_decimal_units,
             output: UiOutput::new_terminal(),
         })
     }
@@ -119,6 +150,8 @@ impl Ui {
         self.color = use_color(color_setting(config));
         self.pager_cmd = pager_setting(config)?;
         self.progress_indicator = progress_indicator_setting(config);
+        self.progress_refresh_rate = progress_refresh_rate_setting(config)?;
+        self.progress_decimal_units = progress_decimal_units_setting(config);
         let sanitize = io::stdout().is_tty();
         self.formatter_factory = FormatterFactory::prepare(config, self.color, sanitize)?;
         Ok(())
@@ -186,9 +219,31 @@ impl Ui {
     }
 
     /// Whether continuous feedback should be displayed for long-running
-    /// operations
+    /// operations. If the output isn't a terminal, the feedback is printed as
+    /// plain lines.
     pub fn use_progress_indicator(&self) -> bool {
-        self.progress_indicator && io::stdout().is_tty()
+        self.progress_indicator
+    }
+
+    /// Width of the terminal the progress indicator can be redrawn in, or
+    /// `None` if the output isn't an interactive terminal.
+    pub fn progress_term_width(&self) -> Option<u16> {
+        if io::stdout().is_tty() {
+            self.term_width()
+        } else {
+            None
+        }
+    }
+
+    /// Minimum interval between updates of the progress indicator
+    pub fn progress_update_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.progress_refresh_rate
+    }
+
+    /// Whether the progress indicator shows sizes in decimal (SI) units, e.g.
+    /// MB, instead of binary units, e.g. MiB
+    pub fn progress_decimal_units(&self) -> bool {
+        self.progress_decimal_units
     }
 
     pub fn write(&mut self, text: &str) -> io::Result<()> {
@@ -366,6 +366,18 @@ fn test_invalid_config() {
     "###);
 }
 
+#[test]
+fn test_invalid_progress_refresh_rate() {
+    let test_env = TestEnvironment::default();
+
+    test_env.add_config("ui.progress-refresh-rate = 0");
+    let stderr = test_env.jj_cmd_failure(test_env.env_root(), &["init", "repo"]);
+    insta::assert_snapshot!(stderr, @r###"
+    Config error: Invalid `ui.progress-refresh-rate`: 0 is not a positive integer
+    For help, see https://github.com/martinvonz/jj/blob/main/docs/config.md.
+    "###);
+}
+
 #[test]
 fn test_no_user_configured() {
     // Test that the user is reminded if they haven't configured their name or email
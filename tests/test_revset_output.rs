@@ -194,6 +194,24 @@ fn test_bad_function_call() {
       |
       = expected expression
     "###);
+
+    let stderr = test_env.jj_cmd_failure(&repo_path, &["log", "-r", r#"regex:"foo""#]);
+    insta::assert_snapshot!(stderr, @r###"
+    Error: Failed to parse revset:  --> 1:1
+      |
+    1 | regex:"foo"
+      | ^---------^
+      |
+      = String pattern is only allowed as a function argument
+    "###);
+
+    let stderr = test_env.jj_cmd_failure(&repo_path, &["log", "-r", r#"author(regex:"(foo")"#]);
+    insta::assert_snapshot!(stderr, @r###"
+    Error: Invalid regular expression "(foo": regex parse error:
+        (foo
+        ^
+    error: unclosed group
+    "###);
 }
 
 #[test]
@@ -231,7 +249,7 @@ fn test_function_name_hint() {
       | ^-----^
       |
       = Revset function "author_" doesn't exist
-    Hint: Did you mean "author", "my_author"?
+    Hint: Did you mean "author", "author_date", "author_email", "author_email_domain", "author_name", "coauthor", "my_author"?
     "###);
 }
 